@@ -16,15 +16,6 @@ def get_full_instruction(values):
     return instruction
 
 
-def create_decode_match(values):
-    if args.cb:
-        return '{} => Some(cb_to_string({}, "{}", &get_operands(cpu, {}))),' \
-            .format(values['addr'], values['addr'], get_full_instruction(values), values['length'])
-    else:
-        return '{} => Some(to_string({}, "{}", &get_operands(cpu, {}))),' \
-            .format(values['addr'], values['addr'], get_full_instruction(values), values['length'])
-
-
 def create_execute_match(values):
     if args.cb:
         return '{} => cb_execute_{}(cpu),'.format(values['addr'], values['addr'][2:])
@@ -76,10 +67,8 @@ def main():
             for i in range(len(values['cycles'])):
                 values['cycles'][i] = int(values['cycles'][i] / 4)
 
-        if args.decode_matches:  # Print out every decode match (unprefixed)
-            for opcode, values in data.items():
-                print(create_decode_match(values))
-        elif args.execute_matches:  # Print out every execute match (unprefixed)
+        # Decoding lives in src/decode.rs and works from the opcode bit fields, no tables needed
+        if args.execute_matches:  # Print out every execute match (unprefixed)
             for opcode, values in data.items():
                 print(create_execute_match(values))
         elif args.execute_funcs:  # Print out every execute function stub (unprefixed)
@@ -90,7 +79,6 @@ def main():
 
 
 parser = ArgumentParser()
-parser.add_argument("--decode-matches", action="store_true", help="Print out the decode matches")
 parser.add_argument("--execute-matches", action="store_true", help="Print out the execute matches")
 parser.add_argument("--execute-funcs", action="store_true", help="Print out the execute function stubs")
 parser.add_argument("--cb", action="store_true", help="Use the CB prefixed instructions")
@@ -0,0 +1,124 @@
+use crate::bootrom::BootRom;
+use crate::model::Model;
+use crate::trace::{TraceFilter, TraceFormat, Tracer};
+
+/* Helpers for the frontends' command lines. They return errors rather than exiting, each binary
+ * prints them and exits itself. */
+
+// Remove `<flag>` from the arguments, returning whether it was present
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    if let Some(index) = args.iter().position(|arg| arg == flag) {
+        args.remove(index);
+        return true;
+    }
+    false
+}
+
+// Remove `<flag> <value>` from the arguments, returning the value
+pub fn take_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let Some(index) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err(format!("{} requires a value", flag));
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+// `--model <name>`, defaulting to DMG
+pub fn take_model(args: &mut Vec<String>) -> Result<Model, String> {
+    match take_value(args, "--model")? {
+        Some(name) => name.parse(),
+        None => Ok(Model::Dmg),
+    }
+}
+
+// `--skip-boot` or any number of `--bootrom [<model>=]<path>`, defaulting to the embedded boot ROM. A path
+// without a model is the boot ROM for `model`.
+pub fn take_boot_rom(args: &mut Vec<String>, model: Model) -> Result<BootRom, String> {
+    if take_flag(args, "--skip-boot") {
+        return Ok(BootRom::Skip);
+    }
+    let mut boot_rom = BootRom::Embedded;
+    while let Some(value) = take_value(args, "--bootrom")? {
+        match value.split_once('=') {
+            Some((name, path)) => boot_rom.load(name.parse()?, path)?,
+            None => boot_rom.load(model, &value)?,
+        }
+    }
+    Ok(boot_rom)
+}
+
+// Parse a hex address, with or without a `0x`/`$` prefix
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' isn't a valid address", text))
+}
+
+fn take_address(args: &mut Vec<String>, flag: &str) -> Result<Option<u16>, String> {
+    take_value(args, flag)?.map(|text| parse_address(&text)).transpose()
+}
+
+// `--trace <path>` with `--trace-format`, `--trace-range <from>-<to>`, `--trace-bank`, `--trace-start`, `--trace-stop`
+// and `--trace-labels`
+pub fn take_tracer(args: &mut Vec<String>) -> Result<Option<Tracer>, String> {
+    let format = match take_value(args, "--trace-format")? {
+        Some(name) => name.parse()?,
+        None => TraceFormat::Doctor,
+    };
+    let addresses = match take_value(args, "--trace-range")? {
+        Some(range) => {
+            let (from, to) = range.split_once('-').unwrap_or((&range, &range));
+            match (parse_address(from), parse_address(to)) {
+                (Ok(from), Ok(to)) => Some(from..=to),
+                _ => return Err("--trace-range expects <from>-<to>, e.g. 0150-01FF".to_string()),
+            }
+        }
+        None => None,
+    };
+    let rom_bank = match take_value(args, "--trace-bank")? {
+        Some(bank) => Some(bank.parse().map_err(|_| "--trace-bank expects a bank number".to_string())?),
+        None => None,
+    };
+    let filter = TraceFilter {
+        addresses,
+        rom_bank,
+        start_pc: take_address(args, "--trace-start")?,
+        stop_pc: take_address(args, "--trace-stop")?,
+    };
+    let labels = take_flag(args, "--trace-labels");
+    if labels && format != TraceFormat::Bgb {
+        return Err("--trace-labels needs --trace-format bgb, other formats are compared line for line".to_string());
+    }
+    let Some(path) = take_value(args, "--trace")? else {
+        return Ok(None);
+    };
+    let mut tracer = Tracer::new(&path, format, filter).map_err(|e| format!("Unable to create the trace file {}: {}", path, e))?;
+    tracer.labels = labels;
+    Ok(Some(tracer))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::{take_flag, take_value};
+
+    #[test]
+    fn take_value_ok() {
+        let mut args: Vec<String> = ["metalboy", "--model", "cgb", "rom.gb"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(take_value(&mut args, "--model"), Ok(Some("cgb".to_string())));
+        assert_eq!(args, vec!["metalboy", "rom.gb"]);
+        assert_eq!(take_value(&mut args, "--model"), Ok(None));
+        args.push("--model".to_string());
+        assert!(take_value(&mut args, "--model").is_err());
+    }
+
+    #[test]
+    fn take_flag_ok() {
+        let mut args: Vec<String> = ["metalboy", "--skip-boot", "rom.gb"].iter().map(|s| s.to_string()).collect();
+        assert!(take_flag(&mut args, "--skip-boot"));
+        assert!(!take_flag(&mut args, "--skip-boot"));
+        assert_eq!(args, vec!["metalboy", "rom.gb"]);
+    }
+}
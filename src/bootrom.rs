@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::fs;
+use crate::model::Model;
+
+/* Bootix is a copyright-free DMG boot ROM, see:
+   https://github.com/Hacktix/Bootix
+ */
+pub const BOOTIX_DMG: &[u8; 256] = include_bytes!("../bootix_dmg.bin");
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+pub enum BootRom {
+    Embedded,                        // The bundled boot ROM for the model, skipping boot where there isn't one
+    Custom(HashMap<Model, Vec<u8>>), // User-supplied boot ROMs, models without one fall back to Embedded
+    Skip,                            // Start at 0x100 with the post-boot state applied
+}
+
+impl BootRom {
+    // Use the boot ROM at `rom_path` for `model`, it has to be the size that model maps
+    pub fn load(&mut self, model: Model, rom_path: &str) -> Result<(), String> {
+        let data = fs::read(rom_path).map_err(|e| format!("Unable to read boot ROM '{}': {}", rom_path, e))?;
+        check(model, &data)?;
+        match self {
+            BootRom::Custom(roms) => {
+                roms.insert(model, data);
+            }
+            _ => *self = BootRom::Custom(HashMap::from([(model, data)])),
+        }
+        Ok(())
+    }
+
+    // The boot ROM to map for a model, or None if booting should be skipped
+    pub fn data_for(&self, model: Model) -> Option<&[u8]> {
+        match self {
+            BootRom::Custom(roms) if roms.contains_key(&model) => Some(&roms[&model]),
+            BootRom::Embedded | BootRom::Custom(_) if model == Model::Dmg => Some(BOOTIX_DMG),
+            BootRom::Embedded | BootRom::Custom(_) | BootRom::Skip => None,
+        }
+    }
+}
+
+pub fn check(model: Model, data: &[u8]) -> Result<(), String> {
+    let expected = if model.is_cgb() { CGB_BOOT_ROM_SIZE } else { DMG_BOOT_ROM_SIZE };
+    if data.len() != expected {
+        return Err(format!("A {} boot ROM must be {} bytes, the one given is {} bytes", model, expected, data.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::bootrom::{check, BootRom, BOOTIX_DMG, CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+    use crate::model::Model;
+
+    #[test]
+    fn checked_against_the_model() {
+        assert!(check(Model::Cgb, &[0; CGB_BOOT_ROM_SIZE]).is_ok());
+        assert!(check(Model::Dmg, &[0; CGB_BOOT_ROM_SIZE]).is_err());
+        assert!(check(Model::Agb, &[0; DMG_BOOT_ROM_SIZE]).is_err());
+    }
+
+    #[test]
+    fn one_per_model() {
+        let boot_rom = BootRom::Custom(HashMap::from([(Model::Cgb, vec![0xCB; CGB_BOOT_ROM_SIZE])]));
+        assert_eq!(boot_rom.data_for(Model::Cgb).map(|data| data[0]), Some(0xCB));
+        assert_eq!(boot_rom.data_for(Model::Dmg), Some(&BOOTIX_DMG[..])); // Embedded for the rest
+        assert_eq!(boot_rom.data_for(Model::Mgb), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::cell::{Cell, RefCell};
+use crate::mmu::Mmu;
+
+// Everything the CPU can see: the MMU on a real system, flat memory for instruction tests
+pub trait Bus {
+    fn read(&self, address: u16) -> u8;
+    // A read for the debugger, which mustn't trip watchpoints or show up in access logs
+    fn peek(&self, address: u16) -> u8 { self.read(address) }
+    fn write(&mut self, address: u16, byte: u8);
+    // Called after every instruction with the M-cycles it took
+    fn tick(&mut self, _cycles: usize) {}
+    // An M-cycle the CPU spends working internally, with nothing on the bus
+    fn idle(&mut self) {}
+    // STOP switches speed on the CGB when KEY1 is armed
+    fn speed_switch(&mut self) -> bool { false }
+    // The ROM bank mapped at an address, for the call stack
+    fn rom_bank(&self, _address: u16) -> u16 { 0 }
+}
+
+impl Bus for Mmu {
+    fn read(&self, address: u16) -> u8 {
+        let byte = self.get(address);
+        if !self.watchpoints.is_empty() {
+            self.watch_read(address, byte);
+        }
+        if self.log_accesses {
+            self.accesses.borrow_mut().push((address, AccessKind::Read));
+        }
+        byte
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.get(address)
+    }
+
+    fn write(&mut self, address: u16, byte: u8) {
+        if !self.watchpoints.is_empty() {
+            self.watch_write(address, byte);
+        }
+        if self.log_accesses {
+            self.accesses.get_mut().push((address, AccessKind::Write));
+        }
+        self.set(address, byte);
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        self.try_speed_switch()
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        self.rom_bank_at(address)
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Access {
+    pub cycle: usize, // M-cycles since the bus was created, each access takes one
+    pub address: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+// 64KB of plain RAM with no IO, recording every access and the M-cycle it happened on for the
+// SM83 single-step tests
+pub struct FlatBus {
+    pub memory: Vec<u8>,
+    pub accesses: RefCell<Vec<Access>>,
+    pub cycle: Cell<usize>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            accesses: RefCell::new(vec![]),
+            cycle: Cell::new(0),
+        }
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus::default()
+    }
+
+    fn record(&self, address: u16, value: u8, kind: AccessKind) {
+        self.accesses.borrow_mut().push(Access { cycle: self.cycle.get(), address, value, kind });
+        self.cycle.set(self.cycle.get() + 1);
+    }
+
+    pub fn writes(&self) -> Vec<(u16, u8)> {
+        self.accesses.borrow().iter()
+            .filter(|access| access.kind == AccessKind::Write)
+            .map(|access| (access.address, access.value))
+            .collect()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&self, address: u16) -> u8 {
+        let value = self.memory[address as usize];
+        self.record(address, value, AccessKind::Read);
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, byte: u8) {
+        self.memory[address as usize] = byte;
+        self.record(address, byte, AccessKind::Write);
+    }
+
+    fn idle(&mut self) {
+        self.cycle.set(self.cycle.get() + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::{Access, AccessKind, Bus, FlatBus};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn flat_bus_records_accesses() {
+        let mut cpu = Cpu::with_bus(FlatBus::new());
+        cpu.mmu.memory[0] = 0x77; // LD (HL),A
+        cpu.reg.set_hl(0xC000);
+        cpu.reg.a = 0x3C;
+        cpu.step();
+        assert_eq!(cpu.mmu.cycle.get(), 2);
+        assert_eq!(cpu.mmu.read(0xC000), 0x3C);
+        assert_eq!(cpu.mmu.writes(), vec![(0xC000, 0x3C)]);
+        assert_eq!(cpu.mmu.accesses.borrow()[0], Access { cycle: 0, address: 0, value: 0x77, kind: AccessKind::Read });
+        assert_eq!(cpu.mmu.accesses.borrow()[1], Access { cycle: 1, address: 0xC000, value: 0x3C, kind: AccessKind::Write });
+    }
+
+    // CALL a16: both operand bytes, an internal M-cycle to decrement SP, then the return address high byte first
+    #[test]
+    fn accesses_on_their_own_cycles() {
+        let mut cpu = Cpu::with_bus(FlatBus::new());
+        cpu.mmu.memory[..3].copy_from_slice(&[0xCD, 0x34, 0x12]);
+        cpu.reg.sp = 0xD000;
+        cpu.step();
+        let accesses: Vec<_> = cpu.mmu.accesses.borrow().iter().map(|access| (access.cycle, access.address, access.kind)).collect();
+        assert_eq!(accesses, vec![
+            (0, 0x0000, AccessKind::Read),
+            (1, 0x0001, AccessKind::Read),
+            (2, 0x0002, AccessKind::Read),
+            (4, 0xCFFF, AccessKind::Write),
+            (5, 0xCFFE, AccessKind::Write),
+        ]);
+    }
+
+    // Accesses and internal M-cycles add up to the instruction timings, for branches taken or not
+    #[test]
+    fn bus_cycles_match_timings() {
+        for prefixed in [false, true] {
+            for opcode in 0..=0xFF {
+                for flags in [0x00, 0xF0] {
+                    let mut cpu = Cpu::with_bus(FlatBus::new());
+                    let program = if prefixed { [0xCB, opcode, 0x00] } else { [opcode, 0x10, 0x20] };
+                    cpu.mmu.memory[..3].copy_from_slice(&program);
+                    cpu.reg.sp = 0xD000;
+                    cpu.reg.set_hl(0xC000);
+                    cpu.reg.f.set_from_u8(flags);
+                    cpu.step();
+                    if cpu.fault.is_some() || (!prefixed && matches!(opcode, 0x10 | 0x76 | 0xCB)) {
+                        continue; // Not implemented, or STOP/HALT which stop the clock
+                    }
+                    cpu.mmu.read(cpu.reg.pc); // Fetching the next opcode is the last M-cycle
+                    assert_eq!(cpu.mmu.cycle.get(), cpu.cycles + 1, "{}{:02X} with F={:02X}", if prefixed { "CB " } else { "" }, opcode, flags);
+                }
+            }
+        }
+    }
+}
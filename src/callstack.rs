@@ -0,0 +1,179 @@
+use std::io;
+use crate::disasm::Labels;
+use crate::savestate::{StateReader, StateWriter};
+
+/* Shadow call stack, kept beside the real one by watching CALL/RST/interrupt dispatch and RET/RETI.
+ * Code doesn't always return the way it called: jump tables PUSH an address and RET to it, routines POP
+ * their return address and JP, and LD SP resets the stack. Frames are keyed by where their return address
+ * was pushed, so a RET only ends the frame whose address it actually pops, and frames whose slot gets
+ * reused by a newer CALL are dropped as abandoned. */
+
+pub const MAX_DEPTH: usize = 256; // Runaway recursion drops the oldest frames past this
+pub const INTERRUPT_NAMES: [&str; 5] = ["VBlank", "LCD", "Timer", "Serial", "Joypad"];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum EntryKind {
+    Call,
+    Rst,
+    Interrupt(u8), // Interrupt id, 0 = VBlank ~ 4 = Joypad
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Frame {
+    pub kind: EntryKind,
+    pub caller: u16, // The CALL/RST, or the instruction an interrupt was about to run
+    pub caller_bank: u16,
+    pub target: u16,
+    pub target_bank: u16,
+    pub return_address: u16,
+    pub sp: u16, // Where the return address was pushed
+}
+
+impl Frame {
+    pub fn interrupt(&self) -> Option<&'static str> {
+        match self.kind {
+            EntryKind::Interrupt(id) => INTERRUPT_NAMES.get(id as usize).copied(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct CallStack {
+    pub frames: Vec<Frame>, // Innermost last
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn enter(&mut self, frame: Frame) {
+        // Anything pushed at or below this slot was left without returning
+        self.frames.retain(|old| old.sp > frame.sp);
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // A return popping its address from `sp`, returns that aren't from a tracked frame are jumps
+    pub fn leave(&mut self, sp: u16) {
+        if let Some(index) = self.frames.iter().rposition(|frame| frame.sp == sp) {
+            self.frames.truncate(index);
+        }
+    }
+
+    // Frames whose return address is still on the stack at `sp`, innermost first
+    pub fn live(&self, sp: u16) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().rev().filter(move |frame| frame.sp >= sp)
+    }
+
+    // gdb style, `#0` is where the CPU is now and each line after it is the caller of the one before
+    pub fn backtrace(&self, pc: u16, bank: u16, sp: u16, labels: &Labels) -> String {
+        let mut text = format!("#0  {}\n", labels.describe(pc, bank));
+        for (i, frame) in self.live(sp).enumerate() {
+            text += &format!("#{:<2} {}", i + 1, labels.describe(frame.caller, frame.caller_bank));
+            if let Some(interrupt) = frame.interrupt() {
+                text += &format!(" [{} interrupt]", interrupt);
+            }
+            text += "\n";
+        }
+        text
+    }
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            out.u8(match frame.kind {
+                EntryKind::Call => 0,
+                EntryKind::Rst => 1,
+                EntryKind::Interrupt(id) => 2 + id,
+            });
+            for word in [frame.caller, frame.caller_bank, frame.target, frame.target_bank, frame.return_address, frame.sp] {
+                out.u16(word);
+            }
+        }
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        let count = input.u32()?;
+        self.frames.clear();
+        for _ in 0..count {
+            let kind = match input.u8()? {
+                0 => EntryKind::Call,
+                1 => EntryKind::Rst,
+                id => EntryKind::Interrupt(id - 2),
+            };
+            self.frames.push(Frame {
+                kind,
+                caller: input.u16()?,
+                caller_bank: input.u16()?,
+                target: input.u16()?,
+                target_bank: input.u16()?,
+                return_address: input.u16()?,
+                sp: input.u16()?,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::callstack::EntryKind;
+    use crate::disasm::Labels;
+    use crate::model::Model;
+    use crate::system::System;
+
+    fn system(program: &[(u16, &[u8])]) -> System {
+        let mut system = System::with_program(Model::Dmg, program);
+        system.cpu.ime = false;
+        system
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let mut system = system(&[
+            (0x100, &[0xCD, 0x00, 0x02, 0xCD, 0x00, 0x03]), // CALL $0200 / CALL $0300
+            (0x200, &[0xFF]), // RST $38
+            (0x038, &[0xC9]), // RET
+            (0x201, &[0xC9]), // RET
+            (0x300, &[0xE1, 0x00]), // POP HL, the return address is thrown away
+        ]);
+        system.step(&[]);
+        system.step(&[]);
+        let frames = &system.cpu.calls.frames;
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].caller, frames[0].target, frames[0].kind), (0x100, 0x200, EntryKind::Call));
+        assert_eq!((frames[1].caller, frames[1].target, frames[1].kind), (0x200, 0x38, EntryKind::Rst));
+        assert_eq!(system.backtrace(), "#0  00:0038\n#1  00:0200\n#2  00:0100\n");
+
+        system.step(&[]);
+        system.step(&[]);
+        assert!(system.cpu.calls.frames.is_empty());
+        system.step(&[]);
+        system.step(&[]); // POP HL
+        assert_eq!(system.cpu.calls.live(system.cpu.reg.sp).count(), 0);
+    }
+
+    #[test]
+    fn interrupts_and_symbols() {
+        let mut system = system(&[(0x100, &[0x00, 0x00]), (0x40, &[0xD9])]); // NOP NOP / RETI
+        let mut labels = Labels::new();
+        labels.insert(0, 0x40, "VBlank");
+        labels.insert(0, 0x100, "Main");
+        system.symbols = labels;
+        system.cpu.ime = true;
+        system.cpu.mmu.set(0xFFFF, 0x01);
+        system.cpu.mmu.set(0xFF0F, 0x01);
+        system.step(&[]);
+        assert_eq!(system.cpu.reg.pc, 0x40);
+        assert_eq!(system.backtrace(), "#0  00:0040 VBlank\n#1  00:0101 Main+1 [VBlank interrupt]\n");
+        system.step(&[]); // RETI
+        assert!(system.cpu.calls.frames.is_empty());
+    }
+}
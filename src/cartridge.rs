@@ -3,6 +3,7 @@ use std::io::Read;
 use std::path::Path;
 use log::warn;
 
+#[derive(Clone)]
 pub struct Cartridge {
     pub data: Vec<u8>,
     pub mbc: u8,
@@ -26,6 +27,45 @@ impl Cartridge {
         self.set_mbc();
     }
 
+    // CGB flag in the header: 0x80 = CGB enhanced, 0xC0 = CGB only
+    pub fn supports_cgb(&self) -> bool {
+        self.data.get(0x143).is_some_and(|flag| flag & 0x80 == 0x80)
+    }
+
+    // SGB functions need both the SGB flag and the new licensee code in the header
+    pub fn supports_sgb(&self) -> bool {
+        self.data.get(0x146) == Some(&0x03) && self.data.get(0x14B) == Some(&0x33)
+    }
+
+    pub fn cgb_only(&self) -> bool {
+        self.data.get(0x143) == Some(&0xC0)
+    }
+
+    pub fn header_checksum(&self) -> u8 {
+        self.data.get(0x14D).copied().unwrap_or(0)
+    }
+
+    // Big-endian sum of the whole ROM, which the hardware never checks
+    pub fn global_checksum(&self) -> u16 {
+        match self.data.get(0x14E..0x150) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        }
+    }
+
+    // Sum of the title bytes, used by the CGB boot ROM to pick a palette for DMG carts
+    pub fn title_checksum(&self) -> u8 {
+        self.data.iter().skip(0x134).take(0x10).fold(0, |sum, byte| sum.wrapping_add(*byte))
+    }
+
+    pub fn licensee_is_nintendo(&self) -> bool {
+        match self.data.get(0x14B) {
+            Some(0x01) => true,
+            Some(0x33) => self.data.get(0x144..0x146) == Some(b"01"),
+            _ => false,
+        }
+    }
+
     fn set_mbc(&mut self) {
         let mbc_id = self.data[0x147];
         self.mbc = match mbc_id {
@@ -0,0 +1,174 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use crate::disasm::ROM_BANK_SIZE;
+
+/* Code/data log: a byte of flags per ROM byte saying how the CPU has used it. The file is the flags
+ * as they are, so runs are merged by ORing them together and it seeds the exporter as it is. */
+
+pub const CDL_CODE: u8 = 0b01;
+pub const CDL_DATA: u8 = 0b10;
+pub const CDL_OPERAND: u8 = 0b100; // Fetched as part of an instruction, after the opcode
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Usage {
+    Untouched,
+    Opcode, // First byte of an instruction that was executed
+    Operand,
+    Data,
+}
+
+// Bytes of each kind in a bank, or the whole ROM
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub struct Coverage {
+    pub opcode: usize,
+    pub operand: usize,
+    pub data: usize,
+    pub untouched: usize,
+}
+
+impl Coverage {
+    pub fn total(&self) -> usize {
+        self.opcode + self.operand + self.data + self.untouched
+    }
+
+    // Of the bytes used in any way
+    pub fn percent(&self) -> f64 {
+        (self.total() - self.untouched) as f64 * 100.0 / self.total().max(1) as f64
+    }
+}
+
+#[derive(Clone)]
+pub struct CodeDataLog {
+    pub flags: Vec<u8>,
+}
+
+impl CodeDataLog {
+    pub fn new(rom_size: usize) -> Self {
+        CodeDataLog { flags: vec![0; rom_size] }
+    }
+
+    // An earlier log for the same ROM if there is one, so this session adds to it
+    pub fn load(path: &Path, rom_size: usize) -> io::Result<Self> {
+        let mut log = CodeDataLog::new(rom_size);
+        match fs::read(path) {
+            Ok(flags) => log.merge(&flags),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        Ok(log)
+    }
+
+    // Merged with what's already in the file, in case another session saved since this one loaded
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if let Ok(flags) = fs::read(path) {
+            self.merge(&flags);
+        }
+        fs::write(path, &self.flags)
+    }
+
+    pub fn merge(&mut self, flags: &[u8]) {
+        for (mine, theirs) in self.flags.iter_mut().zip(flags) {
+            *mine |= theirs;
+        }
+    }
+
+    pub fn mark(&mut self, offset: usize, flag: u8) {
+        if let Some(flags) = self.flags.get_mut(offset) {
+            *flags |= flag;
+        }
+    }
+
+    // An instruction's bytes, opcode first, and the ROM it read as data
+    pub fn log(&mut self, fetched: &[usize], data: impl Iterator<Item = usize>) {
+        for (i, offset) in fetched.iter().enumerate() {
+            self.mark(*offset, if i == 0 { CDL_CODE } else { CDL_OPERAND });
+        }
+        for offset in data {
+            self.mark(offset, CDL_DATA);
+        }
+    }
+
+    // Executed wins over read, so the first byte of an instruction that's also read as data counts as code
+    pub fn usage(&self, offset: usize) -> Usage {
+        let flags = self.flags.get(offset).copied().unwrap_or(0);
+        if flags & CDL_CODE != 0 {
+            Usage::Opcode
+        } else if flags & CDL_OPERAND != 0 {
+            Usage::Operand
+        } else if flags & CDL_DATA != 0 {
+            Usage::Data
+        } else {
+            Usage::Untouched
+        }
+    }
+
+    pub fn banks(&self) -> usize {
+        self.flags.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    pub fn coverage(&self, range: Range<usize>) -> Coverage {
+        let mut coverage = Coverage::default();
+        for offset in range.start..range.end.min(self.flags.len()) {
+            match self.usage(offset) {
+                Usage::Opcode => coverage.opcode += 1,
+                Usage::Operand => coverage.operand += 1,
+                Usage::Data => coverage.data += 1,
+                Usage::Untouched => coverage.untouched += 1,
+            }
+        }
+        coverage
+    }
+
+    pub fn bank_coverage(&self, bank: usize) -> Coverage {
+        self.coverage(bank * ROM_BANK_SIZE..(bank + 1) * ROM_BANK_SIZE)
+    }
+}
+
+// `game.gb` -> `game.cdl`
+pub fn cdl_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("cdl")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::cdl::{CodeDataLog, Usage};
+    use crate::model::Model;
+    use crate::system::System;
+
+    // LD A,[$4000] / LD HL,$0000 / LD A,[HL] / JR -3
+    #[test]
+    fn opcodes_operands_and_data() {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0xFA, 0x00, 0x40, 0x21, 0x00, 0x00, 0x7E, 0x18, 0xFD])]);
+        system.cdl = Some(CodeDataLog::new(0x8000));
+        for _ in 0..6 {
+            system.step(&[]);
+        }
+        let cdl = system.cdl.as_ref().unwrap();
+        assert_eq!(cdl.usage(0x100), Usage::Opcode);
+        assert_eq!(cdl.usage(0x101), Usage::Operand);
+        assert_eq!(cdl.usage(0x4000), Usage::Data);
+        assert_eq!(cdl.usage(0x0000), Usage::Data); // Through HL
+        assert_eq!(cdl.usage(0x109), Usage::Untouched);
+        let coverage = cdl.bank_coverage(0);
+        assert_eq!((coverage.opcode, coverage.operand, coverage.data), (4, 5, 1));
+    }
+
+    #[test]
+    fn merged_across_sessions() {
+        let path = std::env::temp_dir().join(format!("metalboy-cdl-{}.cdl", std::process::id()));
+        let mut first = CodeDataLog::new(4);
+        first.flags = vec![1, 0, 2, 0];
+        first.save(&path).unwrap();
+        let mut second = CodeDataLog::load(&path, 4).unwrap();
+        second.mark(3, 4);
+        let mut other = CodeDataLog::new(4);
+        other.mark(1, 2); // Saved by another session in the meantime
+        other.save(&path).unwrap();
+        second.save(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![1, 2, 2, 4]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::debugger::{self, Breakpoint, BreakReason, RunTarget, WatchKind, Watchpoint};
+use crate::disasm::{self, bank_address};
+use crate::expr::{Condition, LogMessage};
+use crate::graphics::FRAME_CYCLES;
+use crate::history::SaveState;
+use crate::joypad::Button;
+use crate::symbols::{self, Location};
+use crate::system::System;
+
+/* GDB-style commands for the headless debugger, metalboy-cli. Every command returns the text to
+ * print rather than printing it, so a script of commands always produces the same transcript. */
+
+pub const HELP: &str = "\
+break [<loc>] [if <cond>]       Add a breakpoint, or list them without a location
+dprintf <loc> <message>         Print a message at <loc> without stopping, {expr} and {expr:d} are replaced
+delete [<n>]                    Delete breakpoint <n>, or all of them
+watch [<range>] [read|write|change]   Add a watchpoint (write by default), or list them
+unwatch [<n>]                   Delete watchpoint <n>, or all of them
+step [<n>]                      Execute <n> instructions (s, si)
+next                            Step over CALL and RST (n)
+finish                          Run until the current function returns
+until <loc>                     Run until <loc> is reached (u)
+continue [<frames>]             Run until something stops execution, or for at most <frames> frames (c)
+reverse-step                    Go back one instruction (rs)
+reverse-continue                Run backwards to the previous break (rc)
+x/<n><x|d|i><b|h> <addr>        Examine memory as hex, decimal or instructions, in bytes or halfwords
+print <expr>                    Evaluate an expression (p)
+regs                            Show the registers (info registers)
+disas [<loc> [<n>]]             Disassemble <n> instructions from <loc> or PC
+set <reg>=<expr>                Set a register, flag (zf, nf, hf, cf), ime, or memory with [<addr>]=<expr>
+frame                           Show where execution is (f)
+backtrace                       Show the call stack (bt)
+input [<buttons>]               Hold up/down/left/right/a/b/start/select until changed, nothing releases all
+savestate [<name>|<path>]       Save the system in memory for this session, or to a file given a path
+loadstate [<name>|<path>]       Load a state saved earlier, names with a . or / are paths
+screen                          Draw the screen as text
+reset                           Reset the system
+quit                            Exit (q)
+Locations are symbols, 03:4A2F or a hex address. Expressions are the same as breakpoint conditions.
+Ctrl-C stops a running command and returns to the prompt.";
+
+const DEFAULT_STATE: &str = "default";
+const DISAS_LINES: usize = 10;
+const SCREEN_SHADES: &[u8] = b"@%#*+=-:. "; // Darkest first
+const SCREEN_CELL: (usize, usize) = (2, 4); // Pixels per character, terminal cells are about twice as tall as wide
+
+pub struct Session {
+    pub system: System,
+    pub pressed: Vec<Button>, // Held until the next `input`
+    states: HashMap<String, SaveState>,
+    pub interrupt: Option<&'static AtomicBool>, // Set from a Ctrl-C handler to stop running
+    pub batch: bool, // Nobody is there to press Ctrl-C, so `continue` has to have an end
+}
+
+impl Session {
+    pub fn new(mut system: System) -> Self {
+        system.enable_history();
+        Session { system, pressed: vec![], states: HashMap::new(), interrupt: None, batch: false }
+    }
+
+    // Run one line of input, `#` starts a comment so command files can be annotated
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(interrupt) = self.interrupt {
+            interrupt.store(false, Ordering::Relaxed); // A Ctrl-C at the prompt shouldn't stop the next command
+        }
+        let (command, rest) = line.split_once(char::is_whitespace).map_or((line, ""), |(command, rest)| (command, rest.trim()));
+        if let Some(format) = command.strip_prefix("x/") {
+            return self.examine(format, rest);
+        }
+        match command {
+            "" => Ok(String::new()),
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" if rest.is_empty() => Ok(self.list_breakpoints()),
+            "break" | "b" => self.add_breakpoint(rest),
+            "dprintf" => self.add_log_point(rest),
+            "delete" | "d" => self.delete_breakpoint(rest),
+            "watch" if rest.is_empty() => Ok(self.list_watchpoints()),
+            "watch" => self.add_watchpoint(rest),
+            "unwatch" => self.delete_watchpoint(rest),
+            "step" | "s" | "si" | "stepi" => self.step(rest),
+            "next" | "n" => {
+                self.system.step_over();
+                Ok(self.resume(None))
+            }
+            "finish" => {
+                self.system.step_out();
+                Ok(self.resume(None))
+            }
+            "until" | "u" => {
+                let location = symbols::parse_location(rest, &self.system.symbols)?;
+                self.system.run_to(location);
+                Ok(self.resume(None))
+            }
+            "continue" | "c" => {
+                let frames = match rest {
+                    "" => None,
+                    _ => Some(rest.parse().map_err(|_| format!("'{}' isn't a number of frames", rest))?),
+                };
+                if self.batch && frames.is_none() && self.system.breakpoints.is_empty() && self.system.cpu.mmu.watchpoints.is_empty() {
+                    return Err("In batch mode continue needs a number of frames, or a breakpoint or watchpoint to stop at".to_string());
+                }
+                Ok(self.resume(frames))
+            }
+            "reverse-step" | "rs" => match self.system.step_back() {
+                true => Ok(self.stopped()),
+                false => Err("No history to go back to".to_string()),
+            },
+            "reverse-continue" | "rc" => match self.system.reverse_continue() {
+                Some(_) => Ok(self.stopped()),
+                None if self.system.break_reason.take().is_some() => Ok(format!("Reached the start of history\n{}", self.current_line())),
+                None => Err("No history to go back to".to_string()),
+            },
+            "print" | "p" => {
+                let value = self.eval(rest)?;
+                Ok(format!("= ${:X} ({})", value, value))
+            }
+            "regs" => Ok(self.registers()),
+            "info" => match rest {
+                "registers" | "r" => Ok(self.registers()),
+                "breakpoints" | "b" => Ok(self.list_breakpoints()),
+                "watchpoints" | "w" => Ok(self.list_watchpoints()),
+                _ => Err("info expects registers, breakpoints or watchpoints".to_string()),
+            },
+            "disas" => self.disassemble(rest),
+            "set" => self.set(rest),
+            "frame" | "f" => Ok(format!("{}\n{}", self.system.backtrace().lines().next().unwrap_or(""), self.current_line())),
+            "backtrace" | "bt" => Ok(self.system.backtrace().trim_end().to_string()),
+            "input" => {
+                self.pressed = rest.split_whitespace().map(parse_button).collect::<Result<_, _>>()?;
+                Ok(String::new())
+            }
+            "savestate" => {
+                let name = if rest.is_empty() { DEFAULT_STATE } else { rest };
+                if is_path(name) {
+                    self.system.save_state_file(Path::new(name)).map_err(|e| format!("Unable to save {}: {}", name, e))?;
+                } else {
+                    self.states.insert(name.to_string(), self.system.save_state());
+                }
+                Ok(format!("Saved '{}' at step {}", name, self.system.steps))
+            }
+            "loadstate" => {
+                let name = if rest.is_empty() { DEFAULT_STATE } else { rest };
+                if is_path(name) {
+                    self.system.load_state_file(Path::new(name)).map_err(|e| format!("Unable to load {}: {}", name, e))?;
+                } else {
+                    let state = self.states.get(name).ok_or(format!("No state named '{}'", name))?;
+                    self.system.load_state(state);
+                }
+                Ok(format!("Loaded '{}'\n{}", name, self.current_line()))
+            }
+            "screen" => Ok(self.screen()),
+            "reset" => {
+                self.system.reset();
+                Ok(self.current_line())
+            }
+            _ => Err(format!("Unknown command '{}', try help", command)),
+        }
+    }
+
+    fn eval(&self, text: &str) -> Result<i64, String> {
+        Ok(Condition::parse(text, &self.system.symbols)?.eval(&self.system, 0))
+    }
+
+    fn eval_address(&self, text: &str) -> Result<u16, String> {
+        let value = self.eval(text)?;
+        u16::try_from(value).map_err(|_| format!("{} isn't an address", value))
+    }
+
+    fn describe(&self, location: &Location) -> String {
+        let bank = location.bank.unwrap_or_else(|| self.system.cpu.mmu.rom_bank_at(location.address));
+        self.system.symbols.describe(location.address, bank)
+    }
+
+    fn add_breakpoint(&mut self, text: &str) -> Result<String, String> {
+        let (location, condition) = match text.split_once(" if ") {
+            Some((location, condition)) => (location, Some(Condition::parse(condition, &self.system.symbols)?)),
+            None => (text, None),
+        };
+        let mut breakpoint = Breakpoint::new(symbols::parse_location(location.trim(), &self.system.symbols)?);
+        breakpoint.condition = condition;
+        let text = format!("Breakpoint {} at {}", self.system.breakpoints.len() + 1, self.describe(&breakpoint.location));
+        self.system.breakpoints.push(breakpoint);
+        Ok(text)
+    }
+
+    fn add_log_point(&mut self, text: &str) -> Result<String, String> {
+        let (location, message) = text.split_once(char::is_whitespace).ok_or("dprintf expects a location and a message")?;
+        let mut breakpoint = Breakpoint::new(symbols::parse_location(location, &self.system.symbols)?);
+        breakpoint.log = Some(LogMessage::parse(message.trim(), &self.system.symbols)?);
+        let text = format!("Log-point {} at {}", self.system.breakpoints.len() + 1, self.describe(&breakpoint.location));
+        self.system.breakpoints.push(breakpoint);
+        Ok(text)
+    }
+
+    fn delete_breakpoint(&mut self, text: &str) -> Result<String, String> {
+        let breakpoints = &mut self.system.breakpoints;
+        match text {
+            "" => breakpoints.clear(),
+            _ => {
+                let index = parse_index(text, breakpoints.len())?;
+                breakpoints.remove(index);
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.system.breakpoints.is_empty() {
+            return "No breakpoints".to_string();
+        }
+        let mut text = String::new();
+        for (i, breakpoint) in self.system.breakpoints.iter().enumerate() {
+            text += &format!("{:<3} {}", i + 1, self.describe(&breakpoint.location));
+            if let Some(condition) = &breakpoint.condition {
+                text += &format!(" if {}", condition);
+            }
+            if let Some(log) = &breakpoint.log {
+                text += &format!(" log \"{}\"", log.source);
+            }
+            text += &format!(", hit {} time(s)\n", breakpoint.hits);
+        }
+        text.trim_end().to_string()
+    }
+
+    fn add_watchpoint(&mut self, text: &str) -> Result<String, String> {
+        let (range, kind) = match text.rsplit_once(char::is_whitespace) {
+            Some((range, "read")) => (range, WatchKind::Read),
+            Some((range, "write")) => (range, WatchKind::Write),
+            Some((range, "change")) => (range, WatchKind::Change),
+            _ => (text, WatchKind::Write),
+        };
+        let range = debugger::parse_range(range.trim(), &self.system.symbols)?;
+        let watchpoints = &mut self.system.cpu.mmu.watchpoints;
+        let text = format!("Watchpoint {} on {} of {:04X}-{:04X}", watchpoints.len() + 1, kind, range.start(), range.end());
+        watchpoints.push(Watchpoint { range, kind });
+        Ok(text)
+    }
+
+    fn delete_watchpoint(&mut self, text: &str) -> Result<String, String> {
+        let watchpoints = &mut self.system.cpu.mmu.watchpoints;
+        match text {
+            "" => watchpoints.clear(),
+            _ => {
+                let index = parse_index(text, watchpoints.len())?;
+                watchpoints.remove(index);
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn list_watchpoints(&self) -> String {
+        let watchpoints = &self.system.cpu.mmu.watchpoints;
+        if watchpoints.is_empty() {
+            return "No watchpoints".to_string();
+        }
+        let lines: Vec<String> = watchpoints.iter().enumerate()
+            .map(|(i, watchpoint)| format!("{:<3} {} of {:04X}-{:04X}", i + 1, watchpoint.kind, watchpoint.range.start(), watchpoint.range.end()))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn step(&mut self, text: &str) -> Result<String, String> {
+        let count: u64 = match text {
+            "" => 1,
+            _ => text.parse().map_err(|_| format!("'{}' isn't a number of instructions", text))?,
+        };
+        let mut output = String::new();
+        for _ in 1..count {
+            self.system.step_into();
+            if self.run(&mut output, None) == Finished::Interrupted {
+                return Ok(format!("{}Interrupted\n{}", output, self.current_line()));
+            }
+            if self.system.break_reason != Some(BreakReason::Stepped(RunTarget::Instruction)) {
+                return Ok(output + &self.stopped()); // Cut short by a breakpoint
+            }
+        }
+        self.system.step_into();
+        Ok(output + &self.resume(None))
+    }
+
+    // Run until something stops execution, then say why and where
+    fn resume(&mut self, frames: Option<u64>) -> String {
+        let mut output = String::new();
+        let start = self.system.graphics.frame;
+        let finished = self.run(&mut output, frames);
+        let lcd_off = !self.system.graphics.lcd_enabled(&self.system.cpu.mmu);
+        output += &match finished {
+            Finished::Break => self.stopped(),
+            Finished::Frames if lcd_off => format!("Ran {} frame(s) of cycles, the LCD is off\n{}", frames.unwrap_or(0), self.current_line()),
+            Finished::Frames => format!("Ran {} frame(s)\n{}", self.system.graphics.frame - start, self.current_line()),
+            Finished::Stuck => format!("Stuck in an infinite loop with no interrupts enabled\n{}", self.current_line()),
+            Finished::Interrupted => format!("Interrupted\n{}", self.current_line()),
+        };
+        output
+    }
+
+    // Step until there's a break reason, collecting log-point and serial output on the way. Frames
+    // don't go by with the LCD off, so then it's the cycles they'd take.
+    fn run(&mut self, output: &mut String, frames: Option<u64>) -> Finished {
+        let serial = self.system.serial.output.len();
+        let until_frame = frames.map(|frames| self.system.graphics.frame + frames);
+        let mut cycles = 0;
+        let finished = loop {
+            cycles += self.system.step(&self.pressed);
+            for message in self.system.log_messages.drain(..) {
+                *output += &message;
+                *output += "\n";
+            }
+            if self.system.break_reason.is_some() {
+                break Finished::Break;
+            }
+            if until_frame.is_some_and(|frame| self.system.graphics.frame >= frame) {
+                break Finished::Frames;
+            }
+            let lcd_off = !self.system.graphics.lcd_enabled(&self.system.cpu.mmu);
+            if lcd_off && frames.is_some_and(|frames| cycles >= frames as usize * FRAME_CYCLES) {
+                break Finished::Frames;
+            }
+            if self.system.stuck() {
+                break Finished::Stuck;
+            }
+            if self.interrupt.is_some_and(|interrupt| interrupt.load(Ordering::Relaxed)) {
+                break Finished::Interrupted;
+            }
+        };
+        self.system.run_target = None;
+        let sent = &self.system.serial.output[serial..];
+        if !sent.is_empty() {
+            *output += &String::from_utf8_lossy(sent);
+            if !output.ends_with('\n') {
+                *output += "\n";
+            }
+        }
+        finished
+    }
+
+    fn stopped(&mut self) -> String {
+        let reason = self.system.break_reason.take();
+        self.stopped_with(reason)
+    }
+
+    fn stopped_with(&self, reason: Option<BreakReason>) -> String {
+        match reason {
+            Some(BreakReason::Stepped(_)) | None => self.current_line(),
+            Some(reason) => {
+                let pc = reason.pc().unwrap_or(self.system.cpu.reg.pc);
+                let at = self.system.symbols.describe(pc, self.system.cpu.mmu.rom_bank_at(pc));
+                format!("{} ({})\n{}", reason, at, self.current_line())
+            }
+        }
+    }
+
+    fn current_line(&self) -> String {
+        let pc = self.system.cpu.reg.pc;
+        self.listing(&self.lines(pc, self.system.cpu.mmu.rom_bank_at(pc), 1))
+    }
+
+    fn lines(&self, from: u16, bank: u16, count: usize) -> Vec<disasm::Line> {
+        let mmu = &self.system.cpu.mmu;
+        let rom = &mmu.cartridge.data;
+        let mapped = mmu.rom_bank_at(from);
+        let read = |address: u16| match address {
+            // A bank other than the one mapped in is read straight from the ROM
+            0x4000..=0x7FFF if bank != mapped => rom.get(disasm::rom_offset(address, bank)).copied().unwrap_or(0),
+            _ => mmu.get(address),
+        };
+        let to = from.saturating_add(count as u16 * 3);
+        let mut lines = disasm::disassemble(&read, from, to, bank);
+        lines.truncate(count);
+        lines
+    }
+
+    fn disassemble(&self, text: &str) -> Result<String, String> {
+        let mut words = text.split_whitespace();
+        let location = match words.next() {
+            Some(location) => symbols::parse_location(location, &self.system.symbols)?,
+            None => Location { bank: None, address: self.system.cpu.reg.pc },
+        };
+        let count = match words.next() {
+            Some(count) => count.parse().map_err(|_| format!("'{}' isn't a number of instructions", count))?,
+            None => DISAS_LINES,
+        };
+        let bank = location.bank.unwrap_or_else(|| self.system.cpu.mmu.rom_bank_at(location.address));
+        Ok(self.listing(&self.lines(location.address, bank, count)))
+    }
+
+    // `=> 00:0150  3E 01     LD A,$01` with the current instruction marked, and labels on lines of their own
+    fn listing(&self, lines: &[disasm::Line]) -> String {
+        let pc = self.system.cpu.reg.pc;
+        let mut text = String::new();
+        for line in lines {
+            let marker = if line.address == pc { "=>" } else { "  " };
+            if let Some(label) = self.system.symbols.resolve(line.address, line.bank) {
+                text += &format!("{}:\n", label);
+            }
+            let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            text += &format!("{} {:<9}  {:<8}  {}\n", marker, bank_address(line.address, line.bank), bytes.join(" "), line.text(&self.system.symbols));
+        }
+        text.trim_end().to_string()
+    }
+
+    // `x/16xb $C000`, count then format (x, d or i) then unit (b or h), all optional
+    fn examine(&self, format: &str, text: &str) -> Result<String, String> {
+        let digits = format.chars().take_while(|c| c.is_ascii_digit()).count();
+        let count: usize = if digits == 0 { 1 } else { format[..digits].parse().map_err(|_| "Bad count".to_string())? };
+        let (mut style, mut halfwords) = ('x', false);
+        for c in format[digits..].chars() {
+            match c {
+                'x' | 'd' | 'i' => style = c,
+                'b' => halfwords = false,
+                'h' => halfwords = true,
+                _ => return Err(format!("Unknown format letter '{}'", c)),
+            }
+        }
+        let address = self.eval_address(text)?;
+        if style == 'i' {
+            return self.disassemble(&format!("{:04X} {}", address, count));
+        }
+
+        let size = if halfwords { 2 } else { 1 };
+        let per_line = if halfwords { 8 } else { 16 };
+        let mmu = &self.system.cpu.mmu;
+        let mut lines = vec![];
+        for row in 0..count.div_ceil(per_line) {
+            let start = address.wrapping_add((row * per_line * size) as u16);
+            let values: Vec<String> = (0..per_line.min(count - row * per_line)).map(|i| {
+                let at = start.wrapping_add((i * size) as u16);
+                let value = match halfwords {
+                    true => mmu.get(at) as u16 | (mmu.get(at.wrapping_add(1)) as u16) << 8,
+                    false => mmu.get(at) as u16,
+                };
+                match (style, halfwords) {
+                    ('d', true) => format!("{:>5}", value),
+                    ('d', false) => format!("{:>3}", value),
+                    (_, true) => format!("{:04X}", value),
+                    (_, false) => format!("{:02X}", value),
+                }
+            }).collect();
+            lines.push(format!("{:04X}:  {}", start, values.join(" ")));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn registers(&self) -> String {
+        let cpu = &self.system.cpu;
+        let reg = &cpu.reg;
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        format!(
+            "AF {:04X}  BC {:04X}  DE {:04X}  HL {:04X}\nSP {:04X}  PC {:04X}  {}{}{}{}  IME {}\nLY {:02X}  BANK {:02X}  FRAME {}",
+            reg.af(), reg.bc(), reg.de(), reg.hl(), reg.sp, reg.pc,
+            flag(reg.f.zero, 'Z'), flag(reg.f.sub, 'N'), flag(reg.f.half_carry, 'H'), flag(reg.f.carry, 'C'), cpu.ime as u8,
+            cpu.mmu.get(0xFF44), cpu.mmu.rom_bank_at(reg.pc), self.system.graphics.frame,
+        )
+    }
+
+    // `a=0x10`, `hl=wTarget`, `zf=1` or `[$C000]=$FF`
+    fn set(&mut self, text: &str) -> Result<String, String> {
+        let (target, value) = text.split_once('=').ok_or("set expects <target>=<value>")?;
+        let target = target.trim().to_ascii_lowercase();
+        let value = self.eval(value)?;
+        let byte = || u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", value));
+        let word = || u16::try_from(value).map_err(|_| format!("{} doesn't fit in a word", value));
+        let flag = || match value {
+            0 | 1 => Ok(value == 1),
+            _ => Err(format!("{} isn't 0 or 1", value)),
+        };
+        if let Some(address) = target.strip_prefix('[').and_then(|target| target.strip_suffix(']')) {
+            let address = self.eval_address(address)?;
+            self.system.cpu.mmu.set(address, byte()?);
+            return Ok(String::new());
+        }
+        let cpu = &mut self.system.cpu;
+        let reg = &mut cpu.reg;
+        match target.as_str() {
+            "a" => reg.a = byte()?,
+            "b" => reg.b = byte()?,
+            "c" => reg.c = byte()?,
+            "d" => reg.d = byte()?,
+            "e" => reg.e = byte()?,
+            "h" => reg.h = byte()?,
+            "l" => reg.l = byte()?,
+            "f" => reg.f.set_from_u8(byte()?),
+            "af" => reg.set_af(word()?),
+            "bc" => reg.set_bc(word()?),
+            "de" => reg.set_de(word()?),
+            "hl" => reg.set_hl(word()?),
+            "sp" => reg.sp = word()?,
+            "pc" => reg.pc = word()?,
+            "zf" => reg.f.zero = flag()?,
+            "nf" => reg.f.sub = flag()?,
+            "hf" => reg.f.half_carry = flag()?,
+            "cf" => reg.f.carry = flag()?,
+            "ime" => cpu.ime = flag()?,
+            _ => return Err(format!("Can't set '{}'", target)),
+        }
+        Ok(String::new())
+    }
+
+    // Each character covers a few pixels, the darker the pixels the denser the character
+    fn screen(&self) -> String {
+        let fb = &self.system.graphics.fb;
+        let (width, height) = SCREEN_CELL;
+        let border = format!("+{}+", "-".repeat(fb.len() / width));
+        let mut text = border.clone() + "\n";
+        for row in 0..fb[0].len() / height {
+            text.push('|');
+            for column in 0..fb.len() / width {
+                let brightness: u32 = fb[column * width..(column + 1) * width].iter()
+                    .flat_map(|pixels| &pixels[row * height..(row + 1) * height])
+                    .map(|rgb| (rgb >> 16 & 0xFF) * 3 + (rgb >> 8 & 0xFF) * 6 + (rgb & 0xFF)) // Roughly perceived
+                    .sum();
+                let brightness = brightness as usize / (width * height * 10); // 0~255
+                text.push(SCREEN_SHADES[brightness * SCREEN_SHADES.len() / 256] as char);
+            }
+            text += "|\n";
+        }
+        text + &border
+    }
+}
+
+#[derive(PartialEq)]
+enum Finished {
+    Break,
+    Frames,
+    Stuck,
+    Interrupted,
+}
+
+// 1-based, as listed
+fn parse_index(text: &str, len: usize) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(n) if (1..=len).contains(&n) => Ok(n - 1),
+        _ => Err(format!("No number {}", text)),
+    }
+}
+
+// `savestate`/`loadstate` names that are file paths rather than states kept in memory
+fn is_path(name: &str) -> bool {
+    name.contains(['.', '/', '\\'])
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "up" => Button::Up,
+        "down" => Button::Down,
+        "left" => Button::Left,
+        "right" => Button::Right,
+        "a" => Button::A,
+        "b" => Button::B,
+        "start" => Button::Start,
+        "select" => Button::Select,
+        _ => return Err(format!("Unknown button '{}'", name)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::cli::Session;
+    use crate::model::Model;
+    use crate::system::System;
+
+    // LD A,$10 / CALL $0110 / LD [$C000],A / JR -2 ... $0110: INC A / RET
+    fn session() -> Session {
+        let program: &[(u16, &[u8])] = &[(0x100, &[0x3E, 0x10, 0xCD, 0x10, 0x01, 0xEA, 0x00, 0xC0, 0x18, 0xFE]), (0x110, &[0x3C, 0xC9])];
+        let mut system = System::with_program(Model::Dmg, program);
+        system.symbols.insert(0, 0x0110, "Increment");
+        Session::new(system)
+    }
+
+    #[test]
+    fn breakpoints_and_registers() {
+        let mut session = session();
+        assert_eq!(session.execute("break Increment if A == $10").unwrap(), "Breakpoint 1 at 00:0110 Increment");
+        assert_eq!(session.execute("c").unwrap(), "Breakpoint at 0110 (00:0110 Increment)\nIncrement:\n=> 00:0110    3C        INC A");
+        assert_eq!(session.execute("set a=$41 # comments are ignored").unwrap(), "");
+        assert_eq!(session.execute("p a + 1").unwrap(), "= $42 (66)");
+        assert!(session.execute("regs").unwrap().starts_with("AF 41"));
+        assert!(session.execute("set a=$100").is_err());
+        session.execute("watch $C000").unwrap();
+        assert!(session.execute("continue").unwrap().starts_with("Wrote 42 to C000 (was 00)"));
+        assert_eq!(session.execute("x/4xb $C000").unwrap(), "C000:  42 00 00 00");
+        assert_eq!(session.execute("x/1dh $C000").unwrap(), "C000:     66");
+        assert!(session.execute("continue").unwrap().starts_with("Stuck in an infinite loop"));
+        assert!(session.execute("bogus").is_err());
+    }
+
+    #[test]
+    fn stepping_and_states() {
+        let mut session = session();
+        session.execute("step").unwrap();
+        session.execute("savestate").unwrap();
+        assert_eq!(session.execute("next").unwrap(), "=> 00:0105    EA 00 C0  LD [$C000], A");
+        assert_eq!(session.system.cpu.reg.a, 0x11);
+        session.execute("loadstate").unwrap();
+        assert_eq!(session.execute("s").unwrap(), "Increment:\n=> 00:0110    3C        INC A");
+        assert!(session.execute("finish").unwrap().ends_with("=> 00:0105    EA 00 C0  LD [$C000], A"));
+        assert!(session.execute("reverse-step").unwrap().contains("RET"));
+        assert_eq!(session.execute("bt").unwrap(), "#0  00:0111 Increment+1\n#1  00:0102");
+    }
+
+    #[test]
+    fn states_in_files() {
+        let path = std::env::temp_dir().join(format!("metalboy-cli-{}.state", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut saved = session();
+        saved.execute("step 2").unwrap();
+        saved.execute("set [$C000]=$5A").unwrap();
+        assert_eq!(saved.execute(&format!("savestate {}", path)).unwrap(), format!("Saved '{}' at step 2", path));
+
+        let mut other = session();
+        other.system.cpu.mmu.cartridge.data[0x14E] = 0x12; // A different game
+        assert!(other.execute(&format!("loadstate {}", path)).unwrap_err().contains("different ROM"));
+        let mut other = session();
+        assert!(other.execute(&format!("loadstate {}", path)).unwrap().ends_with("Increment:\n=> 00:0110    3C        INC A"));
+        assert_eq!(other.execute("x/1xb $C000").unwrap(), "C000:  5A");
+        assert_eq!(other.execute("bt").unwrap(), "#0  00:0110 Increment\n#1  00:0102");
+        assert_eq!(other.system.steps, 2);
+        std::fs::remove_file(path).unwrap();
+        assert!(other.execute(&format!("loadstate {}", path)).is_err());
+    }
+
+    #[test]
+    fn batch_continue_needs_an_end() {
+        let mut session = session();
+        session.batch = true;
+        assert!(session.execute("continue").is_err());
+        session.execute("break Increment").unwrap();
+        assert!(session.execute("continue").unwrap().starts_with("Breakpoint at 0110"));
+        session.execute("delete").unwrap();
+        assert!(session.execute("continue").is_err());
+        assert!(session.execute("continue 1").is_ok());
+    }
+
+    #[test]
+    fn frames_with_the_lcd_off() {
+        let mut session = session();
+        session.execute("set [$FF40]=$00").unwrap();
+        session.execute("set [$C100]=$00").unwrap();
+        session.execute("set [$C101]=$18").unwrap();
+        session.execute("set [$C102]=$FD").unwrap();
+        session.execute("set pc=$C100").unwrap();
+        assert!(session.execute("continue 2").unwrap().starts_with("Ran 2 frame(s) of cycles, the LCD is off"));
+    }
+
+    // NOP / JR -3 in WRAM, which never ends on its own
+    #[test]
+    fn interrupted_by_ctrl_c() {
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        let mut session = session();
+        session.interrupt = Some(&INTERRUPT);
+        session.execute("set [$C100]=$00").unwrap();
+        session.execute("set [$C101]=$18").unwrap();
+        session.execute("set [$C102]=$FD").unwrap();
+        session.execute("set pc=$C100").unwrap();
+        INTERRUPT.store(true, Ordering::Relaxed); // Pressed at the prompt, before the command
+        let pressed = std::thread::spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            INTERRUPT.store(true, Ordering::Relaxed);
+        });
+        let output = session.execute("continue").unwrap();
+        pressed.join().unwrap();
+        assert!(output.starts_with("Interrupted\n=> C10"), "{}", output);
+        assert!(session.system.steps > 1000);
+    }
+
+    #[test]
+    fn screen_as_text() {
+        let mut session = session();
+        let screen = session.execute("screen").unwrap();
+        let lines: Vec<&str> = screen.lines().collect();
+        assert_eq!(lines.len(), 36 + 2);
+        assert!(lines.iter().all(|line| line.len() == 80 + 2));
+    }
+}
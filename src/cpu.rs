@@ -1,10 +1,15 @@
+use std::io;
 use crate::execute::execute;
 use super::registers::{Registers, R8, R16};
 use super::flags::Flags;
 use super::mmu::Mmu;
+use crate::bus::Bus;
+use crate::callstack::{CallStack, EntryKind, Frame};
+use crate::savestate::{StateReader, StateWriter};
 use super::timer::Timer;
 use crate::{bytes_from, set_bit, unset_bit, word_from};
 use crate::cpu::Status::{Halt, Running};
+use log::warn;
 
 pub enum Interrupt {
     VBlank = 0x40,
@@ -14,7 +19,7 @@ pub enum Interrupt {
     JOYPAD = 0x60
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Status {
     Stopped,
     Running,
@@ -22,6 +27,15 @@ pub enum Status {
     InfiniteLoop,
 }
 
+const STATUSES: [Status; 4] = [Status::Stopped, Status::Running, Status::Halt, Status::InfiniteLoop];
+
+// An opcode the CPU couldn't run, kept until a debugger picks it up
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Fault {
+    Unimplemented { pc: u16, opcode: u8, prefixed: bool },
+    Illegal { pc: u16, opcode: u8 }, // Locks up real hardware
+}
+
 pub const CLOCK_SPEED: usize = 4194304;
 
 /* The following array is based on data from:
@@ -68,9 +82,10 @@ pub const CB_TIMINGS: [usize; 256] = [
     2,2,2,2,2,2,4,2,2,2,2,2,2,2,4,2
 ];
 
-pub struct Cpu {
+#[derive(Clone)]
+pub struct Cpu<B: Bus = Mmu> {
     pub reg: Registers,
-    pub mmu: Mmu,
+    pub mmu: B,
     pub timer: Timer,
     pub status: Status,
     pub opcode: u8,
@@ -78,67 +93,153 @@ pub struct Cpu {
     pub cycles: usize,
     pub cb_prefix: bool,
     pub ime: bool,
+    pub fault: Option<Fault>,
+    pub calls: CallStack,
     pub _tmp_warn_count: usize,
 }
 
 impl Cpu {
     pub fn new() -> Self {
-        Cpu {
-            reg: Registers::new(),
-            mmu: Mmu::new(),
-            timer: Timer::new(),
-            status: Running,
-            opcode: 0x00,
-            advance_pc: 1,
-            cycles: 0,
-            cb_prefix: false,
-            ime: true,
-            _tmp_warn_count: 0,
-        }
+        Cpu::with_bus(Mmu::new())
     }
 
     pub fn reset(&mut self) {
         self.reg.reset();
         self.mmu.reset();
         if !self.mmu.bootrom_mapped {
-            self.reg.pc = 0x100;
+            self.reg = self.mmu.model.initial_registers(&self.mmu.cartridge);
         }
+        self.status = Running;
+        self.fault = None;
+        self.calls.clear();
         self.opcode = 0x00;
         self.advance_pc = 1;
         self.cycles = 0;
     }
 
     pub fn tick(&mut self) {
+        if self.status == Halt {
+            return;
+        }
+        let bootrom_was_mapped = self.mmu.bootrom_mapped;
+        self.step();
+        // A general-purpose or HBlank HDMA halts the CPU while it copies
+        self.cycles += self.mmu.stall_cycles;
+        self.mmu.stall_cycles = 0;
+        // Boot ROMs hand over by writing to 0xFF50, or by simply running into the cartridge header
+        if self.mmu.bootrom_mapped && (0x100..0x200).contains(&self.reg.pc) {
+            self.mmu.bootrom_mapped = false;
+        }
+        if bootrom_was_mapped && !self.mmu.bootrom_mapped {
+            self.mmu.set_initial_state();
+            self.check_handover();
+        }
+    }
+
+    // Compare the registers the boot ROM handed over with the model's documented state
+    pub fn check_handover(&self) -> bool {
+        let expected = self.mmu.model.initial_registers(&self.mmu.cartridge);
+        let pairs = [
+            ("AF", self.reg.af(), expected.af()),
+            ("BC", self.reg.bc(), expected.bc()),
+            ("DE", self.reg.de(), expected.de()),
+            ("HL", self.reg.hl(), expected.hl()),
+            ("SP", self.reg.sp, expected.sp),
+            ("PC", self.reg.pc, expected.pc),
+        ];
+        let mut matches = true;
+        for (name, actual, expected) in pairs {
+            if actual != expected {
+                warn!("Boot ROM handed over with {}={:04X}, expected {:04X} for {}", name, actual, expected, self.mmu.model);
+                matches = false;
+            }
+        }
+        matches
+    }
+
+    // Start at the cartridge entry point with the state the model's boot ROM would leave behind
+    pub fn skip_boot(&mut self) {
+        self.mmu.bootrom_mapped = false;
+        self.mmu.set_initial_state();
+        self.reg = self.mmu.model.initial_registers(&self.mmu.cartridge);
+    }
+
+    // A fault is for the debugger to pick up, so it isn't saved
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.raw(&[self.reg.a, self.reg.f.as_u8(), self.reg.b, self.reg.c, self.reg.d, self.reg.e, self.reg.h, self.reg.l]);
+        out.u16(self.reg.pc);
+        out.u16(self.reg.sp);
+        self.timer.write_state(out);
+        out.variant(&STATUSES, &self.status);
+        out.u8(self.opcode);
+        out.u16(self.advance_pc as u16);
+        out.u64(self.cycles as u64);
+        out.bool(self.cb_prefix);
+        out.bool(self.ime);
+        self.calls.write_state(out);
+        self.mmu.write_state(out);
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        let mut registers = [0; 8];
+        input.fill(&mut registers)?;
+        let [a, f, b, c, d, e, h, l] = registers;
+        (self.reg.a, self.reg.b, self.reg.c, self.reg.d, self.reg.e, self.reg.h, self.reg.l) = (a, b, c, d, e, h, l);
+        self.reg.f.set_from_u8(f);
+        self.reg.pc = input.u16()?;
+        self.reg.sp = input.u16()?;
+        self.timer.read_state(input)?;
+        self.status = input.variant(&STATUSES)?;
+        self.opcode = input.u8()?;
+        self.advance_pc = input.u16()? as i16;
+        self.cycles = input.u64()? as usize;
+        self.cb_prefix = input.bool()?;
+        self.ime = input.bool()?;
+        self.fault = None;
+        self.calls.read_state(input)?;
+        self.mmu.read_state(input)
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn with_bus(bus: B) -> Self {
+        Cpu {
+            reg: Registers::new(),
+            mmu: bus,
+            timer: Timer::new(),
+            status: Running,
+            opcode: 0x00,
+            advance_pc: 1,
+            cycles: 0,
+            cb_prefix: false,
+            ime: true,
+            fault: None,
+            calls: CallStack::new(),
+            _tmp_warn_count: 0,
+        }
+    }
+
+    // Fetch, execute and retire a single instruction
+    pub fn step(&mut self) {
         if self.status == Halt {
             return;
         }
         self.cycles = 0;
-        self.opcode = self.mmu.get(self.reg.pc);
+        self.opcode = self.mmu.read(self.reg.pc);
         execute(self);
         self.reg.pc = (self.reg.pc as i16 + self.advance_pc) as u16;
         self.advance_pc = 1;
-        if self.mmu.bootrom_mapped && self.reg.pc >= 0x100 {
-            self.mmu.bootrom_mapped = false;
-            self.mmu.set_initial_state();
-            assert_eq!(self.reg.a, 0x01);
-            assert_eq!(self.reg.f.as_u8(), 0xB0);
-            assert_eq!(self.reg.b, 0);
-            assert_eq!(self.reg.c, 0x13);
-            assert_eq!(self.reg.d, 0);
-            assert_eq!(self.reg.e, 0xd8);
-            assert_eq!(self.reg.h, 0x01);
-            assert_eq!(self.reg.l, 0x4d);
-            assert_eq!(self.reg.sp, 0xFFFE);
-            assert_eq!(self.reg.pc, 0x100);
-        }
+        self.mmu.tick(self.cycles);
     }
 
     pub fn get_op(&self, offset: u16) -> u8 {
-        self.mmu.get(self.reg.pc + offset)
+        self.mmu.read(self.reg.pc + offset)
     }
 
+    // Low byte first, as the hardware reads it
     pub fn get_d16(&self) -> u16 {
-        word_from(self.get_op(2), self.get_op(1))
+        let low = self.get_op(1);
+        word_from(self.get_op(2), low)
     }
 
     pub fn get_reg8_by_index(&mut self, index: u8) -> u8 {
@@ -149,7 +250,7 @@ impl Cpu {
             3 => self.reg.e,
             4 => self.reg.h,
             5 => self.reg.l,
-            6 => self.mmu.get(self.reg.hl()),
+            6 => self.mmu.read(self.reg.hl()),
             7 => self.reg.a,
             _ => panic!("This is supposed to be unreachable"),
         }
@@ -194,7 +295,7 @@ impl Cpu {
             R8::E => self.reg.e,
             R8::H => self.reg.h,
             R8::L => self.reg.l,
-            R8::HLRam => self.mmu.get(self.reg.hl()),
+            R8::HLRam => self.mmu.read(self.reg.hl()),
         }
     }
 
@@ -207,7 +308,7 @@ impl Cpu {
             R8::E => self.reg.e = byte,
             R8::H => self.reg.h = byte,
             R8::L => self.reg.l = byte,
-            R8::HLRam => self.mmu.set(self.reg.hl(), byte),
+            R8::HLRam => self.mmu.write(self.reg.hl(), byte),
         }
     }
 
@@ -224,16 +325,31 @@ impl Cpu {
             3 => self.reg.e = value,
             4 => self.reg.h = value,
             5 => self.reg.l = value,
-            6 => self.mmu.set(self.reg.hl(), value),
+            6 => self.mmu.write(self.reg.hl(), value),
             7 => self.reg.a = value,
             _ => panic!("This is supposed to be unreachable"),
         }
     }
 
+    // Record a CALL/RST/interrupt on the shadow call stack, after the return address is pushed
+    pub fn enter(&mut self, kind: EntryKind, return_address: u16, target: u16) {
+        self.calls.enter(Frame {
+            kind,
+            caller: self.reg.pc,
+            caller_bank: self.mmu.rom_bank(self.reg.pc),
+            target,
+            target_bank: self.mmu.rom_bank(target),
+            return_address,
+            sp: self.reg.sp,
+        });
+    }
+
+    // PUSH, CALL, RST and interrupts all spend an M-cycle decrementing SP before writing
     pub fn push_word(&mut self, word: u16) {
         let (left, right) = bytes_from(word);
-        self.mmu.set(self.reg.sp - 1, left);
-        self.mmu.set(self.reg.sp - 2, right);
+        self.mmu.idle();
+        self.mmu.write(self.reg.sp - 1, left);
+        self.mmu.write(self.reg.sp - 2, right);
         self.reg.sp -= 2;
     }
 
@@ -248,6 +364,7 @@ impl Cpu {
     }
 
     pub fn inc_rr(&mut self, reg: R16) {
+        self.mmu.idle();
         let reg_val = self.get_reg16(reg);
         let result = u16::wrapping_add(reg_val, 1);
         self.set_reg16(reg, result);
@@ -264,6 +381,7 @@ impl Cpu {
     }
 
     pub fn dec_rr(&mut self, reg: R16) {
+        self.mmu.idle();
         let reg_val = self.get_reg16(reg);
         let result = u16::wrapping_sub(reg_val, 1);
         self.set_reg16(reg, result);
@@ -276,7 +394,9 @@ impl Cpu {
         self.reg.f.zero = self.reg.a == 0;
     }
 
+    // Takes an M-cycle for each byte of SP
     pub fn add_sp_s8(&mut self, byte: i8) {
+        self.mmu.idle();
         let sp_old_lo = (self.reg.sp & 0xff) as u8;
         self.reg.sp = if byte > 0 {
             u16::wrapping_add(self.reg.sp, byte as u16)
@@ -458,9 +578,10 @@ impl Cpu {
     pub fn service_interrupt(&mut self, id: u8, interrupt_flag: u8) {
         self.ime = false;
         let cleared = interrupt_flag & (0b1111_1111 ^ (1 << id));
-        self.mmu.set(0xFF0F, cleared);
+        self.mmu.write(0xFF0F, cleared);
 
         self.push_word(self.reg.pc);
+        self.enter(EntryKind::Interrupt(id), self.reg.pc, 0x40 + id as u16 * 8);
         match id {
             0 => self.reg.pc = Interrupt::VBlank as u16, // 0x40
             1 => self.reg.pc = Interrupt::LCD as u16,    // 0x48
@@ -472,8 +593,8 @@ impl Cpu {
     }
 
     pub fn service_interrupts(&mut self) {
-        let interrupt_flag = self.mmu.get(0xFF0F);
-        let interrupt_enable = self.mmu.get(0xFFFF);
+        let interrupt_flag = self.mmu.read(0xFF0F);
+        let interrupt_enable = self.mmu.read(0xFFFF);
         if self.status == Halt && (interrupt_flag & interrupt_enable > 0) {
             self.status = Running;
         }
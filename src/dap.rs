@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use serde_json::{json, Value};
+use crate::args;
+use crate::bootrom::BootRom;
+use crate::debugger::{Breakpoint, BreakReason, RunTarget};
+use crate::disasm;
+use crate::expr::{Condition, LogMessage};
+use crate::model::Model;
+use crate::sourcemap::SourceMap;
+use crate::symbols::Location;
+use crate::system::System;
+
+/* A Debug Adapter Protocol server, so editors like VS Code can debug a game from its RGBDS source:
+ * https://microsoft.github.io/debug-adapter-protocol/specification
+ * Messages are JSON with a `Content-Length` header, over stdio or a TCP connection. There's one
+ * thread (the CPU), source breakpoints go through SourceMap, and the only scope is the registers. */
+
+pub const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const STEPS_PER_POLL: usize = 10_000; // How long to run between checking for requests such as pause
+
+// Start-up options from the `launch` request
+struct Launch {
+    program: String,
+    source_directory: Option<String>,
+    model: Model,
+    boot_rom: BootRom,
+    stop_on_entry: bool,
+}
+
+impl Launch {
+    fn parse(arguments: &Value) -> Result<Self, String> {
+        let program = arguments["program"].as_str().ok_or("launch needs a program (the ROM)")?.to_string();
+        let model = match arguments["model"].as_str() {
+            Some(name) => name.parse()?,
+            None => Model::Dmg,
+        };
+        Ok(Launch {
+            program,
+            source_directory: arguments["sourceDirectory"].as_str().map(|directory| directory.to_string()),
+            model,
+            // Stopping on entry in the boot ROM isn't much use to someone debugging their game
+            boot_rom: if arguments["skipBoot"].as_bool().unwrap_or(true) { BootRom::Skip } else { BootRom::Embedded },
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+pub struct Adapter<W: Write> {
+    output: W,
+    seq: u64,
+    system: Option<System>,
+    sources: SourceMap,
+    source_breakpoints: HashMap<PathBuf, Vec<Breakpoint>>, // Replaced whole for each file by setBreakpoints
+    lines_start_at1: bool,
+    stop_on_entry: bool,
+    running: bool,
+    serial_sent: usize,
+    events: Vec<(&'static str, Value)>, // Sent after the response to the request that caused them
+    pub finished: bool,
+}
+
+impl<W: Write> Adapter<W> {
+    pub fn new(output: W) -> Self {
+        Adapter {
+            output,
+            seq: 1,
+            system: None,
+            sources: SourceMap::new(),
+            source_breakpoints: HashMap::new(),
+            lines_start_at1: true,
+            stop_on_entry: false,
+            running: false,
+            serial_sent: 0,
+            events: vec![],
+            finished: false,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        write_message(&mut self.output, &message)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    pub fn handle(&mut self, message: &Value) -> io::Result<()> {
+        if message["type"] != "request" {
+            return Ok(());
+        }
+        let command = message["command"].as_str().unwrap_or("");
+        let result = self.request(command, &message["arguments"]);
+        let mut response = json!({
+            "type": "response",
+            "request_seq": message["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(e) => response["message"] = json!(e),
+        }
+        self.send(response)?;
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send_event(event, body)?;
+        }
+        Ok(())
+    }
+
+    fn system(&mut self) -> Result<&mut System, String> {
+        self.system.as_mut().ok_or("No ROM has been launched".to_string())
+    }
+
+    fn request(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => {
+                self.lines_start_at1 = arguments["linesStartAt1"].as_bool().unwrap_or(true);
+                self.events.push(("initialized", json!({})));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsConditionalBreakpoints": true,
+                    "supportsLogPoints": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true,
+                }))
+            }
+            "launch" => {
+                let launch = Launch::parse(arguments)?;
+                if !Path::new(&launch.program).is_file() {
+                    return Err(format!("{} isn't a ROM file", launch.program));
+                }
+                let mut system = System::new(launch.model);
+                system.load_cartridge(&launch.program);
+                system.set_boot_rom(launch.boot_rom);
+                let directory = launch.source_directory.map(PathBuf::from)
+                    .unwrap_or_else(|| Path::new(&launch.program).parent().unwrap_or(Path::new(".")).to_path_buf());
+                self.sources = SourceMap::load(&directory).map_err(|e| format!("Unable to read {}: {}", directory.display(), e))?;
+                self.stop_on_entry = launch.stop_on_entry;
+                self.system = Some(system);
+                Ok(json!({}))
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({})), // Bad opcodes always stop
+            "configurationDone" => {
+                self.system()?;
+                match self.stop_on_entry {
+                    true => self.events.push(("stopped", stopped_body("entry", None))),
+                    false => self.running = true,
+                }
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "SM83" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [{ "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false }]
+            })),
+            "variables" => match arguments["variablesReference"].as_u64() {
+                Some(REGISTERS_REFERENCE) => Ok(json!({ "variables": registers(self.system()?) })),
+                _ => Ok(json!({ "variables": [] })),
+            },
+            "readMemory" => {
+                let system = self.system()?;
+                let reference = arguments["memoryReference"].as_str().ok_or("readMemory needs a memoryReference")?;
+                let offset = arguments["offset"].as_i64().unwrap_or(0);
+                if offset < 0 {
+                    return Err("readMemory can't read before the memoryReference".to_string());
+                }
+                let address = args::parse_address(reference)? as i64 + offset;
+                let count = arguments["count"].as_i64().unwrap_or(0).clamp(0, 0x10000 - address.clamp(0, 0x10000));
+                let bytes: Vec<u8> = (address..address + count).map(|address| system.cpu.mmu.get(address as u16)).collect();
+                Ok(json!({ "address": format!("0x{:04X}", address), "data": base64(&bytes) }))
+            }
+            "evaluate" => {
+                let system = self.system()?;
+                let expression = arguments["expression"].as_str().unwrap_or("");
+                let value = Condition::parse(expression, &system.symbols)?.eval(system, 0);
+                Ok(json!({ "result": format!("${:X} ({})", value, value), "variablesReference": 0 }))
+            }
+            "continue" => {
+                self.system()?;
+                self.running = true;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "pause" => {
+                self.system()?;
+                if self.running {
+                    self.running = false;
+                    self.events.push(("stopped", stopped_body("pause", None)));
+                }
+                Ok(json!({}))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let system = self.system()?;
+                match command {
+                    "next" => system.step_over(),
+                    "stepIn" => system.step_into(),
+                    _ => system.step_out(),
+                }
+                self.running = true;
+                Ok(json!({}))
+            }
+            "disconnect" | "terminate" => {
+                self.finished = true;
+                self.running = false;
+                self.events.push(("terminated", json!({})));
+                Ok(json!({}))
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = PathBuf::from(arguments["source"]["path"].as_str().ok_or("setBreakpoints needs a source path")?);
+        let offset = if self.lines_start_at1 { 1 } else { 0 };
+        let system = self.system.as_mut().ok_or("No ROM has been launched")?;
+        let mut breakpoints = vec![];
+        let mut results = vec![];
+        for requested in arguments["breakpoints"].as_array().cloned().unwrap_or_default() {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let found = {
+                let rom = &system.cpu.mmu.cartridge.data;
+                let read = |bank: u16, address: u16| rom_byte(system, rom, bank, address);
+                self.sources.address_of(&path, line.saturating_sub(offset), &system.symbols, &read)
+            };
+            let breakpoint = found.and_then(|(bank, address, line)| {
+                let mut breakpoint = Breakpoint::new(Location { bank: disasm::bank_of(address, bank), address });
+                if let Some(condition) = requested["condition"].as_str().filter(|condition| !condition.is_empty()) {
+                    breakpoint.condition = Some(Condition::parse(condition, &system.symbols)?);
+                }
+                if let Some(message) = requested["logMessage"].as_str() {
+                    breakpoint.log = Some(LogMessage::parse(message, &system.symbols)?);
+                }
+                Ok((breakpoint, line))
+            });
+            results.push(match breakpoint {
+                Ok((breakpoint, line)) => {
+                    let address = disasm::bank_address(breakpoint.location.address, breakpoint.location.bank.unwrap_or(0));
+                    breakpoints.push(breakpoint);
+                    json!({ "verified": true, "line": line + offset, "instructionReference": address })
+                }
+                Err(e) => json!({ "verified": false, "line": line, "message": e }),
+            });
+        }
+        self.source_breakpoints.insert(path, breakpoints);
+        // Breakpoints that are still set keep their hit counts, in this file and every other
+        let mut previous = std::mem::take(&mut system.breakpoints);
+        system.breakpoints = self.source_breakpoints.values().flatten().map(|breakpoint| {
+            match previous.iter().position(|old| same_breakpoint(old, breakpoint)) {
+                Some(index) => previous.swap_remove(index),
+                None => breakpoint.clone(),
+            }
+        }).collect();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let offset = if self.lines_start_at1 { 1 } else { 0 };
+        let system = self.system.as_ref().ok_or("No ROM has been launched")?;
+        let cpu = &system.cpu;
+        let pc = cpu.reg.pc;
+        let mut places = vec![(pc, cpu.mmu.rom_bank_at(pc))];
+        places.extend(cpu.calls.live(cpu.reg.sp).map(|frame| (frame.caller, frame.caller_bank)));
+
+        let rom = &cpu.mmu.cartridge.data;
+        let read = |bank: u16, address: u16| rom_byte(system, rom, bank, address);
+        let frames: Vec<Value> = places.iter().enumerate().map(|(id, (address, bank))| {
+            let name = match system.symbols.nearest(*address, *bank) {
+                Some((name, 0)) => name.to_string(),
+                Some((name, offset)) => format!("{}+{}", name, offset),
+                None => disasm::bank_address(*address, *bank),
+            };
+            let mut frame = json!({
+                "id": id,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("0x{:04X}", address),
+            });
+            if let Some((path, line)) = self.sources.line_of(*address, *bank, &system.symbols, &read) {
+                frame["source"] = json!({ "path": path.display().to_string() });
+                frame["line"] = json!(line + offset);
+                frame["column"] = json!(offset);
+            }
+            frame
+        }).collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    // Run for a while, reporting anything that stops execution
+    pub fn run(&mut self) -> io::Result<()> {
+        let Some(system) = self.system.as_mut() else {
+            self.running = false;
+            return Ok(());
+        };
+        let mut stopped = None;
+        for _ in 0..STEPS_PER_POLL {
+            system.step(&[]);
+            if let Some(reason) = system.break_reason.take() {
+                stopped = Some(reason);
+                break;
+            }
+            if system.stuck() {
+                stopped = Some(BreakReason::Stepped(RunTarget::Instruction));
+                self.events.push(("output", json!({ "category": "console", "output": "Stuck in an infinite loop with no interrupts enabled\n" })));
+                break;
+            }
+        }
+        let mut output: String = system.log_messages.drain(..).map(|message| message + "\n").collect();
+        output += &String::from_utf8_lossy(&system.serial.output[self.serial_sent..]);
+        self.serial_sent = system.serial.output.len();
+        if !output.is_empty() {
+            self.events.insert(0, ("output", json!({ "category": "stdout", "output": output })));
+        }
+        if let Some(reason) = stopped {
+            system.run_target = None;
+            self.running = false;
+            let (kind, description) = match reason {
+                BreakReason::Breakpoint(_) => ("breakpoint", None),
+                BreakReason::Watchpoint { .. } => ("data breakpoint", Some(reason.to_string())),
+                BreakReason::Fault(_) => ("exception", Some(reason.to_string())),
+                BreakReason::Stepped(_) => ("step", None),
+            };
+            self.events.push(("stopped", stopped_body(kind, description)));
+        }
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send_event(event, body)?;
+        }
+        Ok(())
+    }
+}
+
+fn same_breakpoint(a: &Breakpoint, b: &Breakpoint) -> bool {
+    a.location == b.location
+        && a.condition.as_ref().map(|condition| &condition.source) == b.condition.as_ref().map(|condition| &condition.source)
+        && a.log.as_ref().map(|log| &log.source) == b.log.as_ref().map(|log| &log.source)
+}
+
+fn stopped_body(reason: &str, description: Option<String>) -> Value {
+    let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+    if let Some(description) = description {
+        body["description"] = json!(description);
+    }
+    body
+}
+
+// ROM addresses in a given bank come from the file, everything else from memory as mapped now
+fn rom_byte(system: &System, rom: &[u8], bank: u16, address: u16) -> u8 {
+    match address {
+        0x0000..=0x7FFF => rom.get(disasm::rom_offset(address, bank)).copied().unwrap_or(0),
+        _ => system.cpu.mmu.get(address),
+    }
+}
+
+fn registers(system: &System) -> Vec<Value> {
+    let cpu = &system.cpu;
+    let reg = &cpu.reg;
+    let byte = |name: &str, value: u8| json!({ "name": name, "value": format!("${:02X}", value), "variablesReference": 0 });
+    // Pairs point somewhere in memory, so the editor can open a memory view there
+    let word = |name: &str, value: u16| json!({
+        "name": name, "value": format!("${:04X}", value), "variablesReference": 0, "memoryReference": format!("0x{:04X}", value),
+    });
+    let flag = |name: &str, set: bool| json!({ "name": name, "value": (set as u8).to_string(), "variablesReference": 0 });
+    vec![
+        byte("A", reg.a), byte("F", reg.f.as_u8()), byte("B", reg.b), byte("C", reg.c),
+        byte("D", reg.d), byte("E", reg.e), byte("H", reg.h), byte("L", reg.l),
+        word("AF", reg.af()), word("BC", reg.bc()), word("DE", reg.de()), word("HL", reg.hl()),
+        word("SP", reg.sp), word("PC", reg.pc),
+        flag("Z", reg.f.zero), flag("N", reg.f.sub), flag("H (half carry)", reg.f.half_carry), flag("C (carry)", reg.f.carry),
+        flag("IME", cpu.ime), byte("LY", cpu.mmu.get(0xFF44)), word("ROM bank", cpu.mmu.rom_bank_at(0x4000)),
+    ]
+}
+
+// `Content-Length: <n>\r\n\r\n` then the JSON, None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// Requests are read on their own thread, so a running game can still be paused
+pub fn serve<R: Read + Send + 'static, W: Write>(input: R, output: W) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    let mut adapter = Adapter::new(output);
+    while !adapter.finished {
+        let message = match adapter.running() {
+            true => match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            },
+            false => match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break, // The client went away
+            },
+        };
+        match message {
+            Some(message) => adapter.handle(&message)?,
+            None => adapter.run()?,
+        }
+    }
+    Ok(())
+}
+
+// For readMemory, which sends memory as base64
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => text.push(ALPHABET[(bits >> (18 - i * 6) & 0x3F) as usize] as char),
+                false => text.push('='),
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use serde_json::json;
+    use crate::dap::{base64, read_message, write_message, Adapter};
+    use crate::debugger::Breakpoint;
+    use crate::model::Model;
+    use crate::symbols::Location;
+    use crate::system::System;
+
+    #[test]
+    fn messages_round_trip() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, &json!({ "seq": 1, "type": "request", "command": "threads" })).unwrap();
+        write_message(&mut buffer, &json!({ "seq": 2, "type": "request", "command": "pause" })).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: "));
+        let mut input = Cursor::new(buffer);
+        assert_eq!(read_message(&mut input).unwrap().unwrap()["command"], "threads");
+        assert_eq!(read_message(&mut input).unwrap().unwrap()["seq"], 2);
+        assert!(read_message(&mut input).unwrap().is_none());
+    }
+
+    #[test]
+    fn other_files_keep_their_hit_counts() {
+        let mut adapter = Adapter::new(vec![]);
+        let breakpoint = Breakpoint::new(Location { bank: None, address: 0x150 });
+        adapter.source_breakpoints.insert(PathBuf::from("main.asm"), vec![breakpoint.clone()]);
+        let mut system = System::with_program(Model::Dmg, &[]);
+        system.breakpoints = vec![Breakpoint { hits: 3, ..breakpoint }];
+        adapter.system = Some(system);
+        adapter.request("setBreakpoints", &json!({ "source": { "path": "other.asm" }, "breakpoints": [] })).unwrap();
+        assert_eq!(adapter.system.unwrap().breakpoints[0].hits, 3);
+    }
+
+    #[test]
+    fn negative_read_offset_rejected() {
+        let mut adapter = Adapter::new(vec![]);
+        adapter.system = Some(System::with_program(Model::Dmg, &[]));
+        let arguments = json!({ "memoryReference": "0xC000", "offset": -1, "count": 2 });
+        assert!(adapter.request("readMemory", &arguments).is_err());
+    }
+
+    #[test]
+    fn base64_ok() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(&[0xFF; 4]), "/////w==");
+    }
+}
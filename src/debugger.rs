@@ -0,0 +1,246 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use crate::cpu::Fault;
+use crate::disasm::Labels;
+use crate::expr::{Condition, LogMessage};
+use crate::symbols::{self, Location};
+
+/* Breakpoints and watchpoints. Watchpoints are checked on CPU accesses only (the Bus impl for Mmu),
+ * so the PPU, timers and the memory editor reading registers never trip them. */
+
+// Stops at `location` when the condition holds, or prints `log` and carries on if it's a log-point
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub location: Location,
+    pub condition: Option<Condition>,
+    pub log: Option<LogMessage>,
+    pub hits: u64, // Times the location was reached, conditions can use it as HITS
+}
+
+impl Breakpoint {
+    pub fn new(location: Location) -> Self {
+        Breakpoint { location, condition: None, log: None, hits: 0 }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Change, // A write that changes the stored value
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchKind::Read => write!(f, "read"),
+            WatchKind::Write => write!(f, "write"),
+            WatchKind::Change => write!(f, "change"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn on_read(&self, address: u16) -> bool {
+        self.kind == WatchKind::Read && self.range.contains(&address)
+    }
+
+    pub fn on_write(&self, address: u16, old: u8, byte: u8) -> bool {
+        match self.kind {
+            WatchKind::Read => false,
+            WatchKind::Write => self.range.contains(&address),
+            WatchKind::Change => old != byte && self.range.contains(&address),
+        }
+    }
+}
+
+// The first access to trip a watchpoint during an instruction, `old` equals `value` for reads
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct WatchHit {
+    pub kind: WatchKind,
+    pub address: u16,
+    pub old: u8,
+    pub value: u8,
+}
+
+// Where System should stop on its own, set by the step functions and cleared when reached
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RunTarget {
+    Instruction, // Step into, a single instruction
+    Return { pc: u16, sp: u16 }, // Step over, back from a CALL/RST with the stack where it was
+    Out { sp: u16 }, // Step out, a return that pops above this stack pointer
+    Location(Location), // Run to
+    Scanline(u8), // LY when the step started
+    Frame(u64), // Frame number when the step started
+}
+
+// Why execution stopped
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BreakReason {
+    Breakpoint(Location),
+    Watchpoint { pc: u16, hit: WatchHit },
+    Fault(Fault),
+    Stepped(RunTarget),
+}
+
+impl BreakReason {
+    // The instruction responsible, if something other than a step caused it
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            BreakReason::Breakpoint(location) => Some(location.address),
+            BreakReason::Watchpoint { pc, .. } => Some(*pc),
+            BreakReason::Fault(Fault::Unimplemented { pc, .. } | Fault::Illegal { pc, .. }) => Some(*pc),
+            BreakReason::Stepped(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint(location) => write!(f, "Breakpoint at {:04X}", location.address),
+            BreakReason::Watchpoint { hit, .. } if hit.kind == WatchKind::Read => {
+                write!(f, "Read {:02X} from {:04X}", hit.value, hit.address)
+            }
+            BreakReason::Watchpoint { hit, .. } => {
+                write!(f, "Wrote {:02X} to {:04X} (was {:02X})", hit.value, hit.address, hit.old)
+            }
+            BreakReason::Fault(Fault::Unimplemented { opcode, prefixed: true, .. }) => {
+                write!(f, "Unimplemented opcode CB {:02X}", opcode)
+            }
+            BreakReason::Fault(Fault::Unimplemented { opcode, .. }) => write!(f, "Unimplemented opcode {:02X}", opcode),
+            BreakReason::Fault(Fault::Illegal { opcode, .. }) => write!(f, "Illegal opcode {:02X}", opcode),
+            BreakReason::Stepped(RunTarget::Location(location)) => write!(f, "Ran to {:04X}", location.address),
+            BreakReason::Stepped(_) => write!(f, "Step finished"),
+        }
+    }
+}
+
+// `C000-C0FF`, a single address, or a symbol (one byte)
+pub fn parse_range(text: &str, labels: &Labels) -> Result<RangeInclusive<u16>, String> {
+    let (from, to) = text.split_once('-').unwrap_or((text, text));
+    let from = symbols::parse_location(from, labels)?.address;
+    let to = symbols::parse_location(to, labels)?.address;
+    if from > to {
+        return Err(format!("{:04X} is after {:04X}", from, to));
+    }
+    Ok(from..=to)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bootrom::BootRom;
+    use crate::bus::Bus;
+    use crate::debugger::{parse_range, Breakpoint, BreakReason, RunTarget, WatchHit, WatchKind, Watchpoint};
+    use crate::expr::{Condition, LogMessage};
+    use crate::cpu::Fault;
+    use crate::disasm::Labels;
+    use crate::model::Model;
+    use crate::symbols::Location;
+    use crate::system::System;
+
+    fn system(program: &[u8]) -> System {
+        System::with_program(Model::Dmg, &[(0x100, program)])
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut system = system(&[0xEA, 0x00, 0xC0, 0xEA, 0x00, 0xC0, 0xFA, 0x10, 0xC0]); // LD [$C000],A x2 / LD A,[$C010]
+        system.cpu.mmu.watchpoints.push(Watchpoint { range: 0xC000..=0xC000, kind: WatchKind::Change });
+        system.step(&[]);
+        assert_eq!(system.break_reason, Some(BreakReason::Watchpoint {
+            pc: 0x100, hit: WatchHit { kind: WatchKind::Change, address: 0xC000, old: 0x00, value: 0x01 },
+        }));
+        system.step(&[]);
+        assert_eq!(system.break_reason, None); // Same value again
+
+        system.cpu.mmu.watchpoints.push(Watchpoint { range: 0xC010..=0xC01F, kind: WatchKind::Read });
+        system.cpu.mmu.read(0xC010); // Only counted once the instruction runs
+        system.step(&[]);
+        assert!(matches!(system.break_reason, Some(BreakReason::Watchpoint { pc: 0x106, hit }) if hit.kind == WatchKind::Read));
+    }
+
+    #[test]
+    fn breakpoints_and_faults() {
+        let mut system = system(&[0x00, 0xD3]); // NOP / illegal
+        system.breakpoints.push(Breakpoint::new(Location { bank: Some(0), address: 0x101 }));
+        system.step(&[]);
+        assert_eq!(system.break_reason, Some(BreakReason::Breakpoint(Location { bank: Some(0), address: 0x101 })));
+        system.step(&[]);
+        assert_eq!(system.break_reason, Some(BreakReason::Fault(Fault::Illegal { pc: 0x101, opcode: 0xD3 })));
+    }
+
+    #[test]
+    fn conditions_and_log_points() {
+        let mut system = system(&[0x3C, 0x18, 0xFD]); // INC A / JR -3
+        let labels = Labels::new();
+        let mut breakpoint = Breakpoint::new(Location { bank: None, address: 0x100 });
+        breakpoint.condition = Some(Condition::parse("A == 5 && HITS > 1", &labels).unwrap());
+        system.breakpoints.push(breakpoint);
+        let mut log_point = Breakpoint::new(Location { bank: None, address: 0x101 });
+        log_point.log = Some(LogMessage::parse("A={A}", &labels).unwrap());
+        system.breakpoints.push(log_point);
+
+        let mut steps = 0;
+        system.cpu.reg.a = 0;
+        while system.break_reason.is_none() && steps < 100 {
+            system.step(&[]);
+            steps += 1;
+        }
+        assert_eq!(system.cpu.reg.a, 5);
+        assert_eq!(system.breakpoints[0].hits, 5);
+        assert_eq!(system.log_messages, ["A=01", "A=02", "A=03", "A=04", "A=05"]);
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        // CALL $0110 / NOP ... $0110: NOP / RET
+        let mut program = vec![0xCD, 0x10, 0x01, 0x00];
+        program.resize(0x10, 0x00);
+        program.extend_from_slice(&[0x00, 0xC9]);
+        let mut system = system(&program);
+        let run = |system: &mut System| {
+            let mut steps = 0;
+            while system.break_reason.is_none() && steps < 100 {
+                system.step(&[]);
+                steps += 1;
+            }
+            steps
+        };
+
+        system.step_over();
+        assert_eq!(run(&mut system), 3);
+        assert_eq!(system.cpu.reg.pc, 0x103);
+        assert!(matches!(system.break_reason, Some(BreakReason::Stepped(RunTarget::Return { .. }))));
+
+        system.set_boot_rom(BootRom::Skip);
+        system.step_into();
+        run(&mut system);
+        assert_eq!(system.cpu.reg.pc, 0x110);
+        system.step_out();
+        system.break_reason = None;
+        run(&mut system);
+        assert_eq!(system.cpu.reg.pc, 0x103);
+
+        system.run_to(Location { bank: None, address: 0x108 });
+        system.break_reason = None;
+        run(&mut system);
+        assert_eq!(system.cpu.reg.pc, 0x108);
+        assert!(system.run_target.is_none());
+    }
+
+    #[test]
+    fn parse_range_ok() {
+        let mut labels = Labels::new();
+        labels.insert(0, 0xC100, "wBuffer");
+        assert_eq!(parse_range("C000-C0FF", &labels), Ok(0xC000..=0xC0FF));
+        assert_eq!(parse_range("wBuffer", &labels), Ok(0xC100..=0xC100));
+        assert!(parse_range("C0FF-C000", &labels).is_err());
+    }
+}
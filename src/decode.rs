@@ -1,549 +1,303 @@
-use super::cpu::Cpu;
-use log::{debug, warn};
+use std::fmt;
+use super::cpu::{Cpu, NORMAL_TIMINGS, CB_TIMINGS};
+use crate::bus::Bus;
+use crate::registers::{R8, R16};
+use log::debug;
 
-// Format string representation
-fn to_string(opcode: u8, mnemonic: &str, operands: &str) -> String {
-    format!("|    {:02X} - {:<10} | {:<5} |", opcode, mnemonic, operands)
+/* Opcodes are decoded from their bit fields rather than a table, based on:
+ * https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+ * Operand syntax follows RGBDS, see: https://rgbds.gbdev.io/docs/gbz80.7 */
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum Mnemonic {
+    Nop, Stop, Halt, Di, Ei,
+    Ld, Ldh, Push, Pop,
+    Inc, Dec, Add, Adc, Sub, Sbc, And, Xor, Or, Cp,
+    Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf,
+    Jr, Jp, Call, Ret, Reti, Rst,
+    Rlc, Rrc, Rl, Rr, Sla, Sra, Swap, Srl, Bit, Res, Set,
+    Illegal,
 }
 
-fn cb_to_string(opcode: u8, mnemonic: &str, operands: &str) -> String {
-    format!("| CB {:02X} - {:<10} | {:<5} |", opcode, mnemonic, operands)
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mnemonic::Illegal => "DB", // Shown as the raw byte
+            _ => return write!(f, "{}", format!("{:?}", self).to_uppercase()),
+        };
+        write!(f, "{}", name)
+    }
 }
 
-// Get the operands of the instruction from memory as a String
-fn get_operands(cpu: &Cpu, len: u8) -> String {
-    let mut operands = String::new();
-    for i in 1..len {
-        operands.push_str(&format!("{:02X} ", cpu.mmu.get(cpu.reg.pc + i as u16)));
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Condition {
+    NZ, Z, NC, C,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Operand {
+    R8(R8), // Including [HL]
+    R16(R16),
+    Indirect(R16), // [BC] or [DE]
+    HlIncrement, // [HL+]
+    HlDecrement, // [HL-]
+    HighC, // [C], i.e. 0xFF00 + C
+    Imm8(u8),
+    Imm16(u16),
+    Address(u16), // [a16]
+    HighAddress(u8), // [a8], i.e. 0xFF00 + a8
+    Relative(i8), // JR offset from the next instruction
+    SpOffset(i8), // SP+e8
+    Signed(i8), // ADD SP, e8
+    Condition(Condition),
+    Bit(u8),
+    Vector(u8), // RST target
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::R8(R8::HLRam) => write!(f, "[HL]"),
+            Operand::R8(reg) => write!(f, "{:?}", reg),
+            Operand::R16(reg) => write!(f, "{:?}", reg),
+            Operand::Indirect(reg) => write!(f, "[{:?}]", reg),
+            Operand::HlIncrement => write!(f, "[HL+]"),
+            Operand::HlDecrement => write!(f, "[HL-]"),
+            Operand::HighC => write!(f, "[C]"),
+            Operand::Imm8(byte) => write!(f, "${:02X}", byte),
+            Operand::Imm16(word) => write!(f, "${:04X}", word),
+            Operand::Address(word) => write!(f, "[${:04X}]", word),
+            Operand::HighAddress(byte) => write!(f, "[$FF{:02X}]", byte),
+            Operand::Relative(offset) => write!(f, "{:+}", offset),
+            Operand::SpOffset(offset) => write!(f, "SP{:+}", offset),
+            Operand::Signed(offset) => write!(f, "{}", offset),
+            Operand::Condition(condition) => write!(f, "{:?}", condition),
+            Operand::Bit(bit) => write!(f, "{}", bit),
+            Operand::Vector(vector) => write!(f, "${:02X}", vector),
+        }
     }
-    operands.pop(); // Remove the trailing space if it exists
-    operands
 }
 
-// Unknown opcode
-fn unknown(cpu: &Cpu) -> String {
-    format!("| {:02X} - {} | Unknown opcode |", cpu.opcode, "???")
+#[derive(PartialEq, Clone, Debug)]
+pub struct Instruction {
+    pub opcode: u8, // The byte after 0xCB for prefixed instructions
+    pub prefixed: bool,
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Operand>,
+    pub length: u8,
+    pub cycles_taken: usize, // M-cycles, the same as cycles_not_taken unless the instruction is conditional
+    pub cycles_not_taken: usize,
 }
 
-// Log the decoded opcode appropriately
-pub fn log_decode(cpu: &Cpu) {
-    if let Some(decoded) = decode(cpu) {
-        debug!("{}", decoded);
-    } else {
-        warn!("{}", unknown(cpu));
+impl Instruction {
+    pub fn is_conditional(&self) -> bool {
+        self.operands.iter().any(|operand| matches!(operand, Operand::Condition(_)))
     }
 }
 
-// Match the opcode and return a formatted String, else return None
-pub fn decode(cpu: &Cpu) -> Option<String> {
-    if cpu.cb_prefix {
-        match cpu.opcode {
-            0x00 => Some(cb_to_string(0x00, "RLC B ", &get_operands(cpu, 2))),
-            0x01 => Some(cb_to_string(0x01, "RLC C ", &get_operands(cpu, 2))),
-            0x02 => Some(cb_to_string(0x02, "RLC D ", &get_operands(cpu, 2))),
-            0x03 => Some(cb_to_string(0x03, "RLC E ", &get_operands(cpu, 2))),
-            0x04 => Some(cb_to_string(0x04, "RLC H ", &get_operands(cpu, 2))),
-            0x05 => Some(cb_to_string(0x05, "RLC L ", &get_operands(cpu, 2))),
-            0x06 => Some(cb_to_string(0x06, "RLC (HL) ", &get_operands(cpu, 2))),
-            0x07 => Some(cb_to_string(0x07, "RLC A ", &get_operands(cpu, 2))),
-            0x08 => Some(cb_to_string(0x08, "RRC B ", &get_operands(cpu, 2))),
-            0x09 => Some(cb_to_string(0x09, "RRC C ", &get_operands(cpu, 2))),
-            0x0a => Some(cb_to_string(0x0a, "RRC D ", &get_operands(cpu, 2))),
-            0x0b => Some(cb_to_string(0x0b, "RRC E ", &get_operands(cpu, 2))),
-            0x0c => Some(cb_to_string(0x0c, "RRC H ", &get_operands(cpu, 2))),
-            0x0d => Some(cb_to_string(0x0d, "RRC L ", &get_operands(cpu, 2))),
-            0x0e => Some(cb_to_string(0x0e, "RRC (HL) ", &get_operands(cpu, 2))),
-            0x0f => Some(cb_to_string(0x0f, "RRC A ", &get_operands(cpu, 2))),
-            0x10 => Some(cb_to_string(0x10, "RL B ", &get_operands(cpu, 2))),
-            0x11 => Some(cb_to_string(0x11, "RL C ", &get_operands(cpu, 2))),
-            0x12 => Some(cb_to_string(0x12, "RL D ", &get_operands(cpu, 2))),
-            0x13 => Some(cb_to_string(0x13, "RL E ", &get_operands(cpu, 2))),
-            0x14 => Some(cb_to_string(0x14, "RL H ", &get_operands(cpu, 2))),
-            0x15 => Some(cb_to_string(0x15, "RL L ", &get_operands(cpu, 2))),
-            0x16 => Some(cb_to_string(0x16, "RL (HL) ", &get_operands(cpu, 2))),
-            0x17 => Some(cb_to_string(0x17, "RL A ", &get_operands(cpu, 2))),
-            0x18 => Some(cb_to_string(0x18, "RR B ", &get_operands(cpu, 2))),
-            0x19 => Some(cb_to_string(0x19, "RR C ", &get_operands(cpu, 2))),
-            0x1a => Some(cb_to_string(0x1a, "RR D ", &get_operands(cpu, 2))),
-            0x1b => Some(cb_to_string(0x1b, "RR E ", &get_operands(cpu, 2))),
-            0x1c => Some(cb_to_string(0x1c, "RR H ", &get_operands(cpu, 2))),
-            0x1d => Some(cb_to_string(0x1d, "RR L ", &get_operands(cpu, 2))),
-            0x1e => Some(cb_to_string(0x1e, "RR (HL) ", &get_operands(cpu, 2))),
-            0x1f => Some(cb_to_string(0x1f, "RR A ", &get_operands(cpu, 2))),
-            0x20 => Some(cb_to_string(0x20, "SLA B ", &get_operands(cpu, 2))),
-            0x21 => Some(cb_to_string(0x21, "SLA C ", &get_operands(cpu, 2))),
-            0x22 => Some(cb_to_string(0x22, "SLA D ", &get_operands(cpu, 2))),
-            0x23 => Some(cb_to_string(0x23, "SLA E ", &get_operands(cpu, 2))),
-            0x24 => Some(cb_to_string(0x24, "SLA H ", &get_operands(cpu, 2))),
-            0x25 => Some(cb_to_string(0x25, "SLA L ", &get_operands(cpu, 2))),
-            0x26 => Some(cb_to_string(0x26, "SLA (HL) ", &get_operands(cpu, 2))),
-            0x27 => Some(cb_to_string(0x27, "SLA A ", &get_operands(cpu, 2))),
-            0x28 => Some(cb_to_string(0x28, "SRA B ", &get_operands(cpu, 2))),
-            0x29 => Some(cb_to_string(0x29, "SRA C ", &get_operands(cpu, 2))),
-            0x2a => Some(cb_to_string(0x2a, "SRA D ", &get_operands(cpu, 2))),
-            0x2b => Some(cb_to_string(0x2b, "SRA E ", &get_operands(cpu, 2))),
-            0x2c => Some(cb_to_string(0x2c, "SRA H ", &get_operands(cpu, 2))),
-            0x2d => Some(cb_to_string(0x2d, "SRA L ", &get_operands(cpu, 2))),
-            0x2e => Some(cb_to_string(0x2e, "SRA (HL) ", &get_operands(cpu, 2))),
-            0x2f => Some(cb_to_string(0x2f, "SRA A ", &get_operands(cpu, 2))),
-            0x30 => Some(cb_to_string(0x30, "SWAP B ", &get_operands(cpu, 2))),
-            0x31 => Some(cb_to_string(0x31, "SWAP C ", &get_operands(cpu, 2))),
-            0x32 => Some(cb_to_string(0x32, "SWAP D ", &get_operands(cpu, 2))),
-            0x33 => Some(cb_to_string(0x33, "SWAP E ", &get_operands(cpu, 2))),
-            0x34 => Some(cb_to_string(0x34, "SWAP H ", &get_operands(cpu, 2))),
-            0x35 => Some(cb_to_string(0x35, "SWAP L ", &get_operands(cpu, 2))),
-            0x36 => Some(cb_to_string(0x36, "SWAP (HL) ", &get_operands(cpu, 2))),
-            0x37 => Some(cb_to_string(0x37, "SWAP A ", &get_operands(cpu, 2))),
-            0x38 => Some(cb_to_string(0x38, "SRL B ", &get_operands(cpu, 2))),
-            0x39 => Some(cb_to_string(0x39, "SRL C ", &get_operands(cpu, 2))),
-            0x3a => Some(cb_to_string(0x3a, "SRL D ", &get_operands(cpu, 2))),
-            0x3b => Some(cb_to_string(0x3b, "SRL E ", &get_operands(cpu, 2))),
-            0x3c => Some(cb_to_string(0x3c, "SRL H ", &get_operands(cpu, 2))),
-            0x3d => Some(cb_to_string(0x3d, "SRL L ", &get_operands(cpu, 2))),
-            0x3e => Some(cb_to_string(0x3e, "SRL (HL) ", &get_operands(cpu, 2))),
-            0x3f => Some(cb_to_string(0x3f, "SRL A ", &get_operands(cpu, 2))),
-            0x40 => Some(cb_to_string(0x40, "BIT 0 B", &get_operands(cpu, 2))),
-            0x41 => Some(cb_to_string(0x41, "BIT 0 C", &get_operands(cpu, 2))),
-            0x42 => Some(cb_to_string(0x42, "BIT 0 D", &get_operands(cpu, 2))),
-            0x43 => Some(cb_to_string(0x43, "BIT 0 E", &get_operands(cpu, 2))),
-            0x44 => Some(cb_to_string(0x44, "BIT 0 H", &get_operands(cpu, 2))),
-            0x45 => Some(cb_to_string(0x45, "BIT 0 L", &get_operands(cpu, 2))),
-            0x46 => Some(cb_to_string(0x46, "BIT 0 (HL)", &get_operands(cpu, 2))),
-            0x47 => Some(cb_to_string(0x47, "BIT 0 A", &get_operands(cpu, 2))),
-            0x48 => Some(cb_to_string(0x48, "BIT 1 B", &get_operands(cpu, 2))),
-            0x49 => Some(cb_to_string(0x49, "BIT 1 C", &get_operands(cpu, 2))),
-            0x4a => Some(cb_to_string(0x4a, "BIT 1 D", &get_operands(cpu, 2))),
-            0x4b => Some(cb_to_string(0x4b, "BIT 1 E", &get_operands(cpu, 2))),
-            0x4c => Some(cb_to_string(0x4c, "BIT 1 H", &get_operands(cpu, 2))),
-            0x4d => Some(cb_to_string(0x4d, "BIT 1 L", &get_operands(cpu, 2))),
-            0x4e => Some(cb_to_string(0x4e, "BIT 1 (HL)", &get_operands(cpu, 2))),
-            0x4f => Some(cb_to_string(0x4f, "BIT 1 A", &get_operands(cpu, 2))),
-            0x50 => Some(cb_to_string(0x50, "BIT 2 B", &get_operands(cpu, 2))),
-            0x51 => Some(cb_to_string(0x51, "BIT 2 C", &get_operands(cpu, 2))),
-            0x52 => Some(cb_to_string(0x52, "BIT 2 D", &get_operands(cpu, 2))),
-            0x53 => Some(cb_to_string(0x53, "BIT 2 E", &get_operands(cpu, 2))),
-            0x54 => Some(cb_to_string(0x54, "BIT 2 H", &get_operands(cpu, 2))),
-            0x55 => Some(cb_to_string(0x55, "BIT 2 L", &get_operands(cpu, 2))),
-            0x56 => Some(cb_to_string(0x56, "BIT 2 (HL)", &get_operands(cpu, 2))),
-            0x57 => Some(cb_to_string(0x57, "BIT 2 A", &get_operands(cpu, 2))),
-            0x58 => Some(cb_to_string(0x58, "BIT 3 B", &get_operands(cpu, 2))),
-            0x59 => Some(cb_to_string(0x59, "BIT 3 C", &get_operands(cpu, 2))),
-            0x5a => Some(cb_to_string(0x5a, "BIT 3 D", &get_operands(cpu, 2))),
-            0x5b => Some(cb_to_string(0x5b, "BIT 3 E", &get_operands(cpu, 2))),
-            0x5c => Some(cb_to_string(0x5c, "BIT 3 H", &get_operands(cpu, 2))),
-            0x5d => Some(cb_to_string(0x5d, "BIT 3 L", &get_operands(cpu, 2))),
-            0x5e => Some(cb_to_string(0x5e, "BIT 3 (HL)", &get_operands(cpu, 2))),
-            0x5f => Some(cb_to_string(0x5f, "BIT 3 A", &get_operands(cpu, 2))),
-            0x60 => Some(cb_to_string(0x60, "BIT 4 B", &get_operands(cpu, 2))),
-            0x61 => Some(cb_to_string(0x61, "BIT 4 C", &get_operands(cpu, 2))),
-            0x62 => Some(cb_to_string(0x62, "BIT 4 D", &get_operands(cpu, 2))),
-            0x63 => Some(cb_to_string(0x63, "BIT 4 E", &get_operands(cpu, 2))),
-            0x64 => Some(cb_to_string(0x64, "BIT 4 H", &get_operands(cpu, 2))),
-            0x65 => Some(cb_to_string(0x65, "BIT 4 L", &get_operands(cpu, 2))),
-            0x66 => Some(cb_to_string(0x66, "BIT 4 (HL)", &get_operands(cpu, 2))),
-            0x67 => Some(cb_to_string(0x67, "BIT 4 A", &get_operands(cpu, 2))),
-            0x68 => Some(cb_to_string(0x68, "BIT 5 B", &get_operands(cpu, 2))),
-            0x69 => Some(cb_to_string(0x69, "BIT 5 C", &get_operands(cpu, 2))),
-            0x6a => Some(cb_to_string(0x6a, "BIT 5 D", &get_operands(cpu, 2))),
-            0x6b => Some(cb_to_string(0x6b, "BIT 5 E", &get_operands(cpu, 2))),
-            0x6c => Some(cb_to_string(0x6c, "BIT 5 H", &get_operands(cpu, 2))),
-            0x6d => Some(cb_to_string(0x6d, "BIT 5 L", &get_operands(cpu, 2))),
-            0x6e => Some(cb_to_string(0x6e, "BIT 5 (HL)", &get_operands(cpu, 2))),
-            0x6f => Some(cb_to_string(0x6f, "BIT 5 A", &get_operands(cpu, 2))),
-            0x70 => Some(cb_to_string(0x70, "BIT 6 B", &get_operands(cpu, 2))),
-            0x71 => Some(cb_to_string(0x71, "BIT 6 C", &get_operands(cpu, 2))),
-            0x72 => Some(cb_to_string(0x72, "BIT 6 D", &get_operands(cpu, 2))),
-            0x73 => Some(cb_to_string(0x73, "BIT 6 E", &get_operands(cpu, 2))),
-            0x74 => Some(cb_to_string(0x74, "BIT 6 H", &get_operands(cpu, 2))),
-            0x75 => Some(cb_to_string(0x75, "BIT 6 L", &get_operands(cpu, 2))),
-            0x76 => Some(cb_to_string(0x76, "BIT 6 (HL)", &get_operands(cpu, 2))),
-            0x77 => Some(cb_to_string(0x77, "BIT 6 A", &get_operands(cpu, 2))),
-            0x78 => Some(cb_to_string(0x78, "BIT 7 B", &get_operands(cpu, 2))),
-            0x79 => Some(cb_to_string(0x79, "BIT 7 C", &get_operands(cpu, 2))),
-            0x7a => Some(cb_to_string(0x7a, "BIT 7 D", &get_operands(cpu, 2))),
-            0x7b => Some(cb_to_string(0x7b, "BIT 7 E", &get_operands(cpu, 2))),
-            0x7c => Some(cb_to_string(0x7c, "BIT 7 H", &get_operands(cpu, 2))),
-            0x7d => Some(cb_to_string(0x7d, "BIT 7 L", &get_operands(cpu, 2))),
-            0x7e => Some(cb_to_string(0x7e, "BIT 7 (HL)", &get_operands(cpu, 2))),
-            0x7f => Some(cb_to_string(0x7f, "BIT 7 A", &get_operands(cpu, 2))),
-            0x80 => Some(cb_to_string(0x80, "RES 0 B", &get_operands(cpu, 2))),
-            0x81 => Some(cb_to_string(0x81, "RES 0 C", &get_operands(cpu, 2))),
-            0x82 => Some(cb_to_string(0x82, "RES 0 D", &get_operands(cpu, 2))),
-            0x83 => Some(cb_to_string(0x83, "RES 0 E", &get_operands(cpu, 2))),
-            0x84 => Some(cb_to_string(0x84, "RES 0 H", &get_operands(cpu, 2))),
-            0x85 => Some(cb_to_string(0x85, "RES 0 L", &get_operands(cpu, 2))),
-            0x86 => Some(cb_to_string(0x86, "RES 0 (HL)", &get_operands(cpu, 2))),
-            0x87 => Some(cb_to_string(0x87, "RES 0 A", &get_operands(cpu, 2))),
-            0x88 => Some(cb_to_string(0x88, "RES 1 B", &get_operands(cpu, 2))),
-            0x89 => Some(cb_to_string(0x89, "RES 1 C", &get_operands(cpu, 2))),
-            0x8a => Some(cb_to_string(0x8a, "RES 1 D", &get_operands(cpu, 2))),
-            0x8b => Some(cb_to_string(0x8b, "RES 1 E", &get_operands(cpu, 2))),
-            0x8c => Some(cb_to_string(0x8c, "RES 1 H", &get_operands(cpu, 2))),
-            0x8d => Some(cb_to_string(0x8d, "RES 1 L", &get_operands(cpu, 2))),
-            0x8e => Some(cb_to_string(0x8e, "RES 1 (HL)", &get_operands(cpu, 2))),
-            0x8f => Some(cb_to_string(0x8f, "RES 1 A", &get_operands(cpu, 2))),
-            0x90 => Some(cb_to_string(0x90, "RES 2 B", &get_operands(cpu, 2))),
-            0x91 => Some(cb_to_string(0x91, "RES 2 C", &get_operands(cpu, 2))),
-            0x92 => Some(cb_to_string(0x92, "RES 2 D", &get_operands(cpu, 2))),
-            0x93 => Some(cb_to_string(0x93, "RES 2 E", &get_operands(cpu, 2))),
-            0x94 => Some(cb_to_string(0x94, "RES 2 H", &get_operands(cpu, 2))),
-            0x95 => Some(cb_to_string(0x95, "RES 2 L", &get_operands(cpu, 2))),
-            0x96 => Some(cb_to_string(0x96, "RES 2 (HL)", &get_operands(cpu, 2))),
-            0x97 => Some(cb_to_string(0x97, "RES 2 A", &get_operands(cpu, 2))),
-            0x98 => Some(cb_to_string(0x98, "RES 3 B", &get_operands(cpu, 2))),
-            0x99 => Some(cb_to_string(0x99, "RES 3 C", &get_operands(cpu, 2))),
-            0x9a => Some(cb_to_string(0x9a, "RES 3 D", &get_operands(cpu, 2))),
-            0x9b => Some(cb_to_string(0x9b, "RES 3 E", &get_operands(cpu, 2))),
-            0x9c => Some(cb_to_string(0x9c, "RES 3 H", &get_operands(cpu, 2))),
-            0x9d => Some(cb_to_string(0x9d, "RES 3 L", &get_operands(cpu, 2))),
-            0x9e => Some(cb_to_string(0x9e, "RES 3 (HL)", &get_operands(cpu, 2))),
-            0x9f => Some(cb_to_string(0x9f, "RES 3 A", &get_operands(cpu, 2))),
-            0xa0 => Some(cb_to_string(0xa0, "RES 4 B", &get_operands(cpu, 2))),
-            0xa1 => Some(cb_to_string(0xa1, "RES 4 C", &get_operands(cpu, 2))),
-            0xa2 => Some(cb_to_string(0xa2, "RES 4 D", &get_operands(cpu, 2))),
-            0xa3 => Some(cb_to_string(0xa3, "RES 4 E", &get_operands(cpu, 2))),
-            0xa4 => Some(cb_to_string(0xa4, "RES 4 H", &get_operands(cpu, 2))),
-            0xa5 => Some(cb_to_string(0xa5, "RES 4 L", &get_operands(cpu, 2))),
-            0xa6 => Some(cb_to_string(0xa6, "RES 4 (HL)", &get_operands(cpu, 2))),
-            0xa7 => Some(cb_to_string(0xa7, "RES 4 A", &get_operands(cpu, 2))),
-            0xa8 => Some(cb_to_string(0xa8, "RES 5 B", &get_operands(cpu, 2))),
-            0xa9 => Some(cb_to_string(0xa9, "RES 5 C", &get_operands(cpu, 2))),
-            0xaa => Some(cb_to_string(0xaa, "RES 5 D", &get_operands(cpu, 2))),
-            0xab => Some(cb_to_string(0xab, "RES 5 E", &get_operands(cpu, 2))),
-            0xac => Some(cb_to_string(0xac, "RES 5 H", &get_operands(cpu, 2))),
-            0xad => Some(cb_to_string(0xad, "RES 5 L", &get_operands(cpu, 2))),
-            0xae => Some(cb_to_string(0xae, "RES 5 (HL)", &get_operands(cpu, 2))),
-            0xaf => Some(cb_to_string(0xaf, "RES 5 A", &get_operands(cpu, 2))),
-            0xb0 => Some(cb_to_string(0xb0, "RES 6 B", &get_operands(cpu, 2))),
-            0xb1 => Some(cb_to_string(0xb1, "RES 6 C", &get_operands(cpu, 2))),
-            0xb2 => Some(cb_to_string(0xb2, "RES 6 D", &get_operands(cpu, 2))),
-            0xb3 => Some(cb_to_string(0xb3, "RES 6 E", &get_operands(cpu, 2))),
-            0xb4 => Some(cb_to_string(0xb4, "RES 6 H", &get_operands(cpu, 2))),
-            0xb5 => Some(cb_to_string(0xb5, "RES 6 L", &get_operands(cpu, 2))),
-            0xb6 => Some(cb_to_string(0xb6, "RES 6 (HL)", &get_operands(cpu, 2))),
-            0xb7 => Some(cb_to_string(0xb7, "RES 6 A", &get_operands(cpu, 2))),
-            0xb8 => Some(cb_to_string(0xb8, "RES 7 B", &get_operands(cpu, 2))),
-            0xb9 => Some(cb_to_string(0xb9, "RES 7 C", &get_operands(cpu, 2))),
-            0xba => Some(cb_to_string(0xba, "RES 7 D", &get_operands(cpu, 2))),
-            0xbb => Some(cb_to_string(0xbb, "RES 7 E", &get_operands(cpu, 2))),
-            0xbc => Some(cb_to_string(0xbc, "RES 7 H", &get_operands(cpu, 2))),
-            0xbd => Some(cb_to_string(0xbd, "RES 7 L", &get_operands(cpu, 2))),
-            0xbe => Some(cb_to_string(0xbe, "RES 7 (HL)", &get_operands(cpu, 2))),
-            0xbf => Some(cb_to_string(0xbf, "RES 7 A", &get_operands(cpu, 2))),
-            0xc0 => Some(cb_to_string(0xc0, "SET 0 B", &get_operands(cpu, 2))),
-            0xc1 => Some(cb_to_string(0xc1, "SET 0 C", &get_operands(cpu, 2))),
-            0xc2 => Some(cb_to_string(0xc2, "SET 0 D", &get_operands(cpu, 2))),
-            0xc3 => Some(cb_to_string(0xc3, "SET 0 E", &get_operands(cpu, 2))),
-            0xc4 => Some(cb_to_string(0xc4, "SET 0 H", &get_operands(cpu, 2))),
-            0xc5 => Some(cb_to_string(0xc5, "SET 0 L", &get_operands(cpu, 2))),
-            0xc6 => Some(cb_to_string(0xc6, "SET 0 (HL)", &get_operands(cpu, 2))),
-            0xc7 => Some(cb_to_string(0xc7, "SET 0 A", &get_operands(cpu, 2))),
-            0xc8 => Some(cb_to_string(0xc8, "SET 1 B", &get_operands(cpu, 2))),
-            0xc9 => Some(cb_to_string(0xc9, "SET 1 C", &get_operands(cpu, 2))),
-            0xca => Some(cb_to_string(0xca, "SET 1 D", &get_operands(cpu, 2))),
-            0xcb => Some(cb_to_string(0xcb, "SET 1 E", &get_operands(cpu, 2))),
-            0xcc => Some(cb_to_string(0xcc, "SET 1 H", &get_operands(cpu, 2))),
-            0xcd => Some(cb_to_string(0xcd, "SET 1 L", &get_operands(cpu, 2))),
-            0xce => Some(cb_to_string(0xce, "SET 1 (HL)", &get_operands(cpu, 2))),
-            0xcf => Some(cb_to_string(0xcf, "SET 1 A", &get_operands(cpu, 2))),
-            0xd0 => Some(cb_to_string(0xd0, "SET 2 B", &get_operands(cpu, 2))),
-            0xd1 => Some(cb_to_string(0xd1, "SET 2 C", &get_operands(cpu, 2))),
-            0xd2 => Some(cb_to_string(0xd2, "SET 2 D", &get_operands(cpu, 2))),
-            0xd3 => Some(cb_to_string(0xd3, "SET 2 E", &get_operands(cpu, 2))),
-            0xd4 => Some(cb_to_string(0xd4, "SET 2 H", &get_operands(cpu, 2))),
-            0xd5 => Some(cb_to_string(0xd5, "SET 2 L", &get_operands(cpu, 2))),
-            0xd6 => Some(cb_to_string(0xd6, "SET 2 (HL)", &get_operands(cpu, 2))),
-            0xd7 => Some(cb_to_string(0xd7, "SET 2 A", &get_operands(cpu, 2))),
-            0xd8 => Some(cb_to_string(0xd8, "SET 3 B", &get_operands(cpu, 2))),
-            0xd9 => Some(cb_to_string(0xd9, "SET 3 C", &get_operands(cpu, 2))),
-            0xda => Some(cb_to_string(0xda, "SET 3 D", &get_operands(cpu, 2))),
-            0xdb => Some(cb_to_string(0xdb, "SET 3 E", &get_operands(cpu, 2))),
-            0xdc => Some(cb_to_string(0xdc, "SET 3 H", &get_operands(cpu, 2))),
-            0xdd => Some(cb_to_string(0xdd, "SET 3 L", &get_operands(cpu, 2))),
-            0xde => Some(cb_to_string(0xde, "SET 3 (HL)", &get_operands(cpu, 2))),
-            0xdf => Some(cb_to_string(0xdf, "SET 3 A", &get_operands(cpu, 2))),
-            0xe0 => Some(cb_to_string(0xe0, "SET 4 B", &get_operands(cpu, 2))),
-            0xe1 => Some(cb_to_string(0xe1, "SET 4 C", &get_operands(cpu, 2))),
-            0xe2 => Some(cb_to_string(0xe2, "SET 4 D", &get_operands(cpu, 2))),
-            0xe3 => Some(cb_to_string(0xe3, "SET 4 E", &get_operands(cpu, 2))),
-            0xe4 => Some(cb_to_string(0xe4, "SET 4 H", &get_operands(cpu, 2))),
-            0xe5 => Some(cb_to_string(0xe5, "SET 4 L", &get_operands(cpu, 2))),
-            0xe6 => Some(cb_to_string(0xe6, "SET 4 (HL)", &get_operands(cpu, 2))),
-            0xe7 => Some(cb_to_string(0xe7, "SET 4 A", &get_operands(cpu, 2))),
-            0xe8 => Some(cb_to_string(0xe8, "SET 5 B", &get_operands(cpu, 2))),
-            0xe9 => Some(cb_to_string(0xe9, "SET 5 C", &get_operands(cpu, 2))),
-            0xea => Some(cb_to_string(0xea, "SET 5 D", &get_operands(cpu, 2))),
-            0xeb => Some(cb_to_string(0xeb, "SET 5 E", &get_operands(cpu, 2))),
-            0xec => Some(cb_to_string(0xec, "SET 5 H", &get_operands(cpu, 2))),
-            0xed => Some(cb_to_string(0xed, "SET 5 L", &get_operands(cpu, 2))),
-            0xee => Some(cb_to_string(0xee, "SET 5 (HL)", &get_operands(cpu, 2))),
-            0xef => Some(cb_to_string(0xef, "SET 5 A", &get_operands(cpu, 2))),
-            0xf0 => Some(cb_to_string(0xf0, "SET 6 B", &get_operands(cpu, 2))),
-            0xf1 => Some(cb_to_string(0xf1, "SET 6 C", &get_operands(cpu, 2))),
-            0xf2 => Some(cb_to_string(0xf2, "SET 6 D", &get_operands(cpu, 2))),
-            0xf3 => Some(cb_to_string(0xf3, "SET 6 E", &get_operands(cpu, 2))),
-            0xf4 => Some(cb_to_string(0xf4, "SET 6 H", &get_operands(cpu, 2))),
-            0xf5 => Some(cb_to_string(0xf5, "SET 6 L", &get_operands(cpu, 2))),
-            0xf6 => Some(cb_to_string(0xf6, "SET 6 (HL)", &get_operands(cpu, 2))),
-            0xf7 => Some(cb_to_string(0xf7, "SET 6 A", &get_operands(cpu, 2))),
-            0xf8 => Some(cb_to_string(0xf8, "SET 7 B", &get_operands(cpu, 2))),
-            0xf9 => Some(cb_to_string(0xf9, "SET 7 C", &get_operands(cpu, 2))),
-            0xfa => Some(cb_to_string(0xfa, "SET 7 D", &get_operands(cpu, 2))),
-            0xfb => Some(cb_to_string(0xfb, "SET 7 E", &get_operands(cpu, 2))),
-            0xfc => Some(cb_to_string(0xfc, "SET 7 H", &get_operands(cpu, 2))),
-            0xfd => Some(cb_to_string(0xfd, "SET 7 L", &get_operands(cpu, 2))),
-            0xfe => Some(cb_to_string(0xfe, "SET 7 (HL)", &get_operands(cpu, 2))),
-            0xff => Some(cb_to_string(0xff, "SET 7 A", &get_operands(cpu, 2))),
-            // _ => None
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mnemonic == Mnemonic::Illegal {
+            return write!(f, "DB ${:02X}", self.opcode);
         }
-    } else {
-        match cpu.opcode {
-            0x00 => Some(to_string(0x00, "NOP ", &get_operands(cpu, 1))),
-            0x01 => Some(to_string(0x01, "LD BC d16", &get_operands(cpu, 3))),
-            0x02 => Some(to_string(0x02, "LD (BC) A", &get_operands(cpu, 1))),
-            0x03 => Some(to_string(0x03, "INC BC ", &get_operands(cpu, 1))),
-            0x04 => Some(to_string(0x04, "INC B ", &get_operands(cpu, 1))),
-            0x05 => Some(to_string(0x05, "DEC B ", &get_operands(cpu, 1))),
-            0x06 => Some(to_string(0x06, "LD B d8", &get_operands(cpu, 2))),
-            0x07 => Some(to_string(0x07, "RLCA ", &get_operands(cpu, 1))),
-            0x08 => Some(to_string(0x08, "LD (a16) SP", &get_operands(cpu, 3))),
-            0x09 => Some(to_string(0x09, "ADD HL BC", &get_operands(cpu, 1))),
-            0x0a => Some(to_string(0x0a, "LD A (BC)", &get_operands(cpu, 1))),
-            0x0b => Some(to_string(0x0b, "DEC BC ", &get_operands(cpu, 1))),
-            0x0c => Some(to_string(0x0c, "INC C ", &get_operands(cpu, 1))),
-            0x0d => Some(to_string(0x0d, "DEC C ", &get_operands(cpu, 1))),
-            0x0e => Some(to_string(0x0e, "LD C d8", &get_operands(cpu, 2))),
-            0x0f => Some(to_string(0x0f, "RRCA ", &get_operands(cpu, 1))),
-            0x10 => Some(to_string(0x10, "STOP 0 ", &get_operands(cpu, 1))),
-            0x11 => Some(to_string(0x11, "LD DE d16", &get_operands(cpu, 3))),
-            0x12 => Some(to_string(0x12, "LD (DE) A", &get_operands(cpu, 1))),
-            0x13 => Some(to_string(0x13, "INC DE ", &get_operands(cpu, 1))),
-            0x14 => Some(to_string(0x14, "INC D ", &get_operands(cpu, 1))),
-            0x15 => Some(to_string(0x15, "DEC D ", &get_operands(cpu, 1))),
-            0x16 => Some(to_string(0x16, "LD D d8", &get_operands(cpu, 2))),
-            0x17 => Some(to_string(0x17, "RLA ", &get_operands(cpu, 1))),
-            0x18 => Some(to_string(0x18, "JR r8 ", &get_operands(cpu, 2))),
-            0x19 => Some(to_string(0x19, "ADD HL DE", &get_operands(cpu, 1))),
-            0x1a => Some(to_string(0x1a, "LD A (DE)", &get_operands(cpu, 1))),
-            0x1b => Some(to_string(0x1b, "DEC DE ", &get_operands(cpu, 1))),
-            0x1c => Some(to_string(0x1c, "INC E ", &get_operands(cpu, 1))),
-            0x1d => Some(to_string(0x1d, "DEC E ", &get_operands(cpu, 1))),
-            0x1e => Some(to_string(0x1e, "LD E d8", &get_operands(cpu, 2))),
-            0x1f => Some(to_string(0x1f, "RRA ", &get_operands(cpu, 1))),
-            0x20 => Some(to_string(0x20, "JR NZ r8", &get_operands(cpu, 2))),
-            0x21 => Some(to_string(0x21, "LD HL d16", &get_operands(cpu, 3))),
-            0x22 => Some(to_string(0x22, "LD (HL+) A", &get_operands(cpu, 1))),
-            0x23 => Some(to_string(0x23, "INC HL ", &get_operands(cpu, 1))),
-            0x24 => Some(to_string(0x24, "INC H ", &get_operands(cpu, 1))),
-            0x25 => Some(to_string(0x25, "DEC H ", &get_operands(cpu, 1))),
-            0x26 => Some(to_string(0x26, "LD H d8", &get_operands(cpu, 2))),
-            0x27 => Some(to_string(0x27, "DAA ", &get_operands(cpu, 1))),
-            0x28 => Some(to_string(0x28, "JR Z r8", &get_operands(cpu, 2))),
-            0x29 => Some(to_string(0x29, "ADD HL HL", &get_operands(cpu, 1))),
-            0x2a => Some(to_string(0x2a, "LD A (HL+)", &get_operands(cpu, 1))),
-            0x2b => Some(to_string(0x2b, "DEC HL ", &get_operands(cpu, 1))),
-            0x2c => Some(to_string(0x2c, "INC L ", &get_operands(cpu, 1))),
-            0x2d => Some(to_string(0x2d, "DEC L ", &get_operands(cpu, 1))),
-            0x2e => Some(to_string(0x2e, "LD L d8", &get_operands(cpu, 2))),
-            0x2f => Some(to_string(0x2f, "CPL ", &get_operands(cpu, 1))),
-            0x30 => Some(to_string(0x30, "JR NC r8", &get_operands(cpu, 2))),
-            0x31 => Some(to_string(0x31, "LD SP d16", &get_operands(cpu, 3))),
-            0x32 => Some(to_string(0x32, "LD (HL-) A", &get_operands(cpu, 1))),
-            0x33 => Some(to_string(0x33, "INC SP ", &get_operands(cpu, 1))),
-            0x34 => Some(to_string(0x34, "INC (HL) ", &get_operands(cpu, 1))),
-            0x35 => Some(to_string(0x35, "DEC (HL) ", &get_operands(cpu, 1))),
-            0x36 => Some(to_string(0x36, "LD (HL) d8", &get_operands(cpu, 2))),
-            0x37 => Some(to_string(0x37, "SCF ", &get_operands(cpu, 1))),
-            0x38 => Some(to_string(0x38, "JR C r8", &get_operands(cpu, 2))),
-            0x39 => Some(to_string(0x39, "ADD HL SP", &get_operands(cpu, 1))),
-            0x3a => Some(to_string(0x3a, "LD A (HL-)", &get_operands(cpu, 1))),
-            0x3b => Some(to_string(0x3b, "DEC SP ", &get_operands(cpu, 1))),
-            0x3c => Some(to_string(0x3c, "INC A ", &get_operands(cpu, 1))),
-            0x3d => Some(to_string(0x3d, "DEC A ", &get_operands(cpu, 1))),
-            0x3e => Some(to_string(0x3e, "LD A d8", &get_operands(cpu, 2))),
-            0x3f => Some(to_string(0x3f, "CCF ", &get_operands(cpu, 1))),
-            0x40 => Some(to_string(0x40, "LD B B", &get_operands(cpu, 1))),
-            0x41 => Some(to_string(0x41, "LD B C", &get_operands(cpu, 1))),
-            0x42 => Some(to_string(0x42, "LD B D", &get_operands(cpu, 1))),
-            0x43 => Some(to_string(0x43, "LD B E", &get_operands(cpu, 1))),
-            0x44 => Some(to_string(0x44, "LD B H", &get_operands(cpu, 1))),
-            0x45 => Some(to_string(0x45, "LD B L", &get_operands(cpu, 1))),
-            0x46 => Some(to_string(0x46, "LD B (HL)", &get_operands(cpu, 1))),
-            0x47 => Some(to_string(0x47, "LD B A", &get_operands(cpu, 1))),
-            0x48 => Some(to_string(0x48, "LD C B", &get_operands(cpu, 1))),
-            0x49 => Some(to_string(0x49, "LD C C", &get_operands(cpu, 1))),
-            0x4a => Some(to_string(0x4a, "LD C D", &get_operands(cpu, 1))),
-            0x4b => Some(to_string(0x4b, "LD C E", &get_operands(cpu, 1))),
-            0x4c => Some(to_string(0x4c, "LD C H", &get_operands(cpu, 1))),
-            0x4d => Some(to_string(0x4d, "LD C L", &get_operands(cpu, 1))),
-            0x4e => Some(to_string(0x4e, "LD C (HL)", &get_operands(cpu, 1))),
-            0x4f => Some(to_string(0x4f, "LD C A", &get_operands(cpu, 1))),
-            0x50 => Some(to_string(0x50, "LD D B", &get_operands(cpu, 1))),
-            0x51 => Some(to_string(0x51, "LD D C", &get_operands(cpu, 1))),
-            0x52 => Some(to_string(0x52, "LD D D", &get_operands(cpu, 1))),
-            0x53 => Some(to_string(0x53, "LD D E", &get_operands(cpu, 1))),
-            0x54 => Some(to_string(0x54, "LD D H", &get_operands(cpu, 1))),
-            0x55 => Some(to_string(0x55, "LD D L", &get_operands(cpu, 1))),
-            0x56 => Some(to_string(0x56, "LD D (HL)", &get_operands(cpu, 1))),
-            0x57 => Some(to_string(0x57, "LD D A", &get_operands(cpu, 1))),
-            0x58 => Some(to_string(0x58, "LD E B", &get_operands(cpu, 1))),
-            0x59 => Some(to_string(0x59, "LD E C", &get_operands(cpu, 1))),
-            0x5a => Some(to_string(0x5a, "LD E D", &get_operands(cpu, 1))),
-            0x5b => Some(to_string(0x5b, "LD E E", &get_operands(cpu, 1))),
-            0x5c => Some(to_string(0x5c, "LD E H", &get_operands(cpu, 1))),
-            0x5d => Some(to_string(0x5d, "LD E L", &get_operands(cpu, 1))),
-            0x5e => Some(to_string(0x5e, "LD E (HL)", &get_operands(cpu, 1))),
-            0x5f => Some(to_string(0x5f, "LD E A", &get_operands(cpu, 1))),
-            0x60 => Some(to_string(0x60, "LD H B", &get_operands(cpu, 1))),
-            0x61 => Some(to_string(0x61, "LD H C", &get_operands(cpu, 1))),
-            0x62 => Some(to_string(0x62, "LD H D", &get_operands(cpu, 1))),
-            0x63 => Some(to_string(0x63, "LD H E", &get_operands(cpu, 1))),
-            0x64 => Some(to_string(0x64, "LD H H", &get_operands(cpu, 1))),
-            0x65 => Some(to_string(0x65, "LD H L", &get_operands(cpu, 1))),
-            0x66 => Some(to_string(0x66, "LD H (HL)", &get_operands(cpu, 1))),
-            0x67 => Some(to_string(0x67, "LD H A", &get_operands(cpu, 1))),
-            0x68 => Some(to_string(0x68, "LD L B", &get_operands(cpu, 1))),
-            0x69 => Some(to_string(0x69, "LD L C", &get_operands(cpu, 1))),
-            0x6a => Some(to_string(0x6a, "LD L D", &get_operands(cpu, 1))),
-            0x6b => Some(to_string(0x6b, "LD L E", &get_operands(cpu, 1))),
-            0x6c => Some(to_string(0x6c, "LD L H", &get_operands(cpu, 1))),
-            0x6d => Some(to_string(0x6d, "LD L L", &get_operands(cpu, 1))),
-            0x6e => Some(to_string(0x6e, "LD L (HL)", &get_operands(cpu, 1))),
-            0x6f => Some(to_string(0x6f, "LD L A", &get_operands(cpu, 1))),
-            0x70 => Some(to_string(0x70, "LD (HL) B", &get_operands(cpu, 1))),
-            0x71 => Some(to_string(0x71, "LD (HL) C", &get_operands(cpu, 1))),
-            0x72 => Some(to_string(0x72, "LD (HL) D", &get_operands(cpu, 1))),
-            0x73 => Some(to_string(0x73, "LD (HL) E", &get_operands(cpu, 1))),
-            0x74 => Some(to_string(0x74, "LD (HL) H", &get_operands(cpu, 1))),
-            0x75 => Some(to_string(0x75, "LD (HL) L", &get_operands(cpu, 1))),
-            0x76 => Some(to_string(0x76, "HALT ", &get_operands(cpu, 1))),
-            0x77 => Some(to_string(0x77, "LD (HL) A", &get_operands(cpu, 1))),
-            0x78 => Some(to_string(0x78, "LD A B", &get_operands(cpu, 1))),
-            0x79 => Some(to_string(0x79, "LD A C", &get_operands(cpu, 1))),
-            0x7a => Some(to_string(0x7a, "LD A D", &get_operands(cpu, 1))),
-            0x7b => Some(to_string(0x7b, "LD A E", &get_operands(cpu, 1))),
-            0x7c => Some(to_string(0x7c, "LD A H", &get_operands(cpu, 1))),
-            0x7d => Some(to_string(0x7d, "LD A L", &get_operands(cpu, 1))),
-            0x7e => Some(to_string(0x7e, "LD A (HL)", &get_operands(cpu, 1))),
-            0x7f => Some(to_string(0x7f, "LD A A", &get_operands(cpu, 1))),
-            0x80 => Some(to_string(0x80, "ADD A B", &get_operands(cpu, 1))),
-            0x81 => Some(to_string(0x81, "ADD A C", &get_operands(cpu, 1))),
-            0x82 => Some(to_string(0x82, "ADD A D", &get_operands(cpu, 1))),
-            0x83 => Some(to_string(0x83, "ADD A E", &get_operands(cpu, 1))),
-            0x84 => Some(to_string(0x84, "ADD A H", &get_operands(cpu, 1))),
-            0x85 => Some(to_string(0x85, "ADD A L", &get_operands(cpu, 1))),
-            0x86 => Some(to_string(0x86, "ADD A (HL)", &get_operands(cpu, 1))),
-            0x87 => Some(to_string(0x87, "ADD A A", &get_operands(cpu, 1))),
-            0x88 => Some(to_string(0x88, "ADC A B", &get_operands(cpu, 1))),
-            0x89 => Some(to_string(0x89, "ADC A C", &get_operands(cpu, 1))),
-            0x8a => Some(to_string(0x8a, "ADC A D", &get_operands(cpu, 1))),
-            0x8b => Some(to_string(0x8b, "ADC A E", &get_operands(cpu, 1))),
-            0x8c => Some(to_string(0x8c, "ADC A H", &get_operands(cpu, 1))),
-            0x8d => Some(to_string(0x8d, "ADC A L", &get_operands(cpu, 1))),
-            0x8e => Some(to_string(0x8e, "ADC A (HL)", &get_operands(cpu, 1))),
-            0x8f => Some(to_string(0x8f, "ADC A A", &get_operands(cpu, 1))),
-            0x90 => Some(to_string(0x90, "SUB B ", &get_operands(cpu, 1))),
-            0x91 => Some(to_string(0x91, "SUB C ", &get_operands(cpu, 1))),
-            0x92 => Some(to_string(0x92, "SUB D ", &get_operands(cpu, 1))),
-            0x93 => Some(to_string(0x93, "SUB E ", &get_operands(cpu, 1))),
-            0x94 => Some(to_string(0x94, "SUB H ", &get_operands(cpu, 1))),
-            0x95 => Some(to_string(0x95, "SUB L ", &get_operands(cpu, 1))),
-            0x96 => Some(to_string(0x96, "SUB (HL) ", &get_operands(cpu, 1))),
-            0x97 => Some(to_string(0x97, "SUB A ", &get_operands(cpu, 1))),
-            0x98 => Some(to_string(0x98, "SBC A B", &get_operands(cpu, 1))),
-            0x99 => Some(to_string(0x99, "SBC A C", &get_operands(cpu, 1))),
-            0x9a => Some(to_string(0x9a, "SBC A D", &get_operands(cpu, 1))),
-            0x9b => Some(to_string(0x9b, "SBC A E", &get_operands(cpu, 1))),
-            0x9c => Some(to_string(0x9c, "SBC A H", &get_operands(cpu, 1))),
-            0x9d => Some(to_string(0x9d, "SBC A L", &get_operands(cpu, 1))),
-            0x9e => Some(to_string(0x9e, "SBC A (HL)", &get_operands(cpu, 1))),
-            0x9f => Some(to_string(0x9f, "SBC A A", &get_operands(cpu, 1))),
-            0xa0 => Some(to_string(0xa0, "AND B ", &get_operands(cpu, 1))),
-            0xa1 => Some(to_string(0xa1, "AND C ", &get_operands(cpu, 1))),
-            0xa2 => Some(to_string(0xa2, "AND D ", &get_operands(cpu, 1))),
-            0xa3 => Some(to_string(0xa3, "AND E ", &get_operands(cpu, 1))),
-            0xa4 => Some(to_string(0xa4, "AND H ", &get_operands(cpu, 1))),
-            0xa5 => Some(to_string(0xa5, "AND L ", &get_operands(cpu, 1))),
-            0xa6 => Some(to_string(0xa6, "AND (HL) ", &get_operands(cpu, 1))),
-            0xa7 => Some(to_string(0xa7, "AND A ", &get_operands(cpu, 1))),
-            0xa8 => Some(to_string(0xa8, "XOR B ", &get_operands(cpu, 1))),
-            0xa9 => Some(to_string(0xa9, "XOR C ", &get_operands(cpu, 1))),
-            0xaa => Some(to_string(0xaa, "XOR D ", &get_operands(cpu, 1))),
-            0xab => Some(to_string(0xab, "XOR E ", &get_operands(cpu, 1))),
-            0xac => Some(to_string(0xac, "XOR H ", &get_operands(cpu, 1))),
-            0xad => Some(to_string(0xad, "XOR L ", &get_operands(cpu, 1))),
-            0xae => Some(to_string(0xae, "XOR (HL) ", &get_operands(cpu, 1))),
-            0xaf => Some(to_string(0xaf, "XOR A ", &get_operands(cpu, 1))),
-            0xb0 => Some(to_string(0xb0, "OR B ", &get_operands(cpu, 1))),
-            0xb1 => Some(to_string(0xb1, "OR C ", &get_operands(cpu, 1))),
-            0xb2 => Some(to_string(0xb2, "OR D ", &get_operands(cpu, 1))),
-            0xb3 => Some(to_string(0xb3, "OR E ", &get_operands(cpu, 1))),
-            0xb4 => Some(to_string(0xb4, "OR H ", &get_operands(cpu, 1))),
-            0xb5 => Some(to_string(0xb5, "OR L ", &get_operands(cpu, 1))),
-            0xb6 => Some(to_string(0xb6, "OR (HL) ", &get_operands(cpu, 1))),
-            0xb7 => Some(to_string(0xb7, "OR A ", &get_operands(cpu, 1))),
-            0xb8 => Some(to_string(0xb8, "CP B ", &get_operands(cpu, 1))),
-            0xb9 => Some(to_string(0xb9, "CP C ", &get_operands(cpu, 1))),
-            0xba => Some(to_string(0xba, "CP D ", &get_operands(cpu, 1))),
-            0xbb => Some(to_string(0xbb, "CP E ", &get_operands(cpu, 1))),
-            0xbc => Some(to_string(0xbc, "CP H ", &get_operands(cpu, 1))),
-            0xbd => Some(to_string(0xbd, "CP L ", &get_operands(cpu, 1))),
-            0xbe => Some(to_string(0xbe, "CP (HL) ", &get_operands(cpu, 1))),
-            0xbf => Some(to_string(0xbf, "CP A ", &get_operands(cpu, 1))),
-            0xc0 => Some(to_string(0xc0, "RET NZ ", &get_operands(cpu, 1))),
-            0xc1 => Some(to_string(0xc1, "POP BC ", &get_operands(cpu, 1))),
-            0xc2 => Some(to_string(0xc2, "JP NZ a16", &get_operands(cpu, 3))),
-            0xc3 => Some(to_string(0xc3, "JP a16 ", &get_operands(cpu, 3))),
-            0xc4 => Some(to_string(0xc4, "CALL NZ a16", &get_operands(cpu, 3))),
-            0xc5 => Some(to_string(0xc5, "PUSH BC ", &get_operands(cpu, 1))),
-            0xc6 => Some(to_string(0xc6, "ADD A d8", &get_operands(cpu, 2))),
-            0xc7 => Some(to_string(0xc7, "RST 00H ", &get_operands(cpu, 1))),
-            0xc8 => Some(to_string(0xc8, "RET Z ", &get_operands(cpu, 1))),
-            0xc9 => Some(to_string(0xc9, "RET ", &get_operands(cpu, 1))),
-            0xca => Some(to_string(0xca, "JP Z a16", &get_operands(cpu, 3))),
-            0xcb => Some(to_string(0xcb, "PREFIX CB ", &get_operands(cpu, 1))),
-            0xcc => Some(to_string(0xcc, "CALL Z a16", &get_operands(cpu, 3))),
-            0xcd => Some(to_string(0xcd, "CALL a16 ", &get_operands(cpu, 3))),
-            0xce => Some(to_string(0xce, "ADC A d8", &get_operands(cpu, 2))),
-            0xcf => Some(to_string(0xcf, "RST 08H ", &get_operands(cpu, 1))),
-            0xd0 => Some(to_string(0xd0, "RET NC ", &get_operands(cpu, 1))),
-            0xd1 => Some(to_string(0xd1, "POP DE ", &get_operands(cpu, 1))),
-            0xd2 => Some(to_string(0xd2, "JP NC a16", &get_operands(cpu, 3))),
-            0xd4 => Some(to_string(0xd4, "CALL NC a16", &get_operands(cpu, 3))),
-            0xd5 => Some(to_string(0xd5, "PUSH DE ", &get_operands(cpu, 1))),
-            0xd6 => Some(to_string(0xd6, "SUB d8 ", &get_operands(cpu, 2))),
-            0xd7 => Some(to_string(0xd7, "RST 10H ", &get_operands(cpu, 1))),
-            0xd8 => Some(to_string(0xd8, "RET C ", &get_operands(cpu, 1))),
-            0xd9 => Some(to_string(0xd9, "RETI ", &get_operands(cpu, 1))),
-            0xda => Some(to_string(0xda, "JP C a16", &get_operands(cpu, 3))),
-            0xdc => Some(to_string(0xdc, "CALL C a16", &get_operands(cpu, 3))),
-            0xde => Some(to_string(0xde, "SBC A d8", &get_operands(cpu, 2))),
-            0xdf => Some(to_string(0xdf, "RST 18H ", &get_operands(cpu, 1))),
-            0xe0 => Some(to_string(0xe0, "LDH (a8) A", &get_operands(cpu, 2))),
-            0xe1 => Some(to_string(0xe1, "POP HL ", &get_operands(cpu, 1))),
-            0xe2 => Some(to_string(0xe2, "LD (C) A", &get_operands(cpu, 1))),
-            0xe5 => Some(to_string(0xe5, "PUSH HL ", &get_operands(cpu, 1))),
-            0xe6 => Some(to_string(0xe6, "AND d8 ", &get_operands(cpu, 2))),
-            0xe7 => Some(to_string(0xe7, "RST 20H ", &get_operands(cpu, 1))),
-            0xe8 => Some(to_string(0xe8, "ADD SP r8", &get_operands(cpu, 2))),
-            0xe9 => Some(to_string(0xe9, "JP (HL) ", &get_operands(cpu, 1))),
-            0xea => Some(to_string(0xea, "LD (a16) A", &get_operands(cpu, 3))),
-            0xee => Some(to_string(0xee, "XOR d8 ", &get_operands(cpu, 2))),
-            0xef => Some(to_string(0xef, "RST 28H ", &get_operands(cpu, 1))),
-            0xf0 => Some(to_string(0xf0, "LDH A (a8)", &get_operands(cpu, 2))),
-            0xf1 => Some(to_string(0xf1, "POP AF ", &get_operands(cpu, 1))),
-            0xf2 => Some(to_string(0xf2, "LD A (C)", &get_operands(cpu, 1))),
-            0xf3 => Some(to_string(0xf3, "DI ", &get_operands(cpu, 1))),
-            0xf5 => Some(to_string(0xf5, "PUSH AF ", &get_operands(cpu, 1))),
-            0xf6 => Some(to_string(0xf6, "OR d8 ", &get_operands(cpu, 2))),
-            0xf7 => Some(to_string(0xf7, "RST 30H ", &get_operands(cpu, 1))),
-            0xf8 => Some(to_string(0xf8, "LD HL SP+r8", &get_operands(cpu, 2))),
-            0xf9 => Some(to_string(0xf9, "LD SP HL", &get_operands(cpu, 1))),
-            0xfa => Some(to_string(0xfa, "LD A (a16)", &get_operands(cpu, 3))),
-            0xfb => Some(to_string(0xfb, "EI ", &get_operands(cpu, 1))),
-            0xfe => Some(to_string(0xfe, "CP d8 ", &get_operands(cpu, 2))),
-            0xff => Some(to_string(0xff, "RST 38H ", &get_operands(cpu, 1))),
-            _ => None
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
         }
+        Ok(())
+    }
+}
+
+const CONDITIONS: [Condition; 4] = [Condition::NZ, Condition::Z, Condition::NC, Condition::C];
+const RP: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::SP];
+const RP2: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::AF];
+const ALU: [Mnemonic; 8] = [Mnemonic::Add, Mnemonic::Adc, Mnemonic::Sub, Mnemonic::Sbc, Mnemonic::And, Mnemonic::Xor, Mnemonic::Or, Mnemonic::Cp];
+const ROT: [Mnemonic; 8] = [Mnemonic::Rlc, Mnemonic::Rrc, Mnemonic::Rl, Mnemonic::Rr, Mnemonic::Sla, Mnemonic::Sra, Mnemonic::Swap, Mnemonic::Srl];
+
+// ADD, ADC and SBC name A explicitly, the other ALU operations imply it
+fn alu(mnemonic: Mnemonic, operand: Operand) -> (Mnemonic, Vec<Operand>) {
+    match mnemonic {
+        Mnemonic::Add | Mnemonic::Adc | Mnemonic::Sbc => (mnemonic, vec![Operand::R8(R8::A), operand]),
+        _ => (mnemonic, vec![operand]),
+    }
+}
+
+// Decode the instruction at the start of `bytes`, missing bytes read as 0x00
+pub fn decode_at(bytes: &[u8]) -> Instruction {
+    let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let n = Operand::Imm8(byte(1));
+    let nn = Operand::Imm16(u16::from_le_bytes([byte(1), byte(2)]));
+    let a16 = Operand::Address(u16::from_le_bytes([byte(1), byte(2)]));
+    let e8 = byte(1) as i8;
+
+    let opcode = byte(0);
+    if opcode == 0xCB {
+        let opcode = byte(1);
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let reg = Operand::R8(R8::from_spec(z));
+        let (mnemonic, operands) = match x {
+            0 => (ROT[y as usize], vec![reg]),
+            1 => (Mnemonic::Bit, vec![Operand::Bit(y), reg]),
+            2 => (Mnemonic::Res, vec![Operand::Bit(y), reg]),
+            _ => (Mnemonic::Set, vec![Operand::Bit(y), reg]),
+        };
+        let cycles = CB_TIMINGS[opcode as usize];
+        return Instruction { opcode, prefixed: true, mnemonic, operands, length: 2, cycles_taken: cycles, cycles_not_taken: cycles };
+    }
+
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let (p, q) = ((y >> 1) as usize, y & 1);
+    let r = |index: u8| Operand::R8(R8::from_spec(index));
+    let a = Operand::R8(R8::A);
+    use Mnemonic::*;
+    let (mnemonic, operands) = match (x, z) {
+        (0, 0) => match y {
+            0 => (Nop, vec![]),
+            1 => (Ld, vec![a16, Operand::R16(R16::SP)]),
+            2 => (Stop, vec![]),
+            3 => (Jr, vec![Operand::Relative(e8)]),
+            _ => (Jr, vec![Operand::Condition(CONDITIONS[y as usize - 4]), Operand::Relative(e8)]),
+        },
+        (0, 1) if q == 0 => (Ld, vec![Operand::R16(RP[p]), nn]),
+        (0, 1) => (Add, vec![Operand::R16(R16::HL), Operand::R16(RP[p])]),
+        (0, 2) => {
+            let memory = match p {
+                0 => Operand::Indirect(R16::BC),
+                1 => Operand::Indirect(R16::DE),
+                2 => Operand::HlIncrement,
+                _ => Operand::HlDecrement,
+            };
+            if q == 0 { (Ld, vec![memory, a]) } else { (Ld, vec![a, memory]) }
+        }
+        (0, 3) => (if q == 0 { Inc } else { Dec }, vec![Operand::R16(RP[p])]),
+        (0, 4) => (Inc, vec![r(y)]),
+        (0, 5) => (Dec, vec![r(y)]),
+        (0, 6) => (Ld, vec![r(y), n]),
+        (0, _) => ([Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf][y as usize], vec![]),
+        (1, 6) if y == 6 => (Halt, vec![]),
+        (1, _) => (Ld, vec![r(y), r(z)]),
+        (2, _) => alu(ALU[y as usize], r(z)),
+        (_, 0) => match y {
+            0..=3 => (Ret, vec![Operand::Condition(CONDITIONS[y as usize])]),
+            4 => (Ldh, vec![Operand::HighAddress(byte(1)), a]),
+            5 => (Add, vec![Operand::R16(R16::SP), Operand::Signed(e8)]),
+            6 => (Ldh, vec![a, Operand::HighAddress(byte(1))]),
+            _ => (Ld, vec![Operand::R16(R16::HL), Operand::SpOffset(e8)]),
+        },
+        (_, 1) if q == 0 => (Pop, vec![Operand::R16(RP2[p])]),
+        (_, 1) => match p {
+            0 => (Ret, vec![]),
+            1 => (Reti, vec![]),
+            2 => (Jp, vec![Operand::R16(R16::HL)]),
+            _ => (Ld, vec![Operand::R16(R16::SP), Operand::R16(R16::HL)]),
+        },
+        (_, 2) => match y {
+            0..=3 => (Jp, vec![Operand::Condition(CONDITIONS[y as usize]), nn]),
+            4 => (Ldh, vec![Operand::HighC, a]),
+            5 => (Ld, vec![a16, a]),
+            6 => (Ldh, vec![a, Operand::HighC]),
+            _ => (Ld, vec![a, a16]),
+        },
+        (_, 3) => match y {
+            0 => (Jp, vec![nn]),
+            6 => (Di, vec![]),
+            7 => (Ei, vec![]),
+            _ => (Illegal, vec![]), // 0xCB is handled above
+        },
+        (_, 4) if y < 4 => (Call, vec![Operand::Condition(CONDITIONS[y as usize]), nn]),
+        (_, 5) if q == 0 => (Push, vec![Operand::R16(RP2[p])]),
+        (_, 5) if p == 0 => (Call, vec![nn]),
+        (_, 6) => alu(ALU[y as usize], n),
+        (_, 7) => (Rst, vec![Operand::Vector(y * 8)]),
+        _ => (Illegal, vec![]),
+    };
+
+    let length = match mnemonic {
+        Stop => 2, // STOP is followed by a padding byte
+        _ => 1 + operands.iter().map(|operand| match operand {
+            Operand::Imm8(_) | Operand::HighAddress(_) | Operand::Relative(_) | Operand::SpOffset(_) | Operand::Signed(_) => 1,
+            Operand::Imm16(_) | Operand::Address(_) => 2,
+            _ => 0,
+        }).sum::<u8>(),
+    };
+    let cycles_not_taken = NORMAL_TIMINGS[opcode as usize];
+    let conditional = operands.iter().any(|operand| matches!(operand, Operand::Condition(_)));
+    let cycles_taken = match mnemonic {
+        Jr if conditional => 3,
+        Jp if conditional => 4,
+        Call if conditional => 6,
+        Ret if conditional => 5,
+        _ => cycles_not_taken,
+    };
+    Instruction { opcode, prefixed: false, mnemonic, operands, length, cycles_taken, cycles_not_taken }
+}
+
+// Decode the instruction at PC without touching the CPU state
+pub fn decode<B: Bus>(cpu: &Cpu<B>) -> Instruction {
+    let pc = cpu.reg.pc;
+    decode_at(&[0, 1, 2].map(|offset| cpu.mmu.peek(pc.wrapping_add(offset))))
+}
+
+// Log the decoded instruction at PC
+pub fn log_decode<B: Bus>(cpu: &Cpu<B>) {
+    debug!("{:04X}: {}", cpu.reg.pc, decode(cpu));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger::{WatchKind, Watchpoint};
+    use crate::decode::{decode, decode_at, Condition, Mnemonic, Operand};
+    use crate::model::Model;
+    use crate::registers::R8;
+    use crate::system::System;
+
+    #[test]
+    fn decode_lengths_and_cycles() {
+        let jr = decode_at(&[0x20, 0xFE]);
+        assert_eq!(jr.mnemonic, Mnemonic::Jr);
+        assert_eq!(jr.operands, vec![Operand::Condition(Condition::NZ), Operand::Relative(-2)]);
+        assert_eq!((jr.length, jr.cycles_taken, jr.cycles_not_taken), (2, 3, 2));
+        let call = decode_at(&[0xCD, 0x50, 0x01]);
+        assert_eq!((call.length, call.cycles_taken, call.cycles_not_taken), (3, 6, 6));
+        let bit = decode_at(&[0xCB, 0x7E]);
+        assert!(bit.prefixed);
+        assert_eq!(bit.operands, vec![Operand::Bit(7), Operand::R8(R8::HLRam)]);
+        assert_eq!((bit.length, bit.cycles_taken), (2, 3));
+    }
+
+    #[test]
+    fn display_ok() {
+        assert_eq!(decode_at(&[0x00]).to_string(), "NOP");
+        assert_eq!(decode_at(&[0x3E, 0x3C]).to_string(), "LD A, $3C");
+        assert_eq!(decode_at(&[0x22]).to_string(), "LD [HL+], A");
+        assert_eq!(decode_at(&[0xE0, 0x40]).to_string(), "LDH [$FF40], A");
+        assert_eq!(decode_at(&[0xEA, 0x00, 0xC0]).to_string(), "LD [$C000], A");
+        assert_eq!(decode_at(&[0xF8, 0xFF]).to_string(), "LD HL, SP-1");
+        assert_eq!(decode_at(&[0x90]).to_string(), "SUB B");
+        assert_eq!(decode_at(&[0x8E]).to_string(), "ADC A, [HL]");
+        assert_eq!(decode_at(&[0xFF]).to_string(), "RST $38");
+        assert_eq!(decode_at(&[0xC5]).to_string(), "PUSH BC");
+        assert_eq!(decode_at(&[0xF1]).to_string(), "POP AF");
+        assert_eq!(decode_at(&[0xD3]).to_string(), "DB $D3");
+    }
+
+    #[test]
+    fn every_opcode_decodes() {
+        for opcode in 0..=0xFF {
+            let instruction = decode_at(&[opcode, 0, 0]);
+            assert!((1..=3).contains(&instruction.length));
+            let prefixed = decode_at(&[0xCB, opcode]);
+            assert_eq!(prefixed.length, 2);
+        }
+    }
+
+    #[test]
+    fn decode_doesnt_trip_watchpoints() {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0xC3, 0x50, 0x01])]);
+        system.cpu.mmu.watchpoints.push(Watchpoint { range: 0x100..=0x102, kind: WatchKind::Read });
+        system.cpu.mmu.log_accesses = true;
+        assert_eq!(decode(&system.cpu).to_string(), "JP $0150");
+        assert_eq!(system.cpu.mmu.watch_hit.get(), None);
+        assert!(system.cpu.mmu.accesses.borrow().is_empty());
     }
 }
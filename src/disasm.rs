@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::decode::{decode_at, Instruction, Mnemonic, Operand};
+
+/* Symbolic disassembly, register names are from hardware.inc:
+ * https://github.com/gbdev/hardware.inc */
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+pub const HARDWARE_REGISTERS: [(u16, &str); 56] = [
+    (0xFF00, "rP1"), (0xFF01, "rSB"), (0xFF02, "rSC"), (0xFF04, "rDIV"),
+    (0xFF05, "rTIMA"), (0xFF06, "rTMA"), (0xFF07, "rTAC"), (0xFF0F, "rIF"),
+    (0xFF10, "rNR10"), (0xFF11, "rNR11"), (0xFF12, "rNR12"), (0xFF13, "rNR13"), (0xFF14, "rNR14"),
+    (0xFF16, "rNR21"), (0xFF17, "rNR22"), (0xFF18, "rNR23"), (0xFF19, "rNR24"),
+    (0xFF1A, "rNR30"), (0xFF1B, "rNR31"), (0xFF1C, "rNR32"), (0xFF1D, "rNR33"), (0xFF1E, "rNR34"),
+    (0xFF20, "rNR41"), (0xFF21, "rNR42"), (0xFF22, "rNR43"), (0xFF23, "rNR44"),
+    (0xFF24, "rNR50"), (0xFF25, "rNR51"), (0xFF26, "rNR52"),
+    (0xFF40, "rLCDC"), (0xFF41, "rSTAT"), (0xFF42, "rSCY"), (0xFF43, "rSCX"), (0xFF44, "rLY"),
+    (0xFF45, "rLYC"), (0xFF46, "rDMA"), (0xFF47, "rBGP"), (0xFF48, "rOBP0"), (0xFF49, "rOBP1"),
+    (0xFF4A, "rWY"), (0xFF4B, "rWX"), (0xFF4D, "rKEY1"), (0xFF4F, "rVBK"), (0xFF50, "rBANK"),
+    (0xFF51, "rHDMA1"), (0xFF52, "rHDMA2"), (0xFF53, "rHDMA3"), (0xFF54, "rHDMA4"), (0xFF55, "rHDMA5"),
+    (0xFF56, "rRP"), (0xFF68, "rBCPS"), (0xFF69, "rBCPD"), (0xFF6A, "rOCPS"), (0xFF6B, "rOCPD"),
+    (0xFF70, "rSVBK"), (0xFFFF, "rIE"),
+];
+
+pub fn register_name(address: u16) -> Option<&'static str> {
+    HARDWARE_REGISTERS.iter().find(|(register, _)| *register == address).map(|(_, name)| *name)
+}
+
+// The bank an address belongs to when executing from `bank`, None outside ROM
+pub fn bank_of(address: u16, bank: u16) -> Option<u16> {
+    match address {
+        0x0000..=0x3FFF => Some(0),
+        0x4000..=0x7FFF => Some(bank),
+        _ => None,
+    }
+}
+
+// So a symbol in WRAM is never used to describe an address in HRAM, and so on
+fn region(address: u16) -> u8 {
+    match address {
+        0x0000..=0x3FFF => 0, // ROM bank 0
+        0x4000..=0x7FFF => 1, // Switchable ROM bank
+        0x8000..=0x9FFF => 2, // VRAM
+        0xA000..=0xBFFF => 3, // External RAM
+        0xC000..=0xFDFF => 4, // WRAM and echo RAM
+        0xFE00..=0xFF7F => 5, // OAM and IO
+        _ => 6, // HRAM and IE
+    }
+}
+
+// `03:4A2F` for ROM, plain `C000` for everything else
+pub fn bank_address(address: u16, bank: u16) -> String {
+    match bank_of(address, bank) {
+        Some(bank) => format!("{:02X}:{:04X}", bank, address),
+        None => format!("{:04X}", address),
+    }
+}
+
+// Where a ROM address lives in the ROM file
+pub fn rom_offset(address: u16, bank: u16) -> usize {
+    match bank_of(address, bank) {
+        Some(0) | None => address as usize,
+        Some(bank) => bank as usize * ROM_BANK_SIZE + address as usize - ROM_BANK_SIZE,
+    }
+}
+
+// Where control can go after the instruction at `address`, besides falling through
+pub fn jump_target(instruction: &Instruction, address: u16) -> Option<u16> {
+    match (instruction.mnemonic, instruction.operands.last()) {
+        (Mnemonic::Jr, Some(Operand::Relative(offset))) => {
+            Some(address.wrapping_add(instruction.length as u16).wrapping_add(*offset as u16))
+        }
+        (Mnemonic::Jp | Mnemonic::Call, Some(Operand::Imm16(target))) => Some(*target),
+        (Mnemonic::Rst, Some(Operand::Vector(vector))) => Some(*vector as u16),
+        _ => None,
+    }
+}
+
+// Names for ROM addresses, keyed by bank so the same address in different banks can differ
+#[derive(Default, Clone)]
+pub struct Labels {
+    names: HashMap<(u16, u16), String>,
+}
+
+impl Labels {
+    pub fn new() -> Self {
+        Labels::default()
+    }
+
+    pub fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        self.names.insert((bank, address), name.to_string());
+    }
+
+    pub fn get(&self, bank: u16, address: u16) -> Option<&str> {
+        self.names.get(&(bank, address)).map(|name| name.as_str())
+    }
+
+    // Look up an address as seen from code running in `bank`
+    pub fn resolve(&self, address: u16, bank: u16) -> Option<&str> {
+        match bank_of(address, bank) {
+            Some(bank) => self.get(bank, address),
+            None => self.get(0, address), // RAM and HRAM symbols are stored against bank 0
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<(u16, u16)> {
+        self.names.iter().find(|(_, label)| label.as_str() == name).map(|(key, _)| *key)
+    }
+
+    // The closest name at or before an address in the same bank, and how far past it the address is
+    pub fn nearest(&self, address: u16, bank: u16) -> Option<(&str, u16)> {
+        let bank = bank_of(address, bank).unwrap_or(0);
+        self.names.iter()
+            .filter(|((label_bank, label_address), _)| {
+                *label_bank == bank && *label_address <= address && region(*label_address) == region(address)
+            })
+            .max_by_key(|((_, label_address), _)| *label_address)
+            .map(|((_, label_address), name)| (name.as_str(), address - label_address))
+    }
+
+    // `03:4A2F MyFunc`, or `03:4A31 MyFunc+2` inside it, for call stacks and status lines
+    pub fn describe(&self, address: u16, bank: u16) -> String {
+        match self.nearest(address, bank) {
+            Some((name, 0)) => format!("{} {}", bank_address(address, bank), name),
+            Some((name, offset)) => format!("{} {}+{}", bank_address(address, bank), name, offset),
+            None => bank_address(address, bank),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // Name every ROM jump and call target in the lines that doesn't have a name yet
+    pub fn generate(&mut self, lines: &[Line]) {
+        for line in lines {
+            let Some(target) = jump_target(&line.instruction, line.address) else { continue };
+            let Some(bank) = bank_of(target, line.bank) else { continue };
+            let prefix = match line.instruction.mnemonic {
+                Mnemonic::Call => "Call",
+                Mnemonic::Rst => "Rst",
+                _ => "Jump",
+            };
+            self.names.entry((bank, target)).or_insert_with(|| format!("{}_{:02X}_{:04X}", prefix, bank, target));
+        }
+    }
+}
+
+// The instruction's text with targets, RAM addresses and IO registers given names
+pub fn symbolic(instruction: &Instruction, address: u16, bank: u16, labels: &Labels) -> String {
+    if instruction.mnemonic == Mnemonic::Illegal {
+        return instruction.to_string();
+    }
+    let name = |target: u16| match labels.resolve(target, bank) {
+        Some(label) => label.to_string(),
+        None => format!("${:04X}", target),
+    };
+    let operands: Vec<String> = instruction.operands.iter().map(|operand| match operand {
+        Operand::Relative(_) => name(jump_target(instruction, address).unwrap()),
+        Operand::Imm16(target) if matches!(instruction.mnemonic, Mnemonic::Jp | Mnemonic::Call) => name(*target),
+        Operand::Address(target) => match register_name(*target) {
+            Some(register) => format!("[{}]", register),
+            None => format!("[{}]", name(*target)),
+        },
+        Operand::HighAddress(low) => match register_name(0xFF00 | *low as u16) {
+            Some(register) => format!("[{}]", register),
+            None => format!("[{}]", name(0xFF00 | *low as u16)),
+        },
+        _ => operand.to_string(),
+    }).collect();
+    if operands.is_empty() {
+        instruction.mnemonic.to_string()
+    } else {
+        format!("{} {}", instruction.mnemonic, operands.join(", "))
+    }
+}
+
+pub struct Line {
+    pub bank: u16,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+}
+
+impl Line {
+    pub fn text(&self, labels: &Labels) -> String {
+        symbolic(&self.instruction, self.address, self.bank, labels)
+    }
+
+    // `03:4A2F  CD 50 01  CALL MyFunc`, preceded by a label line if the address has one
+    pub fn display<'a>(&'a self, labels: &'a Labels) -> impl fmt::Display + 'a {
+        DisplayLine { line: self, labels }
+    }
+}
+
+struct DisplayLine<'a> {
+    line: &'a Line,
+    labels: &'a Labels,
+}
+
+impl fmt::Display for DisplayLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = self.line;
+        if let Some(label) = self.labels.resolve(line.address, line.bank) {
+            writeln!(f, "{}:", label)?;
+        }
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "{:<9}  {:<8}  {}", bank_address(line.address, line.bank), bytes.join(" "), line.text(self.labels))
+    }
+}
+
+// Linear sweep from `from` up to (not including) `to`, reading through `read`
+pub fn disassemble(read: &dyn Fn(u16) -> u8, from: u16, to: u16, bank: u16) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut address = from as u32;
+    while address < to as u32 {
+        let bytes: Vec<u8> = (0..3).map(|offset| read((address as u16).wrapping_add(offset))).collect();
+        let instruction = decode_at(&bytes);
+        let length = instruction.length as usize;
+        lines.push(Line { bank, address: address as u16, bytes: bytes[..length].to_vec(), instruction });
+        address += length as u32;
+    }
+    lines
+}
+
+// Disassemble a whole ROM bank straight from the ROM file
+pub fn disassemble_bank(rom: &[u8], bank: u16) -> Vec<Line> {
+    let from: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let size = rom.len().saturating_sub(bank as usize * ROM_BANK_SIZE).min(ROM_BANK_SIZE); // Small ROMs end early
+    let to = from + size as u16;
+    let read = |address: u16| rom.get(rom_offset(address, bank)).copied().unwrap_or(0);
+    disassemble(&read, from, to, bank)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_at;
+    use crate::disasm::{bank_address, disassemble_bank, rom_offset, symbolic, Labels};
+
+    #[test]
+    fn register_and_relative_targets() {
+        let labels = Labels::new();
+        assert_eq!(symbolic(&decode_at(&[0xE0, 0x40]), 0x150, 0, &labels), "LDH [rLCDC], A");
+        assert_eq!(symbolic(&decode_at(&[0xEA, 0xFF, 0xFF]), 0x150, 0, &labels), "LD [rIE], A");
+        assert_eq!(symbolic(&decode_at(&[0x18, 0xFE]), 0x4A2F, 3, &labels), "JR $4A2F");
+        assert_eq!(symbolic(&decode_at(&[0x20, 0x03]), 0x150, 0, &labels), "JR NZ, $0155");
+    }
+
+    #[test]
+    fn banked_labels() {
+        let mut rom = vec![0; 0x10000];
+        let call = rom_offset(0x4000, 3);
+        rom[call..call + 3].copy_from_slice(&[0xCD, 0x2F, 0x4A]); // CALL $4A2F
+        let lines = disassemble_bank(&rom, 3);
+        let mut labels = Labels::new();
+        labels.generate(&lines);
+        assert_eq!(labels.get(3, 0x4A2F), Some("Call_03_4A2F"));
+        assert_eq!(lines[0].text(&labels), "CALL Call_03_4A2F");
+        assert_eq!(bank_address(lines[0].address, lines[0].bank), "03:4000");
+        assert_eq!(lines[0].display(&labels).to_string(), "03:4000    CD 2F 4A  CALL Call_03_4A2F");
+    }
+}
@@ -1,39 +1,31 @@
-use super::cpu::{Cpu, NORMAL_TIMINGS, CB_TIMINGS};
-use super::decode::decode;
+use super::cpu::{Cpu, Fault, NORMAL_TIMINGS, CB_TIMINGS};
+use crate::bus::Bus;
+use super::decode::{decode, Mnemonic};
 use log::warn;
 use crate::flags::Flags;
-use crate::{word_from, LOGGING_ENABLED, set_bit, unset_bit, bytes_from};
+use crate::{word_from, set_bit, unset_bit, bytes_from};
+use crate::callstack::EntryKind;
 use crate::cpu::Status::InfiniteLoop;
 use crate::registers::{R8, R16};
 
-fn op_implemented(cpu: &Cpu) {
-    if LOGGING_ENABLED && !cpu.mmu.bootrom_mapped { // TODO: Remove debug code
-        // println!("I PC: {:04x} {} [A:{:02X} F:{}] [B:{:02X} C:{:02X}] [D:{:02X} E:{:02X}] [H:{:02X} L:{:02X}] [SP:{:04X}] |",
-        //     cpu.reg.pc, decode(cpu).expect("Unknown opcode"),
-        //     cpu.reg.a, cpu.reg.f.to_string(), cpu.reg.b, cpu.reg.c, cpu.reg.d, cpu.reg.e, cpu.reg.h, cpu.reg.l, cpu.reg.sp,
-        // );
-        // println!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
-        //     cpu.reg.a, cpu.reg.f.as_u8(), cpu.reg.b, cpu.reg.c, cpu.reg.d, cpu.reg.e, cpu.reg.h, cpu.reg.l, cpu.reg.sp, cpu.reg.pc,
-        //     cpu.get_op(0), cpu.get_op(1), cpu.get_op(2), cpu.get_op(3)
-        // );
-    }
-}
-
-fn op_unimplemented(cpu: &mut Cpu) {
-    if LOGGING_ENABLED { // TODO: Remove debug code
-        warn!("U PC: {:04x} {} [A:{:02X} F:{}] [B:{:02X} C:{:02X}] [D:{:02X} E:{:02X}] [H:{:02X} L:{:02X}] [SP:{:04X}] |",
-            cpu.reg.pc, decode(cpu).expect(&format!("Unknown opcode : {:02X}", cpu.opcode)),
-            cpu.reg.a, cpu.reg.f.to_string(), cpu.reg.b, cpu.reg.c, cpu.reg.d, cpu.reg.e, cpu.reg.h, cpu.reg.l, cpu.reg.sp,
-        );
-    }
+fn op_unimplemented<B: Bus>(cpu: &mut Cpu<B>) {
+    let instruction = decode(cpu);
+    cpu.fault = Some(match instruction.mnemonic {
+        Mnemonic::Illegal => Fault::Illegal { pc: cpu.reg.pc, opcode: cpu.opcode },
+        _ => Fault::Unimplemented { pc: cpu.reg.pc, opcode: cpu.opcode, prefixed: cpu.cb_prefix },
+    });
+    warn!("U PC: {:04x} {} [A:{:02X} F:{}] [B:{:02X} C:{:02X}] [D:{:02X} E:{:02X}] [H:{:02X} L:{:02X}] [SP:{:04X}] |",
+        cpu.reg.pc, instruction,
+        cpu.reg.a, cpu.reg.f.to_string(), cpu.reg.b, cpu.reg.c, cpu.reg.d, cpu.reg.e, cpu.reg.h, cpu.reg.l, cpu.reg.sp,
+    );
     cpu._tmp_warn_count += 1;
 }
 
 #[allow(unreachable_patterns)]
-pub fn execute(cpu: &mut Cpu) {
+pub fn execute<B: Bus>(cpu: &mut Cpu<B>) {
     if cpu.opcode == 0xCB {
         cpu.cb_prefix = true;
-        cpu.opcode = cpu.mmu.get(cpu.reg.pc + 1);
+        cpu.opcode = cpu.mmu.read(cpu.reg.pc + 1);
         cpu.cycles = CB_TIMINGS[cpu.opcode as usize];
         cpu.advance_pc = 2; // Every CB instruction is 2 bytes
 
@@ -41,7 +33,6 @@ pub fn execute(cpu: &mut Cpu) {
         let reg_no = (cpu.opcode & 0x0F) % 8;
         let reg = R8::from_spec(reg_no);
 
-        op_implemented(cpu);
         match cpu.opcode {
             0x00..=0x07 => cpu.rlc(reg), // RLC
             0x08..=0x0F => cpu.rrc(reg), // RRC
@@ -71,12 +62,10 @@ pub fn execute(cpu: &mut Cpu) {
         cpu.cycles = NORMAL_TIMINGS[cpu.opcode as usize];
         match cpu.opcode {
             0x76 => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 cpu.halt()
             }, // HALT
             0x40..=0x7F => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let reg_1_no = (cpu.opcode - 0x40) / 0x08;
                 let reg_2_no = (cpu.opcode & 0x0F) % 8;
@@ -84,7 +73,6 @@ pub fn execute(cpu: &mut Cpu) {
                 cpu.set_reg(reg_1_no, value);
             }, // LD r,r
             0x80..=0xBF => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let op_no = (cpu.opcode - 0x80) / 0x08;
                 let reg_2_no = (cpu.opcode & 0x0F) % 8;
@@ -102,7 +90,6 @@ pub fn execute(cpu: &mut Cpu) {
                 };
             }, // ARITHMETIC r,r
             0xC6 | 0xD6 | 0xE6 | 0xF6 | 0xCE | 0xDE | 0xEE | 0xFE => {
-                op_implemented(cpu);
                 cpu.advance_pc = 2;
 
                 let d8 = cpu.get_op(1);
@@ -119,7 +106,6 @@ pub fn execute(cpu: &mut Cpu) {
                 }
             }, // ARITHMETIC r,d8
             0xC2 | 0xD2 | 0xCA | 0xDA => {
-                op_implemented(cpu);
                 cpu.advance_pc = 3;
 
                 let addr = cpu.get_d16();
@@ -128,6 +114,7 @@ pub fn execute(cpu: &mut Cpu) {
                         if !cpu.reg.f.zero {
                             cpu.advance_pc = 0;
                             cpu.cycles = 4;
+                            cpu.mmu.idle();
                             cpu.reg.pc = addr;
                         }
                     },
@@ -135,6 +122,7 @@ pub fn execute(cpu: &mut Cpu) {
                         if !cpu.reg.f.carry {
                             cpu.advance_pc = 0;
                             cpu.cycles = 4;
+                            cpu.mmu.idle();
                             cpu.reg.pc = addr;
                         }
                     },
@@ -142,6 +130,7 @@ pub fn execute(cpu: &mut Cpu) {
                         if cpu.reg.f.zero {
                             cpu.advance_pc = 0;
                             cpu.cycles = 4;
+                            cpu.mmu.idle();
                             cpu.reg.pc = addr;
                         }
                     },
@@ -149,6 +138,7 @@ pub fn execute(cpu: &mut Cpu) {
                         if cpu.reg.f.carry {
                             cpu.advance_pc = 0;
                             cpu.cycles = 4;
+                            cpu.mmu.idle();
                             cpu.reg.pc = addr;
                         }
                     },
@@ -156,13 +146,11 @@ pub fn execute(cpu: &mut Cpu) {
                 }
             }, // CONDITIONAL JP
             0x01 | 0x11 | 0x21 | 0x31 => {
-                op_implemented(cpu);
                 cpu.advance_pc = 3;
-                let word = word_from(cpu.get_op(2), cpu.get_op(1));
+                let word = cpu.get_d16();
                 cpu.set_reg16_by_index((cpu.opcode & 0xF0) >> 4, word);
             }, // LD rr, d16
             0x02 | 0x12 | 0x22 | 0x32 | 0x0A | 0x1A | 0x2A | 0x3A => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let address = match cpu.opcode & 0xf0 {
                     0x00 => cpu.reg.bc(),
@@ -172,81 +160,76 @@ pub fn execute(cpu: &mut Cpu) {
                     _ => panic!("This pattern should be unreachable"),
                 };
                 if cpu.opcode & 0xf == 0x2 {
-                    cpu.mmu.set(address, cpu.reg.a);
+                    cpu.mmu.write(address, cpu.reg.a);
                 } else if cpu.opcode & 0xf == 0xA {
-                    cpu.reg.a = cpu.mmu.get(address);
+                    cpu.reg.a = cpu.mmu.read(address);
                 }
             } // LD (rr), a | LD A, (rr)
             0xE2 | 0xF2 => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let address = word_from(0xFF, cpu.reg.c);
                 match cpu.opcode { 
                     0xE2 => ld_mem_d8(cpu, address, cpu.reg.a),
-                    0xF2 => cpu.reg.a = cpu.mmu.get(address),
+                    0xF2 => cpu.reg.a = cpu.mmu.read(address),
                     _ => panic!("This pattern should be unreachable"),
                 }
             }
             0xC7 | 0xD7 | 0xE7 | 0xF7 | 0xCF | 0xDF | 0xEF | 0xFF => {
-                op_implemented(cpu);
                 cpu.advance_pc = 0; // Don't advance AFTER this instruction
                 cpu.push_word(cpu.reg.pc + 1); // Advance the return pointer by one
+                cpu.enter(EntryKind::Rst, cpu.reg.pc + 1, (cpu.opcode - 0xC7) as u16);
                 cpu.reg.pc = (cpu.opcode - 0xC7) as u16;
             }, // RST
             0x04 | 0x14 | 0x24 | 0x34 | 0x0C | 0x1C | 0x2C | 0x3C => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let index = (cpu.opcode - 0x04) / 8;
                 let reg = R8::from_spec(index);
                 cpu.inc(reg);
             }, // INC r
             0x03 | 0x13 | 0x23 | 0x33 => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let index = (cpu.opcode - 0x03) / 16;
                 let reg = R16::from_spec(index);
                 cpu.inc_rr(reg);
             }, // INC rr
             0x05 | 0x15 | 0x25 | 0x35 | 0x0D | 0x1D | 0x2D | 0x3D => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let index = (cpu.opcode - 0x05) / 8;
                 let reg = R8::from_spec(index);
                 cpu.dec(reg);
             }, // DEC r
             0x0B | 0x1B | 0x2B | 0x3B => {
-                op_implemented(cpu);
                 cpu.advance_pc = 1;
                 let index = (cpu.opcode - 0x0B) / 16;
                 let reg = R16::from_spec(index);
                 cpu.dec_rr(reg);
             }, // INC rr
             0xC4 | 0xD4 | 0xCC | 0xDC => {
-                op_implemented(cpu);
                 cpu.advance_pc = 3;
+                let address = cpu.get_d16(); // Read whether or not the call is taken
                 match cpu.opcode {
                     0xC4 => {
                         if !cpu.reg.f.zero {
                             cpu.cycles = 6;
-                            call_a16(cpu);
+                            call_a16(cpu, address);
                         }
                     },
                     0xD4 => {
                         if !cpu.reg.f.carry {
                             cpu.cycles = 6;
-                            call_a16(cpu);
+                            call_a16(cpu, address);
                         }
                     },
                     0xCC => {
                         if cpu.reg.f.zero {
                             cpu.cycles = 6;
-                            call_a16(cpu);
+                            call_a16(cpu, address);
                         }
                     },
                     0xDC => {
                         if cpu.reg.f.carry {
                             cpu.cycles = 6;
-                            call_a16(cpu);
+                            call_a16(cpu, address);
                         }
                     },
                     _ => ()
@@ -312,14 +295,14 @@ pub fn execute(cpu: &mut Cpu) {
     }
 }
 
-fn add_a_u8(cpu: &mut Cpu, byte: u8) {
+fn add_a_u8<B: Bus>(cpu: &mut Cpu<B>, byte: u8) {
     cpu.reg.f.compute_half_carry_add(cpu.reg.a, byte);
     (cpu.reg.a, cpu.reg.f.carry) = u8::overflowing_add(cpu.reg.a, byte);
     cpu.reg.f.sub = false;
     cpu.reg.f.zero = cpu.reg.a == 0;
 }
 
-fn adc_a_u8(cpu: &mut Cpu, byte: u8) {
+fn adc_a_u8<B: Bus>(cpu: &mut Cpu<B>, byte: u8) {
     let cy = cpu.reg.f.carry as u8;
     let (byte_plus_cy, c1) = u8::overflowing_add(byte, cy);
     let (result, c2) = u8::overflowing_add(cpu.reg.a, byte_plus_cy);
@@ -332,7 +315,8 @@ fn adc_a_u8(cpu: &mut Cpu, byte: u8) {
     cpu.reg.f.half_carry = h1 || h2;
 }
 
-fn add_hl_u16(cpu: &mut Cpu, word: u16) {
+fn add_hl_u16<B: Bus>(cpu: &mut Cpu<B>, word: u16) {
+    cpu.mmu.idle(); // The high byte is added in a second M-cycle
     cpu.reg.f.compute_half_carry_add_u16(cpu.reg.hl(), word);
     let (result, carry) = cpu.reg.hl().overflowing_add(word);
     cpu.reg.set_hl(result);
@@ -340,36 +324,44 @@ fn add_hl_u16(cpu: &mut Cpu, word: u16) {
     cpu.reg.f.sub = false;
 }
 
-fn sub_u8(cpu: &mut Cpu, byte: u8) {
+fn sub_u8<B: Bus>(cpu: &mut Cpu<B>, byte: u8) {
     cpu.reg.f.compute_half_carry_sub(cpu.reg.a, byte);
     (cpu.reg.a, cpu.reg.f.carry) = u8::overflowing_sub(cpu.reg.a, byte);
     cpu.reg.f.sub = true;
     cpu.reg.f.zero = cpu.reg.a == 0;
 }
 
-fn pop_word(cpu: &mut Cpu) -> u16 {
-    let left = cpu.mmu.get(cpu.reg.sp + 1);
-    let right = cpu.mmu.get(cpu.reg.sp);
+fn pop_word<B: Bus>(cpu: &mut Cpu<B>) -> u16 {
+    let right = cpu.mmu.read(cpu.reg.sp);
+    let left = cpu.mmu.read(cpu.reg.sp + 1);
     cpu.reg.sp += 2;
     word_from(left, right)
 }
 
-fn call_a16(cpu: &mut Cpu) {
+// The address has already been read, the hardware does that before pushing
+fn call_a16<B: Bus>(cpu: &mut Cpu<B>, address: u16) {
     // Store PC on stack
-    cpu.push_word(cpu.reg.pc + cpu.advance_pc as u16);
+    let return_address = cpu.reg.pc + cpu.advance_pc as u16;
+    cpu.push_word(return_address);
     cpu.advance_pc = 0;
     // Set PC to address
-    let left = cpu.get_op(1);
-    let right = cpu.get_op(2);
-    cpu.reg.pc = word_from(right, left);
+    cpu.enter(EntryKind::Call, return_address, address);
+    cpu.reg.pc = address;
+}
+
+// RET and RETI, the shadow call stack has to see SP before the pop
+fn return_from<B: Bus>(cpu: &mut Cpu<B>) {
+    cpu.calls.leave(cpu.reg.sp);
+    cpu.reg.pc = pop_word(cpu);
+    cpu.mmu.idle(); // Setting PC
 }
 
 fn ld_d8(reg: &mut u8, byte: u8) {
     *reg = byte;
 }
 
-fn ld_mem_d8(cpu: &mut Cpu, address: u16, byte: u8) {
-    cpu.mmu.set(address, byte);
+fn ld_mem_d8<B: Bus>(cpu: &mut Cpu<B>, address: u16, byte: u8) {
+    cpu.mmu.write(address, byte);
 }
 
 fn rl_d8(reg: &mut u8, flags: &mut Flags) {
@@ -402,105 +394,91 @@ pub fn srl(reg: &mut u8, flags: &mut Flags) {
     flags.carry = b0 != 0;
 }
 
-fn execute_00(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_00<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
 } // NOP  [-/-/-/-]
-fn execute_06(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_06<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_d8(&mut cpu.reg.b, byte);
 } // LD B d8 [-/-/-/-]
-fn execute_07(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_07<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.rlc(R8::A);
     cpu.reg.f.zero = false;
 } // RLCA  [0/0/0/C]
-fn execute_08(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_08<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 3;
     let addr = cpu.get_d16();
     let (hi, lo) = bytes_from(cpu.reg.sp);
-    cpu.mmu.set(addr, lo);
-    cpu.mmu.set(addr + 1, hi);
+    cpu.mmu.write(addr, lo);
+    cpu.mmu.write(addr + 1, hi);
 } // LD (a16) SP [-/-/-/-]
-fn execute_09(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_09<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     add_hl_u16(cpu, cpu.reg.bc());
 } // ADD HL BC [-/0/H/C]
-fn execute_0e(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_0e<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
-    let byte = cpu.mmu.get(cpu.reg.pc + 1);
+    let byte = cpu.mmu.read(cpu.reg.pc + 1);
     ld_d8(&mut cpu.reg.c, byte);
 } // LD C d8 [-/-/-/-]
-fn execute_0f(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_0f<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.rrc(R8::A);
     cpu.reg.f.zero = false;
 } // RRCA  [0/0/0/C]
-fn execute_10(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_10<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
+    cpu.mmu.speed_switch();
 } // STOP 0  [-/-/-/-]
-fn execute_16(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_16<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_d8(&mut cpu.reg.d, byte);
 } // LD D d8 [-/-/-/-]
-fn execute_17(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_17<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     rl_d8(&mut cpu.reg.a, &mut cpu.reg.f);
 } // RLA  [0/0/0/C]
-fn execute_18(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_18<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let s8 = cpu.get_op(1) as i8;
     cpu.advance_pc += s8 as i16;
+    cpu.mmu.idle();
     if cpu.advance_pc == 0 {
         cpu.status = InfiniteLoop;
     }
 } // JR r8  [-/-/-/-]
-fn execute_19(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_19<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     add_hl_u16(cpu, cpu.reg.de());
 } // ADD HL DE [-/0/H/C]
-fn execute_1e(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_1e<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_d8(&mut cpu.reg.e, byte);
 } // LD E d8 [-/-/-/-]
-fn execute_1f(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_1f<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     rr(&mut cpu.reg.a, &mut cpu.reg.f);
     cpu.reg.f.zero = false;
 } // RRA  [0/0/0/C]
-fn execute_20(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_20<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
+    let s8 = cpu.get_op(1) as i8; // Read whether or not the jump is taken
     if !cpu.reg.f.zero {
-        let s8 = cpu.get_op(1) as i8;
         cpu.advance_pc += s8 as i16;
         cpu.cycles = 3;
+        cpu.mmu.idle();
     }
 } // JR NZ r8 [-/-/-/-]
-fn execute_26(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_26<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_d8(&mut cpu.reg.h, byte);
 } // LD H d8 [-/-/-/-]
-fn execute_27(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_27<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     let mut correction = 0;
     let a = cpu.reg.a;
@@ -525,223 +503,199 @@ fn execute_27(cpu: &mut Cpu) {
     cpu.reg.f.zero = cpu.reg.a == 0;
     cpu.reg.f.half_carry = false;
 } // DAA  [Z/-/0/C]
-fn execute_28(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_28<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
+    let s8 = cpu.get_op(1) as i8; // Read whether or not the jump is taken
     if cpu.reg.f.zero {
-        let s8 = cpu.get_op(1) as i8;
         cpu.advance_pc += s8 as i16;
         cpu.cycles = 3;
+        cpu.mmu.idle();
     }
 } // JR Z r8 [-/-/-/-]
-fn execute_29(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_29<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     add_hl_u16(cpu, cpu.reg.hl());
 } // ADD HL HL [-/0/H/C]
-fn execute_2e(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_2e<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_d8(&mut cpu.reg.l, byte);
 } // LD L d8 [-/-/-/-]
-fn execute_2f(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_2f<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.reg.a = !cpu.reg.a;
     cpu.reg.f.sub = true;
     cpu.reg.f.half_carry = true;
 } // CPL  [-/1/1/-]
-fn execute_30(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_30<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
+    let s8 = cpu.get_op(1) as i8; // Read whether or not the jump is taken
     if !cpu.reg.f.carry {
-        let s8 = cpu.get_op(1) as i8;
         cpu.advance_pc += s8 as i16;
         cpu.cycles = 3;
+        cpu.mmu.idle();
     }
 } // JR NC r8 [-/-/-/-]
-fn execute_36(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_36<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_mem_d8(cpu, cpu.reg.hl(), byte);
 } // LD (HL) d8 [-/-/-/-]
-fn execute_37(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_37<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.reg.f.sub = false;
     cpu.reg.f.half_carry = false;
     cpu.reg.f.carry = true;
 } // SCF  [-/0/0/1]
-fn execute_38(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_38<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
+    let s8 = cpu.get_op(1) as i8; // Read whether or not the jump is taken
     if cpu.reg.f.carry {
+        cpu.advance_pc += s8 as i16;
         cpu.cycles = 3;
-        cpu.advance_pc += cpu.get_op(1) as i16;
+        cpu.mmu.idle();
     }
 } // JR C r8 [-/-/-/-]
-fn execute_39(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_39<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     add_hl_u16(cpu, cpu.reg.sp);
 } // ADD HL SP [-/0/H/C]
-fn execute_3e(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_3e<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let byte = cpu.get_op(1);
     ld_d8(&mut cpu.reg.a, byte);
 } // LD A d8 [-/-/-/-]
-fn execute_3f(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_3f<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.reg.f.sub = false;
     cpu.reg.f.half_carry = false;
     cpu.reg.f.carry = !cpu.reg.f.carry;
 } // CCF  [-/0/0/C]
-fn execute_c0(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_c0<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
+    cpu.mmu.idle(); // Checking the condition
     if !cpu.reg.f.zero {
         cpu.advance_pc = 0;
         cpu.cycles = 5;
-        cpu.reg.pc = pop_word(cpu);
+        return_from(cpu);
     }
 } // RET NZ  [-/-/-/-]
-fn execute_c1(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_c1<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     let word = pop_word(cpu);
     cpu.reg.set_bc(word);
 } // POP BC  [-/-/-/-]
-fn execute_c3(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_c3<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 0;
-    let (left, right) = (cpu.get_op(1), cpu.get_op(2));
-    cpu.reg.pc = word_from(right, left);
+    cpu.reg.pc = cpu.get_d16();
+    cpu.mmu.idle();
 } // JP a16  [-/-/-/-]
-fn execute_c5(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_c5<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.push_word(cpu.reg.bc());
 } // PUSH BC  [-/-/-/-]
-fn execute_c8(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_c8<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
+    cpu.mmu.idle(); // Checking the condition
     if cpu.reg.f.zero {
         cpu.advance_pc = 0;
         cpu.cycles = 5;
-        cpu.reg.pc = pop_word(cpu);
+        return_from(cpu);
     }
 } // RET Z  [-/-/-/-]
-fn execute_c9(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_c9<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 0;
-    cpu.reg.pc = pop_word(cpu);
+    return_from(cpu);
 } // RET  [-/-/-/-]
-fn execute_cb(cpu: &mut Cpu) {
+fn execute_cb<B: Bus>(cpu: &mut Cpu<B>) {
     op_unimplemented(cpu);
     cpu.advance_pc = 1;
 } // PREFIX CB  [-/-/-/-]
-fn execute_cd(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_cd<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 3;
-    call_a16(cpu);
+    let address = cpu.get_d16();
+    call_a16(cpu, address);
 } // CALL a16  [-/-/-/-]
-fn execute_d0(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_d0<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
+    cpu.mmu.idle(); // Checking the condition
     if !cpu.reg.f.carry {
         cpu.advance_pc = 0;
         cpu.cycles = 5;
-        cpu.reg.pc = pop_word(cpu);
+        return_from(cpu);
     }
 } // RET NC  [-/-/-/-]
-fn execute_d1(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_d1<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     let word = pop_word(cpu);
     cpu.reg.set_de(word);
 } // POP DE  [-/-/-/-]
-fn execute_d5(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_d5<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.push_word(cpu.reg.de());
 } // PUSH DE  [-/-/-/-]
-fn execute_d8(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_d8<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
+    cpu.mmu.idle(); // Checking the condition
     if cpu.reg.f.carry {
         cpu.advance_pc = 0;
         cpu.cycles = 5;
-        cpu.reg.pc = pop_word(cpu);
+        return_from(cpu);
     }
 } // RET C  [-/-/-/-]
-fn execute_d9(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_d9<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 0;
     cpu.ime = true;
-    cpu.reg.pc = pop_word(cpu);
+    return_from(cpu);
 } // RETI  [-/-/-/-]
-fn execute_e0(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_e0<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let address = word_from(0xFF, cpu.get_op(1));
     ld_mem_d8(cpu, address, cpu.reg.a);
 } // LDH (a8) A [-/-/-/-]
-fn execute_e1(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_e1<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     let word = pop_word(cpu);
     cpu.reg.set_hl(word);
 } // POP HL  [-/-/-/-]
-fn execute_e5(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_e5<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.push_word(cpu.reg.hl());
 } // PUSH HL  [-/-/-/-]
-fn execute_e8(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_e8<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let s8 = cpu.get_op(1) as i8;
     cpu.add_sp_s8(s8);
+    cpu.mmu.idle();
 } // ADD SP r8 [0/0/H/C]
-fn execute_e9(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_e9<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 0;
     cpu.reg.pc = cpu.reg.hl();
 } // JP (HL)  [-/-/-/-]
-fn execute_ea(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_ea<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 3;
-    let address = word_from(cpu.get_op(2), cpu.get_op(1));
+    let address = cpu.get_d16();
     ld_mem_d8(cpu, address, cpu.reg.a);
 } // LD (a16) A [-/-/-/-]
-fn execute_f0(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_f0<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let address = word_from(0xFF, cpu.get_op(1));
-    cpu.reg.a = cpu.mmu.get(address);
+    cpu.reg.a = cpu.mmu.read(address);
 } // LDH A (a8) [-/-/-/-]
-fn execute_f1(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_f1<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     let word = pop_word(cpu);
     cpu.reg.set_af(word);
 } // POP AF  [Z/N/H/C]
-fn execute_f3(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_f3<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.ime = false;
 } // DI  [-/-/-/-]
-fn execute_f5(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_f5<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.push_word(cpu.reg.af());
 } // PUSH AF  [-/-/-/-]
-fn execute_f8(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_f8<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 2;
     let old_sp = cpu.reg.sp;
     let s8 = cpu.get_op(1) as i8;
@@ -749,19 +703,17 @@ fn execute_f8(cpu: &mut Cpu) {
     cpu.reg.set_hl(cpu.reg.sp);
     cpu.reg.sp = old_sp;
 } // LD HL SP+r8 [0/0/H/C]
-fn execute_f9(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_f9<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.reg.sp = cpu.reg.hl();
+    cpu.mmu.idle();
 } // LD SP HL [-/-/-/-]
-fn execute_fa(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_fa<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 3;
-    let address = word_from(cpu.get_op(2), cpu.get_op(1));
-    ld_d8(&mut cpu.reg.a, cpu.mmu.get(address));
+    let address = cpu.get_d16();
+    ld_d8(&mut cpu.reg.a, cpu.mmu.read(address));
 } // LD A (a16) [-/-/-/-]
-fn execute_fb(cpu: &mut Cpu) {
-    op_implemented(cpu);
+fn execute_fb<B: Bus>(cpu: &mut Cpu<B>) {
     cpu.advance_pc = 1;
     cpu.ime = true;
 } // EI  [-/-/-/-]
@@ -0,0 +1,327 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::cdl::{CDL_CODE, CDL_DATA, CDL_OPERAND};
+use crate::decode::{decode_at, Instruction, Mnemonic, Operand};
+use crate::disasm::{self, Labels, HARDWARE_REGISTERS, ROM_BANK_SIZE};
+
+/* Recursive descent export to RGBDS (0.6 or newer, which no longer pads HALT or auto-optimises LDH).
+ * Code is found by following control flow from the entry point and the RST/interrupt vectors,
+ * optionally seeded with a code/data log. Everything else is emitted as `DB` so the ROM
+ * reassembles byte for byte. */
+
+const ENTRY_POINTS: [(u16, &str); 14] = [
+    (0x0000, "Rst_00"), (0x0008, "Rst_08"), (0x0010, "Rst_10"), (0x0018, "Rst_18"),
+    (0x0020, "Rst_20"), (0x0028, "Rst_28"), (0x0030, "Rst_30"), (0x0038, "Rst_38"),
+    (0x0040, "VBlankInterrupt"), (0x0048, "LCDInterrupt"), (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"), (0x0060, "JoypadInterrupt"), (0x0100, "Entry"),
+];
+
+#[derive(PartialEq, Clone, Copy)]
+enum Mark {
+    Unknown,
+    Data,
+    Instruction, // First byte of an instruction
+    Operand,
+}
+
+pub struct Exporter<'a> {
+    rom: &'a [u8],
+    marks: Vec<Mark>,
+    pub labels: Labels,
+}
+
+impl<'a> Exporter<'a> {
+    pub fn new(rom: &'a [u8], cdl: Option<&[u8]>) -> Self {
+        let mut exporter = Exporter {
+            rom,
+            marks: vec![Mark::Unknown; rom.len()],
+            labels: Labels::new(),
+        };
+        if let Some(cdl) = cdl {
+            // Data reads win over the descent, so code never swallows a table that was seen being read
+            for (offset, flags) in cdl.iter().enumerate().take(rom.len()) {
+                if flags & CDL_DATA != 0 && flags & (CDL_CODE | CDL_OPERAND) == 0 {
+                    exporter.marks[offset] = Mark::Data;
+                }
+            }
+        }
+        for (address, name) in ENTRY_POINTS {
+            if (address as usize) < rom.len() {
+                exporter.trace(0, address);
+                if exporter.marks[address as usize] == Mark::Instruction {
+                    exporter.labels.insert(0, address, name);
+                }
+            }
+        }
+        if let Some(cdl) = cdl {
+            // Anything executed during play that the descent couldn't reach, e.g. through JP HL
+            for (offset, flags) in cdl.iter().enumerate().take(rom.len()) {
+                if flags & CDL_CODE != 0 && exporter.marks[offset] == Mark::Unknown {
+                    let bank = (offset / ROM_BANK_SIZE) as u16;
+                    let address = (offset % ROM_BANK_SIZE) as u16 + if bank == 0 { 0 } else { 0x4000 };
+                    exporter.trace(bank, address);
+                }
+            }
+        }
+        exporter.name_targets();
+        exporter
+    }
+
+    fn bank_count(&self) -> u16 {
+        self.rom.len().div_ceil(ROM_BANK_SIZE) as u16
+    }
+
+    // The bank a ROM address refers to from code in `bank`, if it can be known statically
+    fn resolve_bank(&self, address: u16, bank: u16) -> Option<u16> {
+        match disasm::bank_of(address, bank)? {
+            0 if address >= 0x4000 => if self.bank_count() == 2 { Some(1) } else { None }, // No MBC, bank 1 is fixed
+            bank => Some(bank),
+        }
+    }
+
+    // Follow control flow from an address, marking instructions as we go
+    fn trace(&mut self, bank: u16, address: u16) {
+        let mut pending = vec![(bank, address)];
+        while let Some((bank, mut address)) = pending.pop() {
+            loop {
+                let offset = disasm::rom_offset(address, bank);
+                if offset >= self.rom.len() || self.marks[offset] != Mark::Unknown {
+                    break;
+                }
+                let end = (offset + 3).min(self.rom.len());
+                let instruction = decode_at(&self.rom[offset..end]);
+                let length = instruction.length as usize;
+                // Stop at anything that can't be code: illegal opcodes, instructions running off the
+                // bank or into known bytes, and STOP without the padding byte RGBDS emits
+                let fits = offset + length <= self.rom.len()
+                    && (address as usize + length) <= if address < 0x4000 { 0x4000 } else { 0x8000 }
+                    && self.marks[offset + 1..offset + length].iter().all(|mark| *mark == Mark::Unknown);
+                let stop_padded = instruction.mnemonic != Mnemonic::Stop || self.rom.get(offset + 1) == Some(&0);
+                if instruction.mnemonic == Mnemonic::Illegal || !fits || !stop_padded {
+                    break;
+                }
+                self.marks[offset] = Mark::Instruction;
+                for mark in &mut self.marks[offset + 1..offset + length] {
+                    *mark = Mark::Operand;
+                }
+
+                if let Some(target) = disasm::jump_target(&instruction, address) {
+                    if let Some(target_bank) = self.resolve_bank(target, bank) {
+                        pending.push((target_bank, target));
+                    }
+                }
+                let ends_flow = match instruction.mnemonic {
+                    Mnemonic::Jp | Mnemonic::Jr | Mnemonic::Ret => !instruction.is_conditional(),
+                    Mnemonic::Reti => true,
+                    _ => false,
+                };
+                if ends_flow {
+                    break;
+                }
+                address = address.wrapping_add(length as u16);
+            }
+        }
+    }
+
+    // Label every jump and call target that landed on the start of an instruction
+    fn name_targets(&mut self) {
+        for bank in 0..self.bank_count() {
+            for (address, instruction) in self.instructions(bank) {
+                let Some(target) = disasm::jump_target(&instruction, address) else { continue };
+                let Some(target_bank) = self.resolve_bank(target, bank) else { continue };
+                let offset = disasm::rom_offset(target, target_bank);
+                if self.marks.get(offset) != Some(&Mark::Instruction) || self.labels.get(target_bank, target).is_some() {
+                    continue;
+                }
+                let prefix = if instruction.mnemonic == Mnemonic::Call { "Call" } else { "Jump" };
+                self.labels.insert(target_bank, target, &format!("{}_{:02X}_{:04X}", prefix, target_bank, target));
+            }
+        }
+    }
+
+    fn instructions(&self, bank: u16) -> Vec<(u16, Instruction)> {
+        let base: u16 = if bank == 0 { 0 } else { 0x4000 };
+        let start = bank as usize * ROM_BANK_SIZE;
+        let end = (start + ROM_BANK_SIZE).min(self.rom.len());
+        (start..end)
+            .filter(|offset| self.marks[*offset] == Mark::Instruction)
+            .map(|offset| (base + (offset - start) as u16, decode_at(&self.rom[offset..(offset + 3).min(self.rom.len())])))
+            .collect()
+    }
+
+    pub fn is_code(&self, offset: usize) -> bool {
+        matches!(self.marks.get(offset), Some(Mark::Instruction | Mark::Operand))
+    }
+
+    // The RGBDS source for one bank
+    pub fn bank_source(&self, bank: u16) -> String {
+        let base: u16 = if bank == 0 { 0 } else { 0x4000 };
+        let start = bank as usize * ROM_BANK_SIZE;
+        let end = (start + ROM_BANK_SIZE).min(self.rom.len());
+        let mut source = String::from("INCLUDE \"hardware.inc\"\n\n");
+        source += &if bank == 0 {
+            "SECTION \"ROM Bank $000\", ROM0[$0000]\n".to_string()
+        } else {
+            format!("SECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:X}]\n", bank, bank)
+        };
+
+        let mut data: Vec<u8> = vec![];
+        let flush = |source: &mut String, data: &mut Vec<u8>| {
+            for chunk in data.chunks(16) {
+                let bytes: Vec<String> = chunk.iter().map(|byte| format!("${:02X}", byte)).collect();
+                *source += &format!("    DB {}\n", bytes.join(", "));
+            }
+            data.clear();
+        };
+        let mut offset = start;
+        while offset < end {
+            let address = base + (offset - start) as u16;
+            if let Some(label) = self.labels.get(bank, address) {
+                flush(&mut source, &mut data);
+                source += &format!("\n{}::\n", label);
+            }
+            if self.marks[offset] != Mark::Instruction {
+                data.push(self.rom[offset]);
+                offset += 1;
+                continue;
+            }
+            flush(&mut source, &mut data);
+            let instruction = decode_at(&self.rom[offset..(offset + 3).min(end)]);
+            let length = instruction.length as usize;
+            if let Some(Operand::Relative(jump)) = instruction.operands.last() {
+                // A JR that wraps around the address space can't be written as a target RGBDS accepts
+                let target = address as i32 + length as i32 + *jump as i32;
+                if !(0..=0xFFFF).contains(&target) {
+                    data.extend_from_slice(&self.rom[offset..offset + length]);
+                    offset += length;
+                    continue;
+                }
+            }
+            source += &format!("    {}\n", self.instruction_source(&instruction, address, bank));
+            offset += length;
+        }
+        flush(&mut source, &mut data);
+        source
+    }
+
+    fn instruction_source(&self, instruction: &Instruction, address: u16, bank: u16) -> String {
+        // Bank 0 of a ROM without an MBC sees bank 1 at 0x4000, otherwise switchable targets stay numbers
+        let view = self.resolve_bank(0x4000, bank).unwrap_or(bank);
+        disasm::symbolic(instruction, address, view, &self.labels)
+    }
+
+    // Every file needed to rebuild the ROM, as (file name, contents)
+    pub fn files(&self, name: &str) -> Vec<(String, String)> {
+        let mut files = vec![];
+        let mut objects = vec![];
+        for bank in 0..self.bank_count() {
+            let file = format!("bank_{:03X}", bank);
+            files.push((format!("{}.asm", file), self.bank_source(bank)));
+            objects.push(format!("{}.o", file));
+        }
+
+        let mut hardware = String::from("; IO registers referenced by the disassembly, names from hardware.inc\n");
+        for (address, register) in HARDWARE_REGISTERS {
+            hardware += &format!("DEF {} EQU ${:04X}\n", register, address);
+        }
+        files.push(("hardware.inc".to_string(), hardware));
+
+        // The header is already in bank 0 so there's nothing to fix, and every byte is emitted so there's no padding
+        let makefile = format!(
+            "# Rebuilds {name}.gb byte for byte with RGBDS 0.6 or newer\n\
+             OBJS := {objects}\n\n\
+             {name}.gb: $(OBJS)\n\
+             \trgblink -n {name}.sym -o $@ $(OBJS)\n\n\
+             %.o: %.asm hardware.inc\n\
+             \trgbasm -o $@ $<\n\n\
+             clean:\n\
+             \trm -f $(OBJS) {name}.gb {name}.sym\n",
+            name = name, objects = objects.join(" "));
+        files.push(("Makefile".to_string(), makefile));
+        files
+    }
+}
+
+pub fn write(dir: &Path, files: &[(String, String)]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, contents) in files {
+        fs::write(dir.join(name), contents)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cdl::{CDL_CODE, CDL_DATA};
+    use crate::export::Exporter;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        for vector in (0x00..=0x60).step_by(8) {
+            rom[vector] = 0xC9; // RET
+        }
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // NOP / JP $0150
+        rom[0x150..0x158].copy_from_slice(&[0xCD, 0x00, 0x40, 0x18, 0xFB, 0xC9, 0x12, 0x34]); // CALL $4000 / JR $0150 / RET / data
+        rom[0x4000] = 0xC9; // RET
+        rom
+    }
+
+    #[test]
+    fn code_and_data() {
+        let rom = rom();
+        let exporter = Exporter::new(&rom, None);
+        assert!(exporter.is_code(0x150) && exporter.is_code(0x153) && exporter.is_code(0x4000));
+        assert!(!exporter.is_code(0x01) && !exporter.is_code(0x104)); // Padding and header
+        assert!(!exporter.is_code(0x155)); // The RET after JR is never reached
+        assert_eq!(exporter.labels.get(1, 0x4000), Some("Call_01_4000"));
+
+        let bank0 = exporter.bank_source(0);
+        assert!(bank0.contains("Entry::\n    NOP\n    JP Jump_00_0150\n"));
+        assert!(bank0.contains("Jump_00_0150::\n    CALL Call_01_4000\n    JR Jump_00_0150\n    DB $C9, $12, $34"));
+        assert!(exporter.bank_source(1).starts_with("INCLUDE \"hardware.inc\"\n\nSECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]\n"));
+    }
+
+    #[test]
+    fn code_data_log() {
+        let rom = rom();
+        let mut cdl = vec![0; rom.len()];
+        cdl[0x155] = CDL_CODE; // Reached through JP HL in play
+        cdl[0x4000] = CDL_DATA; // Read as a table
+        let exporter = Exporter::new(&rom, Some(&cdl));
+        assert!(exporter.is_code(0x155));
+        assert!(!exporter.is_code(0x4000));
+    }
+
+    #[test]
+    fn every_byte_is_emitted() {
+        let rom = rom();
+        let exporter = Exporter::new(&rom, None);
+        let source = exporter.bank_source(0);
+        let data_bytes: usize = source.lines()
+            .filter_map(|line| line.trim().strip_prefix("DB "))
+            .map(|bytes| bytes.split(", ").count())
+            .sum();
+        let code_bytes = (0..0x4000).filter(|offset| exporter.is_code(*offset)).count();
+        assert_eq!(data_bytes + code_bytes, 0x4000);
+    }
+
+    // Bank numbers past 255 keep their own sections and labels instead of wrapping onto bank 0
+    #[test]
+    fn more_than_256_banks() {
+        let mut rom = rom();
+        rom.resize(512 * 0x4000, 0x00);
+        let last = 0x1FF * 0x4000;
+        rom[last..last + 4].copy_from_slice(&[0xCD, 0x04, 0x40, 0xC9]); // CALL $4004 / RET
+        rom[last + 4] = 0xC9;
+        let mut cdl = vec![0; rom.len()];
+        cdl[last] = CDL_CODE;
+        let exporter = Exporter::new(&rom, Some(&cdl));
+        assert!(exporter.is_code(last + 4) && !exporter.is_code(0x4004));
+        assert_eq!(exporter.labels.get(0x1FF, 0x4004), Some("Call_1FF_4004"));
+        assert_eq!(exporter.labels.get(0xFF, 0x4004), None);
+        let source = exporter.bank_source(0x1FF);
+        assert!(source.starts_with("INCLUDE \"hardware.inc\"\n\nSECTION \"ROM Bank $1FF\", ROMX[$4000], BANK[$1FF]\n"));
+        assert!(source.contains("    CALL Call_1FF_4004\n"));
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt;
+use crate::disasm::Labels;
+use crate::system::System;
+
+/* A small expression language for breakpoint conditions and log-points, for example:
+ *   [HL] == $3C && A > 10 && LY == 144
+ * Numbers are decimal, `$3C` or `0x3C`. Names are registers (A~L, AF, BC, DE, HL, SP, PC), flags
+ * (ZF, NF, HF, CF), IME, LY, BANK (ROM bank), FRAME, HITS (times the breakpoint was reached) or
+ * symbols, which stand for their address. `[x]` reads the byte at x. Operators follow C. */
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Var {
+    A, B, C, D, E, F, H, L, AF, BC, DE, HL, SP, PC,
+    ZF, NF, HF, CF, Ime, Ly, Bank, Frame, Hits,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_uppercase().as_str() {
+            "A" => Var::A, "B" => Var::B, "C" => Var::C, "D" => Var::D,
+            "E" => Var::E, "F" => Var::F, "H" => Var::H, "L" => Var::L,
+            "AF" => Var::AF, "BC" => Var::BC, "DE" => Var::DE, "HL" => Var::HL,
+            "SP" => Var::SP, "PC" => Var::PC,
+            "ZF" => Var::ZF, "NF" => Var::NF, "HF" => Var::HF, "CF" => Var::CF,
+            "IME" => Var::Ime, "LY" => Var::Ly, "BANK" => Var::Bank, "FRAME" => Var::Frame, "HITS" => Var::Hits,
+            _ => return None,
+        })
+    }
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="], &["<=", ">=", "<", ">"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"],
+];
+
+#[derive(PartialEq, Clone, Debug)]
+enum Expr {
+    Number(i64),
+    Var(Var),
+    Memory(Box<Expr>),
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, system: &System, hits: u64) -> i64 {
+        let cpu = &system.cpu;
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Var(var) => match var {
+                Var::A => cpu.reg.a as i64,
+                Var::B => cpu.reg.b as i64,
+                Var::C => cpu.reg.c as i64,
+                Var::D => cpu.reg.d as i64,
+                Var::E => cpu.reg.e as i64,
+                Var::F => cpu.reg.f.as_u8() as i64,
+                Var::H => cpu.reg.h as i64,
+                Var::L => cpu.reg.l as i64,
+                Var::AF => cpu.reg.af() as i64,
+                Var::BC => cpu.reg.bc() as i64,
+                Var::DE => cpu.reg.de() as i64,
+                Var::HL => cpu.reg.hl() as i64,
+                Var::SP => cpu.reg.sp as i64,
+                Var::PC => cpu.reg.pc as i64,
+                Var::ZF => cpu.reg.f.zero as i64,
+                Var::NF => cpu.reg.f.sub as i64,
+                Var::HF => cpu.reg.f.half_carry as i64,
+                Var::CF => cpu.reg.f.carry as i64,
+                Var::Ime => cpu.ime as i64,
+                Var::Ly => cpu.mmu.get(0xFF44) as i64,
+                Var::Bank => cpu.mmu.rom_bank as i64,
+                Var::Frame => system.graphics.frame as i64,
+                Var::Hits => hits as i64,
+            },
+            Expr::Memory(address) => cpu.mmu.get(address.eval(system, hits) as u16) as i64,
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(system, hits);
+                match op {
+                    '-' => value.wrapping_neg(),
+                    '~' => !value,
+                    _ => (value == 0) as i64, // !
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(system, hits);
+                // Short circuit so `HL < $8000 && [HL] == 0` never reads where it shouldn't
+                match *op {
+                    "&&" if left == 0 => return 0,
+                    "||" if left != 0 => return 1,
+                    _ => (),
+                }
+                let right = right.eval(system, hits);
+                match *op {
+                    "||" | "&&" => (right != 0) as i64,
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "==" => (left == right) as i64,
+                    "!=" => (left != right) as i64,
+                    "<" => (left < right) as i64,
+                    "<=" => (left <= right) as i64,
+                    ">" => (left > right) as i64,
+                    ">=" => (left >= right) as i64,
+                    "<<" => left.wrapping_shl(right as u32),
+                    ">>" => left.wrapping_shr(right as u32),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" => left.checked_div(right).unwrap_or(0),
+                    _ => left.checked_rem(right).unwrap_or(0), // %
+                }
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let hex = rest.strip_prefix('$').or_else(|| rest.strip_prefix("0x"));
+        let length = if let Some(digits) = hex {
+            let end = digits.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(digits.len());
+            let value = i64::from_str_radix(&digits[..end], 16).map_err(|_| format!("Bad number at '{}'", rest))?;
+            tokens.push(Token::Number(value));
+            rest.len() - digits.len() + end
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            tokens.push(Token::Number(rest[..end].parse().map_err(|_| format!("Bad number at '{}'", rest))?));
+            end
+        } else if rest.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '.') {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            end
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else {
+            return Err(format!("Unexpected '{}'", rest.chars().next().unwrap()));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    labels: &'a Labels,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.peek() {
+            Some(Token::Symbol(found)) if *found == symbol => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(format!("Expected '{}'", symbol)),
+        }
+    }
+
+    // Precedence climbing over PRECEDENCE, one level per call
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Symbol(op)) = self.peek() {
+            let Some(op) = PRECEDENCE[level].iter().find(|candidate| *candidate == op) else { break };
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or("Unexpected end of expression")?;
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) => match Var::from_name(&name) {
+                Some(var) => Ok(Expr::Var(var)),
+                None => match self.labels.find(&name) {
+                    Some((_, address)) => Ok(Expr::Number(address as i64)),
+                    None => Err(format!("Unknown name '{}'", name)),
+                },
+            },
+            Token::Symbol(op @ ("-" | "!" | "~")) => {
+                let operand = self.unary()?;
+                Ok(Expr::Unary(op.chars().next().unwrap(), Box::new(operand)))
+            }
+            Token::Symbol("(") => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Symbol("[") => {
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(address)))
+            }
+            Token::Symbol(op) => Err(format!("Unexpected '{}'", op)),
+        }
+    }
+}
+
+// A parsed expression that remembers how it was written, for showing back in the debugger
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str, labels: &Labels) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0, labels };
+        let expr = parser.binary(0)?;
+        if parser.peek().is_some() {
+            return Err("Unexpected input after the expression".to_string());
+        }
+        Ok(Condition { source: text.trim().to_string(), expr })
+    }
+
+    pub fn eval(&self, system: &System, hits: u64) -> i64 {
+        self.expr.eval(system, hits)
+    }
+
+    pub fn is_true(&self, system: &System, hits: u64) -> bool {
+        self.eval(system, hits) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Text(String),
+    Hex(Condition),
+    Decimal(Condition),
+}
+
+// Log-point text, `{expr}` is replaced by the value in hex and `{expr:d}` in decimal
+#[derive(Clone, Debug)]
+pub struct LogMessage {
+    pub source: String,
+    pieces: Vec<Piece>,
+}
+
+impl LogMessage {
+    pub fn parse(text: &str, labels: &Labels) -> Result<Self, String> {
+        let mut pieces = vec![];
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            pieces.push(Piece::Text(rest[..start].to_string()));
+            let end = rest[start..].find('}').ok_or("Missing '}' in the message")? + start;
+            let inner = &rest[start + 1..end];
+            pieces.push(match inner.strip_suffix(":d") {
+                Some(expr) => Piece::Decimal(Condition::parse(expr, labels)?),
+                None => Piece::Hex(Condition::parse(inner, labels)?),
+            });
+            rest = &rest[end + 1..];
+        }
+        pieces.push(Piece::Text(rest.to_string()));
+        Ok(LogMessage { source: text.to_string(), pieces })
+    }
+
+    pub fn format(&self, system: &System, hits: u64) -> String {
+        self.pieces.iter().map(|piece| match piece {
+            Piece::Text(text) => text.clone(),
+            Piece::Decimal(expr) => expr.eval(system, hits).to_string(),
+            Piece::Hex(expr) => match expr.eval(system, hits) {
+                value @ 0..=0xFF => format!("{:02X}", value),
+                value @ 0..=0xFFFF => format!("{:04X}", value),
+                value => format!("{:X}", value),
+            },
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::Labels;
+    use crate::expr::{Condition, LogMessage};
+    use crate::model::Model;
+    use crate::system::System;
+
+    fn system() -> System {
+        let mut system = System::new(Model::Dmg);
+        system.cpu.reg.a = 12;
+        system.cpu.reg.set_hl(0xC000);
+        system.cpu.mmu.set(0xC000, 0x3C);
+        system.cpu.mmu.set(0xFF44, 144);
+        system
+    }
+
+    #[test]
+    fn eval_ok() {
+        let system = system();
+        let mut labels = Labels::new();
+        labels.insert(0, 0xC000, "wValue");
+        let eval = |text: &str| Condition::parse(text, &labels).unwrap().eval(&system, 3);
+        assert_eq!(eval("[HL] == $3C && A > 10 && LY == 144"), 1);
+        assert_eq!(eval("1 + 2 * 3 - (4 - 2)"), 5);
+        assert_eq!(eval("[wValue] >> 4 | 0x100"), 0x103);
+        assert_eq!(eval("hits % 3 == 0 && !ZF"), 1);
+        assert_eq!(eval("a / 0"), 0);
+        assert!(Condition::parse("A ==", &labels).is_err());
+        assert!(Condition::parse("Missing + 1", &labels).is_err());
+    }
+
+    #[test]
+    fn log_message_ok() {
+        let system = system();
+        let message = LogMessage::parse("A={A} HL={HL} hits={HITS:d}", &Labels::new()).unwrap();
+        assert_eq!(message.format(&system, 10), "A=0C HL=C000 hits=10");
+    }
+}
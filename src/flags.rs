@@ -1,4 +1,5 @@
 
+#[derive(Clone)]
 pub struct Flags {
     pub zero: bool,
     pub sub: bool,
@@ -0,0 +1,125 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::os::raw::c_int;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use metalboy::args;
+use metalboy::cdl::CodeDataLog;
+use metalboy::cli::Session;
+use metalboy::system::System;
+
+const PROMPT: &str = "(metalboy) ";
+const SIGINT: c_int = 2; // The same number on every platform with signal()
+
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    // From the C runtime, which is always linked, so stopping on Ctrl-C doesn't need another crate
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+extern "C" fn on_interrupt(_: c_int) {
+    INTERRUPT.store(true, Ordering::Relaxed);
+}
+
+fn exit_with(message: String) -> ! {
+    println!("{}", message);
+    process::exit(-1);
+}
+
+// A GDB-style debugger on the terminal, for when there's no display (e.g. over SSH)
+fn main() {
+    env_logger::init();
+    let mut args: Vec<String> = env::args().collect();
+    let model = args::take_model(&mut args).unwrap_or_else(|e| exit_with(e));
+    let boot_rom = args::take_boot_rom(&mut args, model).unwrap_or_else(|e| exit_with(e));
+    let tracer = args::take_tracer(&mut args).unwrap_or_else(|e| exit_with(e));
+    let script = args::take_value(&mut args, "-x").unwrap_or_else(|e| exit_with(e));
+    let batch = args::take_flag(&mut args, "--batch");
+    let cdl = args::take_value(&mut args, "--cdl").unwrap_or_else(|e| exit_with(e));
+    if args.len() < 2 {
+        println!("Usage: metalboy-cli <rom> [-x <command file> [--batch]] [--cdl <file>] [--model <model>] [--skip-boot | --bootrom [<model>=]<path> ...]");
+        process::exit(-1);
+    }
+
+    let mut system = System::new(model);
+    system.load_cartridge(&args[1]);
+    system.set_boot_rom(boot_rom);
+    system.tracer = tracer;
+    let mut session = Session::new(system);
+    // Ctrl-C stops `continue` and the like instead of quitting, leave with `quit` or end of input
+    unsafe { signal(SIGINT, on_interrupt) };
+    session.interrupt = Some(&INTERRUPT);
+    session.batch = batch;
+    // How much of the ROM a script exercises, added to the log from earlier runs
+    if let Some(path) = &cdl {
+        let rom_size = session.system.cpu.mmu.cartridge.data.len();
+        session.system.cdl = Some(CodeDataLog::load(Path::new(path), rom_size).unwrap_or_else(|e| {
+            println!("Unable to read {}: {}", path, e);
+            process::exit(-1);
+        }));
+    }
+
+    interact(&mut session, script, batch);
+    if let (Some(path), Some(log)) = (cdl, session.system.cdl.as_mut()) {
+        if let Err(e) = log.save(Path::new(&path)) {
+            println!("Unable to write {}: {}", path, e);
+        }
+    }
+}
+
+// Commands from the file, then from stdin
+fn interact(session: &mut Session, script: Option<String>, batch: bool) {
+    // Commands from the file are echoed, so the output reads as a transcript for bug reports
+    if let Some(path) = script {
+        let commands = fs::read_to_string(&path).unwrap_or_else(|e| {
+            println!("Unable to read {}: {}", path, e);
+            process::exit(-1);
+        });
+        for line in commands.lines() {
+            println!("{}{}", PROMPT, line);
+            if !run(session, line) {
+                return;
+            }
+        }
+        if batch {
+            return;
+        }
+    }
+
+    let interactive = io::stdin().is_terminal();
+    let mut last = String::new();
+    loop {
+        if interactive {
+            print!("{}", PROMPT);
+            io::stdout().flush().unwrap();
+        }
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break; // End of input
+        }
+        // Like GDB, an empty line repeats the last command so stepping is a single key
+        if line.trim().is_empty() && interactive {
+            line = last.clone();
+        }
+        if !run(session, &line) {
+            break;
+        }
+        last = line;
+    }
+}
+
+// Returns false once asked to quit
+fn run(session: &mut Session, line: &str) -> bool {
+    if matches!(line.trim(), "quit" | "q") {
+        return false;
+    }
+    match session.execute(line) {
+        Ok(output) if output.is_empty() => (),
+        Ok(output) => println!("{}", output),
+        Err(e) => println!("{}", e),
+    }
+    true
+}
@@ -0,0 +1,43 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use metalboy::args;
+use metalboy::dap;
+
+// Debug Adapter Protocol server for editors, over stdio or `--port <port>` for TCP
+fn main() {
+    env_logger::init(); // Logs go to stderr, stdout may be the protocol
+    let mut args: Vec<String> = env::args().collect();
+    let port = args::take_value(&mut args, "--port").unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(-1);
+    });
+    if args.len() > 1 {
+        println!("Usage: metalboy-dap [--port <port>]");
+        process::exit(-1);
+    }
+
+    let Some(port) = port else {
+        if let Err(e) = dap::serve(io::stdin(), io::stdout()) {
+            eprintln!("{}", e);
+            process::exit(-1);
+        }
+        return;
+    };
+    let listener = TcpListener::bind(("127.0.0.1", port.parse().unwrap_or_else(|_| {
+        println!("--port expects a port number");
+        process::exit(-1);
+    }))).unwrap_or_else(|e| {
+        println!("Unable to listen on port {}: {}", port, e);
+        process::exit(-1);
+    });
+    println!("Listening on port {}", port);
+    // One debug session at a time, the next can start when it ends
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| dap::serve(stream.try_clone()?, stream));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+}
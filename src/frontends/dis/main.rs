@@ -0,0 +1,70 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use metalboy::args;
+use metalboy::disasm::{self, Labels, ROM_BANK_SIZE};
+use metalboy::export::{self, Exporter};
+
+fn exit_with(message: String) -> ! {
+    println!("{}", message);
+    process::exit(-1);
+}
+
+// Print a symbolic disassembly of a ROM, or just one of its banks, or export it as RGBDS source
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let export_dir = args::take_value(&mut args, "--export").unwrap_or_else(|e| exit_with(e));
+    let cdl = args::take_value(&mut args, "--cdl").unwrap_or_else(|e| exit_with(e)).map(|path| fs::read(&path).unwrap_or_else(|e| {
+        println!("Unable to read {}: {}", path, e);
+        process::exit(-1);
+    }));
+    let bank: Option<u16> = args::take_value(&mut args, "--bank").unwrap_or_else(|e| exit_with(e)).map(|bank| bank.parse().unwrap_or_else(|_| {
+        println!("--bank expects a bank number");
+        process::exit(-1);
+    }));
+    if args.len() < 2 {
+        println!("Usage: metalboy-dis <rom> [--bank <bank>] [--export <dir> [--cdl <file>]]");
+        process::exit(-1);
+    }
+
+    let rom = fs::read(&args[1]).unwrap_or_else(|e| {
+        println!("Unable to read {}: {}", args[1], e);
+        process::exit(-1);
+    });
+    if let Some(dir) = export_dir {
+        let name = Path::new(&args[1]).file_stem().map_or("game".to_string(), |stem| stem.to_string_lossy().to_string());
+        let exporter = Exporter::new(&rom, cdl.as_deref());
+        let code = (0..rom.len()).filter(|offset| exporter.is_code(*offset)).count();
+        if let Err(e) = export::write(Path::new(&dir), &exporter.files(&name)) {
+            println!("Unable to write to {}: {}", dir, e);
+            process::exit(-1);
+        }
+        println!("Exported {} to {} ({} of {} bytes as code), build it with make", args[1], dir, code, rom.len());
+        return;
+    }
+
+    let bank_count = ((rom.len() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE).max(1);
+    let banks: Vec<u16> = match bank {
+        Some(bank) if bank as usize >= bank_count => {
+            println!("Bank {} is out of range, the ROM has {} bank(s)", bank, bank_count);
+            process::exit(-1);
+        }
+        Some(bank) => vec![bank],
+        None => (0..bank_count as u16).collect(),
+    };
+
+    // Label every bank first, so calls into a later bank still get a name
+    let mut labels = Labels::new();
+    let listings: Vec<_> = banks.iter().map(|bank| disasm::disassemble_bank(&rom, *bank)).collect();
+    for lines in &listings {
+        labels.generate(lines);
+    }
+    for (bank, lines) in banks.iter().zip(&listings) {
+        println!("; ROM bank {}", bank);
+        for line in lines {
+            println!("{}", line.display(&labels));
+        }
+        println!();
+    }
+}
@@ -1,16 +1,20 @@
 use egui::{Context, RichText, Ui, Color32, Align, Layout, Direction, TextureHandle, ColorImage};
 use egui::Direction::LeftToRight;
 use egui_memory_editor::MemoryEditor;
-use metalboy::cpu::Cpu;
-use metalboy::graphics::Graphics;
+use metalboy::cdl::CodeDataLog;
+use metalboy::disasm::Labels;
+use metalboy::graphics::TILE_MAP_0;
+use metalboy::model::Model;
+use metalboy::profiler::{HotSpot, Profiler};
+use metalboy::debugger::{BreakReason, WatchKind};
+use metalboy::system::System;
 use metalboy::timer;
 use super::common::*;
 
 pub struct App {
-    pub cpu: Cpu,
+    pub system: System,
     pub old_tileset_vram: [u8; 0x1800],
     pub tileset_image: ColorImage,
-    pub graphics: Graphics,
     pub log_history: Vec<String>,
     pub opcode_history: Vec<(bool, u8)>,
     pub pause_execution: bool,
@@ -19,17 +23,43 @@ pub struct App {
     pub show_control_view: bool,
     pub show_state_view: bool,
     pub show_log_view: bool,
+    pub show_disassembly_view: bool,
+    pub show_call_stack_view: bool,
+    pub show_profiler_view: bool,
+    pub show_coverage_view: bool,
+    pub show_heatmap_view: bool,
+    pub show_tile_map_view: bool,
+    pub show_oam_view: bool,
+    pub show_io_registers_view: bool,
     pub show_mem_editor: bool,
     pub mem_editor: MemoryEditor,
+    pub labels: Labels,
+    pub breakpoint_input: String,
+    pub condition_input: String,
+    pub log_input: String,
+    pub breakpoint_error: Option<String>,
+    pub watchpoint_input: String,
+    pub watchpoint_kind: WatchKind,
+    pub break_reason: Option<BreakReason>, // What paused execution, until it's resumed
+    pub rom_path: String,
+    pub stopped_profiler: Option<Profiler>, // Kept for viewing once profiling stops
+    pub profile_per_function: bool,
+    pub profile_rows: Vec<HotSpot>,
+    pub profile_age: u32, // UI frames since the rows were worked out
+    pub profiler_message: Option<String>,
+    pub stopped_cdl: Option<CodeDataLog>, // Kept for viewing and saving once recording stops
+    pub cdl_bank: usize,
+    pub cdl_message: Option<String>,
+    pub tile_map: u16, // Which of the two the Tile Map window shows
+    pub selected_sprite: Option<usize>, // Outlined on the screen
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(model: Model) -> Self {
         App {
-            cpu: Cpu::new(),
+            system: System::new(model),
             old_tileset_vram: [0; 0x1800],
             tileset_image: ColorImage::new([128, 192], Color32::BLACK),
-            graphics: Graphics::new(),
             log_history: vec![],
             opcode_history: vec![],
             pause_execution: false,
@@ -38,36 +68,70 @@ impl App {
             show_control_view: false,
             show_state_view: true,
             show_log_view: false,
+            show_disassembly_view: true,
+            show_call_stack_view: false,
+            show_profiler_view: false,
+            show_coverage_view: false,
+            show_heatmap_view: false,
+            show_tile_map_view: false,
+            show_oam_view: false,
+            show_io_registers_view: false,
             show_mem_editor: false,
-            mem_editor: MemoryEditor::new()
-                .with_address_range("0. All", 0..0xFFFF)
-                .with_address_range("1. ROM", 0x0000..0x8000)
-                .with_address_range("2. VRAM", 0x8000..0xA000)
-                .with_address_range("3. EXTRAM", 0xA000..0xC000)
-                .with_address_range("4. WRAM", 0xC000..0xE000)
-                .with_address_range("5. OAM", 0xFE00..0xFEA0)
-                .with_address_range("6. IO", 0xFF00..0xFF80)
-                .with_address_range("7. HRAM", 0xFF80..0xFFFF)
+            mem_editor: MEMORY_REGIONS.iter()
+                .fold(MemoryEditor::new(), |editor, (name, range)| editor.with_address_range(*name, range.clone()))
                 .with_window_title("Memory Editor"),
+            labels: Labels::new(),
+            breakpoint_input: String::new(),
+            condition_input: String::new(),
+            log_input: String::new(),
+            breakpoint_error: None,
+            watchpoint_input: String::new(),
+            watchpoint_kind: WatchKind::Write,
+            break_reason: None,
+            rom_path: String::new(),
+            stopped_profiler: None,
+            profile_per_function: true,
+            profile_rows: vec![],
+            profile_age: 0,
+            profiler_message: None,
+            stopped_cdl: None,
+            cdl_bank: 0,
+            cdl_message: None,
+            tile_map: TILE_MAP_0,
+            selected_sprite: None,
         }
     }
 
     pub fn draw_windows(&mut self, egui_ctx: &Context) {
         self.show_menubar(egui_ctx);
         if self.show_tileset_view { self.show_tileset(egui_ctx); }
+        if self.show_tile_map_view { self.show_tile_map(egui_ctx); }
+        if self.show_oam_view { self.show_oam(egui_ctx); }
+        if self.show_io_registers_view { self.show_io_registers(egui_ctx); } else { self.system.io_writes = None; }
         if self.show_control_view { self.show_control(egui_ctx); }
         if self.show_log_view { self.show_log(egui_ctx); }
         if self.show_state_view { self.show_state(egui_ctx); }
+        if self.show_disassembly_view { self.show_disassembly(egui_ctx); }
+        if self.show_call_stack_view { self.show_call_stack(egui_ctx); }
+        if self.show_profiler_view { self.show_profiler(egui_ctx); }
+        if self.show_coverage_view { self.show_coverage(egui_ctx); }
+        if self.show_heatmap_view { self.show_heatmap(egui_ctx); } else { self.system.heatmap = None; } // Only counted while it's open
 
         self.mem_editor.window_ui(
             egui_ctx,
             &mut self.show_mem_editor,
-            &mut self.cpu.mmu,
+            &mut self.system.cpu.mmu,
             |mmu, address| mmu.get(address as u16).into(),
             |mmu, address, val| mmu.set(address as u16, val),
         );
     }
 
+    // Where the CPU is about to execute, with the symbol it's in if there is one
+    pub fn pc_location(&self) -> String {
+        let pc = self.system.cpu.reg.pc;
+        self.labels.describe(pc, self.system.cpu.mmu.rom_bank_at(pc))
+    }
+
     pub(crate) fn header(&mut self, text: &str, ui: &mut Ui) {
         ui.label(RichText::new(text).color(HEADER_COLOUR));
     }
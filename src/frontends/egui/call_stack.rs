@@ -0,0 +1,22 @@
+use egui::{Context, RichText};
+use crate::app::App;
+use crate::common::*;
+
+impl App {
+    pub fn show_call_stack(&mut self, egui_ctx: &Context) {
+        egui::Window::new("Call Stack").show(egui_ctx, |ui| {
+            let cpu = &self.system.cpu;
+            ui.label(RichText::new(self.pc_location()).monospace().color(HEADER_COLOUR));
+            // Innermost first, each entry is the routine and the instruction that got us into it
+            for frame in cpu.calls.live(cpu.reg.sp) {
+                let callee = self.labels.describe(frame.target, frame.target_bank);
+                let caller = self.labels.describe(frame.caller, frame.caller_bank);
+                let text = match frame.interrupt() {
+                    Some(interrupt) => format!("{}  <- {} interrupt at {}", callee, interrupt, caller),
+                    None => format!("{}  <- {}", callee, caller),
+                };
+                ui.label(RichText::new(text).monospace());
+            }
+        });
+    }
+}
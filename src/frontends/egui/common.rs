@@ -1,5 +1,36 @@
+use std::ops::Range;
 use egui::Color32;
 
 pub const HEADER_COLOUR: Color32 = Color32::from_rgb(110, 255, 110);
 pub const BOLD_FONT_COLOUR: Color32 = Color32::from_rgb(110, 150, 110);
 pub const SELECTED_BG_FILL: Color32 = Color32::from_rgb(30, 70, 30);
+pub const BREAK_COLOUR: Color32 = Color32::from_rgb(255, 110, 110);
+pub const BREAK_BG_FILL: Color32 = Color32::from_rgb(90, 30, 30);
+// DMG greens for the shades a palette maps colours to, lightest first
+pub const SHADES: [Color32; 4] = [
+    Color32::from_rgb(0x8B, 0xAC, 0x0F),
+    Color32::from_rgb(0x30, 0x62, 0x30),
+    Color32::from_rgb(0x0F, 0x38, 0x0F),
+    Color32::from_rgb(0x00, 0x00, 0x00),
+];
+
+// Named in the memory editor, and used to say where an address is
+pub const MEMORY_REGIONS: [(&str, Range<usize>); 10] = [
+    ("0. All", 0..0x10000),
+    ("1. ROM", 0x0000..0x8000),
+    ("2. VRAM", 0x8000..0xA000),
+    ("3. EXTRAM", 0xA000..0xC000),
+    ("4. WRAM", 0xC000..0xE000),
+    ("5. Echo RAM", 0xE000..0xFE00), // Mirrors 0xC000~0xDDFF
+    ("6. OAM", 0xFE00..0xFEA0),
+    ("7. IO", 0xFF00..0xFF80),
+    ("8. HRAM", 0xFF80..0xFFFF),
+    ("9. IE", 0xFFFF..0x10000),
+];
+
+// The region an address is in, without the number it's listed by
+pub fn region_name(address: u16) -> Option<&'static str> {
+    MEMORY_REGIONS[1..].iter()
+        .find(|(_, range)| range.contains(&(address as usize)))
+        .map(|(name, _)| name.split_once(". ").map_or(*name, |(_, name)| name))
+}
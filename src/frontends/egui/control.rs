@@ -1,21 +1,179 @@
 use std::ops::MulAssign;
 use egui::{Align, Color32, ColorImage, Context, Direction, Image, Layout, Pos2, TextureFilter, TextureHandle, TextureOptions};
 use log::trace;
+use egui::RichText;
+use metalboy::debugger::{self, Breakpoint, BreakReason, WatchKind, Watchpoint};
+use metalboy::expr::{Condition, LogMessage};
+use metalboy::symbols;
 use metalboy::timer;
 use crate::app::App;
+use crate::common::*;
 
 impl App {
+    fn parse_breakpoint(&self) -> Result<Breakpoint, String> {
+        let mut breakpoint = Breakpoint::new(symbols::parse_location(&self.breakpoint_input, &self.labels)?);
+        if !self.condition_input.trim().is_empty() {
+            breakpoint.condition = Some(Condition::parse(&self.condition_input, &self.labels)?);
+        }
+        if !self.log_input.is_empty() {
+            breakpoint.log = Some(LogMessage::parse(&self.log_input, &self.labels)?);
+        }
+        Ok(breakpoint)
+    }
+
     pub fn show_control(&mut self, egui_ctx: &Context) {
         egui::Window::new("Control").show(egui_ctx, |ui| {
             self.header("Execution", ui);
             ui.horizontal_wrapped(|ui| {
                 if ui.button("Toggle").clicked() {
                     self.pause_execution = !self.pause_execution;
+                    self.break_reason = None;
                 }
                 if ui.button("Step").clicked() {
                     self.step = true;
+                    self.break_reason = None;
+                }
+                // These run until System reports the step finished
+                let over = ui.button("Over").clicked();
+                let out = ui.button("Out").clicked();
+                let line = ui.button("Line").on_hover_text("Run to the next scanline").clicked();
+                let frame = ui.button("Frame").on_hover_text("Run to the next VBlank").clicked();
+                let run_to = ui.button("Run to").on_hover_text("Run to the breakpoint address below").clicked();
+                let mut stepped = over || out || line || frame;
+                if over { self.system.step_over(); }
+                if out { self.system.step_out(); }
+                if line { self.system.step_scanline(); }
+                if frame { self.system.step_frame(); }
+                if run_to {
+                    match symbols::parse_location(&self.breakpoint_input, &self.labels) {
+                        Ok(location) => {
+                            self.system.run_to(location);
+                            stepped = true;
+                        }
+                        Err(e) => self.breakpoint_error = Some(e),
+                    }
+                }
+                if stepped {
+                    self.pause_execution = false;
+                    self.break_reason = None;
+                }
+            });
+            ui.horizontal_wrapped(|ui| {
+                // Replayed from snapshots, so only as far back as history was kept
+                if ui.button("Back").on_hover_text("Step back one instruction").clicked() && self.system.step_back() {
+                    self.pause_execution = true;
+                    self.break_reason = self.system.break_reason.take();
+                }
+                if ui.button("Reverse").on_hover_text("Run backwards to the previous break").clicked() {
+                    self.system.reverse_continue();
+                    self.pause_execution = true;
+                    self.break_reason = self.system.break_reason.take();
+                }
+                if let Some(oldest) = self.system.history.as_ref().and_then(|history| history.oldest()) {
+                    ui.label(format!("{} steps of history", self.system.steps - oldest));
+                }
+            });
+            match self.break_reason {
+                Some(reason @ BreakReason::Stepped(_)) => { ui.label(reason.to_string()); },
+                Some(reason) => {
+                    let pc = reason.pc().unwrap_or(self.system.cpu.reg.pc);
+                    let at = self.labels.describe(pc, self.system.cpu.mmu.rom_bank_at(pc));
+                    ui.label(RichText::new(format!("{} ({})", reason, at)).color(BREAK_COLOUR));
+                }
+                None => (),
+            }
+            ui.separator();
+
+            // Symbol names work here as well as addresses when a .sym file was loaded
+            self.header("Breakpoints", ui);
+            ui.horizontal_wrapped(|ui| {
+                ui.label("At");
+                ui.text_edit_singleline(&mut self.breakpoint_input);
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("If");
+                ui.text_edit_singleline(&mut self.condition_input).on_hover_text("e.g. [HL] == $3C && A > 10 && HITS > 100");
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Log");
+                ui.text_edit_singleline(&mut self.log_input).on_hover_text("Log without stopping, e.g. A={A} score={[wScore]:d}");
+                if ui.button("Add").clicked() {
+                    match self.parse_breakpoint() {
+                        Ok(breakpoint) => {
+                            self.system.breakpoints.push(breakpoint);
+                            self.breakpoint_input.clear();
+                            self.condition_input.clear();
+                            self.log_input.clear();
+                            self.breakpoint_error = None;
+                        }
+                        Err(e) => self.breakpoint_error = Some(e),
+                    }
+                }
+            });
+            let mut removed = None;
+            for (i, breakpoint) in self.system.breakpoints.iter().enumerate() {
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("x").clicked() {
+                        removed = Some(i);
+                    }
+                    let mut text = match breakpoint.location.bank {
+                        Some(bank) => self.labels.describe(breakpoint.location.address, bank),
+                        None => format!("{:04X} (any bank)", breakpoint.location.address),
+                    };
+                    if let Some(condition) = &breakpoint.condition {
+                        text += &format!(" if {}", condition);
+                    }
+                    if let Some(log) = &breakpoint.log {
+                        text += &format!(" log \"{}\"", log.source);
+                    }
+                    ui.monospace(format!("{} [{} hits]", text, breakpoint.hits));
+                });
+            }
+            if let Some(i) = removed {
+                self.system.breakpoints.remove(i);
+            }
+            ui.separator();
+
+            // `C000-C0FF`, one address or a symbol
+            self.header("Watchpoints", ui);
+            ui.horizontal_wrapped(|ui| {
+                let input = ui.text_edit_singleline(&mut self.watchpoint_input);
+                let entered = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                egui::ComboBox::from_id_source("watch_kind")
+                    .selected_text(self.watchpoint_kind.to_string())
+                    .show_ui(ui, |ui| {
+                        for kind in [WatchKind::Read, WatchKind::Write, WatchKind::Change] {
+                            ui.selectable_value(&mut self.watchpoint_kind, kind, kind.to_string());
+                        }
+                    });
+                if ui.button("Add").clicked() || entered {
+                    match debugger::parse_range(&self.watchpoint_input, &self.labels) {
+                        Ok(range) => {
+                            self.system.cpu.mmu.watchpoints.push(Watchpoint { range, kind: self.watchpoint_kind });
+                            self.watchpoint_input.clear();
+                            self.breakpoint_error = None;
+                        }
+                        Err(e) => self.breakpoint_error = Some(e),
+                    }
                 }
             });
+            let mut removed = None;
+            for (i, watchpoint) in self.system.cpu.mmu.watchpoints.iter().enumerate() {
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("x").clicked() {
+                        removed = Some(i);
+                    }
+                    let (from, to) = (*watchpoint.range.start(), *watchpoint.range.end());
+                    let range = if from == to { format!("{:04X}", from) } else { format!("{:04X}-{:04X}", from, to) };
+                    ui.monospace(format!("{} {}", range, watchpoint.kind));
+                });
+            }
+            if let Some(i) = removed {
+                self.system.cpu.mmu.watchpoints.remove(i);
+            }
+            if let Some(error) = &self.breakpoint_error {
+                ui.label(RichText::new(error).color(BREAK_COLOUR));
+            }
         });
     }
-}
\ No newline at end of file
+}
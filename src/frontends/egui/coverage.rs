@@ -0,0 +1,96 @@
+use egui::{Color32, ColorImage, Context, RichText, TextureOptions};
+use metalboy::cdl::{self, CodeDataLog, Usage};
+use metalboy::disasm::ROM_BANK_SIZE;
+use crate::app::App;
+use crate::common::*;
+
+const BITMAP_WIDTH: usize = 128; // A byte per pixel, so a bank is 128x128
+const BITMAP_SCALE: f32 = 2.;
+
+const OPCODE_COLOUR: Color32 = Color32::from_rgb(110, 255, 110);
+const OPERAND_COLOUR: Color32 = Color32::from_rgb(40, 130, 40);
+const DATA_COLOUR: Color32 = Color32::from_rgb(90, 140, 255);
+const UNTOUCHED_COLOUR: Color32 = Color32::from_rgb(30, 30, 30);
+
+fn colour(usage: Usage) -> Color32 {
+    match usage {
+        Usage::Opcode => OPCODE_COLOUR,
+        Usage::Operand => OPERAND_COLOUR,
+        Usage::Data => DATA_COLOUR,
+        Usage::Untouched => UNTOUCHED_COLOUR,
+    }
+}
+
+impl App {
+    pub fn show_coverage(&mut self, egui_ctx: &Context) {
+        egui::Window::new("Code/Data Log").show(egui_ctx, |ui| {
+            let path = cdl::cdl_path(&self.rom_path);
+            ui.horizontal(|ui| {
+                let recording = self.system.cdl.is_some();
+                if ui.button(if recording { "Stop" } else { "Record" }).clicked() {
+                    match self.system.cdl.take() {
+                        Some(log) => self.stopped_cdl = Some(log),
+                        None => {
+                            // Carries on from the last session's log the first time
+                            let rom_size = self.system.cpu.mmu.cartridge.data.len();
+                            match self.stopped_cdl.take().map_or_else(|| CodeDataLog::load(&path, rom_size), Ok) {
+                                Ok(log) => self.system.cdl = Some(log),
+                                Err(e) => self.cdl_message = Some(format!("Unable to read {}: {}", path.display(), e)),
+                            }
+                        }
+                    }
+                }
+                if ui.button("Save").on_hover_text("Merged with the file, so sessions add up").clicked() {
+                    if let Some(log) = self.system.cdl.as_mut().or(self.stopped_cdl.as_mut()) {
+                        self.cdl_message = Some(match log.save(&path) {
+                            Ok(()) => format!("Saved {}", path.display()),
+                            Err(e) => format!("Unable to write {}: {}", path.display(), e),
+                        });
+                    }
+                }
+            });
+            if let Some(message) = &self.cdl_message {
+                ui.label(message);
+            }
+            let Some(log) = self.system.cdl.as_ref().or(self.stopped_cdl.as_ref()) else {
+                ui.label("Record to see which ROM bytes are used as code and data");
+                return;
+            };
+
+            let whole = log.coverage(0..log.flags.len());
+            ui.label(RichText::new(format!("{:.1}% of the ROM used", whole.percent())).color(HEADER_COLOUR));
+            self.cdl_bank = self.cdl_bank.min(log.banks().saturating_sub(1));
+            egui::ComboBox::from_label("Bank")
+                .selected_text(format!("{:02X}", self.cdl_bank))
+                .show_ui(ui, |ui| {
+                    for bank in 0..log.banks() {
+                        let text = format!("{:02X}  {:.1}%", bank, log.bank_coverage(bank).percent());
+                        ui.selectable_value(&mut self.cdl_bank, bank, text);
+                    }
+                });
+
+            let start = self.cdl_bank * ROM_BANK_SIZE;
+            let mut image = ColorImage::new([BITMAP_WIDTH, ROM_BANK_SIZE / BITMAP_WIDTH], UNTOUCHED_COLOUR);
+            for (i, pixel) in image.pixels.iter_mut().enumerate() {
+                *pixel = colour(log.usage(start + i));
+            }
+            let texture = ui.ctx().load_texture("coverage", image, TextureOptions::NEAREST);
+            let response = ui.image(&texture, texture.size_vec2() * BITMAP_SCALE);
+            if let Some(position) = response.hover_pos() {
+                let cell = (position - response.rect.min) / BITMAP_SCALE;
+                let i = (cell.y as usize * BITMAP_WIDTH + cell.x as usize).min(ROM_BANK_SIZE - 1);
+                let address = (i + if self.cdl_bank == 0 { 0 } else { ROM_BANK_SIZE }) as u16;
+                let text = format!("{:02X}:{:04X}  {:?}", self.cdl_bank, address, log.usage(start + i));
+                response.on_hover_text(RichText::new(text).monospace());
+            }
+
+            let coverage = log.bank_coverage(self.cdl_bank);
+            let total = coverage.total().max(1) as f64;
+            for (usage, count) in [(Usage::Opcode, coverage.opcode), (Usage::Operand, coverage.operand), (Usage::Data, coverage.data), (Usage::Untouched, coverage.untouched)] {
+                let text = format!("{:<9} {:>5} {:>5.1}%", format!("{:?}", usage), count, count as f64 * 100. / total);
+                let text_colour = if usage == Usage::Untouched { Color32::GRAY } else { colour(usage) }; // The bitmap's is too dark to read
+                ui.label(RichText::new(text).monospace().color(text_colour));
+            }
+        });
+    }
+}
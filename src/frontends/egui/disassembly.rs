@@ -0,0 +1,43 @@
+use egui::{Context, RichText};
+use metalboy::disasm;
+use crate::app::App;
+use crate::common::*;
+
+pub const DISASSEMBLY_LINES: usize = 24;
+
+impl App {
+    pub fn show_disassembly(&mut self, egui_ctx: &Context) {
+        egui::Window::new("Disassembly").show(egui_ctx, |ui| {
+            let mmu = &self.system.cpu.mmu;
+            let pc = self.system.cpu.reg.pc;
+            let bank = mmu.rom_bank as u16; // Addresses below 0x4000 are always bank 0
+            let read = |address: u16| mmu.get(address);
+            // Start from the instruction that tripped a watchpoint when it's just behind PC
+            let from = match self.break_reason.and_then(|reason| reason.pc()) {
+                Some(cause) if cause < pc && pc - cause <= 3 => cause,
+                _ => pc,
+            };
+            let mut lines = disasm::disassemble(&read, from, from.saturating_add(DISASSEMBLY_LINES as u16 * 3), bank);
+            lines.truncate(DISASSEMBLY_LINES);
+            self.labels.generate(&lines);
+
+            for line in &lines {
+                let text = line.display(&self.labels).to_string();
+                let (label, instruction) = match text.split_once('\n') {
+                    Some((label, instruction)) => (Some(label), instruction),
+                    None => (None, text.as_str()),
+                };
+                if let Some(label) = label {
+                    ui.label(RichText::new(label).monospace().color(HEADER_COLOUR));
+                }
+                let mut row = RichText::new(instruction).monospace();
+                if self.break_reason.and_then(|reason| reason.pc()) == Some(line.address) {
+                    row = row.background_color(BREAK_BG_FILL); // What caused the pause
+                } else if line.address == pc {
+                    row = row.background_color(SELECTED_BG_FILL); // The next instruction to execute
+                }
+                ui.label(row);
+            }
+        });
+    }
+}
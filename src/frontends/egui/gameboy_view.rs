@@ -24,7 +24,7 @@ impl App {
         for i in 0..(160 * 144) {
             let col = i % 160;
             let row = i / 160;
-            let rgb = self.graphics.fb[col][row];
+            let rgb = self.system.graphics.fb[col][row];
             let r = (rgb & 0xFF0000) >> 16;
             let g = (rgb & 0x00FF00) >> 8;
             let b =  rgb & 0x0000FF;
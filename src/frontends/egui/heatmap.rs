@@ -0,0 +1,48 @@
+use egui::{Color32, ColorImage, Context, RichText, TextureOptions};
+use metalboy::heatmap::{Heat, Heatmap, ADDRESS_SPACE};
+use crate::app::App;
+use crate::common::*;
+
+const MAP_WIDTH: usize = 256; // A page per row
+const MAP_SCALE: f32 = 2.;
+const BRIGHTNESS: f32 = 40.; // Heat is logarithmic, so a single access still shows and thousands don't wash everything out
+
+// Writes red, reads green and executes blue
+fn colour(heat: &Heat) -> Color32 {
+    let channel = |heat: f32| (heat.ln_1p() * BRIGHTNESS).min(255.) as u8;
+    Color32::from_rgb(channel(heat.writes), channel(heat.reads), channel(heat.executes))
+}
+
+impl App {
+    pub fn show_heatmap(&mut self, egui_ctx: &Context) {
+        let heatmap = self.system.heatmap.get_or_insert_with(Heatmap::new);
+        egui::Window::new("Memory Heatmap").show(egui_ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Writes").color(Color32::RED));
+                ui.label(RichText::new("Reads").color(Color32::GREEN));
+                ui.label(RichText::new("Executes").color(Color32::LIGHT_BLUE));
+            });
+
+            let mut image = ColorImage::new([MAP_WIDTH, ADDRESS_SPACE / MAP_WIDTH], Color32::BLACK);
+            for (pixel, heat) in image.pixels.iter_mut().zip(&heatmap.heat) {
+                *pixel = colour(heat);
+            }
+            let texture = ui.ctx().load_texture("heatmap", image, TextureOptions::NEAREST);
+            let response = ui.image(&texture, texture.size_vec2() * MAP_SCALE);
+            if let Some(position) = response.hover_pos() {
+                let cell = (position - response.rect.min) / MAP_SCALE;
+                let address = (cell.y as usize * MAP_WIDTH + cell.x as usize).min(ADDRESS_SPACE - 1) as u16;
+                let counts = heatmap.counts[address as usize];
+                let mut text = format!("{:04X}", address);
+                if let Some(region) = region_name(address) {
+                    text += &format!(" {}", region);
+                }
+                if let Some(name) = self.labels.resolve(address, self.system.cpu.mmu.rom_bank_at(address)) {
+                    text += &format!(" {}", name);
+                }
+                text += &format!("\nLast frame: {} read(s), {} write(s), {} execute(s)", counts.reads, counts.writes, counts.executes);
+                response.on_hover_text(RichText::new(text).monospace());
+            }
+        });
+    }
+}
@@ -0,0 +1,68 @@
+use egui::{Color32, Context, RichText, Ui};
+use metalboy::io_registers::{Field, IoWrites, GROUPS};
+use crate::app::App;
+use crate::common::*;
+
+const WRITTEN_COLOUR: Color32 = Color32::from_rgb(255, 200, 80);
+
+impl App {
+    pub fn show_io_registers(&mut self, egui_ctx: &Context) {
+        self.system.io_writes.get_or_insert_with(IoWrites::new);
+        egui::Window::new("IO Registers").default_height(500.).show(egui_ctx, |ui| {
+            ui.label(RichText::new("Registers written in the last frame are highlighted").color(WRITTEN_COLOUR));
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (group, registers) in GROUPS {
+                    egui::CollapsingHeader::new(*group).default_open(*group != "Sound" && *group != "Wave RAM").show(ui, |ui| {
+                        egui::Grid::new(group).striped(true).show(ui, |ui| {
+                            for register in *registers {
+                                let value = self.system.cpu.mmu.get(register.address);
+                                let writes = self.system.io_writes.as_ref().map_or(0, |writes| writes.last_frame(register.address));
+                                let colour = if writes > 0 { WRITTEN_COLOUR } else { HEADER_COLOUR };
+                                ui.label(RichText::new(format!("{:04X} {:<5}", register.address, register.name)).monospace().color(colour))
+                                    .on_hover_text(format!("Written {} time(s) in the last frame", writes));
+                                ui.monospace(format!("{:02X}", value));
+                                ui.horizontal_wrapped(|ui| {
+                                    for field in register.fields {
+                                        if let Some(byte) = edit_field(ui, register.address, field, value) {
+                                            self.system.cpu.mmu.set(register.address, byte);
+                                        }
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                        });
+                    });
+                }
+            });
+        });
+    }
+}
+
+// The new register value if the field was changed
+fn edit_field(ui: &mut Ui, address: u16, field: &Field, byte: u8) -> Option<u8> {
+    if field.read_only {
+        ui.label(format!("{}: {}", field.name, field.describe(byte)));
+        return None;
+    }
+    let mut value = field.get(byte);
+    let changed = if !field.values.is_empty() {
+        let mut changed = false;
+        egui::ComboBox::from_id_source((address, field.shift))
+            .selected_text(format!("{}: {}", field.name, field.describe(byte)))
+            .show_ui(ui, |ui| {
+                for (i, name) in field.values.iter().enumerate() {
+                    changed |= ui.selectable_value(&mut value, i as u8, *name).changed();
+                }
+            });
+        changed
+    } else if field.width == 1 {
+        let mut on = value == 1;
+        let changed = ui.checkbox(&mut on, field.name).changed();
+        value = on as u8;
+        changed
+    } else {
+        ui.label(field.name);
+        ui.add(egui::DragValue::new(&mut value).clamp_range(0..=field.mask()).hexadecimal(2, false, true)).changed()
+    };
+    changed.then(|| field.set(byte, value))
+}
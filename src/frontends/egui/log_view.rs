@@ -11,9 +11,9 @@ pub const MAX_LOG_LINES: usize = 5;
 impl App {
     pub fn show_log(&mut self, egui_ctx: &Context) {
         // let line = format!("PC: {:04x} {} [A:{:02X} F:{}] [B:{:02X} C:{:02X}] [D:{:02X} E:{:02X}] [H:{:02X} L:{:02X}] [SP:{:04X}] |",
-        //    self.cpu.reg.pc, decode(&self.cpu).expect("Unknown opcode"),
-        //    self.cpu.reg.a, self.cpu.reg.f.to_string(), self.cpu.reg.b, self.cpu.reg.c, self.cpu.reg.d,
-        //    self.cpu.reg.e, self.cpu.reg.h, self.cpu.reg.l, self.cpu.reg.sp,
+        //    self.system.cpu.reg.pc, decode(&self.system.cpu).expect("Unknown opcode"),
+        //    self.system.cpu.reg.a, self.system.cpu.reg.f.to_string(), self.system.cpu.reg.b, self.system.cpu.reg.c, self.system.cpu.reg.d,
+        //    self.system.cpu.reg.e, self.system.cpu.reg.h, self.system.cpu.reg.l, self.system.cpu.reg.sp,
         // );
         // if let Some(x) = self.log_history.first() {
         //     if x != &line {
@@ -32,11 +32,15 @@ impl App {
         // });
 
         egui::TopBottomPanel::new(TopBottomSide::Bottom, "bottom_panel").show(egui_ctx, |ui| {
+            // Log-point output, newest last
+            for line in self.log_history.iter().skip(self.log_history.len().saturating_sub(MAX_LOG_LINES)) {
+                ui.monospace(line);
+            }
             for (cb_prefix, opcode) in self.opcode_history.iter().rev() {
                 // let line = format!("PC: {:04x} {} [A:{:02X} F:{}] [B:{:02X} C:{:02X}] [D:{:02X} E:{:02X}] [H:{:02X} L:{:02X}] [SP:{:04X}] |",
-                //    self.cpu.reg.pc, decode(*cb_prefix, *opcode).expect("Unknown opcode"),
-                //    self.cpu.reg.a, self.cpu.reg.f.to_string(), self.cpu.reg.b, self.cpu.reg.c, self.cpu.reg.d,
-                //    self.cpu.reg.e, self.cpu.reg.h, self.cpu.reg.l, self.cpu.reg.sp,
+                //    self.system.cpu.reg.pc, decode(*cb_prefix, *opcode).expect("Unknown opcode"),
+                //    self.system.cpu.reg.a, self.system.cpu.reg.f.to_string(), self.system.cpu.reg.b, self.system.cpu.reg.c, self.system.cpu.reg.d,
+                //    self.system.cpu.reg.e, self.system.cpu.reg.h, self.system.cpu.reg.l, self.system.cpu.reg.sp,
                 // );
                 // ui.label(line);
             }
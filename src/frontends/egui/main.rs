@@ -1,13 +1,21 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
+mod call_stack;
 mod common;
 mod state;
 mod tileset;
+mod tile_map;
 mod control;
+mod coverage;
+mod disassembly;
 mod gameboy_view;
+mod heatmap;
+mod io_registers;
 mod log_view;
 mod menubar;
+mod oam;
+mod profiler;
 
 use std::arch::aarch64::int8x8_t;
 use macroquad::prelude::*;
@@ -25,11 +33,14 @@ use common::*;
 
 extern crate minifb;
 use metalboy::cpu::Status::InfiniteLoop;
-use metalboy::joypad::{Button, Joypad};
+use metalboy::joypad::Button;
+use metalboy::args;
+use metalboy::sgb;
 
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
 const BORDER_SIZE: f32 = 2.0;
+const MAX_LOG_HISTORY: usize = 1000;
 
 const KEY_MAP: [(KeyCode, Button); 8] = [
     (KeyCode::Up,    Button::Up),
@@ -42,6 +53,18 @@ const KEY_MAP: [(KeyCode, Button); 8] = [
     (KeyCode::A,     Button::Select),
 ];
 
+// SGB controller 2, for games that support multiplayer
+const PLAYER_2_KEY_MAP: [(KeyCode, Button); 8] = [
+    (KeyCode::I,          Button::Up),
+    (KeyCode::K,          Button::Down),
+    (KeyCode::J,          Button::Left),
+    (KeyCode::L,          Button::Right),
+    (KeyCode::Period,     Button::A),
+    (KeyCode::Comma,      Button::B),
+    (KeyCode::Enter,      Button::Start),
+    (KeyCode::RightShift, Button::Select),
+];
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "metalboy debug".to_owned(),
@@ -53,9 +76,17 @@ fn window_conf() -> Conf {
     }
 }
 
+fn exit_with(message: String) -> ! {
+    println!("{}", message);
+    process::exit(-1);
+}
+
 #[macroquad::main(window_conf)]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let model = args::take_model(&mut args).unwrap_or_else(|e| exit_with(e));
+    let mut boot_rom = args::take_boot_rom(&mut args, model).unwrap_or_else(|e| exit_with(e));
+    let tracer = args::take_tracer(&mut args).unwrap_or_else(|e| exit_with(e));
     if args.len() < 2 {
         println!("You must provide a ROM file");
         process::exit(-1);
@@ -63,17 +94,18 @@ async fn main() {
     // Initialise the logger
     env_logger::init();
 
-    let mut app = App::new();
-    app.cpu.mmu.cartridge.load(&args[1]);
+    let mut app = App::new(model);
+    app.system.load_cartridge(&args[1]);
+    app.rom_path = args[1].clone();
+    app.labels = app.system.symbols.clone(); // The disassembly window adds generated names wherever these have none
 
-    // Either use a bootrom or initialise manually
+    // A boot ROM can also be given after the ROM file, for the model the cartridge settled on
     if args.len() > 2 {
-        app.cpu.mmu.load_bootrom(&args[2]);
-    } else {
-        app.cpu.reg.pc = 0x100;
-        app.cpu.mmu.bootrom_mapped = false;
-        app.cpu.mmu.set_initial_state();
+        boot_rom.load(app.system.model(), &args[2]).unwrap_or_else(|e| exit_with(e));
     }
+    app.system.set_boot_rom(boot_rom);
+    app.system.tracer = tracer;
+    app.system.enable_history(); // For stepping backwards in the Control window
 
     // Emulation loop
     let mut cycles = 0;
@@ -83,7 +115,7 @@ async fn main() {
     let _max_warnings = 1;
 
     // Set up texture for macroquad
-    let mut texture = fb_to_texture2d(&app.graphics.fb);
+    let mut texture = fb_to_texture2d(&app.system.graphics.fb);
     texture.set_filter(FilterMode::Nearest);
 
     // Setup
@@ -107,40 +139,65 @@ async fn main() {
                 pressed.push(button);
             }
         }
+        let player_2: Vec<Button> = PLAYER_2_KEY_MAP.iter().filter(|(key, _)| is_key_down(*key)).map(|(_, button)| *button).collect();
+        app.system.set_player_input(1, &player_2);
 
         // Emulation loop for 1/60 of the CPU clock
-        while cycles < CLOCK_SPEED / 60 && app.cpu.status != InfiniteLoop && !app.pause_execution || (app.pause_execution && app.step) {
+        while cycles < CLOCK_SPEED / 60 && app.system.cpu.status != InfiniteLoop && !app.pause_execution || (app.pause_execution && app.step) {
             app.step = false;
-            app.cpu.tick(); // Advance the CPU
-            cycles += app.cpu.cycles * 4;
+            cycles += app.system.step(&pressed);
             _cycle_count += cycles;
-            app.cpu.timer.update(&mut app.cpu.mmu, app.cpu.cycles * 4);
-            app.graphics.update(&mut app.cpu.mmu, app.cpu.cycles * 4);
-            Joypad::update(&mut app.cpu.mmu, &pressed);
-            app.cpu.service_interrupts();
+            if let Some(reason) = app.system.break_reason {
+                app.break_reason = Some(reason);
+                app.pause_execution = true;
+                break;
+            }
+        }
+        if app.system.cpu.status == InfiniteLoop {
+            app.show_call_stack_view = true; // Show how it got stuck
+        }
+        if !app.system.log_messages.is_empty() {
+            app.log_history.append(&mut app.system.log_messages);
+            let excess = app.log_history.len().saturating_sub(MAX_LOG_HISTORY);
+            app.log_history.drain(..excess);
+            app.show_log_view = true;
         }
         std::thread::sleep(Duration::from_millis(4));
         cycles = 0;
 
         // Render everything
-        texture = fb_to_texture2d(&app.graphics.fb);
+        texture = fb_to_texture2d(&app.system.graphics.fb);
         clear_background(BLACK);
         set_camera(&Camera2D {
             zoom: vec2(4.0 / screen_width(), 4.0 / screen_height()),
             target: vec2((WIDTH / 2) as f32, (HEIGHT / 2) as f32),
             ..Default::default()
         });
-        draw_rectangle(-BORDER_SIZE, -BORDER_SIZE,
-                       WIDTH as f32 + BORDER_SIZE * 2.,
-                       HEIGHT as f32 + BORDER_SIZE * 2.,
-                       DARKGRAY
-        );
+        if let Some(border) = app.system.sgb_border() {
+            let border_texture = border_to_texture2d(border);
+            draw_texture_ex(border_texture, -(sgb::SCREEN_X as f32), -(sgb::SCREEN_Y as f32), WHITE,
+                            DrawTextureParams{
+                                flip_y: true,
+                                ..Default::default()
+                            }
+            );
+        } else {
+            draw_rectangle(-BORDER_SIZE, -BORDER_SIZE,
+                           WIDTH as f32 + BORDER_SIZE * 2.,
+                           HEIGHT as f32 + BORDER_SIZE * 2.,
+                           DARKGRAY
+            );
+        }
         draw_texture_ex(texture, 0.0, 0.0, WHITE,
                         DrawTextureParams{
                             flip_y: true,
                             ..Default::default()
                         }
         );
+        // The camera has y going up (hence the flipped textures), so the outline is measured from the bottom
+        if let Some((x, y, width, height)) = app.selected_sprite_rect().filter(|_| app.show_oam_view) {
+            draw_rectangle_lines(x, HEIGHT as f32 - y - height, width, height, 1.0, RED);
+        }
         egui_macroquad::draw();
         next_frame().await
     }
@@ -166,6 +223,20 @@ fn fb_to_texture2d(framebuffer: &[[u32; 144]; 160]) -> Texture2D {
     texture
 }
 
+fn border_to_texture2d(border: &[u32]) -> Texture2D {
+    let mut bytes: Vec<u8> = Vec::from([0; sgb::BORDER_WIDTH * sgb::BORDER_HEIGHT * 4]);
+    for (i, rgb) in border.iter().enumerate() {
+        let offset = i * 4;
+        bytes[offset + 0] = ((rgb & 0xFF0000) >> 16) as u8;
+        bytes[offset + 1] = ((rgb & 0x00FF00) >> 8) as u8;
+        bytes[offset + 2] = (rgb & 0x0000FF) as u8;
+        bytes[offset + 3] = 255;
+    }
+    let texture = Texture2D::from_rgba8(sgb::BORDER_WIDTH as u16, sgb::BORDER_HEIGHT as u16, &bytes);
+    texture.set_filter(FilterMode::Nearest);
+    texture
+}
+
 fn setup_custom_fonts(ctx: &egui::Context) {
     let mut fonts = egui::FontDefinitions::default();
 
@@ -14,12 +14,20 @@ impl App {
 
                     }
                     if ui.button("Reset system").clicked() {
-                        self.cpu.reset();
+                        self.system.reset();
                     }
                 });
                 ui.menu_button("View", |ui| {
                     ui.checkbox(&mut self.show_state_view, "System state");
                     ui.checkbox(&mut self.show_tileset_view, "Tileset");
+                    ui.checkbox(&mut self.show_tile_map_view, "Tile map");
+                    ui.checkbox(&mut self.show_oam_view, "OAM");
+                    ui.checkbox(&mut self.show_io_registers_view, "IO registers");
+                    ui.checkbox(&mut self.show_disassembly_view, "Disassembly");
+                    ui.checkbox(&mut self.show_call_stack_view, "Call stack");
+                    ui.checkbox(&mut self.show_profiler_view, "Profiler");
+                    ui.checkbox(&mut self.show_coverage_view, "Code/data log");
+                    ui.checkbox(&mut self.show_heatmap_view, "Memory heatmap");
                     ui.checkbox(&mut self.show_log_view, "Logs");
                     ui.checkbox(&mut self.show_control_view, "Control");
                     ui.checkbox(&mut self.show_mem_editor, "Memory editor");
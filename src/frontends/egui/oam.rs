@@ -0,0 +1,68 @@
+use egui::{Color32, ColorImage, Context, RichText, TextureOptions};
+use metalboy::graphics::{self, Graphics, Sprite, SpriteVisibility, LCD_CONTROL, SPRITE_COUNT};
+use crate::app::App;
+use crate::common::*;
+
+const PREVIEW_SCALE: f32 = 3.;
+
+impl App {
+    pub fn show_oam(&mut self, egui_ctx: &Context) {
+        egui::Window::new("OAM").show(egui_ctx, |ui| {
+            let mmu = &self.system.cpu.mmu;
+            let height = graphics::sprite_height(mmu.get(LCD_CONTROL));
+            let sprites: Vec<Sprite> = (0..SPRITE_COUNT).map(|i| Sprite::read(mmu, i)).collect();
+            let visibility = graphics::sprite_visibility(&sprites, height);
+            ui.label(RichText::new(format!("8x{} sprites, click one to find it on the screen", height)).color(HEADER_COLOUR));
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("oam").striped(true).show(ui, |ui| {
+                    for heading in ["#", "", "Y", "X", "Tile", "Palette", "Flip", "Priority", ""] {
+                        ui.label(RichText::new(heading).color(HEADER_COLOUR));
+                    }
+                    ui.end_row();
+                    for (i, sprite) in sprites.iter().enumerate() {
+                        let selected = self.selected_sprite == Some(i);
+                        if ui.selectable_label(selected, RichText::new(format!("{:02}", i)).monospace()).clicked() {
+                            self.selected_sprite = if selected { None } else { Some(i) };
+                        }
+
+                        let palette = mmu.get(0xFF48 + sprite.palette() as u16);
+                        let mut image = ColorImage::new([8, height as usize], Color32::TRANSPARENT);
+                        for (pixel, colour_no) in image.pixels.iter_mut().zip(sprite.pixels(mmu, height)) {
+                            if colour_no != 0 {
+                                *pixel = SHADES[Graphics::get_shade(colour_no, palette) as usize];
+                            }
+                        }
+                        let texture = ui.ctx().load_texture(format!("sprite{}", i), image, TextureOptions::NEAREST);
+                        ui.image(&texture, texture.size_vec2() * PREVIEW_SCALE);
+
+                        ui.monospace(format!("{:3} ({:4})", sprite.y, sprite.screen_y()));
+                        ui.monospace(format!("{:3} ({:4})", sprite.x, sprite.screen_x()));
+                        ui.monospace(format!("{:02X}", sprite.tile));
+                        ui.monospace(format!("OBP{}", sprite.palette()));
+                        let flips = [(sprite.x_flip(), "X"), (sprite.y_flip(), "Y")];
+                        let flips: Vec<&str> = flips.iter().filter(|(flipped, _)| *flipped).map(|(_, axis)| *axis).collect();
+                        ui.monospace(if flips.is_empty() { "-".to_string() } else { flips.join(" ") });
+                        ui.monospace(if sprite.behind_background() { "Behind BG" } else { "Above BG" });
+                        let (text, colour) = match visibility[i] {
+                            SpriteVisibility::Visible => ("Visible", Color32::GRAY),
+                            SpriteVisibility::OffScreen => ("Off screen", Color32::DARK_GRAY),
+                            SpriteVisibility::PartlyDropped => ("Partly dropped", BREAK_COLOUR),
+                            SpriteVisibility::Dropped => ("Dropped", BREAK_COLOUR),
+                        };
+                        ui.label(RichText::new(text).color(colour)).on_hover_text("Only ten sprites are drawn on each line");
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    }
+
+    // Where the sprite picked in the OAM window is on the screen, as x, y, width and height in pixels
+    pub fn selected_sprite_rect(&self) -> Option<(f32, f32, f32, f32)> {
+        let mmu = &self.system.cpu.mmu;
+        let sprite = Sprite::read(mmu, self.selected_sprite?);
+        let height = graphics::sprite_height(mmu.get(LCD_CONTROL));
+        Some((sprite.screen_x() as f32, sprite.screen_y() as f32, 8., height as f32))
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use egui::{Context, RichText};
+use metalboy::profiler::{self, Profiler};
+use crate::app::App;
+use crate::common::*;
+
+const FRAME_CYCLES: u64 = 70224; // 154 lines of 456 dots, twice as many CPU cycles in double speed
+const MAX_ROWS: usize = 40;
+const REFRESH_FRAMES: u32 = 30; // Naming the rows takes a while with lots of symbols, so it's only redone every half second
+
+impl App {
+    pub fn show_profiler(&mut self, egui_ctx: &Context) {
+        egui::Window::new("Profiler").show(egui_ctx, |ui| {
+            ui.horizontal(|ui| {
+                let recording = self.system.profiler.is_some();
+                if ui.button(if recording { "Stop" } else { "Start" }).clicked() {
+                    match self.system.profiler.take() {
+                        Some(profiler) => self.stopped_profiler = Some(profiler),
+                        None => self.system.profiler = Some(self.stopped_profiler.take().unwrap_or_default()),
+                    }
+                }
+                if ui.button("Reset").clicked() {
+                    let profiler = self.system.profiler.as_mut().or(self.stopped_profiler.as_mut());
+                    if let Some(profiler) = profiler {
+                        *profiler = Profiler::new();
+                    }
+                    self.profile_rows.clear();
+                }
+                if ui.button("Save folded stacks").on_hover_text("For flamegraph.pl").clicked() {
+                    if let Some(profiler) = self.system.profiler.as_ref().or(self.stopped_profiler.as_ref()) {
+                        let path = profiler::folded_path(&self.rom_path);
+                        self.profiler_message = Some(match fs::write(&path, profiler.folded(&self.labels)) {
+                            Ok(()) => format!("Saved {}", path.display()),
+                            Err(e) => format!("Unable to write {}: {}", path.display(), e),
+                        });
+                    }
+                }
+                if ui.checkbox(&mut self.profile_per_function, "Per function").changed() {
+                    self.profile_age = REFRESH_FRAMES;
+                }
+            });
+            if let Some(message) = &self.profiler_message {
+                ui.label(message);
+            }
+            let Some(profiler) = self.system.profiler.as_ref().or(self.stopped_profiler.as_ref()) else {
+                ui.label("Start profiling to see where the cycles go");
+                return;
+            };
+
+            let budget = FRAME_CYCLES * self.system.cpu.mmu.speed_factor() as u64;
+            let per_frame = match profiler.cycles_per_frame() {
+                Some(cycles) => format!("{} per frame, {}% of the budget", cycles, cycles * 100 / budget),
+                None => "less than a frame".to_string(),
+            };
+            ui.label(RichText::new(format!("{} cycles over {} frames, {}", profiler.total.cycles, profiler.frames, per_frame)).color(HEADER_COLOUR));
+
+            self.profile_age += 1;
+            if self.profile_age >= REFRESH_FRAMES {
+                self.profile_rows = profiler.hot_spots(&self.labels, self.profile_per_function);
+                self.profile_rows.truncate(MAX_ROWS);
+                self.profile_age = 0;
+            }
+            let total = profiler.total.cycles.max(1);
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("hot_spots").striped(true).show(ui, |ui| {
+                    for heading in ["", "Executed", "Cycles", "%"] {
+                        ui.label(RichText::new(heading).color(HEADER_COLOUR));
+                    }
+                    ui.end_row();
+                    for row in &self.profile_rows {
+                        ui.monospace(&row.name);
+                        ui.monospace(row.sample.executed.to_string());
+                        ui.monospace(row.sample.cycles.to_string());
+                        ui.monospace(format!("{:.1}", row.sample.cycles as f64 * 100.0 / total as f64));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    }
+}
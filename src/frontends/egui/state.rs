@@ -10,30 +10,34 @@ impl App {
             // CPU
             ui.horizontal_wrapped(|ui| {
                 self.header("CPU Info", ui);
-                ui.label(format!("({:?})", self.cpu.status));
+                ui.label(format!("({:?})", self.system.cpu.status));
             });
             ui.horizontal_wrapped(|ui| {
                 self.label_bold("PC:", ui);
-                ui.label(format!("{:04X} ", self.cpu.reg.pc));
+                ui.label(format!("{:04X} ", self.system.cpu.reg.pc));
                 self.label_bold("OP:", ui);
-                ui.label(format!("{:02X} ", self.cpu.opcode));
+                ui.label(format!("{:02X} ", self.system.cpu.opcode));
                 self.label_bold("SP:", ui);
-                ui.label(format!("{:02X} ", self.cpu.reg.sp));
+                ui.label(format!("{:02X} ", self.system.cpu.reg.sp));
+            });
+            ui.horizontal_wrapped(|ui| {
+                self.label_bold("AT:", ui);
+                ui.label(self.pc_location());
             });
             ui.horizontal_wrapped(|ui| {
                 self.label_bold("NEXT OP:", ui);
-                ui.label(format!("{:02X} ", self.cpu.mmu.get(self.cpu.reg.pc + 1)));
+                ui.label(format!("{:02X} ", self.system.cpu.mmu.get(self.system.cpu.reg.pc + 1)));
             });
             ui.separator();
 
             // MMU
             ui.horizontal_wrapped(|ui| {
                 self.header("MMU Info", ui);
-                ui.label(format!("(Cart uses MBC{})", self.cpu.mmu.cartridge.mbc));
+                ui.label(format!("(Cart uses MBC{})", self.system.cpu.mmu.cartridge.mbc));
             });
             ui.horizontal_wrapped(|ui| {
                 self.label_bold("ROM BANK:", ui);
-                ui.label(format!("{:02X} ", self.cpu.mmu.rom_bank));
+                ui.label(format!("{:02X} ", self.system.cpu.mmu.rom_bank));
             });
             ui.separator();
 
@@ -41,11 +45,11 @@ impl App {
             self.header("Timers", ui);
             ui.horizontal_wrapped(|ui| {
                 self.label_bold("DIV:", ui);
-                ui.label(format!("{:02X} ", self.cpu.mmu.get(timer::DIV)));
+                ui.label(format!("{:02X} ", self.system.cpu.mmu.get(timer::DIV)));
                 self.label_bold("TIMA:", ui);
-                ui.label(format!("{:02X} ", self.cpu.mmu.get(timer::TIMA)));
+                ui.label(format!("{:02X} ", self.system.cpu.mmu.get(timer::TIMA)));
                 self.label_bold("TMA:", ui);
-                ui.label(format!("{:02X} ", self.cpu.mmu.get(timer::TMA)));
+                ui.label(format!("{:02X} ", self.system.cpu.mmu.get(timer::TMA)));
             });
             ui.separator();
 
@@ -55,36 +59,36 @@ impl App {
                 columns[0].with_layout(egui::Layout::top_down(Align::Center), |ui| {
                     ui.horizontal_wrapped(|ui| {
                         self.label_bold("AF:", ui);
-                        ui.label(format!("{:02X} {:02X}", self.cpu.reg.a, self.cpu.reg.f.as_u8()));
+                        ui.label(format!("{:02X} {:02X}", self.system.cpu.reg.a, self.system.cpu.reg.f.as_u8()));
                     }); // AF
                     ui.horizontal_wrapped(|ui| {
                         self.label_bold("BC:", ui);
-                        ui.label(format!("{:02X} {:02X}", self.cpu.reg.b, self.cpu.reg.c));
+                        ui.label(format!("{:02X} {:02X}", self.system.cpu.reg.b, self.system.cpu.reg.c));
                     }); // BC
                     ui.horizontal_wrapped(|ui| {
                         self.label_bold("DE:", ui);
-                        ui.label(format!("{:02X} {:02X}", self.cpu.reg.d, self.cpu.reg.e));
+                        ui.label(format!("{:02X} {:02X}", self.system.cpu.reg.d, self.system.cpu.reg.e));
                     }); // DE
                     ui.horizontal_wrapped(|ui| {
                         self.label_bold("HL:", ui);
-                        ui.label(format!("{:02X} {:02X}", self.cpu.reg.h, self.cpu.reg.l));
+                        ui.label(format!("{:02X} {:02X}", self.system.cpu.reg.h, self.system.cpu.reg.l));
                     }); // HL
                 });
                 columns[1].with_layout(egui::Layout::top_down(Align::TOP), |ui| {
                     ui.add_enabled(false, egui::SelectableLabel::new(
-                        self.cpu.reg.f.zero,
+                        self.system.cpu.reg.f.zero,
                         "Zero"
                     ));
                     ui.add_enabled(false, egui::SelectableLabel::new(
-                        self.cpu.reg.f.sub,
+                        self.system.cpu.reg.f.sub,
                         "Sub"
                     ));
                     ui.add_enabled(false, egui::SelectableLabel::new(
-                        self.cpu.reg.f.half_carry,
+                        self.system.cpu.reg.f.half_carry,
                         "Half-carry"
                     ));
                     ui.add_enabled(false, egui::SelectableLabel::new(
-                        self.cpu.reg.f.carry,
+                        self.system.cpu.reg.f.carry,
                         "Carry"
                     ));
                 });
@@ -93,12 +97,12 @@ impl App {
 
             // Columnar view of register values and set flags
             self.header("Interrupts", ui);
-            let int_enable = self.cpu.mmu.get(0xFFFF);
-            let int_flag = self.cpu.mmu.get(0xFF0F);
+            let int_enable = self.system.cpu.mmu.get(0xFFFF);
+            let int_flag = self.system.cpu.mmu.get(0xFF0F);
             ui.columns(2, |columns| {
                 columns[0].with_layout(egui::Layout::top_down(Align::Center), |ui| {
                     ui.add_enabled(false, egui::SelectableLabel::new(
-                        self.cpu.ime,
+                        self.system.cpu.ime,
                         "IME"
                     ));
                     ui.add_enabled(false, egui::SelectableLabel::new(
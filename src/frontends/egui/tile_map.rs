@@ -0,0 +1,90 @@
+use egui::{Color32, ColorImage, Context, Pos2, Rect, RichText, Stroke, TextureOptions, Vec2};
+use metalboy::graphics::{self, LCD_CONTROL, SCROLL_X, SCROLL_Y, TILE_MAP_0, TILE_MAP_1, TILE_MAP_SIZE, WINDOW_X, WINDOW_Y};
+use crate::app::App;
+use crate::common::*;
+
+const MAP_SCALE: f32 = 2.;
+const SCREEN_SIZE: (f32, f32) = (160., 144.);
+const VIEWPORT_COLOUR: Color32 = Color32::from_rgb(255, 80, 80);
+const WINDOW_COLOUR: Color32 = Color32::from_rgb(80, 160, 255);
+
+// A rectangle on the map in map pixels, split where it wraps around the edges
+fn wrapped(x: f32, y: f32, width: f32, height: f32) -> Vec<Rect> {
+    let size = TILE_MAP_SIZE as f32;
+    let mut rects = vec![];
+    for (x, width) in [(x, width.min(size - x)), (0., x + width - size)] {
+        for (y, height) in [(y, height.min(size - y)), (0., y + height - size)] {
+            if width > 0. && height > 0. {
+                rects.push(Rect::from_min_size(Pos2::new(x, y), Vec2::new(width, height)));
+            }
+        }
+    }
+    rects
+}
+
+impl App {
+    pub fn show_tile_map(&mut self, egui_ctx: &Context) {
+        egui::Window::new("Tile Map").show(egui_ctx, |ui| {
+            let mmu = &self.system.cpu.mmu;
+            let control = mmu.get(LCD_CONTROL);
+            ui.horizontal(|ui| {
+                for map in [TILE_MAP_0, TILE_MAP_1] {
+                    let mut uses = vec![];
+                    if graphics::background_map(control) == map { uses.push("BG") }
+                    if graphics::window_map(control) == map { uses.push("Window") }
+                    let text = match uses.is_empty() {
+                        true => format!("{:04X}", map),
+                        false => format!("{:04X} ({})", map, uses.join(", ")),
+                    };
+                    ui.radio_value(&mut self.tile_map, map, text);
+                }
+            });
+            let unsigned = control & 0x10 != 0;
+            ui.label(RichText::new(format!("Tile data at {}", if unsigned { "8000, unsigned" } else { "8800, signed" })).color(HEADER_COLOUR));
+
+            let shades = graphics::tile_map_shades(mmu, self.tile_map);
+            let mut image = ColorImage::new([TILE_MAP_SIZE, TILE_MAP_SIZE], Color32::BLACK);
+            for (pixel, shade) in image.pixels.iter_mut().zip(shades) {
+                *pixel = SHADES[shade as usize];
+            }
+            let texture = ui.ctx().load_texture("tile_map", image, TextureOptions::NEAREST);
+            let response = ui.image(&texture, texture.size_vec2() * MAP_SCALE);
+            let painter = ui.painter_at(response.rect);
+            let on_screen = |rect: Rect| Rect::from_min_size(response.rect.min + rect.min.to_vec2() * MAP_SCALE, rect.size() * MAP_SCALE);
+
+            // What the screen shows of the background, wrapping around the map
+            let (scroll_x, scroll_y) = (mmu.get(SCROLL_X) as f32, mmu.get(SCROLL_Y) as f32);
+            if graphics::background_map(control) == self.tile_map {
+                for rect in wrapped(scroll_x, scroll_y, SCREEN_SIZE.0, SCREEN_SIZE.1) {
+                    painter.rect_stroke(on_screen(rect), 0., Stroke::new(1., VIEWPORT_COLOUR));
+                }
+            }
+            // The window is drawn from the map's top left, at WX-7,WY on the screen
+            let (window_x, window_y) = (mmu.get(WINDOW_X) as f32 - 7., mmu.get(WINDOW_Y) as f32);
+            let window_enabled = control & 0x20 != 0;
+            let window_visible = window_enabled && window_x < SCREEN_SIZE.0 && window_y < SCREEN_SIZE.1;
+            if window_visible && graphics::window_map(control) == self.tile_map {
+                let start = Pos2::new((-window_x).max(0.), 0.); // WX below 7 cuts off the window's left edge
+                let size = Vec2::new(SCREEN_SIZE.0 - window_x.max(0.), SCREEN_SIZE.1 - window_y);
+                painter.rect_stroke(on_screen(Rect::from_min_size(start, size)), 0., Stroke::new(1., WINDOW_COLOUR));
+            }
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(format!("SCX {:3} SCY {:3}", scroll_x, scroll_y)).monospace().color(VIEWPORT_COLOUR));
+                let state = if !window_enabled { "off" } else if window_visible { "on" } else { "off screen" };
+                ui.label(RichText::new(format!("Window {:3},{:3} ({})", window_x, window_y, state)).monospace().color(WINDOW_COLOUR));
+            });
+
+            if let Some(position) = response.hover_pos() {
+                let pixel = (position - response.rect.min) / MAP_SCALE;
+                let (column, row) = ((pixel.x as u16 / 8).min(31), (pixel.y as u16 / 8).min(31));
+                let entry = self.tile_map + row * 32 + column;
+                let tile_no = mmu.get_vram(0, entry);
+                let text = format!(
+                    "Tile {},{} at {:04X}\nIndex {:02X}, data at {:04X}",
+                    column, row, entry, tile_no, graphics::tile_data_address(tile_no, unsigned),
+                );
+                response.on_hover_text(RichText::new(text).monospace());
+            }
+        });
+    }
+}
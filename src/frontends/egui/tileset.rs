@@ -21,7 +21,7 @@ impl App {
     }
 
     fn render(&mut self) -> Option<ColorImage> {
-        let vram = &self.cpu.mmu.memory[0x8000 % 0x8000..0x9800 % 0x8000];
+        let vram = &self.system.cpu.mmu.vram[0][..0x1800];
         // Return if VRAM hasn't changed since the last run
         if vram == self.old_tileset_vram {
             return None;
@@ -31,12 +31,12 @@ impl App {
         trace!("[app/tileset] Rendering a new tileset image");
 
         let mut image = ColorImage::new([128, 192], Default::default());
-        let palette = self.cpu.mmu.get(0xFF47);
+        let palette = self.system.cpu.mmu.get(0xFF47);
 
         for tile_no in 0..384 {
             // Tiles are 16-bytes in length, tile 0 is at 0x8000, tile 1 is at 0x8010, etc.
             let tile_address = 0x8000 + (tile_no * 0x10);
-            let tile_data = &vram[tile_address % 0x8000 .. (tile_address + 0x10) % 0x8000];
+            let tile_data = &vram[tile_address - 0x8000 .. tile_address + 0x10 - 0x8000];
 
             for (tile_y, line) in tile_data.chunks(2).enumerate() {
                 let (d1, d2) = (line[0], line[1]);
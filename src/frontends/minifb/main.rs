@@ -1,33 +1,54 @@
 extern crate log;
-use metalboy::cpu::{Cpu, CLOCK_SPEED};
-use metalboy::graphics::Graphics;
+use metalboy::cpu::CLOCK_SPEED;
+use metalboy::args;
+use metalboy::sgb;
+use metalboy::system::System;
 use std::env;
 use std::process;
 extern crate minifb;
 use minifb::{Key, Scale, ScaleMode, Window, WindowOptions};
 use metalboy::cpu;
 use metalboy::cpu::Status::InfiniteLoop;
-use metalboy::joypad::{Button, Joypad};
+use metalboy::joypad::Button;
 
 // const SCALE: usize = 3;
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
 
+fn exit_with(message: String) -> ! {
+    println!("{}", message);
+    process::exit(-1);
+}
+
 fn main() {
     // Initialise the logger
     env_logger::init();
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let model = args::take_model(&mut args).unwrap_or_else(|e| exit_with(e));
+    let boot_rom = args::take_boot_rom(&mut args, model).unwrap_or_else(|e| exit_with(e));
+    let tracer = args::take_tracer(&mut args).unwrap_or_else(|e| exit_with(e));
     if args.len() < 2 {
         println!("You must provide a ROM file");
         process::exit(-1);
     }
 
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    // Create the system
+    let mut system = System::new(model);
+    system.load_cartridge(&args[1]);
+    system.set_boot_rom(boot_rom);
+    system.tracer = tracer;
+
+    // The SGB draws its border around the Game Boy screen
+    let (width, height) = match system.sgb_border() {
+        Some(_) => (sgb::BORDER_WIDTH, sgb::BORDER_HEIGHT),
+        None => (WIDTH, HEIGHT),
+    };
+    let mut buffer: Vec<u32> = vec![0; width * height];
 
     let mut window = Window::new(
         "metalboy",
-        WIDTH,
-        HEIGHT,
+        width,
+        height,
         WindowOptions {
             borderless: false,
             title: true,
@@ -43,28 +64,25 @@ fn main() {
     // Limit to max ~60 fps update rate
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
-    // Create CPU
-    let mut cpu = Cpu::new();
-    cpu.mmu.cartridge.load(&args[1]);
-    // cpu.mmu.load_bootrom("bootix_dmg.bin");
-    cpu.reg.pc = 0x100;
-    cpu.mmu.bootrom_mapped = false;
-    cpu.mmu.set_initial_state();
-
-    let mut graphics = Graphics::new();
-
     // Emulation loop
     let mut cycles = 0;
     let mut _cycle_count = 0;
+    let mut stuck_reported = false;
     let max_cycles = CLOCK_SPEED / 60;
     let _quit_at = 7000000 * 100;
     let _max_warnings = 1;
 
     'running: while window.is_open() && !window.is_key_down(Key::Escape) {
         for (i, pixel) in buffer.iter_mut().enumerate() {
-            let col = i % WIDTH;
-            let row = i / WIDTH;
-            *pixel = graphics.fb[col][row];
+            let col = i % width;
+            let row = i / width;
+            *pixel = match system.sgb_border() {
+                Some(border) => {
+                    let (x, y) = (col.wrapping_sub(sgb::SCREEN_X), row.wrapping_sub(sgb::SCREEN_Y));
+                    if x < WIDTH && y < HEIGHT { system.graphics.fb[x][y] } else { border[i] }
+                },
+                None => system.graphics.fb[col][row],
+            };
         }
 
         let mut pressed: Vec<Button> = Vec::new();
@@ -79,23 +97,36 @@ fn main() {
             Key::A => pressed.push(Button::Select),
             _ => (),
         });
+        // SGB controller 2, for games that support multiplayer
+        let mut player_2: Vec<Button> = Vec::new();
+        window.get_keys().iter().for_each(|key| match key {
+            Key::I => player_2.push(Button::Up),
+            Key::K => player_2.push(Button::Down),
+            Key::J => player_2.push(Button::Left),
+            Key::L => player_2.push(Button::Right),
+            Key::Period => player_2.push(Button::A),
+            Key::Comma => player_2.push(Button::B),
+            Key::Enter => player_2.push(Button::Start),
+            Key::RightShift => player_2.push(Button::Select),
+            _ => (),
+        });
+        system.set_player_input(1, &player_2);
 
         // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
         window
-            .update_with_buffer(&buffer, WIDTH, HEIGHT)
+            .update_with_buffer(&buffer, width, height)
             .unwrap();
 
         // One second of CPU execution ~ 4194304 cycles
-        while cycles < max_cycles && cpu.status != InfiniteLoop {
-            cpu.tick(); // Advance the CPU
-            cycles += cpu.cycles * 4; // FIXME: Is this M-cycles or actual cycles?
+        while cycles < max_cycles && system.cpu.status != InfiniteLoop {
+            cycles += system.step(&pressed); // FIXME: Is this M-cycles or actual cycles?
             _cycle_count += cycles;
-            cpu.timer.update(&mut cpu.mmu, cpu.cycles * 4);
-            graphics.update(&mut cpu.mmu, cpu.cycles * 4);
-            Joypad::update(&mut cpu.mmu, &pressed);
-            cpu.service_interrupts();
         }
         cycles = 0;
+        if system.cpu.status == InfiniteLoop && !stuck_reported {
+            eprintln!("Stuck in an infinite loop, backtrace:\n{}", system.backtrace());
+            stuck_reported = true;
+        }
         // if cpu.status == InfiniteLoop {
         //     break 'running;
         // }
@@ -0,0 +1,38 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use metalboy::args;
+use metalboy::testrom;
+
+fn exit_with(message: String) -> ! {
+    println!("{}", message);
+    process::exit(-1);
+}
+
+// Run every test ROM in a directory and print a compatibility table
+fn main() {
+    env_logger::init();
+    let mut args: Vec<String> = env::args().collect();
+    let model = args::take_model(&mut args).unwrap_or_else(|e| exit_with(e));
+    let timeout = match args::take_value(&mut args, "--timeout").unwrap_or_else(|e| exit_with(e)) {
+        Some(seconds) => seconds.parse().unwrap_or_else(|_| {
+            println!("--timeout expects a number of seconds");
+            process::exit(-1);
+        }),
+        None => testrom::DEFAULT_TIMEOUT,
+    };
+    if args.len() < 2 {
+        println!("Usage: metalboy-testrom <rom or directory> [--model <model>] [--timeout <seconds>]");
+        process::exit(-1);
+    }
+
+    let root = Path::new(&args[1]);
+    let roms = if root.is_dir() { testrom::find_roms(root) } else { vec![root.to_path_buf()] };
+    let mut results = vec![];
+    for rom in roms {
+        let result = testrom::run(&rom, model, timeout);
+        eprintln!("{}: {}", rom.display(), result.outcome);
+        results.push(result);
+    }
+    print!("{}", testrom::table(&results, root));
+}
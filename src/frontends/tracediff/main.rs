@@ -0,0 +1,36 @@
+use std::env;
+use std::process;
+use metalboy::args;
+use metalboy::trace::TraceReader;
+use metalboy::tracediff::{self, DEFAULT_CONTEXT};
+
+// Compare our trace against another emulator's and report where they first disagree
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let context = match args::take_value(&mut args, "--context").unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(-1);
+    }) {
+        Some(count) => count.parse().unwrap_or_else(|_| {
+            println!("--context expects a number of instructions");
+            process::exit(-1);
+        }),
+        None => DEFAULT_CONTEXT,
+    };
+    if args.len() < 3 {
+        println!("Usage: metalboy-tracediff <our trace> <reference trace> [--context <instructions>]");
+        process::exit(-1);
+    }
+
+    let open = |path: &str| TraceReader::open(path).unwrap_or_else(|e| {
+        println!("Unable to open {}: {}", path, e);
+        process::exit(-1);
+    });
+    match tracediff::first_divergence(open(&args[1]), open(&args[2]), context) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+        None => println!("Traces are identical"),
+    }
+}
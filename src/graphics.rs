@@ -1,17 +1,31 @@
+use std::io;
 use crate::mmu::Mmu;
 use crate::check_bit;
 use crate::graphics::TileNumber::{Signed, Unsigned};
+use crate::savestate::{StateReader, StateWriter};
 
 const SCANLINE_RESET: i32 = 456;
+pub const SCANLINE_CYCLES: usize = SCANLINE_RESET as usize;
+pub const FRAME_CYCLES: usize = SCANLINE_CYCLES * 154; // Including the 10 lines of VBlank
 pub const LCD_CONTROL: u16 = 0xFF40;
 pub const SCROLL_Y: u16 = 0xFF42;
 pub const SCROLL_X: u16 = 0xFF43;
 pub const WINDOW_Y: u16 = 0xFF4A;
 pub const WINDOW_X: u16 = 0xFF4B;
-
+pub const BG_PALETTE: u16 = 0xFF47;
+pub const TILE_MAP_0: u16 = 0x9800;
+pub const TILE_MAP_1: u16 = 0x9C00;
+pub const TILE_MAP_SIZE: usize = 256; // Pixels square, 32x32 tiles
+pub const OAM: u16 = 0xFE00;
+pub const SPRITE_COUNT: usize = 40;
+pub const SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone)]
 pub struct Graphics {
     pub fb: [[u32; 144]; 160],
+    pub shades: [[u8; 144]; 160], // Palette-mapped shade of every pixel, used for SGB colours
     pub scanline_count: i32,
+    pub frame: u64, // VBlanks since the last reset
 }
 
 pub enum TileNumber {
@@ -23,11 +37,13 @@ impl Graphics {
     pub fn new() -> Self {
         Graphics {
             fb: [[0xFFFFFF; 144]; 160],
+            shades: [[0; 144]; 160],
             scanline_count: SCANLINE_RESET,
+            frame: 0,
         }
     }
 
-    fn lcd_enabled(&self, mmu: &Mmu) -> bool {
+    pub fn lcd_enabled(&self, mmu: &Mmu) -> bool {
         (mmu.get(LCD_CONTROL) >> 7) & 1 == 1
     }
 
@@ -44,7 +60,12 @@ impl Graphics {
 
             // VBlank Period
             if current_line == 144 {
+                self.frame += 1;
                 mmu.request_interrupt(0);
+                if let Some(mut sgb) = mmu.sgb.take() {
+                    sgb.vblank(mmu, &mut self.fb, &self.shades);
+                    mmu.sgb = Some(sgb);
+                }
             }
             // Reset back to scanline 0
             else if current_line > 153 {
@@ -53,6 +74,7 @@ impl Graphics {
             // Draw the scanline
             else if current_line < 144 {
                 self.draw_scanline(mmu);
+                mmu.hblank();
             }
 
             mmu.set(0xFF44, mmu.get(0xFF44) + 1);
@@ -70,10 +92,14 @@ impl Graphics {
         }
     }
 
-    pub fn get_colour(&mut self, colour_no: u8, palette: u8) -> u32 {
+    pub fn get_shade(colour_no: u8, palette: u8) -> u8 {
         let left = check_bit(palette, (colour_no * 2) + 1) as u8;
         let right = check_bit(palette, colour_no * 2) as u8;
-        let colour = (left << 1) | right;
+        (left << 1) | right
+    }
+
+    pub fn get_colour(&mut self, colour_no: u8, palette: u8) -> u32 {
+        let colour = Self::get_shade(colour_no, palette);
 
         match colour {
             0 => 0x8bac0f,
@@ -86,7 +112,6 @@ impl Graphics {
 
     fn render_tiles(&mut self, mmu: &mut Mmu) {
         let control = mmu.get(LCD_CONTROL);
-        let tile_data: u16;
         let bg_memory: u16;
 
         // Get boundaries
@@ -98,17 +123,16 @@ impl Graphics {
         // Check if the window is enabled
         let window_enabled = check_bit(control, 5) && window_y <= mmu.get(0xFF44);
 
-        // Set tile data location & sign
+        // Set tile data sign
         let unsigned = check_bit(control, 4);
-        tile_data = if unsigned { 0x8000 } else { 0x8800 };
 
         // Set window/background memory location & tile pos
         let y: u8;
         if window_enabled {
-            bg_memory = if check_bit(control, 6) { 0x9C00 } else { 0x9800 };
+            bg_memory = window_map(control);
             y = mmu.get(0xFF44).wrapping_sub(window_y);
         } else {
-            bg_memory = if check_bit(control, 3) { 0x9C00 } else { 0x9800 };
+            bg_memory = background_map(control);
             y = mmu.get(0xFF44).wrapping_add(scroll_y);
         }
 
@@ -122,20 +146,11 @@ impl Graphics {
 
             let tile_column = (x / 8) as u16;
             let tile_address = bg_memory + tile_row + tile_column;
-            let tile_no: TileNumber = if unsigned {
-                Unsigned(mmu.get(tile_address) as u8)
-            } else {
-                Signed(mmu.get(tile_address) as i8)
-            };
-
-            let tile_location: u16 = match tile_no {
-                Unsigned(n) => tile_data + (u16::wrapping_mul(n as u16, 16)),
-                Signed(n) => tile_data + u16::wrapping_mul(u16::wrapping_add(n as u16, 128), 16)
-            };
+            let tile_location = tile_data_address(mmu.get_vram(0, tile_address), unsigned);
 
             let line: u8 = (y % 8) * 2;
-            let data_1 = mmu.get(tile_location + line as u16);
-            let data_2 = mmu.get(tile_location + line as u16 + 1);
+            let data_1 = mmu.get_vram(0, tile_location + line as u16);
+            let data_2 = mmu.get_vram(0, tile_location + line as u16 + 1);
 
             let colour_bit = (((x % 8) as i8 - 7) * -1) as u8;
             let mut colour_no = check_bit(data_2, colour_bit) as u8;
@@ -145,6 +160,7 @@ impl Graphics {
             if i < 160 && y < 144 {
                 let colour = self.get_colour(colour_no, mmu.get(0xFF47));
                 self.fb[i as usize][y as usize] = colour;
+                self.shades[i as usize][y as usize] = Self::get_shade(colour_no, mmu.get(0xFF47));
             }
         }
     }
@@ -175,8 +191,8 @@ impl Graphics {
                 line *= 2;
 
                 let tile_data_address = line as u16 + 0x8000 + (tile_location as u16 * 16);
-                let data_1 = mmu.get(tile_data_address);
-                let data_2 = mmu.get(tile_data_address + 1);
+                let data_1 = mmu.get_vram(0, tile_data_address);
+                let data_2 = mmu.get_vram(0, tile_data_address + 1);
 
                 for sprite_pixel in (0..8).rev() {
                     let mut colour_bit = sprite_pixel;
@@ -201,9 +217,234 @@ impl Graphics {
                     // Draw the pixel to the framebuffer
                     if pixel < 160 && scanline < 144 {
                         self.fb[pixel as usize][scanline as usize] = colour;
+                        self.shades[pixel as usize][scanline as usize] = Self::get_shade(colour_no, palette);
                     }
                 }
             }
         }
     }
-}
\ No newline at end of file
+    pub fn write_state(&self, out: &mut StateWriter) {
+        for (column, shades) in self.fb.iter().zip(&self.shades) {
+            column.iter().for_each(|pixel| out.u32(*pixel));
+            out.raw(shades);
+        }
+        out.u32(self.scanline_count as u32);
+        out.u64(self.frame);
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        for (column, shades) in self.fb.iter_mut().zip(&mut self.shades) {
+            for pixel in column.iter_mut() {
+                *pixel = input.u32()?;
+            }
+            input.fill(shades)?;
+        }
+        self.scanline_count = input.u32()? as i32;
+        self.frame = input.u64()?;
+        Ok(())
+    }
+}
+
+pub fn background_map(control: u8) -> u16 {
+    if check_bit(control, 3) { TILE_MAP_1 } else { TILE_MAP_0 }
+}
+
+pub fn window_map(control: u8) -> u16 {
+    if check_bit(control, 6) { TILE_MAP_1 } else { TILE_MAP_0 }
+}
+
+// Where a tile's 16 bytes are, LCDC bit 4 picks 0x8000 with unsigned indices or 0x9000 with signed ones
+pub fn tile_data_address(tile_no: u8, unsigned: bool) -> u16 {
+    let tile_no = if unsigned { Unsigned(tile_no) } else { Signed(tile_no as i8) };
+    match tile_no {
+        Unsigned(n) => 0x8000 + (u16::wrapping_mul(n as u16, 16)),
+        Signed(n) => 0x8800 + u16::wrapping_mul(u16::wrapping_add(n as u16, 128), 16)
+    }
+}
+
+// All 32x32 tiles of the map at `map` as BGP shades, a row at a time, addressed the way LCDC says
+pub fn tile_map_shades(mmu: &Mmu, map: u16) -> Vec<u8> {
+    let unsigned = check_bit(mmu.get(LCD_CONTROL), 4);
+    let palette = mmu.get(BG_PALETTE);
+    let mut shades = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+    for (i, shade) in shades.iter_mut().enumerate() {
+        let (x, y) = (i % TILE_MAP_SIZE, i / TILE_MAP_SIZE);
+        let tile_no = mmu.get_vram(0, map + (y / 8 * 32 + x / 8) as u16);
+        let line = tile_data_address(tile_no, unsigned) + (y % 8 * 2) as u16;
+        let bit = 7 - (x % 8) as u8;
+        let colour_no = (check_bit(mmu.get_vram(0, line + 1), bit) as u8) << 1 | check_bit(mmu.get_vram(0, line), bit) as u8;
+        *shade = Graphics::get_shade(colour_no, palette);
+    }
+    shades
+}
+
+// An OAM entry, with positions as stored so 0,0 is off the top left of the screen
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl Sprite {
+    pub fn read(mmu: &Mmu, index: usize) -> Self {
+        let address = OAM + index as u16 * 4;
+        Sprite { y: mmu.get(address), x: mmu.get(address + 1), tile: mmu.get(address + 2), attributes: mmu.get(address + 3) }
+    }
+
+    pub fn screen_x(&self) -> i16 {
+        self.x as i16 - 8
+    }
+
+    pub fn screen_y(&self) -> i16 {
+        self.y as i16 - 16
+    }
+
+    pub fn behind_background(&self) -> bool {
+        check_bit(self.attributes, 7) // Only shows through background colour 0
+    }
+
+    pub fn y_flip(&self) -> bool {
+        check_bit(self.attributes, 6)
+    }
+
+    pub fn x_flip(&self) -> bool {
+        check_bit(self.attributes, 5)
+    }
+
+    // OBP0 or OBP1
+    pub fn palette(&self) -> u8 {
+        check_bit(self.attributes, 4) as u8
+    }
+
+    // Where the tile is on the CGB, DMG games can leave bit 3 set and still get bank 0
+    pub fn vram_bank(&self, mmu: &Mmu) -> usize {
+        (mmu.cgb_mode && check_bit(self.attributes, 3)) as usize
+    }
+
+    pub fn on_line(&self, line: u8, height: u8) -> bool {
+        (self.screen_y()..self.screen_y() + height as i16).contains(&(line as i16))
+    }
+
+    // Colour numbers as drawn, flips applied and 0 being transparent, a row at a time
+    pub fn pixels(&self, mmu: &Mmu, height: u8) -> Vec<u8> {
+        let tile = if height == 16 { self.tile & 0xFE } else { self.tile }; // Bit 0 is ignored for 8x16 sprites
+        let bank = self.vram_bank(mmu);
+        let mut pixels = vec![0; 8 * height as usize];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let (x, y) = ((i % 8) as u8, (i / 8) as u8);
+            let row = if self.y_flip() { height - 1 - y } else { y };
+            let bit = if self.x_flip() { x } else { 7 - x };
+            let line = 0x8000 + tile as u16 * 16 + row as u16 * 2;
+            *pixel = (check_bit(mmu.get_vram(bank, line + 1), bit) as u8) << 1 | check_bit(mmu.get_vram(bank, line), bit) as u8;
+        }
+        pixels
+    }
+}
+
+pub fn sprite_height(control: u8) -> u8 {
+    if check_bit(control, 2) { 16 } else { 8 }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SpriteVisibility {
+    Visible,
+    OffScreen,
+    PartlyDropped, // Over the limit on some of its lines
+    Dropped, // Over the limit on every line it's on
+}
+
+/* What hardware shows of each sprite this frame: only the first ten on a line, in OAM order, are
+ * drawn, and sprites off screen to the left or right still count towards that. The renderer
+ * doesn't apply the limit yet, so dropped sprites still show up on our screen. */
+pub fn sprite_visibility(sprites: &[Sprite], height: u8) -> Vec<SpriteVisibility> {
+    let mut drawn = vec![0; sprites.len()];
+    let mut dropped = vec![0; sprites.len()];
+    for line in 0..144 {
+        let mut count = 0;
+        for (i, sprite) in sprites.iter().enumerate() {
+            if sprite.on_line(line, height) {
+                match count < SPRITES_PER_LINE {
+                    true => drawn[i] += 1,
+                    false => dropped[i] += 1,
+                }
+                count += 1;
+            }
+        }
+    }
+    sprites.iter().enumerate().map(|(i, sprite)| match (drawn[i], dropped[i]) {
+        (0, 0) => SpriteVisibility::OffScreen,
+        (0, _) => SpriteVisibility::Dropped,
+        _ if sprite.x == 0 || sprite.x >= 168 => SpriteVisibility::OffScreen,
+        (_, 0) => SpriteVisibility::Visible,
+        _ => SpriteVisibility::PartlyDropped,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphics::{sprite_visibility, tile_data_address, tile_map_shades, Sprite, SpriteVisibility, LCD_CONTROL, TILE_MAP_0, TILE_MAP_SIZE};
+    use crate::mmu::Mmu;
+
+    #[test]
+    fn tile_data_addressing() {
+        assert_eq!(tile_data_address(0, true), 0x8000);
+        assert_eq!(tile_data_address(0xFF, true), 0x8FF0);
+        assert_eq!(tile_data_address(0, false), 0x9000);
+        assert_eq!(tile_data_address(0x80, false), 0x8800);
+    }
+
+    #[test]
+    fn tile_map_follows_lcdc() {
+        let mut mmu = Mmu::new();
+        mmu.set(0xFF47, 0b11_10_01_00);
+        mmu.vram[0][TILE_MAP_0 as usize - 0x8000 + 33] = 1; // Second tile of the second row
+        mmu.vram[0][0x10] = 0x80; // Tile 1's top left pixel is colour 1
+        mmu.vram[0][0x1010] = 0xFF; // So are all of tile 1's top pixels with signed indices
+        mmu.set(LCD_CONTROL, 0x91);
+        let shades = tile_map_shades(&mmu, TILE_MAP_0);
+        assert_eq!(shades[8 * TILE_MAP_SIZE + 8], 1);
+        assert_eq!(shades[8 * TILE_MAP_SIZE + 9], 0);
+        mmu.set(LCD_CONTROL, 0x81);
+        let shades = tile_map_shades(&mmu, TILE_MAP_0);
+        assert_eq!(shades[8 * TILE_MAP_SIZE + 9], 1);
+    }
+
+    #[test]
+    fn ten_sprites_per_line() {
+        let sprite = |y, x| Sprite { y, x, tile: 0, attributes: 0 };
+        let mut sprites = vec![sprite(16, 8); 10]; // Fill line 0 to 7
+        sprites.push(sprite(20, 20)); // Dropped on lines 4 to 7, drawn on 8 to 11
+        sprites.push(sprite(16, 40)); // Never gets a slot
+        sprites.push(sprite(0, 8)); // Above the screen
+        sprites.push(sprite(100, 0)); // Left of the screen
+        let visibility = sprite_visibility(&sprites, 8);
+        assert_eq!(visibility[9], SpriteVisibility::Visible);
+        assert_eq!(visibility[10], SpriteVisibility::PartlyDropped);
+        assert_eq!(visibility[11], SpriteVisibility::Dropped);
+        assert_eq!(visibility[12], SpriteVisibility::OffScreen);
+        assert_eq!(visibility[13], SpriteVisibility::OffScreen);
+    }
+
+    #[test]
+    fn sprite_pixels_flipped() {
+        let mut mmu = Mmu::new();
+        mmu.vram[0][0x20] = 0x80; // Tile 2's top left pixel is colour 1
+        let mut sprite = Sprite { y: 16, x: 8, tile: 3, attributes: 0 };
+        assert_eq!(sprite.pixels(&mmu, 16)[0], 1); // The top half of an 8x16 sprite is the even tile
+        sprite.attributes = 0x60; // Both flips
+        assert_eq!(sprite.pixels(&mmu, 16)[16 * 8 - 1], 1);
+        assert_eq!(sprite.pixels(&mmu, 8).iter().sum::<u8>(), 0); // Tile 3 is blank
+    }
+
+    #[test]
+    fn sprite_pixels_from_its_vram_bank() {
+        let mut mmu = Mmu::new();
+        mmu.vram[1][0x10] = 0x80; // Tile 1 in bank 1
+        let sprite = Sprite { y: 16, x: 8, tile: 1, attributes: 0x08 };
+        assert_eq!(sprite.pixels(&mmu, 8)[0], 0); // Bank 0 outside CGB mode
+        mmu.cgb_mode = true;
+        assert_eq!(sprite.pixels(&mmu, 8)[0], 1);
+    }
+}
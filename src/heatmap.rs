@@ -0,0 +1,102 @@
+use crate::bus::AccessKind;
+
+/* Reads, writes and executes per address across the whole 64KB, for seeing which variables a game
+ * uses and when. Counts are per frame, and each kind also has a heat that builds up while an
+ * address is busy and fades once it isn't. */
+
+pub const ADDRESS_SPACE: usize = 0x10000;
+const DECAY: f32 = 0.85; // Kept each frame, so a burst fades out in about half a second
+
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub struct Counts {
+    pub reads: u32,
+    pub writes: u32,
+    pub executes: u32, // Bytes of instructions executed, operands included
+}
+
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub struct Heat {
+    pub reads: f32,
+    pub writes: f32,
+    pub executes: f32,
+}
+
+#[derive(Clone)]
+pub struct Heatmap {
+    counting: Vec<Counts>, // This frame so far
+    pub counts: Vec<Counts>, // The last whole frame
+    pub heat: Vec<Heat>,
+    frame: Option<u64>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap {
+            counting: vec![Counts::default(); ADDRESS_SPACE],
+            counts: vec![Counts::default(); ADDRESS_SPACE],
+            heat: vec![Heat::default(); ADDRESS_SPACE],
+            frame: None,
+        }
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap::default()
+    }
+
+    // An instruction's bytes and the accesses it made, during `frame`
+    pub fn log(&mut self, instruction: impl Iterator<Item = u16>, accesses: impl Iterator<Item = (u16, AccessKind)>, frame: u64) {
+        if self.frame.is_some_and(|last| last != frame) {
+            self.end_frame();
+        }
+        self.frame = Some(frame);
+        for address in instruction {
+            self.counting[address as usize].executes += 1;
+        }
+        for (address, kind) in accesses {
+            let counts = &mut self.counting[address as usize];
+            match kind {
+                AccessKind::Read => counts.reads += 1,
+                AccessKind::Write => counts.writes += 1,
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        for ((counting, counts), heat) in self.counting.iter_mut().zip(&mut self.counts).zip(&mut self.heat) {
+            heat.reads = heat.reads * DECAY + counting.reads as f32;
+            heat.writes = heat.writes * DECAY + counting.writes as f32;
+            heat.executes = heat.executes * DECAY + counting.executes as f32;
+            *counts = *counting;
+            *counting = Counts::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heatmap::Heatmap;
+    use crate::model::Model;
+    use crate::system::System;
+
+    // Loop: LD A,[$C000] / INC A / LD [$C000],A / JR Loop
+    #[test]
+    fn counts_per_frame() {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0xFA, 0x00, 0xC0, 0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xF7])]);
+        system.heatmap = Some(Heatmap::new());
+        let frame = system.graphics.frame;
+        while system.graphics.frame < frame + 2 {
+            system.step(&[]);
+        }
+        let heatmap = system.heatmap.as_ref().unwrap();
+        let counts = heatmap.counts[0xC000];
+        assert!(counts.reads > 0);
+        assert_eq!(counts.reads, counts.writes);
+        assert_eq!(counts.executes, 0);
+        assert_eq!(heatmap.counts[0x100].executes, heatmap.counts[0x102].executes); // Operands too
+        assert_eq!(heatmap.counts[0x100].reads, 0); // Fetching isn't reading
+        assert!(heatmap.heat[0x103].executes > 0.);
+        assert_eq!(heatmap.counts[0x109].executes, 0);
+    }
+}
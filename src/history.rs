@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use crate::cpu::Cpu;
+use crate::debugger::{BreakReason, RunTarget};
+use crate::graphics::Graphics;
+use crate::joypad::Button;
+use crate::serial::Serial;
+use crate::symbols::Location;
+use crate::system::System;
+
+/* Reverse debugging. The system is snapshotted every `interval` instructions and joypad input is
+ * recorded whenever it changes, so any earlier instruction can be reached again by restoring the
+ * snapshot before it and re-executing. That relies on the core being deterministic given the input. */
+
+pub const SNAPSHOT_INTERVAL: u64 = 20_000; // About two frames of instructions
+pub const MAX_SNAPSHOTS: usize = 128; // Each is ~200KB, the ROM isn't copied
+
+#[derive(Clone)]
+pub(crate) struct Snapshot {
+    pub(crate) steps: u64,
+    pub(crate) cpu: Box<Cpu>, // Boxed so moving snapshots around doesn't copy ~200KB on the stack each time
+    pub(crate) graphics: Box<Graphics>,
+    pub(crate) serial: Serial,
+    hits: Vec<(Location, u64)>, // Of the breakpoints at the time, so HITS conditions count the same when replayed
+}
+
+pub struct History {
+    pub interval: u64,
+    pub capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+    inputs: Vec<(u64, Vec<Button>)>, // The step each change of input first applied to
+}
+
+impl History {
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        History { interval, capacity, snapshots: VecDeque::new(), inputs: vec![] }
+    }
+
+    // How far back it's possible to go, in instructions
+    pub fn oldest(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.steps)
+    }
+
+    fn input_at(&self, step: u64) -> &[Button] {
+        let index = self.inputs.partition_point(|(changed, _)| *changed <= step);
+        match index {
+            0 => &[],
+            _ => &self.inputs[index - 1].1,
+        }
+    }
+
+    // Forget anything after `steps`, once we've gone back the old future won't happen again
+    fn truncate(&mut self, steps: u64) {
+        while self.snapshots.back().is_some_and(|snapshot| snapshot.steps > steps) {
+            self.snapshots.pop_back();
+        }
+        self.inputs.retain(|(changed, _)| *changed < steps);
+    }
+}
+
+// A copy of the whole system that can be loaded again later in the session
+#[derive(Clone)]
+pub struct SaveState(pub(crate) Snapshot);
+
+impl System {
+    pub fn enable_history(&mut self) {
+        self.history = Some(History::new(SNAPSHOT_INTERVAL, MAX_SNAPSHOTS));
+    }
+
+    // Called at the start of every live step
+    pub(crate) fn record(&mut self, pressed: &[Button]) {
+        let steps = self.steps;
+        let Some(history) = self.history.as_ref() else { return };
+        let due = history.snapshots.back().is_none_or(|snapshot| steps - snapshot.steps >= history.interval);
+        let changed = history.inputs.last().map(|(_, last)| last.as_slice()) != Some(pressed);
+        let snapshot = if due { Some(self.snapshot()) } else { None };
+
+        let history = self.history.as_mut().unwrap();
+        if let Some(snapshot) = snapshot {
+            if history.snapshots.len() == history.capacity {
+                history.snapshots.pop_front();
+                // Input from before the oldest snapshot can't be replayed any more, except the one in force
+                let oldest = history.snapshots.front().map_or(steps, |snapshot| snapshot.steps);
+                let keep_from = history.inputs.partition_point(|(changed, _)| *changed <= oldest).saturating_sub(1);
+                history.inputs.drain(..keep_from);
+            }
+            history.snapshots.push_back(snapshot);
+        }
+        if changed {
+            history.inputs.push((steps, pressed.to_vec()));
+        }
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        // The ROM never changes, so it's left out rather than copied every time
+        let rom = std::mem::take(&mut self.cpu.mmu.cartridge.data);
+        let hits = self.breakpoints.iter().map(|breakpoint| (breakpoint.location, breakpoint.hits)).collect();
+        let snapshot = Snapshot { steps: self.steps, cpu: Box::new(self.cpu.clone()), graphics: Box::new(self.graphics.clone()), serial: self.serial.clone(), hits };
+        self.cpu.mmu.cartridge.data = rom;
+        snapshot
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        let rom = std::mem::take(&mut self.cpu.mmu.cartridge.data);
+        let watchpoints = std::mem::take(&mut self.cpu.mmu.watchpoints); // Debugger settings aren't history
+        self.cpu = *snapshot.cpu;
+        self.cpu.mmu.cartridge.data = rom;
+        self.cpu.mmu.watchpoints = watchpoints;
+        self.graphics = *snapshot.graphics;
+        self.serial = snapshot.serial;
+        self.steps = snapshot.steps;
+        // Breakpoints added since then hadn't been hit yet
+        for breakpoint in self.breakpoints.iter_mut() {
+            let hits = snapshot.hits.iter().find(|(location, _)| *location == breakpoint.location);
+            breakpoint.hits = hits.map_or(0, |(_, hits)| *hits);
+        }
+    }
+
+    pub fn save_state(&mut self) -> SaveState {
+        SaveState(self.snapshot())
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.restore(state.0.clone());
+        self.break_reason = None;
+        self.run_target = None;
+        if let Some(history) = self.history.as_mut() {
+            *history = History::new(history.interval, history.capacity); // It's a different timeline now
+        }
+    }
+
+    // Re-execute from the latest snapshot at or before `from` until `to` steps have run, calling
+    // `visit` after each one. Tracing, log-points and the CDL, heatmap and IO write logs are left as
+    // they were, hit counts end up as they were at `to`.
+    fn replay(&mut self, from: u64, to: u64, mut visit: impl FnMut(&System)) -> bool {
+        let Some(history) = self.history.as_mut() else { return false };
+        let Some(snapshot) = history.snapshots.iter().rev().find(|snapshot| snapshot.steps <= from).cloned() else {
+            return false;
+        };
+        let tracer = self.tracer.take();
+        let messages = self.log_messages.len();
+        self.replaying = true;
+        self.run_target = None;
+
+        self.restore(snapshot);
+        while self.steps < to {
+            let pressed = self.history.as_ref().unwrap().input_at(self.steps).to_vec();
+            self.step(&pressed);
+            visit(self);
+        }
+
+        self.replaying = false;
+        self.log_messages.truncate(messages);
+        self.tracer = tracer;
+        true
+    }
+
+    // Go back one instruction, false if there's no history that far back
+    pub fn step_back(&mut self) -> bool {
+        if self.steps == 0 || !self.replay(self.steps - 1, self.steps - 1, |_| ()) {
+            return false;
+        }
+        self.break_reason = Some(BreakReason::Stepped(RunTarget::Instruction));
+        self.history.as_mut().unwrap().truncate(self.steps);
+        true
+    }
+
+    // Run backwards to the previous breakpoint, watchpoint or fault, or as far back as history goes
+    pub fn reverse_continue(&mut self) -> Option<BreakReason> {
+        let mut limit = self.steps.checked_sub(1)?; // The latest step worth stopping at
+        let found = loop {
+            let Some(start) = self.history.as_ref()?.snapshots.iter().rev().map(|snapshot| snapshot.steps).find(|steps| *steps < limit) else {
+                break None;
+            };
+            let mut hit = None;
+            self.replay(start, limit, |system| {
+                if let Some(reason) = system.break_reason.filter(|reason| !matches!(reason, BreakReason::Stepped(_))) {
+                    hit = Some((system.steps, reason));
+                }
+            });
+            if hit.is_some() {
+                break hit;
+            }
+            limit = start;
+        };
+
+        let (steps, reason) = match found {
+            Some((steps, reason)) => (steps, Some(reason)),
+            None => (self.history.as_ref()?.oldest()?, None),
+        };
+        self.replay(steps, steps, |_| ());
+        self.break_reason = reason.or(Some(BreakReason::Stepped(RunTarget::Instruction)));
+        self.history.as_mut().unwrap().truncate(self.steps);
+        reason
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cdl::CodeDataLog;
+    use crate::debugger::{Breakpoint, BreakReason, WatchKind, Watchpoint};
+    use crate::expr::Condition;
+    use crate::history::History;
+    use crate::joypad::Button;
+    use crate::model::Model;
+    use crate::symbols::Location;
+    use crate::system::System;
+
+    // INC A / LD [$C000],A / LD A,[$FF00] / XOR $0F ... JR back to the start
+    fn system() -> System {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0x3C, 0xEA, 0x00, 0xC0, 0x47, 0xF0, 0x00, 0x78, 0x18, 0xF6])]);
+        system.history = Some(History::new(16, 8));
+        system
+    }
+
+    fn state(system: &System) -> (u64, u16, u8, u8, u8) {
+        (system.steps, system.cpu.reg.pc, system.cpu.reg.a, system.cpu.reg.b, system.cpu.mmu.get(0xC000))
+    }
+
+    #[test]
+    fn step_back_is_deterministic() {
+        let mut system = system();
+        let mut states = vec![];
+        for step in 0..100 {
+            let pressed: &[Button] = if (30..60).contains(&step) { &[Button::Start] } else { &[] };
+            states.push(state(&system));
+            system.step(pressed);
+        }
+        for expected in states.iter().rev().take(40) {
+            assert!(system.step_back());
+            assert_eq!(state(&system), *expected);
+        }
+    }
+
+    #[test]
+    fn reverse_continue_to_watchpoint() {
+        let mut system = system();
+        for _ in 0..60 {
+            system.step(&[]);
+        }
+        system.cpu.mmu.watchpoints.push(Watchpoint { range: 0xC000..=0xC000, kind: WatchKind::Write });
+        let expected = system.cpu.mmu.get(0xC000);
+        let reason = system.reverse_continue();
+        assert!(matches!(reason, Some(BreakReason::Watchpoint { pc: 0x101, .. })));
+        assert_eq!(system.cpu.reg.pc, 0x104);
+        assert_eq!(system.cpu.mmu.get(0xC000), expected);
+        assert!(system.steps < 60);
+    }
+
+    // The third pass through the loop, which a replay starting from the latest hit counts would never see
+    #[test]
+    fn hits_replayed_with_the_machine() {
+        let mut system = system();
+        let mut breakpoint = Breakpoint::new(Location { bank: None, address: 0x101 });
+        breakpoint.condition = Some(Condition::parse("HITS == 3", &system.symbols).unwrap());
+        system.breakpoints.push(breakpoint);
+        for _ in 0..60 {
+            system.step(&[]);
+        }
+        system.cdl = Some(CodeDataLog::new(system.cpu.mmu.cartridge.data.len()));
+        assert!(matches!(system.reverse_continue(), Some(BreakReason::Breakpoint(_))));
+        assert_eq!((system.steps, system.breakpoints[0].hits), (13, 3));
+        assert!(system.cdl.as_ref().unwrap().flags.iter().all(|flags| *flags == 0)); // Replays aren't logged
+    }
+}
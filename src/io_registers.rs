@@ -0,0 +1,270 @@
+use crate::bus::AccessKind;
+
+/* The IO registers (0xFF00~0xFF7F and IE) broken down into their fields, for showing and editing
+ * them by name (https://gbdev.io/pandocs/Hardware_Reg_List.html), and which of them were written
+ * to in the last frame. */
+
+// Some bits of a register, shown as a flag, a number, or one of `values` when it has them
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub shift: u8,
+    pub width: u8,
+    pub values: &'static [&'static str],
+    pub read_only: bool, // Writes are ignored, or it's only changed by the hardware
+}
+
+const fn flag(name: &'static str, bit: u8) -> Field {
+    Field { name, shift: bit, width: 1, values: &[], read_only: false }
+}
+
+const fn number(name: &'static str, shift: u8, width: u8) -> Field {
+    Field { name, shift, width, values: &[], read_only: false }
+}
+
+const fn choice(name: &'static str, shift: u8, width: u8, values: &'static [&'static str]) -> Field {
+    Field { name, shift, width, values, read_only: false }
+}
+
+const fn read_only(field: Field) -> Field {
+    Field { read_only: true, ..field }
+}
+
+impl Field {
+    pub fn mask(&self) -> u8 {
+        ((1u16 << self.width) - 1) as u8
+    }
+
+    pub fn get(&self, byte: u8) -> u8 {
+        byte >> self.shift & self.mask()
+    }
+
+    // The register with this field changed to `value`
+    pub fn set(&self, byte: u8, value: u8) -> u8 {
+        byte & !(self.mask() << self.shift) | (value & self.mask()) << self.shift
+    }
+
+    pub fn describe(&self, byte: u8) -> String {
+        let value = self.get(byte);
+        match self.values.get(value as usize) {
+            Some(name) => name.to_string(),
+            None if self.width == 1 => (if value == 1 { "on" } else { "off" }).to_string(),
+            None => value.to_string(),
+        }
+    }
+}
+
+pub struct Register {
+    pub address: u16,
+    pub name: &'static str,
+    pub fields: &'static [Field],
+}
+
+const fn register(address: u16, name: &'static str, fields: &'static [Field]) -> Register {
+    Register { address, name, fields }
+}
+
+const INTERRUPTS: &[Field] = &[flag("VBlank", 0), flag("LCD", 1), flag("Timer", 2), flag("Serial", 3), flag("Joypad", 4)];
+const BYTE: &[Field] = &[number("Value", 0, 8)];
+const SHADES: &[&str] = &["White", "Light grey", "Dark grey", "Black"];
+const PALETTE: &[Field] = &[choice("Colour 0", 0, 2, SHADES), choice("Colour 1", 2, 2, SHADES), choice("Colour 2", 4, 2, SHADES), choice("Colour 3", 6, 2, SHADES)];
+const MAPS: &[&str] = &["9800", "9C00"];
+const DUTY: Field = choice("Duty", 6, 2, &["12.5%", "25%", "50%", "75%"]);
+const ENVELOPE: &[Field] = &[number("Initial volume", 4, 4), choice("Envelope", 3, 1, &["Down", "Up"]), number("Envelope pace", 0, 3)];
+const PERIOD_HIGH: &[Field] = &[flag("Trigger", 7), flag("Length enable", 6), number("Period high", 0, 3)];
+const WAVE: &[Field] = &[number("Sample 1", 4, 4), number("Sample 2", 0, 4)];
+const PALETTE_INDEX: &[Field] = &[flag("Auto increment", 7), number("Index", 0, 6)];
+
+pub const GROUPS: &[(&str, &[Register])] = &[
+    ("Joypad and serial", &[
+        register(0xFF00, "JOYP", &[
+            choice("Buttons", 5, 1, &["Selected", "Not selected"]), choice("D-pad", 4, 1, &["Selected", "Not selected"]),
+            read_only(flag("Down/Start", 3)), read_only(flag("Up/Select", 2)), read_only(flag("Left/B", 1)), read_only(flag("Right/A", 0)),
+        ]),
+        register(0xFF01, "SB", BYTE),
+        register(0xFF02, "SC", &[flag("Transfer", 7), choice("Speed", 1, 1, &["Normal", "Fast (CGB)"]), choice("Clock", 0, 1, &["External", "Internal"])]),
+    ]),
+    ("Timer", &[
+        register(0xFF04, "DIV", &[read_only(number("Value", 0, 8))]), // Any write resets it, so it can't be set
+        register(0xFF05, "TIMA", BYTE),
+        register(0xFF06, "TMA", BYTE),
+        register(0xFF07, "TAC", &[flag("Enable", 2), choice("Clock", 0, 2, &["4096 Hz", "262144 Hz", "65536 Hz", "16384 Hz"])]),
+    ]),
+    ("Interrupts", &[
+        register(0xFF0F, "IF", INTERRUPTS),
+        register(0xFFFF, "IE", INTERRUPTS),
+    ]),
+    ("Sound", &[
+        register(0xFF10, "NR10", &[number("Sweep pace", 4, 3), choice("Sweep", 3, 1, &["Up", "Down"]), number("Sweep step", 0, 3)]),
+        register(0xFF11, "NR11", &[DUTY, number("Length", 0, 6)]),
+        register(0xFF12, "NR12", ENVELOPE),
+        register(0xFF13, "NR13", &[number("Period low", 0, 8)]),
+        register(0xFF14, "NR14", PERIOD_HIGH),
+        register(0xFF16, "NR21", &[DUTY, number("Length", 0, 6)]),
+        register(0xFF17, "NR22", ENVELOPE),
+        register(0xFF18, "NR23", &[number("Period low", 0, 8)]),
+        register(0xFF19, "NR24", PERIOD_HIGH),
+        register(0xFF1A, "NR30", &[flag("DAC", 7)]),
+        register(0xFF1B, "NR31", &[number("Length", 0, 8)]),
+        register(0xFF1C, "NR32", &[choice("Output level", 5, 2, &["Mute", "100%", "50%", "25%"])]),
+        register(0xFF1D, "NR33", &[number("Period low", 0, 8)]),
+        register(0xFF1E, "NR34", PERIOD_HIGH),
+        register(0xFF20, "NR41", &[number("Length", 0, 6)]),
+        register(0xFF21, "NR42", ENVELOPE),
+        register(0xFF22, "NR43", &[number("Clock shift", 4, 4), choice("LFSR", 3, 1, &["15-bit", "7-bit"]), number("Clock divider", 0, 3)]),
+        register(0xFF23, "NR44", &[flag("Trigger", 7), flag("Length enable", 6)]),
+        register(0xFF24, "NR50", &[flag("VIN left", 7), number("Left volume", 4, 3), flag("VIN right", 3), number("Right volume", 0, 3)]),
+        register(0xFF25, "NR51", &[
+            flag("CH4 left", 7), flag("CH3 left", 6), flag("CH2 left", 5), flag("CH1 left", 4),
+            flag("CH4 right", 3), flag("CH3 right", 2), flag("CH2 right", 1), flag("CH1 right", 0),
+        ]),
+        register(0xFF26, "NR52", &[
+            flag("Audio", 7), read_only(flag("CH4", 3)), read_only(flag("CH3", 2)), read_only(flag("CH2", 1)), read_only(flag("CH1", 0)),
+        ]),
+    ]),
+    ("Wave RAM", &[
+        register(0xFF30, "WAVE0", WAVE), register(0xFF31, "WAVE1", WAVE), register(0xFF32, "WAVE2", WAVE), register(0xFF33, "WAVE3", WAVE),
+        register(0xFF34, "WAVE4", WAVE), register(0xFF35, "WAVE5", WAVE), register(0xFF36, "WAVE6", WAVE), register(0xFF37, "WAVE7", WAVE),
+        register(0xFF38, "WAVE8", WAVE), register(0xFF39, "WAVE9", WAVE), register(0xFF3A, "WAVEA", WAVE), register(0xFF3B, "WAVEB", WAVE),
+        register(0xFF3C, "WAVEC", WAVE), register(0xFF3D, "WAVED", WAVE), register(0xFF3E, "WAVEE", WAVE), register(0xFF3F, "WAVEF", WAVE),
+    ]),
+    ("LCD", &[
+        register(0xFF40, "LCDC", &[
+            flag("LCD", 7), choice("Window map", 6, 1, MAPS), flag("Window", 5), choice("Tile data", 4, 1, &["8800", "8000"]),
+            choice("BG map", 3, 1, MAPS), choice("Sprite size", 2, 1, &["8x8", "8x16"]), flag("Sprites", 1), flag("BG and window", 0),
+        ]),
+        register(0xFF41, "STAT", &[
+            flag("LYC interrupt", 6), flag("Mode 2 interrupt", 5), flag("Mode 1 interrupt", 4), flag("Mode 0 interrupt", 3),
+            read_only(flag("LY=LYC", 2)), read_only(choice("Mode", 0, 2, &["HBlank", "VBlank", "OAM scan", "Drawing"])),
+        ]),
+        register(0xFF42, "SCY", BYTE),
+        register(0xFF43, "SCX", BYTE),
+        register(0xFF44, "LY", &[read_only(number("Value", 0, 8))]),
+        register(0xFF45, "LYC", BYTE),
+        register(0xFF46, "DMA", &[number("Source page", 0, 8)]), // Writing starts a transfer
+        register(0xFF47, "BGP", PALETTE),
+        register(0xFF48, "OBP0", PALETTE),
+        register(0xFF49, "OBP1", PALETTE),
+        register(0xFF4A, "WY", BYTE),
+        register(0xFF4B, "WX", BYTE),
+    ]),
+    ("CGB", &[
+        register(0xFF4C, "KEY0", &[read_only(choice("CPU mode", 2, 2, &["CGB", "DMG compatibility", "PGB", "PGB"]))]), // Set by the boot ROM
+        register(0xFF4D, "KEY1", &[read_only(choice("Speed", 7, 1, &["Normal", "Double"])), flag("Switch armed", 0)]),
+        register(0xFF4F, "VBK", &[number("VRAM bank", 0, 1)]),
+        register(0xFF50, "BANK", &[flag("Boot ROM off", 0)]),
+        register(0xFF51, "HDMA1", &[number("Source high", 0, 8)]),
+        register(0xFF52, "HDMA2", &[number("Source low", 0, 8)]),
+        register(0xFF53, "HDMA3", &[number("Destination high", 0, 8)]),
+        register(0xFF54, "HDMA4", &[number("Destination low", 0, 8)]),
+        // Any write starts (or stops) a transfer, reads give the blocks left
+        register(0xFF55, "HDMA5", &[read_only(choice("Mode", 7, 1, &["General", "HBlank"])), read_only(number("Blocks left", 0, 7))]),
+        register(0xFF56, "RP", &[number("Read enable", 6, 2), read_only(choice("Read data", 1, 1, &["Receiving", "Normal"])), flag("LED", 0)]),
+        register(0xFF68, "BCPS", PALETTE_INDEX),
+        register(0xFF69, "BCPD", BYTE),
+        register(0xFF6A, "OCPS", PALETTE_INDEX),
+        register(0xFF6B, "OCPD", BYTE),
+        register(0xFF6C, "OPRI", &[choice("Sprite priority", 0, 1, &["OAM index", "X coordinate"])]),
+        register(0xFF70, "SVBK", &[number("WRAM bank", 0, 3)]),
+    ]),
+];
+
+const IO_START: u16 = 0xFF00; // Through to IE at 0xFFFF
+
+// Writes to each register in the last whole frame
+#[derive(Clone)]
+pub struct IoWrites {
+    counting: Vec<u32>,
+    pub counts: Vec<u32>,
+    frame: Option<u64>,
+}
+
+impl Default for IoWrites {
+    fn default() -> Self {
+        IoWrites { counting: vec![0; 0x100], counts: vec![0; 0x100], frame: None }
+    }
+}
+
+impl IoWrites {
+    pub fn new() -> Self {
+        IoWrites::default()
+    }
+
+    pub fn log(&mut self, accesses: impl Iterator<Item = (u16, AccessKind)>, frame: u64) {
+        if self.frame.is_some_and(|last| last != frame) {
+            self.counts = std::mem::replace(&mut self.counting, vec![0; 0x100]);
+        }
+        self.frame = Some(frame);
+        for (address, kind) in accesses {
+            if kind == AccessKind::Write && address >= IO_START {
+                self.counting[(address - IO_START) as usize] += 1;
+            }
+        }
+    }
+
+    pub fn last_frame(&self, address: u16) -> u32 {
+        address.checked_sub(IO_START).map_or(0, |i| self.counts[i as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::io_registers::{IoWrites, GROUPS};
+    use crate::model::Model;
+    use crate::system::System;
+
+    #[test]
+    fn fields_fit_their_registers() {
+        let mut addresses = HashSet::new();
+        for (_, registers) in GROUPS {
+            for register in *registers {
+                assert!(addresses.insert(register.address), "{} is listed twice", register.name);
+                assert!((0xFF00..0xFF80).contains(&register.address) || register.address == 0xFFFF);
+                let mut bits = 0u16;
+                for field in register.fields {
+                    let mask = (field.mask() as u16) << field.shift;
+                    assert!(mask <= 0xFF && bits & mask == 0, "{} {} overlaps", register.name, field.name);
+                    assert!(field.values.is_empty() || field.values.len() == 1 << field.width);
+                    bits |= mask;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_and_edit() {
+        let (_, lcd) = GROUPS.iter().find(|(name, _)| *name == "LCD").unwrap();
+        let lcdc = &lcd[0];
+        let sprite_size = lcdc.fields.iter().find(|field| field.name == "Sprite size").unwrap();
+        assert_eq!(sprite_size.describe(0x91), "8x8");
+        assert_eq!(sprite_size.set(0x91, 1), 0x95);
+        let (_, timer) = GROUPS.iter().find(|(name, _)| *name == "Timer").unwrap();
+        let clock = timer[3].fields[1];
+        assert_eq!(clock.describe(0b101), "262144 Hz");
+        assert_eq!(clock.set(0b101, 3), 0b111);
+    }
+
+    // Editing these would reset the divider or start an HDMA rather than just change a value
+    #[test]
+    fn side_effects_are_read_only() {
+        let registers = GROUPS.iter().flat_map(|(_, registers)| registers.iter());
+        for register in registers.filter(|register| matches!(register.name, "DIV" | "HDMA5" | "KEY0")) {
+            assert!(register.fields.iter().all(|field| field.read_only), "{} can be edited", register.name);
+        }
+    }
+
+    // LD A,$E4 / LDH [$47],A / JR -6
+    #[test]
+    fn writes_in_the_last_frame() {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0x3E, 0xE4, 0xE0, 0x47, 0x18, 0xFA])]);
+        system.io_writes = Some(IoWrites::new());
+        let frame = system.graphics.frame;
+        while system.graphics.frame < frame + 2 {
+            system.step(&[]);
+        }
+        let writes = system.io_writes.as_ref().unwrap();
+        assert!(writes.last_frame(0xFF47) > 1000);
+        assert_eq!(writes.last_frame(0xFF48), 0);
+    }
+}
@@ -3,6 +3,7 @@ use crate::mmu::Mmu;
 
 pub const JOYP: u16 = 0xFF00;
 
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Button {
     Up,
     Down,
@@ -13,6 +14,8 @@ pub enum Button {
     Select,
 }
 
+pub const BUTTONS: [Button; 8] = [Button::Up, Button::Down, Button::Left, Button::Right, Button::A, Button::B, Button::Start, Button::Select];
+
 pub struct Joypad {}
 
 impl Joypad {
@@ -20,6 +23,19 @@ impl Joypad {
         let mut control = mmu.get(JOYP);
         control |= 0x0f; // Clear the previous button presses
 
+        // With SGB multiplayer the other controllers take turns, and the ID is read with both lines HIGH
+        let mut player_input = None;
+        if let Some(sgb) = mmu.sgb.as_ref().filter(|sgb| sgb.multiplayer()) {
+            if control & 0x30 == 0x30 {
+                mmu.set_joypad_buttons(0x0F - sgb.current_player);
+                return;
+            }
+            if sgb.current_player > 0 {
+                player_input = Some(sgb.inputs[sgb.current_player as usize].clone());
+            }
+        }
+        let pressed = player_input.as_deref().unwrap_or(pressed);
+
         if !check_bit(control, 4) { // Direction mode
             for button in pressed.iter() {
                 match button {
@@ -1,18 +1,40 @@
 extern crate core;
 
+pub mod args;
 pub mod mmu;
+pub mod bus;
+pub mod bootrom;
+pub mod callstack;
+pub mod cdl;
+pub mod cli;
+pub mod dap;
 pub mod cpu;
+pub mod debugger;
 pub mod registers;
 pub mod cartridge;
 pub mod system;
 pub mod decode;
+pub mod disasm;
+pub mod export;
 pub mod execute;
+pub mod expr;
 pub mod flags;
 pub mod graphics;
+pub mod heatmap;
+pub mod history;
+pub mod io_registers;
 pub mod timer;
 pub mod joypad;
-
-pub const LOGGING_ENABLED: bool = true;
+pub mod model;
+pub mod profiler;
+pub mod savestate;
+pub mod sgb;
+pub mod serial;
+pub mod sourcemap;
+pub mod symbols;
+pub mod testrom;
+pub mod trace;
+pub mod tracediff;
 
 fn word_from(left: u8, right: u8) -> u16 {
     ((left as u16) << 8) | right as u16
@@ -1,41 +1,125 @@
+use std::cell::{Cell, RefCell};
 use std::cmp::max;
-use std::fs::File;
-use std::io::Read;
-use log::error;
+use std::io;
+use crate::bus::AccessKind;
 use crate::cartridge::Cartridge;
+use crate::check_bit;
+use crate::debugger::{WatchHit, WatchKind, Watchpoint};
+use crate::disasm;
+use crate::model::{Model, MODELS};
+use crate::savestate::{StateReader, StateWriter};
+use crate::sgb::Sgb;
 use crate::timer;
 use crate::joypad;
 
 const OFFSET: usize = 0x8000;
 
+pub const KEY1: u16 = 0xFF4D;  // CGB speed switch -- Bit 7 is the current speed, bit 0 arms a switch on STOP
+pub const VBK: u16 = 0xFF4F;   // CGB VRAM bank   -- Bit 0 selects VRAM bank 0 or 1
+pub const HDMA1: u16 = 0xFF51; // CGB HDMA source high
+pub const HDMA2: u16 = 0xFF52; // CGB HDMA source low
+pub const HDMA3: u16 = 0xFF53; // CGB HDMA destination high
+pub const HDMA4: u16 = 0xFF54; // CGB HDMA destination low
+pub const HDMA5: u16 = 0xFF55; // CGB HDMA length/mode/start
+pub const SVBK: u16 = 0xFF70;  // CGB WRAM bank   -- Bits 0-2 select WRAM bank 1~7 at 0xD000
+
+#[derive(Clone)]
+pub struct Hdma {
+    pub source: u16,
+    pub destination: u16,
+    pub blocks_left: u8, // Remaining 0x10 byte blocks
+    pub hblank_active: bool,
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0x8000,
+            blocks_left: 0,
+            hblank_active: false,
+        }
+    }
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma::default()
+    }
+}
+
+#[derive(Clone)]
 pub struct Mmu {
-    pub bootrom: [u8; 256],
+    pub model: Model,
+    pub bootrom: Vec<u8>,
     pub bootrom_mapped: bool,
     pub cartridge: Cartridge,
     pub memory: [u8; 0x8000],
+    pub vram: [[u8; 0x2000]; 2],
+    pub wram: [[u8; 0x1000]; 8],
     pub rom_bank: u8,
+    pub vram_bank: u8,
+    pub wram_bank: u8,
+    pub cgb_mode: bool,
+    pub double_speed: bool,
+    pub speed_switch_armed: bool,
+    pub hdma: Hdma,
+    pub stall_cycles: usize, // CPU M-cycles spent halted by a general-purpose HDMA
+    pub sgb: Option<Sgb>,
+    pub watchpoints: Vec<Watchpoint>, // Checked on CPU accesses, only when there are any
+    pub watch_hit: Cell<Option<WatchHit>>, // Reads are &self, so the first hit is kept here
+    pub log_accesses: bool, // For the code/data log, heatmap and IO writes
+    pub accesses: RefCell<Vec<(u16, AccessKind)>>, // Reads and writes by the CPU and OAM DMA, while logging
 }
 
 impl Mmu {
     pub fn new() -> Self {
         Self {
             // TODO: Consider switching to one array and taking slices
-            bootrom: [0; 256],
+            model: Model::Dmg,
+            bootrom: vec![0; 256],
             bootrom_mapped: true,
             cartridge: Cartridge::new(),
             memory: [0; 0x8000],
+            vram: [[0; 0x2000]; 2],
+            wram: [[0; 0x1000]; 8],
             rom_bank: 1,
+            vram_bank: 0,
+            wram_bank: 1,
+            cgb_mode: false,
+            double_speed: false,
+            speed_switch_armed: false,
+            hdma: Hdma::new(),
+            stall_cycles: 0,
+            sgb: None,
+            watchpoints: vec![],
+            watch_hit: Cell::new(None),
+            log_accesses: false,
+            accesses: RefCell::new(vec![]),
         }
     }
 
-    pub fn load_bootrom(&mut self, rom_path: &str) {
-        let mut rom_file = File::open(&rom_path).expect("Unable to open the ROM file");
-        rom_file.read(&mut self.bootrom).expect("Unable to read the ROM file data");
+    // DMG/MGB/SGB boot ROMs are 256 bytes, CGB/AGB ones also map 0x200~0x8FF
+    pub fn set_bootrom(&mut self, data: &[u8]) {
+        self.bootrom = data.to_vec();
+        self.bootrom.resize(max(self.bootrom.len(), 0x100), 0);
+        self.bootrom_mapped = true;
+    }
+
+    pub fn clear_bootrom(&mut self) {
+        self.bootrom = vec![0; 256];
+        self.bootrom_mapped = false;
     }
 
     pub fn reset(&mut self) {
+        self.vram_bank = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.hdma = Hdma::new();
+        self.stall_cycles = 0;
         self.set_initial_state();
-        if self.bootrom.gt(&[0;256]) {
+        if self.bootrom.iter().any(|byte| *byte != 0) {
             self.bootrom_mapped = true;
         }
     }
@@ -46,22 +130,33 @@ impl Mmu {
         if self.bootrom_mapped {
             match address {
                 0x00..=0xFF => return self.bootrom[split_address],
+                0x200..=0x8FF if self.bootrom.len() > 0x200 => return self.bootrom[split_address],
                 _ => ()
             }
         }
         match address {
             0x0000..=0x3FFF => self.cartridge.data[split_address], // 16KB ROM bank 00
             0x4000..=0x7FFF => self.cartridge.data[split_address + ((self.rom_bank as usize - 1) * 0x4000)], // 16KB ROM Bank 01~NN
-            0x8000..=0x9FFF => self.memory[split_address], // 8KB Video RAM (VRAM)
+            0x8000..=0x9FFF => self.get_vram(self.vram_bank as usize, address), // 8KB Video RAM (VRAM) bank 0~1
             0xA000..=0xBFFF => self.memory[split_address], // 8KB External RAM TODO: Banking
-            0xC000..=0xCFFF => self.memory[split_address], // 4KB Work RAM (WRAM)
-            0xD000..=0xDFFF => self.memory[split_address], // 4KB Work RAM (WRAM)
-            0xE000..=0xFDFF => self.memory[split_address - 0x2000], // Mirror of C000~DDFF (ECHO RAM)
+            0xC000..=0xCFFF => self.wram[0][address as usize - 0xC000], // 4KB Work RAM (WRAM) bank 0
+            0xD000..=0xDFFF => self.wram[self.wram_bank as usize][address as usize - 0xD000], // 4KB Work RAM (WRAM) bank 1~7
+            0xE000..=0xFDFF => self.get(address - 0x2000), // Mirror of C000~DDFF (ECHO RAM)
             0xFE00..=0xFE9F => self.memory[split_address], // Sprite attribute table (OAM)
             0xFEA0..=0xFEFF => 0, // Not usable
             0xFF00..=0xFF7F => {
                 match address {
-                    0xFF00 => self.memory[split_address],
+                    KEY1 if self.cgb_mode => {
+                        0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+                    },
+                    VBK if self.cgb_mode => 0xFE | self.vram_bank,
+                    HDMA1..=HDMA4 => 0xFF, // Write-only
+                    HDMA5 if self.cgb_mode => {
+                        // Bit 7 reads as 0 while an HBlank transfer is still running
+                        let active = if self.hdma.hblank_active { 0 } else { 0x80 };
+                        active | (self.hdma.blocks_left.wrapping_sub(1) & 0x7F)
+                    },
+                    SVBK if self.cgb_mode => 0xF8 | self.wram_bank,
                     _ => self.memory[split_address]
                 }
             }, // I/O Registers
@@ -75,25 +170,41 @@ impl Mmu {
         let split_address = address as usize % OFFSET;
         #[allow(unreachable_patterns)]
         match address {
-            0x0000..=0x1FFF => { if byte == 0x0A { println!("Warning: RAM banking enabled") } }, // 16KB ROM bank 00
+            0x0000..=0x1FFF => { if byte == 0x0A { log::warn!("RAM banking enabled") } }, // 16KB ROM bank 00
             0x2000..=0x3FFF => self.rom_bank_switch(byte), // ROM bank select
             0x4000..=0x7FFF => {}, // 16KB ROM Bank 01~NN
-            0x8000..=0x9FFF => self.memory[split_address] = byte, // 8KB Video RAM (VRAM)
+            0x8000..=0x9FFF => self.vram[self.vram_bank as usize][address as usize - 0x8000] = byte, // 8KB Video RAM (VRAM) bank 0~1
             0xA000..=0xBFFF => self.memory[split_address] = byte, // 8KB External RAM
-            0xC000..=0xCFFF => self.memory[split_address] = byte, // 4KB Work RAM (WRAM) bank 0
-            0xD000..=0xDFFF => self.memory[split_address] = byte, // 4KB Work RAM (WRAM) bank 1~N // TODO: Banking
-            0xE000..=0xFDFF => self.memory[split_address - 0x2000] = byte, // Mirror of C000~DDFF (ECHO RAM)
+            0xC000..=0xCFFF => self.wram[0][address as usize - 0xC000] = byte, // 4KB Work RAM (WRAM) bank 0
+            0xD000..=0xDFFF => self.wram[self.wram_bank as usize][address as usize - 0xD000] = byte, // 4KB Work RAM (WRAM) bank 1~7
+            0xE000..=0xFDFF => self.set(address - 0x2000, byte), // Mirror of C000~DDFF (ECHO RAM)
             0xFE00..=0xFE9F => self.memory[split_address] = byte , // Sprite attribute table (OAM)
             0xFEA0..=0xFEFF => { }, // Not usable
             0xFF00..=0xFF7F => {
                 match address {
                     joypad::JOYP => {
+                        if let Some(sgb) = self.sgb.as_mut() {
+                            sgb.write_joyp(byte);
+                        }
                         let current = self.get(joypad::JOYP);
                         let new = (byte & 0xf0) | (current & 0x0f);
                         self.memory[joypad::JOYP as usize % OFFSET] = new;
                     },
                     timer::DIV => self.memory[split_address] = 0,
                     0xFF46 => self.dma_transfer(byte),
+                    0xFF50 => if byte != 0 { self.bootrom_mapped = false }, // Boot ROM disable
+                    KEY1 if self.cgb_mode => self.speed_switch_armed = check_bit(byte, 0),
+                    VBK if self.cgb_mode => self.vram_bank = byte & 1,
+                    HDMA1 if self.cgb_mode => self.hdma.source = (self.hdma.source & 0x00F0) | ((byte as u16) << 8),
+                    HDMA2 if self.cgb_mode => self.hdma.source = (self.hdma.source & 0xFF00) | (byte & 0xF0) as u16,
+                    HDMA3 if self.cgb_mode => {
+                        self.hdma.destination = 0x8000 | (self.hdma.destination & 0x00F0) | (((byte & 0x1F) as u16) << 8);
+                    },
+                    HDMA4 if self.cgb_mode => {
+                        self.hdma.destination = (self.hdma.destination & 0xFF00) | (byte & 0xF0) as u16;
+                    },
+                    HDMA5 if self.cgb_mode => self.start_hdma(byte),
+                    SVBK if self.cgb_mode => self.wram_bank = max(byte & 0b111, 1), // Bank 0 selects bank 1
                     _ => self.memory[split_address] = byte
                 }
             }, // I/O Registers
@@ -103,6 +214,41 @@ impl Mmu {
         }
     }
 
+    // The PPU always reads VRAM through an explicit bank, independent of VBK
+    pub fn get_vram(&self, bank: usize, address: u16) -> u8 {
+        self.vram[bank][(address as usize - 0x8000) % 0x2000]
+    }
+
+    // The ROM bank an address in 0x0000~0x7FFF currently reads from
+    pub fn rom_bank_at(&self, address: u16) -> u16 {
+        if address < 0x4000 { 0 } else { self.rom_bank as u16 }
+    }
+
+    // Where in the cartridge ROM an address is, None for the boot ROM and anything that isn't ROM
+    pub fn rom_offset_at(&self, address: u16) -> Option<usize> {
+        let boot_rom = self.bootrom_mapped && (address < 0x100 || (0x200..0x900).contains(&address) && self.bootrom.len() > 0x200);
+        match address {
+            0x0000..=0x7FFF if !boot_rom => Some(disasm::rom_offset(address, self.rom_bank_at(address))),
+            _ => None,
+        }
+    }
+
+    pub fn watch_read(&self, address: u16, byte: u8) {
+        if self.watch_hit.get().is_none() && self.watchpoints.iter().any(|watchpoint| watchpoint.on_read(address)) {
+            self.watch_hit.set(Some(WatchHit { kind: WatchKind::Read, address, old: byte, value: byte }));
+        }
+    }
+
+    pub fn watch_write(&self, address: u16, byte: u8) {
+        if self.watch_hit.get().is_some() {
+            return;
+        }
+        let old = self.get(address);
+        if let Some(watchpoint) = self.watchpoints.iter().find(|watchpoint| watchpoint.on_write(address, old, byte)) {
+            self.watch_hit.set(Some(WatchHit { kind: watchpoint.kind, address, old, value: byte }));
+        }
+    }
+
     fn rom_bank_switch(&mut self, byte: u8) {
         if self.cartridge.mbc == 1 { // If MBC1 is enabled
             self.rom_bank = byte & 0b00011111; // Set new rom bank
@@ -117,11 +263,75 @@ impl Mmu {
         self.memory[joypad::JOYP as usize % OFFSET] = new;
     }
 
+    // Executed by STOP, returns true if the CPU changed speed
+    pub fn try_speed_switch(&mut self) -> bool {
+        if !self.cgb_mode || !self.speed_switch_armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.memory[timer::DIV as usize % OFFSET] = 0;
+        true
+    }
+
+    // The CPU, timer & serial run at twice the rate of the PPU in double speed mode
+    pub fn speed_factor(&self) -> usize {
+        if self.double_speed { 2 } else { 1 }
+    }
+
+    fn start_hdma(&mut self, byte: u8) {
+        let blocks = (byte & 0x7F) + 1;
+        if self.hdma.hblank_active && !check_bit(byte, 7) {
+            // Writing bit 7 = 0 during an HBlank transfer cancels it
+            self.hdma.hblank_active = false;
+            return;
+        }
+        self.hdma.blocks_left = blocks;
+        if check_bit(byte, 7) {
+            self.hdma.hblank_active = true;
+        } else {
+            // General-purpose DMA copies everything at once and halts the CPU meanwhile
+            while self.hdma.blocks_left > 0 {
+                self.hdma_copy_block();
+            }
+            self.stall_cycles += blocks as usize * 8 * self.speed_factor();
+        }
+    }
+
+    // Called by the PPU at the start of every visible line's HBlank
+    pub fn hblank(&mut self) {
+        if self.hdma.hblank_active {
+            self.hdma_copy_block();
+            self.stall_cycles += 8 * self.speed_factor();
+            if self.hdma.blocks_left == 0 {
+                self.hdma.hblank_active = false;
+            }
+        }
+    }
+
+    fn hdma_copy_block(&mut self) {
+        for i in 0..0x10 {
+            let byte = self.get(self.hdma.source.wrapping_add(i));
+            let destination = 0x8000 | (self.hdma.destination.wrapping_add(i) & 0x1FFF);
+            self.vram[self.vram_bank as usize][destination as usize - 0x8000] = byte;
+        }
+        self.hdma.source = self.hdma.source.wrapping_add(0x10);
+        self.hdma.destination = 0x8000 | (self.hdma.destination.wrapping_add(0x10) & 0x1FFF);
+        self.hdma.blocks_left -= 1;
+    }
+
+    /* Hardware register values after the boot ROM hands over, based on:
+       https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+       Registers documented as unknown/random on a model are left at 0x00.
+     */
     pub fn set_initial_state(&mut self) {
-        self.set(0xFF00, 0xCF);
+        let cgb = self.model.is_cgb();
+        self.memory[joypad::JOYP as usize % OFFSET] = 0xCF; // Don't send an SGB reset pulse
         self.set(0xFF01, 0x00);
-        self.set(0xFF02, 0x7E);
-        self.set(0xFF04, 0xAB);
+        self.set(0xFF02, if cgb { 0x7F } else { 0x7E });
+        // DIV depends on how long each boot ROM runs, it's only documented for DMG/MGB
+        let div = if matches!(self.model, Model::Dmg | Model::Mgb) { 0xAB } else { 0x00 };
+        self.memory[timer::DIV as usize % OFFSET] = div;
         self.set(0xFF05, 0x00);
         self.set(0xFF06, 0x00);
         self.set(0xFF07, 0xF8);
@@ -146,32 +356,36 @@ impl Mmu {
         self.set(0xFF23, 0xBF);
         self.set(0xFF24, 0x77);
         self.set(0xFF25, 0xF3);
-        self.set(0xFF26, 0xF1);
+        self.set(0xFF26, if self.model.is_sgb() { 0xF0 } else { 0xF1 });
         self.set(0xFF40, 0x91);
         self.set(0xFF41, 0x85);
         self.set(0xFF42, 0x00);
         self.set(0xFF43, 0x00);
         self.set(0xFF44, 0x00);
         self.set(0xFF45, 0x00);
-        self.set(0xFF46, 0xFF);
+        self.memory[0xFF46 % OFFSET] = if cgb { 0x00 } else { 0xFF }; // Don't start an OAM DMA
         self.set(0xFF47, 0xFC);
         self.set(0xFF48, 0x00);
         self.set(0xFF49, 0x00);
-        self.set(0xFF4A, 0xFF);
-        self.set(0xFF4B, 0xFF);
-        self.set(0xFF4D, 0xFF);
-        self.set(0xFF4F, 0xFF);
-        self.set(0xFF51, 0xFF);
-        self.set(0xFF52, 0xFF);
-        self.set(0xFF53, 0xFF);
-        self.set(0xFF54, 0xFF);
-        self.set(0xFF55, 0xFF);
-        self.set(0xFF56, 0xFF);
-        self.set(0xFF68, 0xFF);
-        self.set(0xFF69, 0xFF);
-        self.set(0xFF6A, 0xFF);
-        self.set(0xFF6B, 0xFF);
-        self.set(0xFF70, 0xFF);
+        self.set(0xFF4A, 0x00);
+        self.set(0xFF4B, 0x00);
+        if !self.cgb_mode { // The CGB registers are write-protected and read 0xFF on DMG
+            self.set(0xFF4D, 0xFF);
+            self.set(0xFF4F, 0xFF);
+            self.set(0xFF51, 0xFF);
+            self.set(0xFF52, 0xFF);
+            self.set(0xFF53, 0xFF);
+            self.set(0xFF54, 0xFF);
+            self.set(0xFF55, 0xFF);
+            self.set(0xFF56, 0xFF);
+            self.set(0xFF68, 0xFF);
+            self.set(0xFF69, 0xFF);
+            self.set(0xFF6A, 0xFF);
+            self.set(0xFF6B, 0xFF);
+            self.set(0xFF70, 0xFF);
+        } else {
+            self.set(0xFF56, 0x3E);
+        }
         self.set(0xFFFF, 0x00);
     }
 
@@ -180,6 +394,11 @@ impl Mmu {
         for i in 0x00..0xA0 {
             self.set(0xFE00 + i, self.get(address + i));
         }
+        if self.log_accesses {
+            let accesses = self.accesses.get_mut();
+            accesses.extend((0x00..0xA0).map(|i| (address + i, AccessKind::Read)));
+            accesses.extend((0x00..0xA0).map(|i| (0xFE00 + i, AccessKind::Write)));
+        }
     }
 
     pub fn request_interrupt(&mut self, id: u8) {
@@ -187,4 +406,148 @@ impl Mmu {
         interrupt_flag |= 1 << id;
         self.set(0xFF0F, interrupt_flag);
     }
-}
\ No newline at end of file
+    // Everything but the ROM, which has to be loaded already, and the debugger's watchpoints
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.variant(&MODELS, &self.model);
+        out.bytes(&self.bootrom);
+        out.bool(self.bootrom_mapped);
+        out.raw(&self.memory);
+        self.vram.iter().for_each(|bank| out.raw(bank));
+        self.wram.iter().for_each(|bank| out.raw(bank));
+        out.raw(&[self.rom_bank, self.vram_bank, self.wram_bank]);
+        out.bool(self.cgb_mode);
+        out.bool(self.double_speed);
+        out.bool(self.speed_switch_armed);
+        out.u16(self.hdma.source);
+        out.u16(self.hdma.destination);
+        out.u8(self.hdma.blocks_left);
+        out.bool(self.hdma.hblank_active);
+        out.u64(self.stall_cycles as u64);
+        out.bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.write_state(out);
+        }
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        self.model = input.variant(&MODELS)?;
+        self.bootrom = input.bytes()?;
+        self.bootrom_mapped = input.bool()?;
+        input.fill(&mut self.memory)?;
+        for bank in &mut self.vram {
+            input.fill(bank)?;
+        }
+        for bank in &mut self.wram {
+            input.fill(bank)?;
+        }
+        self.rom_bank = input.u8()?;
+        self.vram_bank = input.u8()?;
+        self.wram_bank = input.u8()?;
+        self.cgb_mode = input.bool()?;
+        self.double_speed = input.bool()?;
+        self.speed_switch_armed = input.bool()?;
+        self.hdma.source = input.u16()?;
+        self.hdma.destination = input.u16()?;
+        self.hdma.blocks_left = input.u8()?;
+        self.hdma.hblank_active = input.bool()?;
+        self.stall_cycles = input.u64()? as usize;
+        self.sgb = match input.bool()? {
+            true => {
+                let mut sgb = Sgb::new(true);
+                sgb.read_state(input)?;
+                Some(sgb)
+            }
+            false => None,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mmu::{Mmu, HDMA1, HDMA2, HDMA3, HDMA4, HDMA5, KEY1, SVBK, VBK};
+
+    fn cgb_mmu() -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.bootrom_mapped = false;
+        mmu.cgb_mode = true;
+        mmu
+    }
+
+    #[test]
+    fn vram_banking_ok() {
+        let mut mmu = cgb_mmu();
+        mmu.set(0x8000, 0xAA);
+        mmu.set(VBK, 1);
+        assert_eq!(mmu.get(0x8000), 0x00);
+        mmu.set(0x8000, 0xBB);
+        mmu.set(VBK, 0);
+        assert_eq!(mmu.get(0x8000), 0xAA);
+        assert_eq!(mmu.get_vram(1, 0x8000), 0xBB);
+    }
+
+    #[test]
+    fn wram_banking_ok() {
+        let mut mmu = cgb_mmu();
+        mmu.set(SVBK, 3);
+        mmu.set(0xD123, 0x33);
+        mmu.set(SVBK, 0); // Bank 0 maps to bank 1
+        assert_eq!(mmu.get(SVBK) & 0b111, 1);
+        assert_eq!(mmu.get(0xD123), 0x00);
+        mmu.set(SVBK, 3);
+        assert_eq!(mmu.get(0xF123), 0x33); // Echo RAM follows the selected bank
+    }
+
+    #[test]
+    fn wram_banking_ignored_on_dmg() {
+        let mut mmu = Mmu::new();
+        mmu.set(SVBK, 3);
+        mmu.set(0xD000, 0x12);
+        assert_eq!(mmu.wram[1][0], 0x12);
+    }
+
+    #[test]
+    fn general_purpose_hdma_ok() {
+        let mut mmu = cgb_mmu();
+        for i in 0..0x20 {
+            mmu.set(0xC000 + i, i as u8);
+        }
+        mmu.set(HDMA1, 0xC0);
+        mmu.set(HDMA2, 0x00);
+        mmu.set(HDMA3, 0x01);
+        mmu.set(HDMA4, 0x00);
+        mmu.set(HDMA5, 0x01); // Two blocks, general purpose
+        assert_eq!(mmu.get(0x8100), 0x00);
+        assert_eq!(mmu.get(0x811F), 0x1F);
+        assert_eq!(mmu.get(HDMA5), 0xFF);
+        assert_eq!(mmu.stall_cycles, 16);
+    }
+
+    #[test]
+    fn hblank_hdma_ok() {
+        let mut mmu = cgb_mmu();
+        mmu.set(0xC010, 0x42);
+        mmu.set(HDMA1, 0xC0);
+        mmu.set(HDMA2, 0x00);
+        mmu.set(HDMA3, 0x00);
+        mmu.set(HDMA4, 0x00);
+        mmu.set(HDMA5, 0x81); // Two blocks, HBlank
+        assert_eq!(mmu.get(HDMA5), 0x01);
+        mmu.hblank();
+        assert_eq!(mmu.get(HDMA5), 0x00);
+        mmu.hblank();
+        assert_eq!(mmu.get(0x8010), 0x42);
+        assert_eq!(mmu.get(HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn speed_switch_ok() {
+        let mut mmu = cgb_mmu();
+        assert!(!mmu.try_speed_switch());
+        mmu.set(KEY1, 0x01);
+        assert_eq!(mmu.get(KEY1), 0x7F);
+        assert!(mmu.try_speed_switch());
+        assert_eq!(mmu.get(KEY1), 0xFE);
+        assert_eq!(mmu.speed_factor(), 2);
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::cartridge::Cartridge;
+use crate::registers::Registers;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Model {
+    Dmg, // Original Game Boy
+    Mgb, // Game Boy Pocket
+    Sgb, // Super Game Boy
+    Cgb, // Game Boy Color
+    Agb, // Game Boy Advance
+}
+
+pub const MODELS: [Model; 5] = [Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb, Model::Agb];
+
+impl Model {
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        *self == Model::Sgb
+    }
+
+    // CGB-only carts won't run on older hardware, so they always get a colour model
+    pub fn for_cartridge(self, cartridge: &Cartridge) -> Self {
+        if cartridge.cgb_only() && !self.is_cgb() {
+            Model::Cgb
+        } else {
+            self
+        }
+    }
+
+    /* The values the boot ROM of each model leaves in the registers, based on:
+       https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+       Games read A at boot to detect the hardware they're running on.
+     */
+    pub fn initial_registers(&self, cartridge: &Cartridge) -> Registers {
+        let mut reg = Registers::new();
+        reg.pc = 0x100;
+        reg.sp = 0xFFFE;
+        match self {
+            Model::Dmg | Model::Mgb => {
+                reg.a = if *self == Model::Dmg { 0x01 } else { 0xFF };
+                let checksum_set = cartridge.header_checksum() != 0;
+                reg.f.set_from_bool(true, false, checksum_set, checksum_set);
+                reg.set_bc(0x0013);
+                reg.set_de(0x00D8);
+                reg.set_hl(0x014D);
+            },
+            Model::Sgb => {
+                reg.a = 0x01;
+                reg.set_bc(0x0014);
+                reg.set_de(0x0000);
+                reg.set_hl(0xC060);
+            },
+            Model::Cgb | Model::Agb if cartridge.supports_cgb() => {
+                reg.a = 0x11;
+                reg.f.zero = *self == Model::Cgb;
+                reg.set_bc(if *self == Model::Agb { 0x0100 } else { 0x0000 });
+                reg.set_de(0xFF56);
+                reg.set_hl(0x000D);
+            },
+            Model::Cgb | Model::Agb => { // Running a DMG cart in compatibility mode
+                reg.a = 0x11;
+                let title_checksum = if cartridge.licensee_is_nintendo() { cartridge.title_checksum() } else { 0 };
+                reg.b = title_checksum;
+                reg.f.zero = true;
+                if *self == Model::Agb { // The AGB boot ROM ends with an extra INC B
+                    reg.b = title_checksum.wrapping_add(1);
+                    reg.f.zero = reg.b == 0;
+                    reg.f.half_carry = reg.b & 0xF == 0;
+                }
+                reg.set_de(0x0008);
+                reg.set_hl(if title_checksum == 0x43 || title_checksum == 0x58 { 0x991A } else { 0x007C });
+            },
+        }
+        reg
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Model::Dmg => "DMG",
+            Model::Mgb => "MGB",
+            Model::Sgb => "SGB",
+            Model::Cgb => "CGB",
+            Model::Agb => "AGB",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            "agb" => Ok(Model::Agb),
+            _ => Err(format!("Unknown model '{}', expected one of dmg, mgb, sgb, cgb, agb", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::model::Model;
+
+    fn cartridge(cgb_flag: u8, header_checksum: u8) -> Cartridge {
+        let mut cartridge = Cartridge::new();
+        cartridge.data = vec![0; 0x150];
+        cartridge.data[0x143] = cgb_flag;
+        cartridge.data[0x14D] = header_checksum;
+        cartridge
+    }
+
+    #[test]
+    fn dmg_registers_ok() {
+        let reg = Model::Dmg.initial_registers(&cartridge(0x00, 0x66));
+        assert_eq!(reg.af(), 0x01B0);
+        assert_eq!(reg.bc(), 0x0013);
+        assert_eq!(reg.de(), 0x00D8);
+        assert_eq!(reg.hl(), 0x014D);
+        assert_eq!(reg.sp, 0xFFFE);
+        assert_eq!(reg.pc, 0x100);
+    }
+
+    #[test]
+    fn dmg_registers_zero_checksum() {
+        let reg = Model::Dmg.initial_registers(&cartridge(0x00, 0x00));
+        assert_eq!(reg.af(), 0x0180);
+    }
+
+    #[test]
+    fn mgb_registers_ok() {
+        let reg = Model::Mgb.initial_registers(&cartridge(0x00, 0x66));
+        assert_eq!(reg.af(), 0xFFB0);
+        assert_eq!(reg.hl(), 0x014D);
+    }
+
+    #[test]
+    fn sgb_registers_ok() {
+        let reg = Model::Sgb.initial_registers(&cartridge(0x00, 0x66));
+        assert_eq!(reg.af(), 0x0100);
+        assert_eq!(reg.bc(), 0x0014);
+        assert_eq!(reg.de(), 0x0000);
+        assert_eq!(reg.hl(), 0xC060);
+    }
+
+    #[test]
+    fn cgb_registers_ok() {
+        let reg = Model::Cgb.initial_registers(&cartridge(0x80, 0x66));
+        assert_eq!(reg.af(), 0x1180);
+        assert_eq!(reg.bc(), 0x0000);
+        assert_eq!(reg.de(), 0xFF56);
+        assert_eq!(reg.hl(), 0x000D);
+    }
+
+    #[test]
+    fn cgb_registers_dmg_cart() {
+        let reg = Model::Cgb.initial_registers(&cartridge(0x00, 0x66));
+        assert_eq!(reg.af(), 0x1180);
+        assert_eq!(reg.de(), 0x0008);
+        assert_eq!(reg.hl(), 0x007C);
+    }
+
+    #[test]
+    fn agb_registers_ok() {
+        let reg = Model::Agb.initial_registers(&cartridge(0x80, 0x66));
+        assert_eq!(reg.af(), 0x1100);
+        assert_eq!(reg.bc(), 0x0100);
+        assert_eq!(reg.de(), 0xFF56);
+        assert_eq!(reg.hl(), 0x000D);
+    }
+
+    #[test]
+    fn agb_registers_dmg_cart() {
+        let reg = Model::Agb.initial_registers(&cartridge(0x00, 0x66));
+        assert_eq!(reg.a, 0x11);
+        assert_eq!(reg.b, 0x01);
+        assert!(!reg.f.zero);
+    }
+
+    #[test]
+    fn cgb_only_cart_selects_cgb() {
+        assert_eq!(Model::Dmg.for_cartridge(&cartridge(0xC0, 0)), Model::Cgb);
+        assert_eq!(Model::Agb.for_cartridge(&cartridge(0xC0, 0)), Model::Agb);
+        assert_eq!(Model::Dmg.for_cartridge(&cartridge(0x80, 0)), Model::Dmg);
+    }
+
+    #[test]
+    fn model_from_str_ok() {
+        assert_eq!("CGB".parse::<Model>(), Ok(Model::Cgb));
+        assert!("gba".parse::<Model>().is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::cpu::Cpu;
+use crate::disasm::{bank_address, bank_of, Labels};
+
+/* Where the time goes: executed count and clock cycles for every call stack and address, so the
+ * same data gives a hot-spot table per address or per function and folded stacks for flamegraphs
+ * (https://github.com/brendangregg/FlameGraph, `flamegraph.pl game.folded > game.svg`).
+ * Cycles spent halted count against the HALT, which shows how much of each frame is left over. */
+
+// Times executed and clock cycles taken
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub struct Sample {
+    pub executed: u64,
+    pub cycles: u64,
+}
+
+impl Sample {
+    fn add(&mut self, other: Sample) {
+        self.executed += other.executed;
+        self.cycles += other.cycles;
+    }
+}
+
+// A row of the hot-spot table
+#[derive(Clone, Debug)]
+pub struct HotSpot {
+    pub name: String,
+    pub sample: Sample,
+}
+
+const TOP_LEVEL: &str = "(top level)"; // Code outside any call without a symbol to name it
+
+#[derive(Default, Clone)]
+pub struct Profiler {
+    stacks: HashMap<Vec<u32>, Sample>, // Where the outermost call was made from, call targets, then the address executed
+    key: Vec<u32>, // For the instruction being executed, reused to save allocating every step
+    pub total: Sample,
+    pub frames: u64, // Frames drawn while profiling
+    last_frame: Option<u64>,
+}
+
+// Bank and address in one, RAM is always bank 0
+fn pack(address: u16, bank: u16) -> u32 {
+    (bank_of(address, bank).unwrap_or(0) as u32) << 16 | address as u32
+}
+
+fn unpack(key: u32) -> (u16, u16) {
+    (key as u16, (key >> 16) as u16)
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    // Called before the CPU executes the instruction at PC
+    pub fn begin(&mut self, cpu: &Cpu) {
+        self.key.clear();
+        let sp = cpu.reg.sp;
+        let mut frames = cpu.calls.frames.iter().filter(|frame| frame.sp >= sp).peekable();
+        if let Some(outermost) = frames.peek() {
+            self.key.push(pack(outermost.caller, outermost.caller_bank));
+        }
+        self.key.extend(frames.map(|frame| pack(frame.target, frame.target_bank)));
+        self.key.push(pack(cpu.reg.pc, cpu.mmu.rom_bank_at(cpu.reg.pc)));
+    }
+
+    // Called after, with the clock cycles it took. Halted steps only add cycles.
+    pub fn end(&mut self, cycles: usize, executed: bool, frame: u64) {
+        let sample = Sample { executed: executed as u64, cycles: cycles as u64 };
+        match self.stacks.get_mut(self.key.as_slice()) {
+            Some(total) => total.add(sample),
+            None => {
+                self.stacks.insert(self.key.clone(), sample);
+            }
+        }
+        self.total.add(sample);
+        if self.last_frame.is_some_and(|last| last != frame) {
+            self.frames += 1;
+        }
+        self.last_frame = Some(frame);
+    }
+
+    pub fn cycles_per_frame(&self) -> Option<u64> {
+        self.total.cycles.checked_div(self.frames)
+    }
+
+    // Busiest first, `per_function` groups addresses under the symbol before them (or the routine that was called)
+    pub fn hot_spots(&self, labels: &Labels, per_function: bool) -> Vec<HotSpot> {
+        // Symbol lookups aren't cheap, so stacks are first merged down to the places they'd be named by
+        let mut places: HashMap<(u32, Option<u32>), Sample> = HashMap::new();
+        for (key, sample) in &self.stacks {
+            let routine = (per_function && key.len() > 1).then(|| key[key.len() - 2]);
+            places.entry((*key.last().unwrap(), routine)).or_default().add(*sample);
+        }
+        let mut rows: HashMap<String, Sample> = HashMap::new();
+        for ((address, routine), sample) in places {
+            let name = match per_function {
+                true => function(address, routine, labels),
+                false => {
+                    let (address, bank) = unpack(address);
+                    labels.describe(address, bank)
+                }
+            };
+            rows.entry(name).or_default().add(sample);
+        }
+        let mut rows: Vec<HotSpot> = rows.into_iter().map(|(name, sample)| HotSpot { name, sample }).collect();
+        rows.sort_by(|a, b| b.sample.cycles.cmp(&a.sample.cycles).then_with(|| a.name.cmp(&b.name)));
+        rows
+    }
+
+    // One `Outer;Inner;Function cycles` line per call stack, for flamegraph.pl and friends
+    pub fn folded(&self, labels: &Labels) -> String {
+        let mut lines: HashMap<String, u64> = HashMap::new();
+        for (key, sample) in &self.stacks {
+            let mut names = vec![];
+            if key.len() > 1 {
+                let (caller, bank) = unpack(key[0]);
+                names.push(labels.nearest(caller, bank).map_or(TOP_LEVEL.to_string(), |(name, _)| name.to_string()));
+                names.extend(key[1..key.len() - 1].iter().map(|target| name(*target, labels)));
+            }
+            let function = function(*key.last().unwrap(), (key.len() > 1).then(|| key[key.len() - 2]), labels);
+            if names.last() != Some(&function) {
+                names.push(function);
+            }
+            *lines.entry(names.join(";")).or_default() += sample.cycles;
+        }
+        let mut lines: Vec<String> = lines.into_iter().map(|(stack, cycles)| format!("{} {}\n", stack, cycles)).collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+// The symbol at or before the address executed, otherwise the routine that was called
+fn function(address: u32, routine: Option<u32>, labels: &Labels) -> String {
+    let (address, bank) = unpack(address);
+    match (labels.nearest(address, bank), routine) {
+        (Some((name, _)), _) => name.to_string(),
+        (None, Some(routine)) => self::name(routine, labels),
+        (None, None) => TOP_LEVEL.to_string(),
+    }
+}
+
+fn name(key: u32, labels: &Labels) -> String {
+    let (address, bank) = unpack(key);
+    match labels.resolve(address, bank) {
+        Some(name) => name.to_string(),
+        None => bank_address(address, bank),
+    }
+}
+
+// `game.gb` -> `game.folded`
+pub fn folded_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("folded")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Model;
+    use crate::profiler::Profiler;
+    use crate::system::System;
+
+    // Main: CALL Work / JR Main ... Work: NOP / NOP / RET
+    fn system() -> System {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0xCD, 0x10, 0x01, 0x18, 0xFB]), (0x110, &[0x00, 0x00, 0xC9])]);
+        system.symbols.insert(0, 0x100, "Main");
+        system.symbols.insert(0, 0x110, "Work");
+        system.profiler = Some(Profiler::new());
+        system
+    }
+
+    #[test]
+    fn cycles_per_address_and_function() {
+        let mut system = system();
+        for _ in 0..50 { // 10 times round the loop
+            system.step(&[]);
+        }
+        let profiler = system.profiler.as_ref().unwrap();
+        assert_eq!(profiler.total.executed, 50);
+        let functions = profiler.hot_spots(&system.symbols, true);
+        assert_eq!(functions[0].name, "Main"); // CALL 24 + JR 12 beats NOP 4 + NOP 4 + RET 16
+        assert_eq!(functions[0].sample.executed, 20);
+        assert_eq!(functions[0].sample.cycles, 10 * (24 + 12));
+        assert_eq!(functions[1].sample.cycles, 10 * (4 + 4 + 16));
+
+        let addresses = profiler.hot_spots(&system.symbols, false);
+        assert_eq!(addresses.len(), 5);
+        assert_eq!(addresses[0].name, "00:0100 Main");
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let mut system = system();
+        for _ in 0..50 {
+            system.step(&[]);
+        }
+        let folded = system.profiler.as_ref().unwrap().folded(&system.symbols);
+        assert_eq!(folded, "Main 360\nMain;Work 240\n");
+    }
+}
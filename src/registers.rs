@@ -1,7 +1,7 @@
 use crate::{word_from, bytes_from};
 use crate::flags::Flags;
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum R8 {
     A,
     B, C,
@@ -26,7 +26,7 @@ impl R8 {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum R16 {
     AF,
     BC,
@@ -48,6 +48,7 @@ impl R16 {
     }
 }
 
+#[derive(Clone)]
 pub struct Registers {
     pub a: u8, pub f: Flags,
     pub b: u8, pub c: u8,
@@ -0,0 +1,206 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::system::System;
+
+/* Save state files: every field of the emulated machine in order, little-endian, with lengths in
+ * front of anything that can grow. Each part of the system writes and reads its own fields, so the
+ * order is whatever those `write_state`/`read_state` pairs agree on. Debugger settings aren't saved. */
+
+pub const STATE_MAGIC: &[u8; 4] = b"MBS1";
+
+#[derive(Default)]
+pub struct StateWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    // A fixed size block, read back with `fill`
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // Anything that can change size, with its length first
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.raw(bytes);
+    }
+
+    // An enum without data, as its position in `variants`
+    pub fn variant<T: PartialEq>(&mut self, variants: &[T], value: &T) {
+        self.u8(variants.iter().position(|variant| variant == value).expect("Every variant is listed") as u8);
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        StateReader { bytes, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.position..self.position + length).ok_or_else(|| invalid("The save state is truncated"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn fill(&mut self, into: &mut [u8]) -> io::Result<()> {
+        into.copy_from_slice(self.take(into.len())?);
+        Ok(())
+    }
+
+    pub fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    pub fn variant<T: Copy>(&mut self, variants: &[T]) -> io::Result<T> {
+        let index = self.u8()?;
+        variants.get(index as usize).copied().ok_or_else(|| invalid("The save state has an unknown value"))
+    }
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl System {
+    // The ROM isn't in the file, only its checksums so a state can't be loaded into another game
+    pub fn save_state_file(&self, path: &Path) -> io::Result<()> {
+        let mut out = StateWriter::new();
+        out.raw(STATE_MAGIC);
+        out.u8(self.cpu.mmu.cartridge.header_checksum());
+        out.u16(self.cpu.mmu.cartridge.global_checksum());
+        out.u64(self.steps);
+        self.cpu.write_state(&mut out);
+        self.graphics.write_state(&mut out);
+        self.serial.write_state(&mut out);
+        fs::write(path, out.bytes)
+    }
+
+    // Read into a copy first, so a bad file leaves the system as it was
+    pub fn load_state_file(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let mut input = StateReader::new(&bytes);
+        let mut magic = [0; 4];
+        input.fill(&mut magic)?;
+        if &magic != STATE_MAGIC {
+            return Err(invalid("Not a save state"));
+        }
+        let cartridge = &self.cpu.mmu.cartridge;
+        if input.u8()? != cartridge.header_checksum() || input.u16()? != cartridge.global_checksum() {
+            return Err(invalid("The save state is for a different ROM"));
+        }
+        let mut state = self.save_state();
+        state.0.steps = input.u64()?;
+        state.0.cpu.read_state(&mut input)?;
+        state.0.graphics.read_state(&mut input)?;
+        state.0.serial.read_state(&mut input)?;
+        if !input.is_empty() {
+            return Err(invalid("The save state has data after the end"));
+        }
+        self.load_state(&state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Model;
+    use crate::savestate::{StateReader, StateWriter};
+    use crate::system::System;
+
+    #[test]
+    fn round_trip() {
+        let mut out = StateWriter::new();
+        out.u8(0x12);
+        out.u16(0x3456);
+        out.u64(u64::MAX);
+        out.bool(true);
+        out.bytes(&[1, 2, 3]);
+        out.variant(&['a', 'b', 'c'], &'c');
+        let mut input = StateReader::new(&out.bytes);
+        assert_eq!(input.u8().unwrap(), 0x12);
+        assert_eq!(input.u16().unwrap(), 0x3456);
+        assert_eq!(input.u64().unwrap(), u64::MAX);
+        assert!(input.bool().unwrap());
+        assert_eq!(input.bytes().unwrap(), vec![1, 2, 3]);
+        assert_eq!(input.variant(&['a', 'b', 'c']).unwrap(), 'c');
+        assert!(input.is_empty());
+        assert!(input.u8().is_err());
+    }
+
+    // Loading a file and saving it again gives the same bytes, with the SGB's state included
+    #[test]
+    fn state_files_round_trip() {
+        let path = std::env::temp_dir().join(format!("metalboy-savestate-{}.state", std::process::id()));
+        let sgb = || System::with_program(Model::Sgb, &[(0x100, &[0x18, 0xFE])]);
+        let mut saved = sgb();
+        for _ in 0..5000 {
+            saved.step(&[]);
+        }
+        saved.save_state_file(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let mut loaded = sgb();
+        loaded.load_state_file(&path).unwrap();
+        assert!(loaded.cpu.mmu.sgb.is_some());
+        assert_eq!((loaded.steps, loaded.graphics.frame), (saved.steps, saved.graphics.frame));
+        loaded.save_state_file(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(sgb().load_state_file(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
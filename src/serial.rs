@@ -0,0 +1,86 @@
+use std::io;
+use crate::{check_bit, cpu};
+use crate::mmu::Mmu;
+use crate::savestate::{StateReader, StateWriter};
+
+pub const SB: u16 = 0xFF01; // Serial transfer data
+pub const SC: u16 = 0xFF02; // Serial control -- Bit 7 starts a transfer, bit 0 selects the internal clock
+pub const SERIAL_INTERRUPT_ID: u8 = 3;
+pub const SERIAL_CLOCK: usize = 8192; // Bits per second using the internal clock
+
+#[derive(Default, Clone)]
+pub struct Serial {
+    cycles: usize,
+    pub output: Vec<u8>, // Every byte sent, as there's never a link partner
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial::default()
+    }
+
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    // Driven by CPU cycles, so it runs twice as fast in double speed mode like the timer
+    pub fn update(&mut self, mmu: &mut Mmu, cycles: usize) {
+        let control = mmu.get(SC);
+        // Without a link partner an externally clocked transfer never completes
+        if !check_bit(control, 7) || !check_bit(control, 0) {
+            self.cycles = 0;
+            return;
+        }
+
+        let clock = if mmu.cgb_mode && check_bit(control, 1) { SERIAL_CLOCK * 32 } else { SERIAL_CLOCK };
+        let transfer_cycles = cpu::CLOCK_SPEED / clock * 8;
+        self.cycles += cycles;
+        if self.cycles >= transfer_cycles {
+            self.cycles = 0;
+            self.output.push(mmu.get(SB));
+            mmu.set(SB, 0xFF); // Nothing connected, so only 1s are shifted in
+            mmu.set(SC, control & 0x7F);
+            mmu.request_interrupt(SERIAL_INTERRUPT_ID);
+        }
+    }
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.u64(self.cycles as u64);
+        out.bytes(&self.output);
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        self.cycles = input.u64()? as usize;
+        self.output = input.bytes()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mmu::Mmu;
+    use crate::serial::{Serial, SB, SC};
+
+    #[test]
+    fn transfer_ok() {
+        let mut mmu = Mmu::new();
+        let mut serial = Serial::new();
+        mmu.set(SB, b'P');
+        mmu.set(SC, 0x81);
+        serial.update(&mut mmu, 4000);
+        assert!(serial.output.is_empty());
+        serial.update(&mut mmu, 96);
+        assert_eq!(serial.output_string(), "P");
+        assert_eq!(mmu.get(SB), 0xFF);
+        assert_eq!(mmu.get(SC), 0x01);
+        assert_eq!(mmu.get(0xFF0F) & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn external_clock_never_completes() {
+        let mut mmu = Mmu::new();
+        let mut serial = Serial::new();
+        mmu.set(SC, 0x80);
+        serial.update(&mut mmu, 100_000);
+        assert!(serial.output.is_empty());
+    }
+}
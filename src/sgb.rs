@@ -0,0 +1,614 @@
+use std::io;
+use log::{debug, warn};
+use crate::check_bit;
+use crate::joypad::{Button, BUTTONS};
+use crate::mmu::Mmu;
+use crate::savestate::{invalid, StateReader, StateWriter};
+
+pub const BORDER_WIDTH: usize = 256;
+pub const BORDER_HEIGHT: usize = 224;
+pub const SCREEN_X: usize = 48; // Position of the 160x144 Game Boy screen inside the border
+pub const SCREEN_Y: usize = 40;
+
+const PACKET_BITS: usize = 16 * 8;
+const CELLS_X: usize = 20; // The attribute map assigns a palette to every 8x8 cell of the screen
+const CELLS_Y: usize = 18;
+const ATTRIBUTE_FILE_SIZE: usize = 90;
+
+// The palette the SGB BIOS uses when a game never sends one
+const DEFAULT_PALETTE: [u16; 4] = [0x679F, 0x263B, 0x10B5, 0x2866];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Mask {
+    Cancel,
+    Freeze,
+    Black,
+    Colour0,
+}
+
+const MASKS: [Mask; 4] = [Mask::Cancel, Mask::Freeze, Mask::Black, Mask::Colour0];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Transfer {
+    Palettes,
+    Tiles(usize),
+    Border,
+    Attributes,
+}
+
+#[derive(Clone)]
+pub struct Sgb {
+    pub commands_enabled: bool,
+    // Packet transfer state
+    receiving: bool,
+    last_select: u8,
+    bit_index: usize,
+    packet: [u8; 16],
+    packets: Vec<u8>,
+    // Colourisation
+    pub palettes: [[u16; 4]; 4],
+    pub system_palettes: Vec<[u16; 4]>,
+    pub attributes: [u8; CELLS_X * CELLS_Y],
+    pub attribute_files: Vec<[u8; ATTRIBUTE_FILE_SIZE]>,
+    pub mask: Mask,
+    frozen: Option<Box<[[u32; 144]; 160]>>,
+    pending_transfer: Option<Transfer>,
+    // Border
+    pub border_tiles: Vec<u8>,
+    pub border_map: Vec<u16>,
+    pub border_palettes: [[u16; 16]; 4],
+    pub border: Vec<u32>,
+    // Multiplayer
+    pub players: u8,
+    pub current_player: u8,
+    pub inputs: [Vec<Button>; 4],
+}
+
+impl Sgb {
+    pub fn new(commands_enabled: bool) -> Self {
+        let mut sgb = Sgb {
+            commands_enabled,
+            receiving: false,
+            last_select: 0x30,
+            bit_index: 0,
+            packet: [0; 16],
+            packets: vec![],
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; 512],
+            attributes: [0; CELLS_X * CELLS_Y],
+            attribute_files: vec![[0; ATTRIBUTE_FILE_SIZE]; 45],
+            mask: Mask::Cancel,
+            frozen: None,
+            pending_transfer: None,
+            border_tiles: vec![0; 256 * 32],
+            border_map: vec![0; 32 * 32],
+            border_palettes: [[0; 16]; 4],
+            border: vec![],
+            players: 1,
+            current_player: 0,
+            inputs: [vec![], vec![], vec![], vec![]],
+        };
+        sgb.render_border();
+        sgb
+    }
+
+    pub fn multiplayer(&self) -> bool {
+        self.players > 1
+    }
+
+    /* Commands are sent one bit at a time through P14/P15 of JOYP, based on:
+       https://gbdev.io/pandocs/SGB_Command_Packet.html
+       A pulse with both lines LOW resets the transfer, P14 LOW sends a 0 and P15 LOW sends a 1.
+       Both lines go HIGH between pulses, and every 16 byte packet ends with a 0 stop bit.
+     */
+    pub fn write_joyp(&mut self, byte: u8) {
+        let select = byte & 0x30;
+        let previous = self.last_select;
+        self.last_select = select;
+
+        if select == 0x00 {
+            self.receiving = true;
+            self.bit_index = 0;
+            self.packet = [0; 16];
+            return;
+        }
+        if !self.receiving {
+            // The next controller is selected when P15 goes back HIGH
+            if self.multiplayer() && !check_bit(previous, 5) && select == 0x30 {
+                self.current_player = (self.current_player + 1) % self.players;
+            }
+            return;
+        }
+        if previous != 0x30 || select == 0x30 {
+            return; // Only the falling edge of a pulse carries a bit
+        }
+
+        let bit = (select == 0x10) as u8;
+        if self.bit_index < PACKET_BITS {
+            self.packet[self.bit_index / 8] |= bit << (self.bit_index % 8);
+            self.bit_index += 1;
+        } else {
+            // Stop bit
+            self.receiving = false;
+            self.receive_packet();
+        }
+    }
+
+    fn receive_packet(&mut self) {
+        self.packets.extend_from_slice(&self.packet);
+        let length = (self.packets[0] & 0b111).max(1) as usize;
+        if self.packets.len() >= length * 16 {
+            let data = std::mem::take(&mut self.packets);
+            if self.commands_enabled {
+                self.execute(&data);
+            }
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        let command = data[0] >> 3;
+        debug!("SGB command {:02X} ({} packets)", command, data.len() / 16);
+        match command {
+            0x00 => self.set_palette_pair(0, 1, data), // PAL01
+            0x01 => self.set_palette_pair(2, 3, data), // PAL23
+            0x02 => self.set_palette_pair(0, 3, data), // PAL03
+            0x03 => self.set_palette_pair(1, 2, data), // PAL12
+            0x04 => self.attr_blk(data),
+            0x05 => self.attr_lin(data),
+            0x06 => self.attr_div(data),
+            0x07 => self.attr_chr(data),
+            0x0A => self.pal_set(data),
+            0x0B => self.pending_transfer = Some(Transfer::Palettes), // PAL_TRN
+            0x11 => { // MLT_REQ
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            },
+            0x13 => self.pending_transfer = Some(Transfer::Tiles((data[1] & 1) as usize)), // CHR_TRN
+            0x14 => self.pending_transfer = Some(Transfer::Border), // PCT_TRN
+            0x15 => self.pending_transfer = Some(Transfer::Attributes), // ATTR_TRN
+            0x16 => self.attr_set(data[1]),
+            0x17 => self.set_mask(data[1]), // MASK_EN
+            0x08 | 0x09 | 0x0C..=0x10 | 0x12 | 0x18 | 0x19 => {}, // Sound, BIOS & OBJ commands
+            _ => warn!("Unknown SGB command {:02X}", command),
+        }
+    }
+
+    fn set_palette_pair(&mut self, first: usize, second: usize, data: &[u8]) {
+        let colour = |index: usize| u16::from_le_bytes([data[1 + index * 2], data[2 + index * 2]]);
+        // Colour 0 is shared between every palette
+        for palette in self.palettes.iter_mut() {
+            palette[0] = colour(0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = colour(i);
+            self.palettes[second][i] = colour(i + 3);
+        }
+    }
+
+    fn set_mask(&mut self, byte: u8) {
+        self.mask = match byte & 0b11 {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            _ => Mask::Colour0,
+        };
+        if self.mask != Mask::Freeze {
+            self.frozen = None;
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] as usize).min(18);
+        for set in data[2..].chunks(6).take(sets) {
+            if set.len() < 6 {
+                break;
+            }
+            let control = set[0];
+            let (inside, border, outside) = (set[1] & 0b11, (set[1] >> 2) & 0b11, (set[1] >> 4) & 0b11);
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+            // With only the inside or the outside selected, the border takes on that palette too
+            let border = match control & 0b111 {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                _ if check_bit(control, 1) => Some(border),
+                _ => None,
+            };
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        check_bit(control, 0).then_some(inside)
+                    } else if x < x1 || x > x2 || y < y1 || y > y2 {
+                        check_bit(control, 2).then_some(outside)
+                    } else {
+                        border
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * CELLS_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let sets = data[1] as usize;
+        for &set in data[2..].iter().take(sets) {
+            let line = (set & 0x1F) as usize;
+            let palette = (set >> 5) & 0b11;
+            if check_bit(set, 7) { // Horizontal line
+                for x in 0..CELLS_X {
+                    if line < CELLS_Y { self.attributes[line * CELLS_X + x] = palette; }
+                }
+            } else { // Vertical line
+                for y in 0..CELLS_Y {
+                    if line < CELLS_X { self.attributes[y * CELLS_X + line] = palette; }
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on) = (data[1] & 0b11, (data[1] >> 2) & 0b11, (data[1] >> 4) & 0b11);
+        let horizontal = check_bit(data[1], 6);
+        let coordinate = data[2] as usize;
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * CELLS_X + x] = match position {
+                    p if p < coordinate => before,
+                    p if p == coordinate => on,
+                    _ => after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 1 == 1;
+        for i in 0..count.min(CELLS_X * CELLS_Y) {
+            let Some(byte) = data.get(6 + i / 4) else { break };
+            if x >= CELLS_X || y >= CELLS_Y {
+                break;
+            }
+            self.attributes[y * CELLS_X + x] = (byte >> (6 - (i % 4) * 2)) & 0b11;
+            if vertical {
+                y += 1;
+                if y == CELLS_Y { y = 0; x += 1; }
+            } else {
+                x += 1;
+                if x == CELLS_X { x = 0; y += 1; }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for i in 0..4 {
+            let index = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) as usize & 0x1FF;
+            self.palettes[i] = self.system_palettes[index];
+        }
+        if check_bit(data[9], 7) {
+            self.attr_set(data[9]);
+        }
+    }
+
+    fn attr_set(&mut self, byte: u8) {
+        let file = (byte & 0x3F) as usize;
+        if file < self.attribute_files.len() {
+            for (i, packed) in self.attribute_files[file].iter().enumerate() {
+                for j in 0..4 {
+                    self.attributes[i * 4 + j] = (packed >> (6 - j * 2)) & 0b11;
+                }
+            }
+        }
+        if check_bit(byte, 6) {
+            self.set_mask(0);
+        }
+    }
+
+    // VRAM transfers copy the 4KB of tile data that's currently on screen, in display order
+    fn screen_vram(mmu: &Mmu) -> Vec<u8> {
+        let control = mmu.get(0xFF40);
+        let map = if check_bit(control, 3) { 0x9C00 } else { 0x9800 };
+        let mut data = Vec::with_capacity(0x1000);
+        for tile in 0..256u16 {
+            let tile_no = mmu.get_vram(0, map + (tile / 20) * 32 + tile % 20);
+            let address = if check_bit(control, 4) {
+                0x8000 + tile_no as u16 * 16
+            } else {
+                (0x9000 + (tile_no as i8 as i32) * 16) as u16
+            };
+            for i in 0..16 {
+                data.push(mmu.get_vram(0, address + i));
+            }
+        }
+        data
+    }
+
+    fn run_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        let word = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+        match transfer {
+            Transfer::Palettes => {
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    *palette = [word(i * 4), word(i * 4 + 1), word(i * 4 + 2), word(i * 4 + 3)];
+                }
+            },
+            Transfer::Tiles(half) => {
+                self.border_tiles[half * 0x1000..(half + 1) * 0x1000].copy_from_slice(&data[..0x1000]);
+                self.render_border();
+            },
+            Transfer::Border => {
+                for i in 0..self.border_map.len() {
+                    self.border_map[i] = word(i);
+                }
+                for (palette, colours) in self.border_palettes.iter_mut().enumerate() {
+                    for (i, colour) in colours.iter_mut().enumerate() {
+                        *colour = word(0x400 + palette * 16 + i);
+                    }
+                }
+                self.render_border();
+            },
+            Transfer::Attributes => {
+                for (i, file) in self.attribute_files.iter_mut().enumerate() {
+                    file.copy_from_slice(&data[i * ATTRIBUTE_FILE_SIZE..(i + 1) * ATTRIBUTE_FILE_SIZE]);
+                }
+            },
+        }
+    }
+
+    /* The border is made of 4bpp SNES tiles, 32x28 of them cover the 256x224 picture.
+       Map entries hold the tile number (bits 0-7), palette 4~7 (bits 10-12) and X/Y flip (bits 14/15).
+       Colour 0 is transparent and shows the SGB backdrop colour.
+     */
+    fn render_border(&mut self) {
+        let backdrop = rgb555_to_rgb888(self.palettes[0][0]);
+        self.border = vec![backdrop; BORDER_WIDTH * BORDER_HEIGHT];
+        for tile_y in 0..BORDER_HEIGHT / 8 {
+            for tile_x in 0..BORDER_WIDTH / 8 {
+                let entry = self.border_map[tile_y * 32 + tile_x];
+                let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..][..32];
+                let palette = ((entry >> 10) & 0b111).saturating_sub(4) as usize % 4;
+                for row in 0..8 {
+                    let line = if check_bit((entry >> 8) as u8, 7) { 7 - row } else { row };
+                    for column in 0..8 {
+                        let bit = if check_bit((entry >> 8) as u8, 6) { column } else { 7 - column } as u8;
+                        let colour_no = check_bit(tile[line * 2], bit) as usize
+                            | (check_bit(tile[line * 2 + 1], bit) as usize) << 1
+                            | (check_bit(tile[16 + line * 2], bit) as usize) << 2
+                            | (check_bit(tile[16 + line * 2 + 1], bit) as usize) << 3;
+                        if colour_no != 0 {
+                            let pixel = (tile_y * 8 + row) * BORDER_WIDTH + tile_x * 8 + column;
+                            self.border[pixel] = rgb555_to_rgb888(self.border_palettes[palette][colour_no]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Called by the PPU when VBlank starts, the framebuffer is recoloured with the SGB palettes
+    pub fn vblank(&mut self, mmu: &Mmu, fb: &mut [[u32; 144]; 160], shades: &[[u8; 144]; 160]) {
+        if let Some(transfer) = self.pending_transfer.take() {
+            let data = Self::screen_vram(mmu);
+            self.run_transfer(transfer, &data);
+        }
+
+        match self.mask {
+            Mask::Freeze => {
+                if let Some(frozen) = &self.frozen {
+                    *fb = **frozen;
+                    return;
+                }
+            },
+            Mask::Black => {
+                *fb = [[0; 144]; 160];
+                return;
+            },
+            Mask::Colour0 => {
+                *fb = [[rgb555_to_rgb888(self.palettes[0][0]); 144]; 160];
+                return;
+            },
+            Mask::Cancel => {},
+        }
+
+        for x in 0..160 {
+            for y in 0..144 {
+                let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+                fb[x][y] = rgb555_to_rgb888(self.palettes[palette][shades[x][y] as usize]);
+            }
+        }
+        if self.mask == Mask::Freeze {
+            self.frozen = Some(Box::new(*fb));
+        }
+    }
+    // The border is drawn again from its tiles rather than saved
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.bool(self.commands_enabled);
+        out.bool(self.receiving);
+        out.u8(self.last_select);
+        out.u64(self.bit_index as u64);
+        out.raw(&self.packet);
+        out.bytes(&self.packets);
+        self.palettes.iter().chain(&self.system_palettes).flatten().for_each(|colour| out.u16(*colour));
+        out.raw(&self.attributes);
+        self.attribute_files.iter().for_each(|file| out.raw(file));
+        out.variant(&MASKS, &self.mask);
+        out.bool(self.frozen.is_some());
+        if let Some(frozen) = &self.frozen {
+            frozen.iter().flatten().for_each(|pixel| out.u32(*pixel));
+        }
+        match self.pending_transfer {
+            None => out.u8(0),
+            Some(Transfer::Palettes) => out.u8(1),
+            Some(Transfer::Tiles(half)) => {
+                out.u8(2);
+                out.u64(half as u64);
+            }
+            Some(Transfer::Border) => out.u8(3),
+            Some(Transfer::Attributes) => out.u8(4),
+        }
+        out.raw(&self.border_tiles);
+        self.border_map.iter().chain(self.border_palettes.iter().flatten()).for_each(|word| out.u16(*word));
+        out.u8(self.players);
+        out.u8(self.current_player);
+        for inputs in &self.inputs {
+            out.u8(inputs.len() as u8);
+            inputs.iter().for_each(|button| out.variant(&BUTTONS, button));
+        }
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        self.commands_enabled = input.bool()?;
+        self.receiving = input.bool()?;
+        self.last_select = input.u8()?;
+        self.bit_index = input.u64()? as usize;
+        input.fill(&mut self.packet)?;
+        self.packets = input.bytes()?;
+        for colour in self.palettes.iter_mut().chain(&mut self.system_palettes).flatten() {
+            *colour = input.u16()?;
+        }
+        input.fill(&mut self.attributes)?;
+        for file in &mut self.attribute_files {
+            input.fill(file)?;
+        }
+        self.mask = input.variant(&MASKS)?;
+        self.frozen = match input.bool()? {
+            true => {
+                let mut frozen = Box::new([[0; 144]; 160]);
+                for pixel in frozen.iter_mut().flatten() {
+                    *pixel = input.u32()?;
+                }
+                Some(frozen)
+            }
+            false => None,
+        };
+        self.pending_transfer = match input.u8()? {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles(input.u64()? as usize)),
+            3 => Some(Transfer::Border),
+            4 => Some(Transfer::Attributes),
+            _ => return Err(invalid("The save state has an unknown SGB transfer")),
+        };
+        input.fill(&mut self.border_tiles)?;
+        for word in self.border_map.iter_mut().chain(self.border_palettes.iter_mut().flatten()) {
+            *word = input.u16()?;
+        }
+        self.players = input.u8()?;
+        self.current_player = input.u8()?;
+        for inputs in &mut self.inputs {
+            let count = input.u8()?;
+            *inputs = (0..count).map(|_| input.variant(&BUTTONS)).collect::<io::Result<_>>()?;
+        }
+        self.render_border();
+        Ok(())
+    }
+}
+
+pub fn rgb555_to_rgb888(colour: u16) -> u32 {
+    let expand = |c: u16| ((c << 3) | (c >> 2)) as u32;
+    let r = expand(colour & 0x1F);
+    let g = expand((colour >> 5) & 0x1F);
+    let b = expand((colour >> 10) & 0x1F);
+    (r << 16) | (g << 8) | b
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sgb::{Mask, Sgb, rgb555_to_rgb888};
+
+    fn send_packet(sgb: &mut Sgb, packet: &[u8; 16]) {
+        sgb.write_joyp(0x00); // Reset
+        sgb.write_joyp(0x30);
+        for byte in packet {
+            for bit in 0..8 {
+                sgb.write_joyp(if (byte >> bit) & 1 == 1 { 0x10 } else { 0x20 });
+                sgb.write_joyp(0x30);
+            }
+        }
+        sgb.write_joyp(0x20); // Stop bit
+        sgb.write_joyp(0x30);
+    }
+
+    #[test]
+    fn pal01_ok() {
+        let mut sgb = Sgb::new(true);
+        let mut packet = [0; 16];
+        packet[0] = (0x00 << 3) | 1;
+        packet[1..3].copy_from_slice(&0x7FFFu16.to_le_bytes());
+        packet[3..5].copy_from_slice(&0x001Fu16.to_le_bytes());
+        packet[9..11].copy_from_slice(&0x03E0u16.to_le_bytes());
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.palettes[0][0], 0x7FFF);
+        assert_eq!(sgb.palettes[3][0], 0x7FFF);
+        assert_eq!(sgb.palettes[0][1], 0x001F);
+        assert_eq!(sgb.palettes[1][1], 0x03E0);
+    }
+
+    #[test]
+    fn commands_ignored_when_disabled() {
+        let mut sgb = Sgb::new(false);
+        let mut packet = [0; 16];
+        packet[0] = (0x17 << 3) | 1;
+        packet[1] = 2;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.mask, Mask::Cancel);
+    }
+
+    #[test]
+    fn attr_blk_ok() {
+        let mut sgb = Sgb::new(true);
+        let mut packet = [0; 16];
+        packet[0] = (0x04 << 3) | 1;
+        packet[1] = 1; // One data set
+        packet[2] = 0b001; // Inside only, border follows
+        packet[3] = 0b10; // Palette 2
+        packet[4..8].copy_from_slice(&[2, 2, 4, 4]);
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.attributes[3 * 20 + 3], 2);
+        assert_eq!(sgb.attributes[2 * 20 + 2], 2);
+        assert_eq!(sgb.attributes[5 * 20 + 5], 0);
+    }
+
+    #[test]
+    fn attr_div_ok() {
+        let mut sgb = Sgb::new(true);
+        let mut packet = [0; 16];
+        packet[0] = (0x06 << 3) | 1;
+        packet[1] = 0b0_01_10_11; // Vertical division, on = 1, before = 2, after = 3
+        packet[2] = 10;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.attributes[0], 2);
+        assert_eq!(sgb.attributes[10], 1);
+        assert_eq!(sgb.attributes[19], 3);
+    }
+
+    #[test]
+    fn mlt_req_cycles_players() {
+        let mut sgb = Sgb::new(true);
+        let mut packet = [0; 16];
+        packet[0] = (0x11 << 3) | 1;
+        packet[1] = 1;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.players, 2);
+        assert_eq!(sgb.current_player, 0);
+        sgb.write_joyp(0x10);
+        sgb.write_joyp(0x30);
+        assert_eq!(sgb.current_player, 1);
+        sgb.write_joyp(0x10);
+        sgb.write_joyp(0x30);
+        assert_eq!(sgb.current_player, 0);
+    }
+
+    #[test]
+    fn rgb555_to_rgb888_ok() {
+        assert_eq!(rgb555_to_rgb888(0x7FFF), 0xFFFFFF);
+        assert_eq!(rgb555_to_rgb888(0x001F), 0xFF0000);
+        assert_eq!(rgb555_to_rgb888(0x0000), 0x000000);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::decode::decode_at;
+use crate::disasm::Labels;
+
+/* Source lines of an RGBDS project to addresses and back, for debugging from an editor. Neither .sym
+ * nor .map files have line information, so a line is found from the nearest label above it (which
+ * the symbols give an address) by decoding the instructions in between from the ROM. That holds as
+ * long as nothing but instructions (no data or macros) sits between the label and the line. */
+
+const SOURCE_EXTENSIONS: [&str; 5] = ["asm", "s", "inc", "z80", "sm83"];
+
+const MNEMONICS: [&str; 48] = [
+    "adc", "add", "and", "bit", "call", "ccf", "cp", "cpl", "daa", "dec", "di", "ei", "halt", "inc", "jp", "jr",
+    "ld", "ldd", "ldh", "ldi", "ldhl", "nop", "or", "pop", "push", "res", "ret", "reti", "rl", "rla", "rlc", "rlca",
+    "rr", "rra", "rrc", "rrca", "rst", "sbc", "scf", "set", "sla", "sra", "srl", "stop", "sub", "swap", "xor", "ldio",
+];
+
+// Directives that don't put anything in the ROM, so they don't get in the way of counting instructions
+const NO_OUTPUT: [&str; 12] = ["def", "redef", "assert", "static_assert", "export", "purge", "opt", "pusho", "popo", "warn", "print", "println"];
+const CONSTANTS: [&str; 4] = ["equ", "equs", "set", "="]; // `NAME EQU 3`
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Code {
+    None,
+    Instruction,
+    Other, // Data, macros and directives of unknown size
+}
+
+struct SourceLine {
+    label: Option<String>, // With the parent's name in front of local labels, as they are in .sym files
+    code: Code,
+}
+
+struct SourceFile {
+    path: PathBuf,
+    lines: Vec<SourceLine>,
+}
+
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    definitions: HashMap<String, (usize, usize)>, // Label to file and line
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    // Every source file under `root`
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let mut map = SourceMap::new();
+        let mut directories = vec![root.to_path_buf()];
+        let mut paths = vec![];
+        while let Some(directory) = directories.pop() {
+            for entry in fs::read_dir(directory)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    directories.push(path);
+                } else if path.extension().is_some_and(|extension| SOURCE_EXTENSIONS.iter().any(|wanted| extension == *wanted)) {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+        for path in paths {
+            let text = String::from_utf8_lossy(&fs::read(&path)?).into_owned();
+            map.add(&path, &text);
+        }
+        Ok(map)
+    }
+
+    pub fn add(&mut self, path: &Path, text: &str) {
+        let index = self.files.len();
+        let mut scope = String::new();
+        let lines: Vec<SourceLine> = text.lines().map(|line| parse_line(line, &mut scope)).collect();
+        for (number, line) in lines.iter().enumerate() {
+            if let Some(label) = &line.label {
+                self.definitions.insert(label.clone(), (index, number));
+            }
+        }
+        self.files.push(SourceFile { path: canonical(path), lines });
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // The address of the first instruction at or after `line` (counted from 0), and that line
+    pub fn address_of(&self, path: &Path, line: usize, labels: &Labels, read: &dyn Fn(u16, u16) -> u8) -> Result<(u16, u16, usize), String> {
+        let path = canonical(path);
+        let file = self.files.iter().find(|file| file.path == path).ok_or("Not a source file of this ROM")?;
+        let lines = &file.lines;
+        // Breakpoints on blank lines, comments and labels move down to the next instruction
+        let target = (line..lines.len()).find(|&i| lines[i].code != Code::None).ok_or("No code after this line")?;
+        if lines[target].code == Code::Other {
+            return Err("Not an instruction".to_string());
+        }
+        let start = (0..=target).rev()
+            .find(|&i| lines[i].label.as_ref().is_some_and(|label| labels.find(label).is_some()))
+            .ok_or("No label above this line in the symbols")?;
+        let (bank, mut address) = labels.find(lines[start].label.as_ref().unwrap()).unwrap();
+        for (i, line) in lines.iter().enumerate().take(target).skip(start) {
+            match line.code {
+                Code::Instruction => address = address.wrapping_add(length(read, bank, address)),
+                Code::Other => return Err(format!("Line {} has data or a macro, put a label after it", i + 1)),
+                Code::None => (),
+            }
+        }
+        Ok((bank, address, target))
+    }
+
+    // The source file and line (counted from 0) of the instruction at `address`, if it can be found
+    pub fn line_of(&self, address: u16, bank: u16, labels: &Labels, read: &dyn Fn(u16, u16) -> u8) -> Option<(&Path, usize)> {
+        let (name, _) = labels.nearest(address, bank)?;
+        let (label_bank, mut at) = labels.find(name)?;
+        let &(file, start) = self.definitions.get(name)?;
+        let file = &self.files[file];
+        for (i, line) in file.lines.iter().enumerate().skip(start) {
+            match line.code {
+                Code::Instruction if at == address => return Some((&file.path, i)),
+                Code::Instruction => {
+                    at = at.wrapping_add(length(read, label_bank, at));
+                    if at > address {
+                        return None; // Inside an instruction, or the source doesn't match the ROM
+                    }
+                }
+                Code::Other => return None,
+                Code::None => (),
+            }
+        }
+        None
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn length(read: &dyn Fn(u16, u16) -> u8, bank: u16, address: u16) -> u16 {
+    let bytes: Vec<u8> = (0..3).map(|offset| read(bank, address.wrapping_add(offset))).collect();
+    decode_at(&bytes).length as u16
+}
+
+// `Label:`, `.local:` or `Label::` at the start of the line, then an instruction or something else
+fn parse_line(text: &str, scope: &mut String) -> SourceLine {
+    let text = text.split(';').next().unwrap_or("");
+    let mut rest = text;
+    let mut label = None;
+    if !text.starts_with(char::is_whitespace) {
+        let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || "_.@#$".contains(c))).unwrap_or(text.len());
+        let name = &text[..end];
+        if !name.is_empty() && text[end..].starts_with(':') {
+            label = Some(match name.strip_prefix('.') {
+                Some(local) => format!("{}.{}", scope, local),
+                None => {
+                    if !name.contains('.') {
+                        *scope = name.to_string();
+                    }
+                    name.to_string()
+                }
+            });
+            rest = text[end..].trim_start_matches(':');
+        }
+    }
+    let mut words = rest.split_whitespace().map(|word| word.to_ascii_lowercase());
+    let code = match (words.next(), words.next()) {
+        (None, _) => Code::None,
+        (Some(first), _) if MNEMONICS.contains(&first.as_str()) => Code::Instruction,
+        (Some(first), _) if NO_OUTPUT.contains(&first.as_str()) => Code::None,
+        (_, Some(second)) if CONSTANTS.contains(&second.as_str()) => Code::None,
+        _ => Code::Other,
+    };
+    SourceLine { label, code }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::sourcemap::SourceMap;
+    use crate::symbols;
+
+    const SOURCE: &str = "\
+INCLUDE \"hardware.inc\"
+DEF SPEED EQU 3
+
+SECTION \"Main\", ROM0[$150]
+Main:
+    ld a, SPEED   ; 3E 03
+    call Wait     ; CD 60 01
+.loop:
+    jr .loop      ; 18 FE
+    db 1, 2, 3
+.after:
+    nop
+Wait::
+    ret
+";
+    const SYM: &str = "00:0150 Main\n00:0155 Main.loop\n00:015a Main.after\n00:0160 Wait\n";
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x150..0x157].copy_from_slice(&[0x3E, 0x03, 0xCD, 0x60, 0x01, 0x18, 0xFE]);
+        rom[0x160] = 0xC9;
+        rom
+    }
+
+    #[test]
+    fn address_of_line() {
+        let (rom, labels) = (rom(), symbols::parse(SYM));
+        let read = |_: u16, address: u16| rom[address as usize];
+        let mut map = SourceMap::new();
+        map.add(Path::new("main.asm"), SOURCE);
+        let path = Path::new("main.asm");
+        assert_eq!(map.address_of(path, 4, &labels, &read), Ok((0, 0x150, 5))); // The label moves to its first instruction
+        assert_eq!(map.address_of(path, 6, &labels, &read), Ok((0, 0x152, 6)));
+        assert_eq!(map.address_of(path, 8, &labels, &read), Ok((0, 0x155, 8)));
+        assert_eq!(map.address_of(path, 11, &labels, &read), Ok((0, 0x15A, 11)));
+        assert!(map.address_of(path, 9, &labels, &read).is_err()); // Data
+        assert!(map.address_of(path, 0, &labels, &read).is_err()); // Before any label
+        assert!(map.address_of(Path::new("other.asm"), 5, &labels, &read).is_err());
+    }
+
+    #[test]
+    fn line_of_address() {
+        let (rom, labels) = (rom(), symbols::parse(SYM));
+        let read = |_: u16, address: u16| rom[address as usize];
+        let mut map = SourceMap::new();
+        map.add(Path::new("main.asm"), SOURCE);
+        assert_eq!(map.line_of(0x150, 0, &labels, &read), Some((Path::new("main.asm"), 5)));
+        assert_eq!(map.line_of(0x152, 0, &labels, &read), Some((Path::new("main.asm"), 6)));
+        assert_eq!(map.line_of(0x160, 0, &labels, &read), Some((Path::new("main.asm"), 13)));
+        assert_eq!(map.line_of(0x153, 0, &labels, &read), None); // The middle of the CALL
+    }
+}
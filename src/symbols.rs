@@ -0,0 +1,143 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::disasm::{bank_of, Labels};
+
+/* Symbol files from rgblink (-n) and no$gmb, one `BB:AAAA Name` per line with `;` comments:
+ * https://rgbds.gbdev.io/sym/
+ * rgblink's map files (-m) list the same symbols by section, which is used when there's no .sym */
+
+// Read symbols from the text of a .sym file, skipping anything that isn't a symbol
+pub fn parse(text: &str) -> Labels {
+    let mut labels = Labels::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        let Some((location, name)) = line.split_once(char::is_whitespace) else { continue };
+        let Some((bank, address)) = location.split_once(':') else { continue };
+        let (Ok(bank), Ok(address)) = (u16::from_str_radix(bank, 16), u16::from_str_radix(address, 16)) else { continue };
+        // RAM banks don't matter to the debugger, RAM symbols are stored against bank 0
+        let bank = bank_of(address, bank).unwrap_or(0);
+        labels.insert(bank, address, name.trim());
+    }
+    labels
+}
+
+// Read symbols from a map file, `ROMX bank #3:` headers followed by `$4A2F = MyFunc` lines
+pub fn parse_map(text: &str) -> Labels {
+    let mut labels = Labels::new();
+    let mut bank = 0;
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some((_, number)) = line.strip_suffix(':').and_then(|header| header.split_once(" bank #")) {
+            bank = number.parse().unwrap_or(0);
+            continue;
+        }
+        let Some((address, name)) = line.split_once(" = ") else { continue };
+        let Some(Ok(address)) = address.strip_prefix('$').map(|digits| u16::from_str_radix(digits, 16)) else { continue };
+        labels.insert(bank_of(address, bank).unwrap_or(0), address, name.trim());
+    }
+    labels
+}
+
+// Either kind of file, going by the extension
+pub fn load(path: &Path) -> io::Result<Labels> {
+    let text = fs::read_to_string(path)?;
+    match path.extension().is_some_and(|extension| extension == "map") {
+        true => Ok(parse_map(&text)),
+        false => Ok(parse(&text)),
+    }
+}
+
+// `game.gb` -> `game.sym`, where rgblink and no$gmb put them
+pub fn sym_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("sym")
+}
+
+pub fn map_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("map")
+}
+
+// An address to stop at, `bank` is None when it should match whichever bank is mapped
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Location {
+    pub bank: Option<u16>,
+    pub address: u16,
+}
+
+impl Location {
+    pub fn matches(&self, address: u16, bank: u16) -> bool {
+        self.address == address && self.bank.is_none_or(|wanted| bank_of(address, bank) == Some(wanted))
+    }
+}
+
+// A symbol name, `03:4A2F`, or a plain `$4A2F` / `0x4A2F` / `4A2F` address
+pub fn parse_location(text: &str, labels: &Labels) -> Result<Location, String> {
+    let text = text.trim();
+    if let Some((bank, address)) = labels.find(text) {
+        return Ok(Location { bank: bank_of(address, bank), address });
+    }
+    if let Some((bank, address)) = text.split_once(':') {
+        let bank = u16::from_str_radix(bank, 16).map_err(|_| format!("Invalid bank '{}'", bank))?;
+        let address = u16::from_str_radix(address, 16).map_err(|_| format!("Invalid address '{}'", address))?;
+        return Ok(Location { bank: bank_of(address, bank), address });
+    }
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    match u16::from_str_radix(digits, 16) {
+        Ok(address) => Ok(Location { bank: None, address }),
+        Err(_) => Err(format!("No symbol or address '{}'", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbols::{parse, parse_location, parse_map, Location};
+
+    const SYM: &str = "; File generated by rgblink\n\
+                       00:0150 Entry\n\
+                       03:4a2f MyFunc\n\
+                       03:4a40 MyFunc.loop\n\
+                       01:d000 wScore\n";
+
+    #[test]
+    fn parse_ok() {
+        let labels = parse(SYM);
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels.get(3, 0x4A2F), Some("MyFunc"));
+        assert_eq!(labels.resolve(0xD000, 5), Some("wScore"));
+        assert_eq!(labels.describe(0x4A31, 3), "03:4A31 MyFunc+2");
+        assert_eq!(labels.describe(0x4A2F, 1), "01:4A2F");
+    }
+
+    #[test]
+    fn parse_map_ok() {
+        let map = "ROM0 bank #0:\n\
+                   \tSECTION: $0150-$01ff ($00b0 bytes) [\"Main\"]\n\
+                   \t         $0150 = Entry\n\
+                   ROMX bank #3:\n\
+                   \tSECTION: $4a2f-$4aff ($00d1 bytes) [\"Funcs\"]\n\
+                   \t         $4a2f = MyFunc\n\
+                   \t         $4a40 = MyFunc.loop\n\
+                   \tEMPTY: $4b00-$7fff ($3500 bytes)\n\
+                   WRAMX bank #1:\n\
+                   \t         $d000 = wScore\n";
+        let labels = parse_map(map);
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels.get(0, 0x150), Some("Entry"));
+        assert_eq!(labels.get(3, 0x4A40), Some("MyFunc.loop"));
+        assert_eq!(labels.resolve(0xD000, 5), Some("wScore"));
+    }
+
+    #[test]
+    fn parse_location_ok() {
+        let labels = parse(SYM);
+        assert_eq!(parse_location("MyFunc.loop", &labels), Ok(Location { bank: Some(3), address: 0x4A40 }));
+        assert_eq!(parse_location("wScore", &labels), Ok(Location { bank: None, address: 0xD000 }));
+        assert_eq!(parse_location("02:4000", &labels), Ok(Location { bank: Some(2), address: 0x4000 }));
+        assert_eq!(parse_location("$0150", &labels), Ok(Location { bank: None, address: 0x150 }));
+        assert!(parse_location("Missing", &labels).is_err());
+
+        let location = parse_location("MyFunc", &labels).unwrap();
+        assert!(location.matches(0x4A2F, 3));
+        assert!(!location.matches(0x4A2F, 4));
+    }
+}
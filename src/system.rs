@@ -1,19 +1,416 @@
+use std::mem;
+use crate::bootrom::BootRom;
+use crate::bus::AccessKind;
+use crate::cdl::CodeDataLog;
 use crate::cpu::Cpu;
+use crate::cpu::Status::{Halt, InfiniteLoop};
+use crate::debugger::{Breakpoint, BreakReason, RunTarget};
+use crate::decode::{decode, Mnemonic};
+use crate::disasm::Labels;
+use crate::graphics::{Graphics, FRAME_CYCLES, SCANLINE_CYCLES};
+use crate::heatmap::Heatmap;
+use crate::history::History;
+use crate::io_registers::IoWrites;
+use crate::joypad::{Button, Joypad};
+use crate::model::Model;
+use crate::profiler::Profiler;
+use crate::serial::Serial;
+use crate::sgb::Sgb;
+use crate::symbols::{self, Location};
+use crate::trace::Tracer;
+use log::info;
+
+// The instruction being executed, for the code/data log, heatmap and IO writes
+struct Fetched {
+    pc: u16,
+    length: u16,
+    offsets: Vec<usize>, // In the cartridge ROM
+}
 
 pub struct System {
     pub cpu: Cpu,
+    pub graphics: Graphics,
+    pub serial: Serial,
+    pub boot_rom: BootRom,
+    pub tracer: Option<Tracer>, // None unless tracing was asked for, so it costs a single check per step
+    pub symbols: Labels, // From the .sym (or .map) file next to the ROM, if there is one
+    pub breakpoints: Vec<Breakpoint>,
+    pub break_reason: Option<BreakReason>, // Set by the step that hit a breakpoint, watchpoint or bad opcode
+    pub log_messages: Vec<String>, // Output from log-points, for the frontend to take
+    pub run_target: Option<RunTarget>, // Stops with BreakReason::Stepped once reached
+    run_cycles: usize, // PPU cycles since the run target was set, scanlines and frames go by these with the LCD off
+    pub steps: u64, // Instructions since the last reset
+    pub history: Option<History>, // Snapshots for stepping backwards, when enabled
+    pub profiler: Option<Profiler>, // Cycles per address and call stack, when enabled
+    pub cdl: Option<CodeDataLog>, // How each ROM byte has been used, when enabled
+    pub heatmap: Option<Heatmap>, // Accesses per address each frame, when enabled
+    pub io_writes: Option<IoWrites>, // Writes per IO register each frame, when enabled
+    pub(crate) replaying: bool,
 }
 
 impl System {
-    pub fn new() -> Self {
+    pub fn new(model: Model) -> Self {
         let mut system = Self {
             cpu: Cpu::new(),
+            graphics: Graphics::new(),
+            serial: Serial::new(),
+            boot_rom: BootRom::Embedded,
+            tracer: None,
+            symbols: Labels::new(),
+            breakpoints: vec![],
+            break_reason: None,
+            log_messages: vec![],
+            run_target: None,
+            run_cycles: 0,
+            steps: 0,
+            history: None,
+            profiler: None,
+            cdl: None,
+            heatmap: None,
+            io_writes: None,
+            replaying: false,
         };
+        system.cpu.mmu.model = model;
         system.reset();
         system
     }
 
+    pub fn model(&self) -> Model {
+        self.cpu.mmu.model
+    }
+
+    pub fn load_cartridge(&mut self, rom_path: &str) {
+        self.cpu.mmu.cartridge.load(rom_path);
+        let sym_path = [symbols::sym_path(rom_path), symbols::map_path(rom_path)].into_iter().find(|path| path.exists());
+        self.symbols = match sym_path.map(|path| (symbols::load(&path), path)) {
+            Some((Ok(labels), path)) => {
+                info!("Loaded {} symbols from {}", labels.len(), path.display());
+                labels
+            }
+            _ => Labels::new(), // Most ROMs don't come with one
+        };
+        let model = self.model().for_cartridge(&self.cpu.mmu.cartridge);
+        if model != self.model() {
+            info!("Cartridge requires a CGB, switching from {} to {}", self.model(), model);
+            self.cpu.mmu.model = model;
+        }
+        self.cpu.mmu.cgb_mode = model.is_cgb() && self.cpu.mmu.cartridge.supports_cgb();
+        self.reset();
+    }
+
+    pub fn set_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot_rom = boot_rom;
+        self.reset();
+    }
+
     pub fn reset(&mut self) {
-        self.cpu.reset();
+        match self.boot_rom.data_for(self.model()) {
+            Some(data) => self.cpu.mmu.set_bootrom(data),
+            None => self.cpu.mmu.clear_bootrom(),
+        }
+        self.cpu.reset(); // Applies the post-boot state when there's no boot ROM to run
+        self.graphics = Graphics::new();
+        self.break_reason = None;
+        self.run_target = None;
+        self.serial = Serial::new();
+        self.steps = 0;
+        if let Some(history) = self.history.as_mut() {
+            *history = History::new(history.interval, history.capacity);
+        }
+        self.cpu.mmu.sgb = if self.model().is_sgb() {
+            Some(Sgb::new(self.cpu.mmu.cartridge.supports_sgb()))
+        } else {
+            None
+        };
+    }
+
+    // Buttons held on SGB controllers 2~4 (players 1~3), player 0 is passed to step() and any other is ignored
+    pub fn set_player_input(&mut self, player: usize, pressed: &[Button]) {
+        if player == 0 {
+            return;
+        }
+        if let Some(inputs) = self.cpu.mmu.sgb.as_mut().and_then(|sgb| sgb.inputs.get_mut(player)) {
+            *inputs = pressed.to_vec();
+        }
+    }
+
+    // The 256x224 SGB border as RGB pixels, if the system has one
+    pub fn sgb_border(&self) -> Option<&[u32]> {
+        self.cpu.mmu.sgb.as_ref().map(|sgb| sgb.border.as_slice())
+    }
+
+    // Execute one instruction and bring the rest of the hardware up to date, returns the PPU cycles taken
+    pub fn step(&mut self, pressed: &[Button]) -> usize {
+        let pc = self.cpu.reg.pc;
+        if self.history.is_some() && !self.replaying {
+            self.record(pressed);
+        }
+        self.cpu.mmu.watch_hit.set(None); // Anything the frontends read between steps doesn't count
+        if let Some(tracer) = self.tracer.as_mut() {
+            if self.cpu.status != Halt {
+                tracer.trace(&self.cpu, &self.symbols);
+            }
+        }
+        let halted = self.cpu.status == Halt;
+        let profiling = !self.replaying && self.profiler.is_some();
+        if profiling {
+            self.profiler.as_mut().unwrap().begin(&self.cpu);
+        }
+        let fetched = self.fetched();
+        let returning = self.run_target.is_some() && matches!(self.cpu.mmu.get(pc), 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9);
+        self.cpu.tick(); // Advance the CPU
+        if let Some(fetched) = fetched {
+            self.log_accesses(fetched);
+        }
+        let ppu_cycles = self.cpu.cycles * 4 / self.cpu.mmu.speed_factor();
+        self.cpu.timer.update(&mut self.cpu.mmu, self.cpu.cycles * 4);
+        self.serial.update(&mut self.cpu.mmu, self.cpu.cycles * 4);
+        self.graphics.update(&mut self.cpu.mmu, ppu_cycles);
+        Joypad::update(&mut self.cpu.mmu, pressed);
+        self.cpu.service_interrupts();
+        if profiling {
+            self.profiler.as_mut().unwrap().end(self.cpu.cycles * 4, !halted, self.graphics.frame);
+        }
+        self.steps += 1;
+        self.break_reason = self.check_break(pc);
+        if let Some(target) = self.run_target {
+            self.run_cycles += ppu_cycles;
+            if self.break_reason.is_none() && self.reached(target, returning) {
+                self.break_reason = Some(BreakReason::Stepped(target));
+            }
+            if self.break_reason.is_some() {
+                self.run_target = None; // Breakpoints on the way cancel the step
+            }
+        }
+        ppu_cycles
+    }
+
+    // The instruction about to execute, with accesses from now on logged, if anything wants them.
+    // Replayed steps already ran once, so they aren't logged again.
+    fn fetched(&mut self) -> Option<Fetched> {
+        if self.replaying || (self.cdl.is_none() && self.heatmap.is_none() && self.io_writes.is_none()) {
+            return None;
+        }
+        let pc = self.cpu.reg.pc;
+        let length = if self.cpu.status == Halt { 0 } else { decode(&self.cpu).length as u16 };
+        // Worked out now, as the instruction might switch banks or unmap the boot ROM
+        let offsets = match self.cdl {
+            Some(_) => (0..length).filter_map(|i| self.cpu.mmu.rom_offset_at(pc.wrapping_add(i))).collect(),
+            None => vec![],
+        };
+        let mmu = &mut self.cpu.mmu;
+        mmu.accesses.get_mut().clear();
+        mmu.log_accesses = true;
+        Some(Fetched { pc, length, offsets })
+    }
+
+    // Fetching the instruction doesn't count as reading it
+    fn log_accesses(&mut self, fetched: Fetched) {
+        let mmu = &mut self.cpu.mmu;
+        mmu.log_accesses = false;
+        let mut accesses = mem::take(mmu.accesses.get_mut());
+        accesses.retain(|(address, kind)| *kind == AccessKind::Write || address.wrapping_sub(fetched.pc) >= fetched.length);
+        if let Some(cdl) = self.cdl.as_mut() {
+            let data = accesses.iter().filter(|(_, kind)| *kind == AccessKind::Read).filter_map(|(address, _)| mmu.rom_offset_at(*address));
+            cdl.log(&fetched.offsets, data);
+        }
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            let instruction = (0..fetched.length).map(|i| fetched.pc.wrapping_add(i));
+            heatmap.log(instruction, accesses.iter().copied(), self.graphics.frame);
+        }
+        if let Some(io_writes) = self.io_writes.as_mut() {
+            io_writes.log(accesses.iter().copied(), self.graphics.frame);
+        }
+        *mmu.accesses.get_mut() = accesses; // Keeps the allocation
+    }
+
+    // In a JR to itself that no interrupt can get it out of. The status alone stays set in interrupt handlers.
+    pub fn stuck(&self) -> bool {
+        let cpu = &self.cpu;
+        let pc = cpu.reg.pc;
+        let interrupts = cpu.ime && cpu.mmu.get(0xFFFF) & 0x1F != 0;
+        cpu.status == InfiniteLoop && !interrupts && cpu.mmu.get(pc) == 0x18 && cpu.mmu.get(pc.wrapping_add(1)) == 0xFE
+    }
+
+    // How execution got to PC, for the debugger and crash reports
+    pub fn backtrace(&self) -> String {
+        let cpu = &self.cpu;
+        cpu.calls.backtrace(cpu.reg.pc, cpu.mmu.rom_bank_at(cpu.reg.pc), cpu.reg.sp, &self.symbols)
+    }
+
+    // The step functions only set a target, frontends keep calling step() until break_reason is set
+    pub fn step_into(&mut self) {
+        self.run_target = Some(RunTarget::Instruction);
+    }
+
+    // CALL and RST run until they return, anything else is a single step
+    pub fn step_over(&mut self) {
+        let instruction = decode(&self.cpu);
+        self.run_target = Some(match instruction.mnemonic {
+            Mnemonic::Call | Mnemonic::Rst => RunTarget::Return {
+                pc: self.cpu.reg.pc.wrapping_add(instruction.length as u16),
+                sp: self.cpu.reg.sp,
+            },
+            _ => RunTarget::Instruction,
+        });
+    }
+
+    pub fn step_out(&mut self) {
+        self.run_target = Some(RunTarget::Out { sp: self.cpu.reg.sp });
+    }
+
+    pub fn run_to(&mut self, location: Location) {
+        self.run_target = Some(RunTarget::Location(location));
     }
-}
\ No newline at end of file
+
+    // With the LCD off LY stays put, so this stops after a scanline's worth of cycles instead
+    pub fn step_scanline(&mut self) {
+        self.run_target = Some(RunTarget::Scanline(self.cpu.mmu.get(0xFF44)));
+        self.run_cycles = 0;
+    }
+
+    // Stops at the start of the next VBlank, or after a frame's worth of cycles with the LCD off
+    pub fn step_frame(&mut self) {
+        self.run_target = Some(RunTarget::Frame(self.graphics.frame));
+        self.run_cycles = 0;
+    }
+
+    fn reached(&self, target: RunTarget, returning: bool) -> bool {
+        let pc = self.cpu.reg.pc;
+        match target {
+            RunTarget::Instruction => true,
+            // The stack check keeps a recursive call from stopping early
+            RunTarget::Return { pc: return_pc, sp } => pc == return_pc && self.cpu.reg.sp >= sp,
+            RunTarget::Out { sp } => returning && self.cpu.reg.sp > sp,
+            RunTarget::Location(location) => location.matches(pc, self.cpu.mmu.rom_bank_at(pc)),
+            RunTarget::Scanline(ly) => self.cpu.mmu.get(0xFF44) != ly || self.lcd_off_for(SCANLINE_CYCLES),
+            RunTarget::Frame(frame) => self.graphics.frame != frame || self.lcd_off_for(FRAME_CYCLES),
+        }
+    }
+
+    fn lcd_off_for(&self, cycles: usize) -> bool {
+        !self.graphics.lcd_enabled(&self.cpu.mmu) && self.run_cycles >= cycles
+    }
+
+    // The first thing that should stop a debugger after the instruction at `pc`
+    fn check_break(&mut self, pc: u16) -> Option<BreakReason> {
+        if let Some(fault) = self.cpu.fault.take() {
+            return Some(BreakReason::Fault(fault));
+        }
+        if let Some(hit) = self.cpu.mmu.watch_hit.take() {
+            return Some(BreakReason::Watchpoint { pc, hit });
+        }
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+        let next = self.cpu.reg.pc;
+        let bank = self.cpu.mmu.rom_bank_at(next);
+        let mut breakpoints = std::mem::take(&mut self.breakpoints); // Conditions need to see all of self
+        let mut reason = None;
+        for breakpoint in breakpoints.iter_mut().filter(|breakpoint| breakpoint.location.matches(next, bank)) {
+            breakpoint.hits += 1;
+            if breakpoint.condition.as_ref().is_some_and(|condition| !condition.is_true(self, breakpoint.hits)) {
+                continue;
+            }
+            match &breakpoint.log {
+                Some(message) => self.log_messages.push(message.format(self, breakpoint.hits)),
+                None => reason = reason.or(Some(BreakReason::Breakpoint(breakpoint.location))),
+            }
+        }
+        self.breakpoints = breakpoints;
+        reason
+    }
+}
+
+#[cfg(test)]
+impl System {
+    // An otherwise empty 32KB ROM with these (address, bytes) written into it, started at 0x100 without a boot ROM
+    pub fn with_program(model: Model, program: &[(u16, &[u8])]) -> Self {
+        let mut system = System::new(model);
+        system.cpu.mmu.cartridge.data = vec![0; 0x8000];
+        for (address, bytes) in program {
+            let address = *address as usize;
+            system.cpu.mmu.cartridge.data[address..address + bytes.len()].copy_from_slice(bytes);
+        }
+        system.set_boot_rom(BootRom::Skip);
+        system
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::bootrom::BootRom;
+    use crate::graphics::{FRAME_CYCLES, SCANLINE_CYCLES};
+    use crate::joypad::Button;
+    use crate::model::Model;
+    use crate::system::System;
+
+    fn system(model: Model, boot_rom: BootRom) -> System {
+        let mut system = System::with_program(model, &[(0x14D, &[0xE7])]); // Header checksum
+        system.set_boot_rom(boot_rom);
+        system
+    }
+
+    #[test]
+    fn embedded_boot_rom_hands_over() {
+        let mut system = system(Model::Dmg, BootRom::Embedded);
+        assert!(system.cpu.mmu.bootrom_mapped);
+        assert_eq!(system.cpu.reg.pc, 0);
+        let mut steps = 0;
+        while system.cpu.mmu.bootrom_mapped && steps < 5_000_000 {
+            system.step(&[]);
+            steps += 1;
+        }
+        assert_eq!(system.cpu.reg.pc, 0x100);
+        assert!(system.cpu.check_handover());
+    }
+
+    #[test]
+    fn skip_boot_applies_post_boot_state() {
+        let mgb = system(Model::Mgb, BootRom::Embedded); // No embedded MGB boot ROM
+        assert!(!mgb.cpu.mmu.bootrom_mapped);
+        assert_eq!(mgb.cpu.reg.pc, 0x100);
+        assert_eq!(mgb.cpu.reg.af(), 0xFFB0);
+        assert_eq!(mgb.cpu.mmu.get(0xFF04), 0xAB);
+
+        let dmg = system(Model::Dmg, BootRom::Skip);
+        assert_eq!(dmg.cpu.reg.af(), 0x01B0);
+    }
+
+    #[test]
+    fn handover_mismatch_doesnt_panic() {
+        // LD A,0x01 / LDH (0x50),A hands over early with the wrong registers
+        let mut system = system(Model::Dmg, BootRom::Custom(HashMap::from([(Model::Dmg, vec![0x3E, 0x01, 0xE0, 0x50])])));
+        system.step(&[]);
+        system.step(&[]);
+        assert!(!system.cpu.mmu.bootrom_mapped);
+        assert!(!system.cpu.check_handover());
+    }
+
+    #[test]
+    fn sgb_player_input() {
+        let mut system = system(Model::Sgb, BootRom::Skip);
+        system.set_player_input(1, &[Button::Start]);
+        system.set_player_input(0, &[Button::A]); // Passed to step() instead
+        system.set_player_input(4, &[Button::B]); // There's no fifth controller
+        let sgb = system.cpu.mmu.sgb.as_ref().unwrap();
+        assert_eq!(sgb.inputs, [vec![], vec![Button::Start], vec![], vec![]]);
+    }
+
+    // NOP / JR -3 with the LCD off, where LY and the frame counter never move
+    #[test]
+    fn steps_finish_with_the_lcd_off() {
+        let mut system = System::with_program(Model::Dmg, &[(0x100, &[0x00, 0x18, 0xFD])]);
+        system.cpu.mmu.set(0xFF40, 0x00);
+        for (step, cycles) in [(System::step_scanline as fn(&mut System), SCANLINE_CYCLES), (System::step_frame, FRAME_CYCLES)] {
+            step(&mut system);
+            let mut ran = system.step(&[]);
+            while system.break_reason.is_none() && ran < FRAME_CYCLES * 2 {
+                ran += system.step(&[]);
+            }
+            assert!(system.break_reason.is_some());
+            assert!((cycles..cycles + 16).contains(&ran));
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::bootrom::BootRom;
+use crate::cpu::CLOCK_SPEED;
+use crate::cpu::Status::InfiniteLoop;
+use crate::model::Model;
+use crate::system::System;
+
+/* Headless runner for the blargg and mooneye test ROMs, based on:
+ * https://github.com/retrio/gb-test-roms (blargg: the result is written to the serial port)
+ * https://github.com/Gekkio/mooneye-test-suite#passfail-reporting (mooneye: LD B,B with registers set) */
+
+pub const DEFAULT_TIMEOUT: usize = 120; // Emulated seconds, cpu_instrs takes under a minute
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34]; // B, C, D, E, H, L
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "Passed"),
+            Outcome::Failed(reason) => write!(f, "Failed ({})", reason),
+            Outcome::Timeout => write!(f, "Timeout"),
+        }
+    }
+}
+
+pub struct TestResult {
+    pub rom: PathBuf,
+    pub outcome: Outcome,
+    pub seconds: f64, // Emulated time taken
+    pub serial: String,
+}
+
+// The verdict a blargg ROM has printed over serial so far, if any
+pub fn blargg_outcome(serial: &str) -> Option<Outcome> {
+    if serial.contains("Passed") {
+        Some(Outcome::Passed)
+    } else if serial.contains("Failed") {
+        let summary = serial.lines().rfind(|line| !line.trim().is_empty()).unwrap_or("");
+        Some(Outcome::Failed(summary.trim().to_string()))
+    } else {
+        None
+    }
+}
+
+// Mooneye ROMs execute LD B,B once they're done, with the Fibonacci sequence in the registers on a pass
+pub fn mooneye_outcome(system: &System) -> Option<Outcome> {
+    let cpu = &system.cpu;
+    if cpu.opcode != 0x40 || cpu.cb_prefix {
+        return None;
+    }
+    let registers = [cpu.reg.b, cpu.reg.c, cpu.reg.d, cpu.reg.e, cpu.reg.h, cpu.reg.l];
+    if registers == MOONEYE_PASS {
+        Some(Outcome::Passed)
+    } else if registers == MOONEYE_FAIL {
+        Some(Outcome::Failed("LD B,B with 0x42 in the registers".to_string()))
+    } else {
+        None // Just an LD B,B in ordinary code
+    }
+}
+
+pub fn run(rom: &Path, model: Model, timeout: usize) -> TestResult {
+    let mut system = System::new(model);
+    system.load_cartridge(&rom.to_string_lossy());
+    system.set_boot_rom(BootRom::Skip);
+
+    let max_cycles = CLOCK_SPEED * timeout;
+    let mut cycles = 0;
+    let mut checked_bytes = 0;
+    let outcome = loop {
+        cycles += system.step(&[]);
+        if let Some(outcome) = mooneye_outcome(&system) {
+            break outcome;
+        }
+        // Only re-scan the serial output when something new arrived
+        if system.serial.output.len() != checked_bytes {
+            checked_bytes = system.serial.output.len();
+            if let Some(outcome) = blargg_outcome(&system.serial.output_string()) {
+                break outcome;
+            }
+        }
+        if system.cpu.status == InfiniteLoop {
+            break Outcome::Failed(format!("Stuck at {:04X}", system.cpu.reg.pc));
+        }
+        if cycles >= max_cycles {
+            break Outcome::Timeout;
+        }
+    };
+
+    TestResult {
+        rom: rom.to_path_buf(),
+        outcome,
+        seconds: cycles as f64 / CLOCK_SPEED as f64,
+        serial: system.serial.output_string(),
+    }
+}
+
+// Every .gb/.gbc file below the directory, sorted so the tables are stable
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = vec![];
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                roms.extend(find_roms(&path));
+            } else if matches!(path.extension().and_then(|e| e.to_str()), Some("gb") | Some("gbc")) {
+                roms.push(path);
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+// A markdown compatibility table, ready to paste into the README
+pub fn table(results: &[TestResult], root: &Path) -> String {
+    let mut table = String::from("| ROM | Result | Time |\n|-----|--------|------|\n");
+    for result in results {
+        let name = result.rom.strip_prefix(root).unwrap_or(&result.rom).display();
+        let mark = if result.outcome == Outcome::Passed { "✅" } else { "❌" };
+        table += &format!("| {} | {} {} | {:.1}s |\n", name, mark, result.outcome, result.seconds);
+    }
+    let passed = results.iter().filter(|result| result.outcome == Outcome::Passed).count();
+    table += &format!("\n{}/{} passed\n", passed, results.len());
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Model;
+    use crate::system::System;
+    use crate::testrom::{blargg_outcome, mooneye_outcome, Outcome};
+
+    #[test]
+    fn blargg_verdict_from_serial() {
+        assert_eq!(blargg_outcome("cpu_instrs\n\n01:ok  "), None);
+        assert_eq!(blargg_outcome("01-special\n\n\nPassed\n"), Some(Outcome::Passed));
+        assert_eq!(
+            blargg_outcome("02-interrupts\n\n\nTimer doesn't work\nFailed #4\n"),
+            Some(Outcome::Failed("Failed #4".to_string()))
+        );
+    }
+
+    #[test]
+    fn mooneye_verdict_from_registers() {
+        let mut system = System::new(Model::Dmg);
+        system.cpu.opcode = 0x40;
+        assert_eq!(mooneye_outcome(&system), None);
+        let reg = &mut system.cpu.reg;
+        (reg.b, reg.c, reg.d, reg.e, reg.h, reg.l) = (3, 5, 8, 13, 21, 34);
+        assert_eq!(mooneye_outcome(&system), Some(Outcome::Passed));
+        system.cpu.cb_prefix = true; // BIT 0,B
+        assert_eq!(mooneye_outcome(&system), None);
+    }
+}
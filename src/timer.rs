@@ -1,6 +1,8 @@
+use std::io;
 use crate::check_bit;
 use crate::mmu::Mmu;
 use crate::cpu;
+use crate::savestate::{StateReader, StateWriter};
 
 pub const DIV_INC: usize = 16384;
 pub const DIV: u16 = 0xFF04;  // Divider register -- Increments at a rate of DIV_INC Hz
@@ -9,6 +11,7 @@ pub const TMA: u16 = 0xFF06;  // Timer modulo     -- Reset value for TIMA
 pub const TAC: u16 = 0xFF07;  // Timer control    -- Enable & frequency of incrementation
 pub const TIMER_INTERRUPT_ID: u8 = 2;
 
+#[derive(Clone)]
 pub struct Timer {
     div_cycles: usize,
     tima_cycles: usize,
@@ -65,4 +68,14 @@ impl Timer {
             }
         }
     }
+    pub fn write_state(&self, out: &mut StateWriter) {
+        out.u64(self.div_cycles as u64);
+        out.u64(self.tima_cycles as u64);
+    }
+
+    pub fn read_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        self.div_cycles = input.u64()? as usize;
+        self.tima_cycles = input.u64()? as usize;
+        Ok(())
+    }
 }
\ No newline at end of file
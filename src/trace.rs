@@ -0,0 +1,421 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use crate::cpu::Cpu;
+use crate::disasm::Labels;
+
+/* CPU trace logging, the text formats are based on:
+ * https://github.com/robert/gameboy-doctor (one line per instruction, state before it executes)
+ * https://bgb.bircd.org/manual.html (bank:address prefixed register dumps)
+ *
+ * Binary records are 20 bytes, little endian:
+ *   PC (2) | ROM bank (2) | A F B C D E H L (8) | SP (2) | IME (1) | LY (1) | 4 bytes at PC (4)
+ * preceded by the 4 byte magic BINARY_MAGIC. */
+
+pub const BINARY_MAGIC: &[u8; 4] = b"MBT3";
+pub const BINARY_RECORD_SIZE: usize = 20;
+const LY: u16 = 0xFF44;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceFormat {
+    Doctor,
+    Bgb,
+    Binary,
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceFormat::Doctor => write!(f, "doctor"),
+            TraceFormat::Bgb => write!(f, "bgb"),
+            TraceFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "doctor" => Ok(TraceFormat::Doctor),
+            "bgb" => Ok(TraceFormat::Bgb),
+            "binary" | "bin" => Ok(TraceFormat::Binary),
+            _ => Err(format!("Unknown trace format '{}', expected one of: doctor, bgb, binary", s)),
+        }
+    }
+}
+
+// Which instructions make it into the trace. Unset filters let everything through.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub rom_bank: Option<u16>,
+    pub start_pc: Option<u16>, // Tracing begins the first time PC reaches this address
+    pub stop_pc: Option<u16>,  // and ends for good the first time PC reaches this one
+}
+
+// The state of one traced instruction, as written to and read back from a trace
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub bank: u16,
+    pub a: u8, pub f: u8,
+    pub b: u8, pub c: u8,
+    pub d: u8, pub e: u8,
+    pub h: u8, pub l: u8,
+    pub sp: u16,
+    pub ime: bool,
+    pub ly: u8,
+    pub pcmem: [u8; 4],
+}
+
+impl TraceEntry {
+    pub fn capture(cpu: &Cpu) -> Self {
+        TraceEntry {
+            pc: cpu.reg.pc,
+            bank: cpu.mmu.rom_bank_at(cpu.reg.pc),
+            a: cpu.reg.a, f: cpu.reg.f.as_u8(),
+            b: cpu.reg.b, c: cpu.reg.c,
+            d: cpu.reg.d, e: cpu.reg.e,
+            h: cpu.reg.h, l: cpu.reg.l,
+            sp: cpu.reg.sp,
+            ime: cpu.ime,
+            ly: cpu.mmu.get(LY),
+            pcmem: [0, 1, 2, 3].map(|offset| cpu.mmu.get(cpu.reg.pc.wrapping_add(offset))),
+        }
+    }
+
+    pub fn doctor(&self) -> String {
+        format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc,
+            self.pcmem[0], self.pcmem[1], self.pcmem[2], self.pcmem[3])
+    }
+
+    pub fn bgb(&self) -> String {
+        let flag = |bit: u8, name: char| if self.f & (1 << bit) != 0 { name } else { '-' };
+        format!("{:02X}:{:04X}  {:02X} {:02X} {:02X}  AF={:02X}{:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} {}{}{}{} IME={} LY={:02X}",
+            self.bank, self.pc, self.pcmem[0], self.pcmem[1], self.pcmem[2],
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp,
+            flag(7, 'Z'), flag(6, 'N'), flag(5, 'H'), flag(4, 'C'), self.ime as u8, self.ly)
+    }
+
+    pub fn to_bytes(&self) -> [u8; BINARY_RECORD_SIZE] {
+        let [pc_lo, pc_hi] = self.pc.to_le_bytes();
+        let [bank_lo, bank_hi] = self.bank.to_le_bytes();
+        let [sp_lo, sp_hi] = self.sp.to_le_bytes();
+        [pc_lo, pc_hi, bank_lo, bank_hi, self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+            sp_lo, sp_hi, self.ime as u8, self.ly, self.pcmem[0], self.pcmem[1], self.pcmem[2], self.pcmem[3]]
+    }
+
+    pub fn from_bytes(bytes: &[u8; BINARY_RECORD_SIZE]) -> Self {
+        TraceEntry {
+            pc: u16::from_le_bytes([bytes[0], bytes[1]]),
+            bank: u16::from_le_bytes([bytes[2], bytes[3]]),
+            a: bytes[4], f: bytes[5],
+            b: bytes[6], c: bytes[7],
+            d: bytes[8], e: bytes[9],
+            h: bytes[10], l: bytes[11],
+            sp: u16::from_le_bytes([bytes[12], bytes[13]]),
+            ime: bytes[14] != 0,
+            ly: bytes[15],
+            pcmem: [bytes[16], bytes[17], bytes[18], bytes[19]],
+        }
+    }
+
+    // Parse a line written by doctor() or bgb(), or by another emulator in the same format
+    pub fn parse(line: &str) -> Option<Self> {
+        if line.starts_with("A:") {
+            TraceEntry::parse_doctor(line)
+        } else {
+            TraceEntry::parse_bgb(line)
+        }
+    }
+
+    fn parse_doctor(line: &str) -> Option<Self> {
+        let mut entry = TraceEntry::default();
+        for field in line.split_whitespace() {
+            let (name, value) = field.split_once(':')?;
+            let byte = || u8::from_str_radix(value, 16).ok();
+            let word = || u16::from_str_radix(value, 16).ok();
+            match name {
+                "A" => entry.a = byte()?,
+                "F" => entry.f = byte()?,
+                "B" => entry.b = byte()?,
+                "C" => entry.c = byte()?,
+                "D" => entry.d = byte()?,
+                "E" => entry.e = byte()?,
+                "H" => entry.h = byte()?,
+                "L" => entry.l = byte()?,
+                "SP" => entry.sp = word()?,
+                "PC" => entry.pc = word()?,
+                "PCMEM" => {
+                    for (i, byte) in value.split(',').take(4).enumerate() {
+                        entry.pcmem[i] = u8::from_str_radix(byte, 16).ok()?;
+                    }
+                }
+                _ => (),
+            }
+        }
+        Some(entry)
+    }
+
+    fn parse_bgb(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let (bank, pc) = fields.next()?.split_once(':')?;
+        let mut entry = TraceEntry {
+            bank: u16::from_str_radix(bank, 16).ok()?,
+            pc: u16::from_str_radix(pc, 16).ok()?,
+            ..Default::default()
+        };
+        for (i, field) in fields.enumerate() {
+            if i < 3 {
+                entry.pcmem[i] = u8::from_str_radix(field, 16).ok()?;
+                continue;
+            }
+            let Some((name, value)) = field.split_once('=') else { continue }; // The flags
+            let word = u16::from_str_radix(value, 16).ok()?;
+            let [hi, lo] = word.to_be_bytes();
+            match name {
+                "AF" => (entry.a, entry.f) = (hi, lo),
+                "BC" => (entry.b, entry.c) = (hi, lo),
+                "DE" => (entry.d, entry.e) = (hi, lo),
+                "HL" => (entry.h, entry.l) = (hi, lo),
+                "SP" => entry.sp = word,
+                "IME" => entry.ime = word != 0,
+                "LY" => entry.ly = lo,
+                _ => (),
+            }
+        }
+        Some(entry)
+    }
+}
+
+pub struct Tracer {
+    pub format: TraceFormat,
+    pub filter: TraceFilter,
+    pub labels: bool, // `Name:` lines before instructions at symbols, BGB format only
+    writer: Box<dyn Write>,
+    started: bool,
+    stopped: bool,
+}
+
+impl Tracer {
+    pub fn new(path: &str, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Tracer::with_writer(Box::new(file), format, filter)
+    }
+
+    pub fn with_writer(mut writer: Box<dyn Write>, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+        }
+        Ok(Tracer {
+            format,
+            started: filter.start_pc.is_none(),
+            filter,
+            labels: false,
+            writer,
+            stopped: false,
+        })
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // Record the CPU state before the instruction at PC executes, with a `Name:` line at symbols when asked for
+    pub fn trace(&mut self, cpu: &Cpu, symbols: &Labels) {
+        let pc = cpu.reg.pc;
+        if self.stopped {
+            return;
+        }
+        if self.filter.stop_pc == Some(pc) {
+            self.stopped = true;
+            let _ = self.writer.flush();
+            return;
+        }
+        if !self.started {
+            if self.filter.start_pc != Some(pc) {
+                return;
+            }
+            self.started = true;
+        }
+        if let Some(addresses) = &self.filter.addresses {
+            if !addresses.contains(&pc) {
+                return;
+            }
+        }
+        if let Some(bank) = self.filter.rom_bank {
+            if pc >= 0x8000 || cpu.mmu.rom_bank_at(pc) != bank {
+                return;
+            }
+        }
+
+        let entry = TraceEntry::capture(cpu);
+        // Doctor traces are diffed line for line against other emulators', so they never get labels
+        if self.labels && self.format == TraceFormat::Bgb {
+            if let Some(name) = symbols.resolve(pc, entry.bank) {
+                let _ = writeln!(self.writer, "{}:", name); // TraceReader skips these
+            }
+        }
+        let result = match self.format {
+            TraceFormat::Doctor => writeln!(self.writer, "{}", entry.doctor()),
+            TraceFormat::Bgb => writeln!(self.writer, "{}", entry.bgb()),
+            TraceFormat::Binary => self.writer.write_all(&entry.to_bytes()),
+        };
+        if result.is_err() {
+            self.stopped = true; // Out of disk space or similar, don't keep trying every instruction
+        }
+    }
+}
+
+// Reads back any trace format one entry at a time, so multi-gigabyte logs never sit in memory
+pub struct TraceReader {
+    reader: Box<dyn BufRead>,
+    binary: bool,
+    pub line: usize, // 1-based line or record number of the last entry read
+}
+
+impl TraceReader {
+    pub fn open(path: &str) -> io::Result<Self> {
+        TraceReader::new(Box::new(BufReader::new(File::open(path)?)))
+    }
+
+    pub fn new(mut reader: Box<dyn BufRead>) -> io::Result<Self> {
+        let binary = reader.fill_buf()?.starts_with(BINARY_MAGIC);
+        if binary {
+            reader.consume(BINARY_MAGIC.len());
+        }
+        Ok(TraceReader { reader, binary, line: 0 })
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = TraceEntry;
+
+    fn next(&mut self) -> Option<TraceEntry> {
+        if self.binary {
+            let mut record = [0; BINARY_RECORD_SIZE];
+            self.reader.read_exact(&mut record).ok()?;
+            self.line += 1;
+            return Some(TraceEntry::from_bytes(&record));
+        }
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            self.line += 1;
+            // Skip anything that isn't an instruction, like emulator banners
+            if let Some(entry) = TraceEntry::parse(line.trim()) {
+                return Some(entry);
+            }
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use crate::cpu::Cpu;
+    use crate::disasm::Labels;
+    use crate::model::Model;
+    use crate::system::System;
+    use crate::trace::{TraceEntry, TraceFilter, TraceFormat, TraceReader, Tracer, BINARY_MAGIC};
+
+    #[derive(Clone)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    fn cpu() -> Cpu {
+        System::with_program(Model::Dmg, &[(0x14D, &[0xE7])]).cpu // Header checksum
+    }
+
+    #[test]
+    fn doctor_format_ok() {
+        let cpu = cpu();
+        assert_eq!(TraceEntry::capture(&cpu).doctor(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,00,00,00");
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let entry = TraceEntry::capture(&cpu());
+        assert_eq!(TraceEntry::parse(&entry.doctor()).unwrap().doctor(), entry.doctor());
+        let bgb = TraceEntry::parse(&entry.bgb()).unwrap();
+        assert_eq!(bgb.bgb(), entry.bgb());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut cpu = cpu();
+        cpu.mmu.cartridge.data[0x100..0x104].copy_from_slice(&[0xC3, 0x50, 0x01, 0x76]);
+        let entry = TraceEntry { bank: 0x1FF, ..TraceEntry::capture(&cpu) }; // MBC5 has 512 banks
+        assert_eq!(TraceEntry::from_bytes(&entry.to_bytes()), entry);
+        let mut trace = BINARY_MAGIC.to_vec();
+        trace.extend_from_slice(&entry.to_bytes());
+        let read: Vec<_> = TraceReader::new(Box::new(io::Cursor::new(trace))).unwrap().collect();
+        assert_eq!(read[0].doctor(), entry.doctor()); // The whole of PCMEM survives
+    }
+
+    #[test]
+    fn start_and_stop_pc() {
+        let output = Shared(Rc::new(RefCell::new(vec![])));
+        let filter = TraceFilter { start_pc: Some(0x101), stop_pc: Some(0x103), ..Default::default() };
+        let mut tracer = Tracer::with_writer(Box::new(output.clone()), TraceFormat::Doctor, filter).unwrap();
+        let mut cpu = cpu();
+        let mut symbols = Labels::new();
+        symbols.insert(0, 0x102, "Main");
+        for _ in 0..5 {
+            tracer.trace(&cpu, &symbols);
+            cpu.tick(); // NOP
+        }
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(!text.contains("Main:"));
+        assert!(text.contains("PC:0101") && text.contains("PC:0102"));
+        assert!(tracer.is_stopped());
+    }
+
+    #[test]
+    fn labels_only_when_asked_for() {
+        let mut symbols = Labels::new();
+        symbols.insert(0, 0x101, "Main");
+        for (format, labels, expected) in [
+            (TraceFormat::Bgb, false, false),
+            (TraceFormat::Bgb, true, true),
+            (TraceFormat::Doctor, true, false),
+        ] {
+            let output = Shared(Rc::new(RefCell::new(vec![])));
+            let mut tracer = Tracer::with_writer(Box::new(output.clone()), format, TraceFilter::default()).unwrap();
+            tracer.labels = labels;
+            let mut cpu = cpu();
+            for _ in 0..2 {
+                tracer.trace(&cpu, &symbols);
+                cpu.tick(); // NOP
+            }
+            let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+            assert_eq!(text.contains("Main:\n"), expected, "{} with labels {}", format, labels);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::fmt;
+use crate::decode::decode_at;
+use crate::trace::TraceEntry;
+
+pub const DEFAULT_CONTEXT: usize = 8; // Instructions shown either side of the divergence
+
+pub struct Divergence {
+    pub instruction: usize, // 0-based index of the first differing instruction
+    pub ours: Option<TraceEntry>, // None if our trace ended first
+    pub reference: Option<TraceEntry>,
+    pub reason: String,
+    pub before: Vec<TraceEntry>, // Matching instructions leading up to it
+    pub ours_after: Vec<TraceEntry>,
+    pub reference_after: Vec<TraceEntry>,
+}
+
+// Disassemble the instruction a trace entry was about to execute, from the bytes it recorded at PC
+pub fn disassemble(entry: &TraceEntry) -> String {
+    decode_at(&entry.pcmem).to_string()
+}
+
+// The first register or flag that differs, PC first as everything else follows from it
+pub fn compare(ours: &TraceEntry, reference: &TraceEntry) -> Option<String> {
+    if ours.pc != reference.pc {
+        return Some(format!("PC is {:04X}, reference has {:04X}", ours.pc, reference.pc));
+    }
+    if ours.a != reference.a {
+        return Some(format!("A is {:02X}, reference has {:02X}", ours.a, reference.a));
+    }
+    for (bit, name) in [(7, "Z"), (6, "N"), (5, "H"), (4, "C")] {
+        let (our_flag, reference_flag) = ((ours.f >> bit) & 1, (reference.f >> bit) & 1);
+        if our_flag != reference_flag {
+            return Some(format!("Flag {} is {}, reference has {} (F {:02X} vs {:02X})", name, our_flag, reference_flag, ours.f, reference.f));
+        }
+    }
+    let bytes = [
+        ("B", ours.b, reference.b), ("C", ours.c, reference.c),
+        ("D", ours.d, reference.d), ("E", ours.e, reference.e),
+        ("H", ours.h, reference.h), ("L", ours.l, reference.l),
+    ];
+    for (name, ours, reference) in bytes {
+        if ours != reference {
+            return Some(format!("{} is {:02X}, reference has {:02X}", name, ours, reference));
+        }
+    }
+    if ours.sp != reference.sp {
+        return Some(format!("SP is {:04X}, reference has {:04X}", ours.sp, reference.sp));
+    }
+    None
+}
+
+// Walk both traces in step and stop at the first instruction that differs
+pub fn first_divergence<O, R>(mut ours: O, mut reference: R, context: usize) -> Option<Divergence>
+    where O: Iterator<Item = TraceEntry>, R: Iterator<Item = TraceEntry> {
+    let mut before: VecDeque<TraceEntry> = VecDeque::with_capacity(context + 1);
+    let mut instruction = 0;
+    loop {
+        let (our_entry, reference_entry) = (ours.next(), reference.next());
+        let reason = match (&our_entry, &reference_entry) {
+            (None, None) => return None,
+            (None, Some(_)) => Some("Our trace ended first".to_string()),
+            (Some(_), None) => Some("The reference trace ended first".to_string()),
+            (Some(our_entry), Some(reference_entry)) => compare(our_entry, reference_entry),
+        };
+        if let Some(reason) = reason {
+            return Some(Divergence {
+                instruction,
+                ours: our_entry,
+                reference: reference_entry,
+                reason,
+                before: before.into(),
+                ours_after: ours.take(context).collect(),
+                reference_after: reference.take(context).collect(),
+            });
+        }
+        before.push_back(our_entry.unwrap());
+        if before.len() > context {
+            before.pop_front();
+        }
+        instruction += 1;
+    }
+}
+
+fn line(marker: &str, instruction: usize, entry: &TraceEntry) -> String {
+    format!("{} {:>10}  {}  {}\n", marker, instruction, entry.doctor(), disassemble(entry))
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Traces diverge at instruction {}: {}\n", self.instruction, self.reason)?;
+        let first = self.instruction - self.before.len();
+        for (i, entry) in self.before.iter().enumerate() {
+            write!(f, "{}", line("  ", first + i, entry))?;
+        }
+        match &self.ours {
+            Some(entry) => write!(f, "{}", line("<<", self.instruction, entry))?,
+            None => writeln!(f, "<< (end of trace)")?,
+        }
+        match &self.reference {
+            Some(entry) => write!(f, "{}", line(">>", self.instruction, entry))?,
+            None => writeln!(f, ">> (end of trace)")?,
+        }
+        writeln!(f, "\nOurs after:")?;
+        for (i, entry) in self.ours_after.iter().enumerate() {
+            write!(f, "{}", line("<<", self.instruction + i + 1, entry))?;
+        }
+        writeln!(f, "Reference after:")?;
+        for (i, entry) in self.reference_after.iter().enumerate() {
+            write!(f, "{}", line(">>", self.instruction + i + 1, entry))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trace::TraceEntry;
+    use crate::tracediff::{disassemble, first_divergence};
+
+    fn entry(pc: u16, a: u8, f: u8) -> TraceEntry {
+        TraceEntry { pc, a, f, pcmem: [0x3C, 0, 0, 0], ..Default::default() }
+    }
+
+    #[test]
+    fn identical_traces() {
+        let trace = vec![entry(0x100, 1, 0), entry(0x101, 2, 0)];
+        assert!(first_divergence(trace.clone().into_iter(), trace.into_iter(), 2).is_none());
+    }
+
+    #[test]
+    fn reports_first_flag() {
+        let ours = vec![entry(0x100, 1, 0), entry(0x101, 2, 0x80), entry(0x102, 3, 0), entry(0x103, 4, 0)];
+        let reference = vec![entry(0x100, 1, 0), entry(0x101, 2, 0x00), entry(0x102, 9, 0)];
+        let divergence = first_divergence(ours.into_iter(), reference.into_iter(), 1).unwrap();
+        assert_eq!(divergence.instruction, 1);
+        assert!(divergence.reason.starts_with("Flag Z is 1"));
+        assert_eq!(divergence.before.len(), 1);
+        assert_eq!(divergence.ours_after.len(), 1);
+        assert!(divergence.to_string().contains("INC A"));
+    }
+
+    #[test]
+    fn reports_early_end() {
+        let ours = vec![entry(0x100, 1, 0)];
+        let reference = vec![entry(0x100, 1, 0), entry(0x101, 2, 0)];
+        let divergence = first_divergence(ours.into_iter(), reference.into_iter(), 4).unwrap();
+        assert!(divergence.ours.is_none());
+        assert_eq!(disassemble(&divergence.reference.unwrap()), "INC A");
+    }
+}
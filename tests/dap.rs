@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use serde_json::{json, Value};
+use metalboy::dap;
+
+/* Drives a debug session over TCP the way an editor would, against a small hand-assembled ROM with
+ * its source and .sym file. */
+
+const SOURCE: &str = "\
+SECTION \"VBlank\", ROM0[$40]
+VBlank:
+    reti
+
+SECTION \"Entry\", ROM0[$100]
+Entry:
+    ld a, $10
+    call Increment
+    ld [wResult], a
+    ld a, 1
+    ldh [$FF], a      ; Enable VBlank interrupts
+    ei
+.loop:
+    jr .loop
+
+SECTION \"Code\", ROM0[$110]
+Increment:
+    inc a
+    ret
+
+SECTION \"Variables\", WRAM0
+wResult: ds 1
+";
+
+const SYM: &str = "00:0040 VBlank\n00:0100 Entry\n00:010d Entry.loop\n00:0110 Increment\n00:c000 wResult\n";
+
+fn project() -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("metalboy-dap-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let mut rom = vec![0; 0x8000];
+    rom[0x40] = 0xD9;
+    rom[0x100..0x10F].copy_from_slice(&[0x3E, 0x10, 0xCD, 0x10, 0x01, 0xEA, 0x00, 0xC0, 0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);
+    rom[0x110..0x112].copy_from_slice(&[0x3C, 0xC9]);
+    fs::write(directory.join("game.gb"), rom).unwrap();
+    fs::write(directory.join("game.sym"), SYM).unwrap();
+    fs::write(directory.join("main.asm"), SOURCE).unwrap();
+    directory
+}
+
+// 1-based, as editors count
+fn line(text: &str) -> u64 {
+    SOURCE.lines().position(|line| line.trim() == text).unwrap() as u64 + 1
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: u64,
+    events: VecDeque<Value>, // Arrived while waiting for a response
+}
+
+impl Client {
+    fn connect() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            dap::serve(stream.try_clone().unwrap(), stream).unwrap();
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream, seq: 1, events: VecDeque::new() }
+    }
+
+    fn receive(&mut self) -> Value {
+        dap::read_message(&mut self.reader).unwrap().expect("The server hung up")
+    }
+
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        let seq = self.seq;
+        self.seq += 1;
+        dap::write_message(&mut self.writer, &json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })).unwrap();
+        loop {
+            let message = self.receive();
+            match message["type"].as_str() {
+                Some("response") if message["request_seq"] == seq => return message,
+                _ => self.events.push_back(message),
+            }
+        }
+    }
+
+    fn event(&mut self, name: &str) -> Value {
+        if let Some(index) = self.events.iter().position(|event| event["event"] == name) {
+            return self.events.remove(index).unwrap();
+        }
+        loop {
+            let message = self.receive();
+            if message["event"] == name {
+                return message;
+            }
+        }
+    }
+
+    fn top_frame(&mut self) -> Value {
+        let trace = self.request("stackTrace", json!({ "threadId": dap::THREAD_ID }));
+        trace["body"]["stackFrames"][0].clone()
+    }
+}
+
+#[test]
+fn debug_session() {
+    let directory = project();
+    let source = directory.join("main.asm");
+    let mut client = Client::connect();
+
+    let response = client.request("initialize", json!({ "adapterID": "metalboy", "linesStartAt1": true }));
+    assert_eq!(response["body"]["supportsReadMemoryRequest"], true);
+    client.event("initialized");
+    let program = directory.join("game.gb");
+    assert_eq!(client.request("launch", json!({ "program": program, "stopOnEntry": false }))["success"], true);
+    assert_eq!(client.request("launch", json!({ "program": directory.join("missing.gb") }))["success"], false);
+
+    let breakpoints = json!([{ "line": line("inc a") }, { "line": line("SECTION \"Code\", ROM0[$110]") }]);
+    let response = client.request("setBreakpoints", json!({ "source": { "path": source }, "breakpoints": breakpoints }));
+    let results = &response["body"]["breakpoints"];
+    assert_eq!(results[0]["verified"], true);
+    assert_eq!(results[0]["instructionReference"], "00:0110");
+    assert_eq!(results[1]["verified"], false);
+
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["body"]["reason"], "breakpoint");
+    let trace = client.request("stackTrace", json!({ "threadId": dap::THREAD_ID }));
+    let frames = &trace["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "Increment");
+    assert_eq!(frames[0]["line"], line("inc a"));
+    assert_eq!(Path::new(frames[0]["source"]["path"].as_str().unwrap()), fs::canonicalize(&source).unwrap());
+    assert_eq!(frames[1]["name"], "Entry+2");
+    assert_eq!(frames[1]["line"], line("call Increment"));
+
+    let scopes = client.request("scopes", json!({ "frameId": 0 }));
+    let reference = scopes["body"]["scopes"][0]["variablesReference"].clone();
+    let variables = client.request("variables", json!({ "variablesReference": reference }));
+    assert_eq!(variables["body"]["variables"][0], json!({ "name": "A", "value": "$10", "variablesReference": 0 }));
+
+    client.request("next", json!({ "threadId": dap::THREAD_ID }));
+    assert_eq!(client.event("stopped")["body"]["reason"], "step");
+    assert_eq!(client.top_frame()["line"], line("ret"));
+    client.request("stepOut", json!({ "threadId": dap::THREAD_ID }));
+    client.event("stopped");
+    assert_eq!(client.top_frame()["line"], line("ld [wResult], a"));
+    client.request("stepIn", json!({ "threadId": dap::THREAD_ID }));
+    client.event("stopped");
+    assert_eq!(client.request("evaluate", json!({ "expression": "A + 1" }))["body"]["result"], "$12 (18)");
+    let memory = client.request("readMemory", json!({ "memoryReference": "0xC000", "count": 2 }));
+    assert_eq!(memory["body"]["data"], "EQA="); // 11 00
+
+    // The game idles in a loop with VBlank interrupts on, so only a pause stops it
+    client.request("continue", json!({ "threadId": dap::THREAD_ID }));
+    thread::sleep(Duration::from_millis(100));
+    client.request("pause", json!({ "threadId": dap::THREAD_ID }));
+    assert_eq!(client.event("stopped")["body"]["reason"], "pause");
+    let name = client.top_frame()["name"].as_str().unwrap().to_string();
+    assert!(name == "Entry.loop" || name == "VBlank", "Paused at {}", name);
+
+    assert_eq!(client.request("disconnect", json!({}))["success"], true);
+    client.event("terminated");
+    fs::remove_dir_all(directory).unwrap();
+}
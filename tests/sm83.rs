@@ -0,0 +1,159 @@
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use metalboy::bus::{AccessKind, Bus, FlatBus};
+use metalboy::cpu::Cpu;
+
+/* The SM83 single-step tests, based on: https://github.com/SingleStepTests/sm83
+ * One JSON file per opcode, each holding initial state, final state and bus activity per M-cycle.
+ * They aren't distributed with the repo, point METALBOY_SM83_TESTS at a local copy of the `v1`
+ * directory (defaults to tests/sm83). The test is skipped when there are no fixtures. */
+
+fn fixture_dir() -> PathBuf {
+    match env::var("METALBOY_SM83_TESTS") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sm83"),
+    }
+}
+
+fn number(state: &Value, key: &str) -> u16 {
+    state[key].as_u64().unwrap_or(0) as u16
+}
+
+fn load(state: &Value) -> Cpu<FlatBus> {
+    let mut cpu = Cpu::with_bus(FlatBus::new());
+    cpu.reg.a = number(state, "a") as u8;
+    cpu.reg.f.set_from_u8(number(state, "f") as u8);
+    cpu.reg.b = number(state, "b") as u8;
+    cpu.reg.c = number(state, "c") as u8;
+    cpu.reg.d = number(state, "d") as u8;
+    cpu.reg.e = number(state, "e") as u8;
+    cpu.reg.h = number(state, "h") as u8;
+    cpu.reg.l = number(state, "l") as u8;
+    cpu.reg.sp = number(state, "sp");
+    // The tests have already fetched the opcode, PC points just past it
+    cpu.reg.pc = number(state, "pc").wrapping_sub(1);
+    cpu.ime = number(state, "ime") != 0;
+    for entry in state["ram"].as_array().into_iter().flatten() {
+        cpu.mmu.memory[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+    if let Some(ie) = state["ie"].as_u64() {
+        cpu.mmu.memory[0xFFFF] = ie as u8;
+    }
+    cpu.mmu.accesses.borrow_mut().clear();
+    cpu
+}
+
+// Everything that differs from the expected final state and bus activity
+fn compare(cpu: &Cpu<FlatBus>, test: &Value) -> Vec<String> {
+    let expected = &test["final"];
+    let mut errors = vec![];
+    let registers = [
+        ("a", cpu.reg.a as u16), ("f", cpu.reg.f.as_u8() as u16),
+        ("b", cpu.reg.b as u16), ("c", cpu.reg.c as u16),
+        ("d", cpu.reg.d as u16), ("e", cpu.reg.e as u16),
+        ("h", cpu.reg.h as u16), ("l", cpu.reg.l as u16),
+        ("sp", cpu.reg.sp), ("pc", cpu.reg.pc.wrapping_add(1)), // The next opcode is prefetched
+        ("ime", cpu.ime as u16),
+    ];
+    for (name, actual) in registers {
+        if expected[name].is_u64() && actual != number(expected, name) {
+            errors.push(format!("{}={:04X}, expected {:04X}", name, actual, number(expected, name)));
+        }
+    }
+    for entry in expected["ram"].as_array().into_iter().flatten() {
+        let address = entry[0].as_u64().unwrap() as u16;
+        let value = entry[1].as_u64().unwrap() as u8;
+        if cpu.mmu.memory[address as usize] != value {
+            errors.push(format!("[{:04X}]={:02X}, expected {:02X}", address, cpu.mmu.memory[address as usize], value));
+        }
+    }
+
+    // One entry per M-cycle, ending with the fetch of the next opcode
+    let expected_cycles: Vec<Option<Activity>> = test["cycles"].as_array().into_iter().flatten().map(activity).collect();
+    if cpu.cycles != expected_cycles.len() {
+        errors.push(format!("took {} M-cycles, expected {}", cpu.cycles, expected_cycles.len()));
+    }
+    let actual_cycles = bus_cycles(cpu);
+    for i in 0..actual_cycles.len().max(expected_cycles.len()) {
+        let actual = actual_cycles.get(i).copied().flatten();
+        let expected = expected_cycles.get(i).copied().flatten();
+        if actual != expected {
+            errors.push(format!("M-cycle {}: {}, expected {}", i + 1, describe(actual), describe(expected)));
+        }
+    }
+    errors
+}
+
+type Activity = (u16, u8, AccessKind);
+
+// `null` (or no "r"/"w" in the activity) when the CPU is busy internally, otherwise `[address, value, "r-m"/"-wm"]`
+fn activity(cycle: &Value) -> Option<Activity> {
+    let kind = match cycle[2].as_str() {
+        Some(kind) if kind.contains('w') => AccessKind::Write,
+        Some(kind) if kind.contains('r') => AccessKind::Read,
+        _ => return None,
+    };
+    Some((cycle[0].as_u64()? as u16, cycle[1].as_u64()? as u8, kind))
+}
+
+// What was on the bus in each M-cycle after the opcode fetch, which belongs to the previous instruction
+fn bus_cycles(cpu: &Cpu<FlatBus>) -> Vec<Option<Activity>> {
+    let mut cycles = vec![None; cpu.mmu.cycle.get().saturating_sub(1)];
+    for access in cpu.mmu.accesses.borrow().iter().skip(1) {
+        // FlatBus counts an M-cycle per access, so they can't share one
+        cycles[access.cycle - 1] = Some((access.address, access.value, access.kind));
+    }
+    cycles
+}
+
+fn describe(activity: Option<Activity>) -> String {
+    match activity {
+        Some((address, value, AccessKind::Read)) => format!("read {:02X} from {:04X}", value, address),
+        Some((address, value, AccessKind::Write)) => format!("wrote {:02X} to {:04X}", value, address),
+        None => "idle".to_string(),
+    }
+}
+
+fn run(test: &Value) -> Vec<String> {
+    let result = panic::catch_unwind(|| {
+        let mut cpu = load(&test["initial"]);
+        cpu.step();
+        cpu.mmu.read(cpu.reg.pc); // The next opcode is fetched in the instruction's last M-cycle
+        compare(&cpu, test)
+    });
+    result.unwrap_or_else(|_| vec!["panicked".to_string()])
+}
+
+#[test]
+fn sm83_single_step() {
+    let mut files: Vec<PathBuf> = fs::read_dir(fixture_dir())
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    files.retain(|path| path.extension().map_or(false, |ext| ext == "json"));
+    files.sort();
+    if files.is_empty() {
+        println!("No SM83 test fixtures found in {}, skipping", fixture_dir().display());
+        return;
+    }
+
+    panic::set_hook(Box::new(|_| {})); // Overflow panics are reported as failures instead
+    let mut failures = vec![];
+    for file in &files {
+        let tests: Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        let tests = tests.as_array().cloned().unwrap_or_default();
+        let failed: Vec<_> = tests.iter().filter_map(|test| {
+            let errors = run(test);
+            (!errors.is_empty()).then(|| format!("{}: {}", test["name"].as_str().unwrap_or("?"), errors.join(", ")))
+        }).collect();
+        if let Some(first) = failed.first() {
+            failures.push(format!("{} ({}/{} failed) {}", file.file_name().unwrap().to_string_lossy(), failed.len(), tests.len(), first));
+        }
+    }
+    let _ = panic::take_hook();
+
+    println!("{}/{} opcodes passed", files.len() - failures.len(), files.len());
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use metalboy::model::Model;
+use metalboy::testrom::{self, Outcome, DEFAULT_TIMEOUT};
+
+/* Conformance run over the blargg/mooneye ROMs, which aren't distributed with the repo.
+ * Point METALBOY_TEST_ROMS at a directory of them (defaults to the gb-test-roms submodule);
+ * the test is skipped when it doesn't exist. Run with `cargo test --release -- --nocapture`
+ * to see the compatibility table, and with METALBOY_RECORD_PASSES=1 to write the ROMs that
+ * pass to a passing_roms.txt in the temp directory, to review and copy over the one in tests/. */
+
+const HEADER: &str = "# Test ROMs that are known to pass, relative to the ROM directory (see test_roms.rs)\n";
+
+fn passing_list() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/passing_roms.txt")
+}
+
+fn rom_dir() -> PathBuf {
+    match env::var("METALBOY_TEST_ROMS") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => Path::new(env!("CARGO_MANIFEST_DIR")).join("gb-test-roms"),
+    }
+}
+
+// ROMs known to pass, one path per line relative to the ROM directory. These must not regress.
+fn expected_passes() -> Vec<String> {
+    fs::read_to_string(passing_list())
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn test_roms() {
+    let root = rom_dir();
+    let roms = testrom::find_roms(&root);
+    if roms.is_empty() {
+        println!("No test ROMs found in {}, skipping", root.display());
+        return;
+    }
+
+    let results: Vec<_> = roms.iter().map(|rom| testrom::run(rom, Model::Dmg, DEFAULT_TIMEOUT)).collect();
+    println!("{}", testrom::table(&results, &root));
+
+    if env::var("METALBOY_RECORD_PASSES").is_ok() {
+        let passes: String = results.iter()
+            .filter(|result| result.outcome == Outcome::Passed)
+            .map(|result| format!("{}\n", result.rom.strip_prefix(&root).unwrap_or(&result.rom).display()))
+            .collect();
+        let path = env::temp_dir().join("passing_roms.txt");
+        fs::write(&path, HEADER.to_string() + &passes).unwrap();
+        println!("Wrote the passing ROMs to {}", path.display());
+    }
+    let expected = expected_passes();
+    if expected.is_empty() {
+        println!("tests/passing_roms.txt lists no ROMs, skipping the regression check");
+        return;
+    }
+
+    let regressions: Vec<_> = expected
+        .into_iter()
+        .filter(|name| {
+            // Missing counts too, so a ROM that's been moved or renamed doesn't drop out of the check
+            !results.iter().any(|result| result.rom == root.join(name) && result.outcome == Outcome::Passed)
+        })
+        .collect();
+    assert!(regressions.is_empty(), "Test ROMs no longer passing: {:?}", regressions);
+}